@@ -0,0 +1,33 @@
+// Compiles the SQLite extensions enabled by the crate's features. The sources
+// in ext/ are copied unchanged from the ext/misc directory of SQLite 3.44.2.
+//
+// The extensions are built with SQLITE_CORE defined, so they call SQLite
+// directly, rather than through a loadable extension's API table, and are
+// registered on each connection as it's opened.
+
+fn main() {
+    #[cfg(any(
+        feature = "regexp",
+        feature = "series",
+        feature = "sha3",
+        feature = "uuid"
+    ))]
+    {
+        let extensions = [
+            (cfg!(feature = "regexp"), "regexp"),
+            (cfg!(feature = "series"), "series"),
+            (cfg!(feature = "sha3"), "shathree"),
+            (cfg!(feature = "uuid"), "uuid"),
+        ];
+
+        let mut build = cc::Build::new();
+        build.define("SQLITE_CORE", None).warnings(false);
+        for (_, source) in extensions.iter().filter(|(enabled, _)| *enabled) {
+            let path = format!("ext/{}.c", source);
+            println!("cargo:rerun-if-changed={}", path);
+            build.file(path);
+        }
+        build.compile("bb8_rusqlite_ext");
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
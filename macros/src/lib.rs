@@ -0,0 +1,293 @@
+//! Macros for `bb8-rusqlite`: `query!`, which checks queries against a
+//! database at compile time, and `#[derive(ToParams)]`. Use them through
+//! `bb8-rusqlite`'s `macros` feature, rather than depending on this crate
+//! directly.
+#![deny(missing_docs, missing_debug_implementations)]
+
+use std::{
+    collections::HashSet,
+    env,
+    path::{Path, PathBuf},
+};
+
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, quote_spanned};
+use rusqlite::{Connection, OpenFlags};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Data, DeriveInput, Expr, Fields, Ident, LitStr, Token,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// The environment variable naming the database queries are checked against.
+const DATABASE_VAR: &str = "BB8_RUSQLITE_DATABASE";
+
+/// Runs a query on a pool, checking it against a development database at
+/// compile time.
+///
+/// ```ignore
+/// let users = query!(pool, "SELECT id AS \"id!\", name FROM users WHERE team = ?", team).await?;
+/// println!("{}: {:?}", users[0].id, users[0].name);
+/// ```
+///
+/// The first argument is the `bb8::Pool<RusqliteConnectionManager>` to run
+/// the query on, the second the SQL, as a string literal, and the rest the
+/// query's positional parameters, which can be anything implementing
+/// `rusqlite::ToSql`.
+///
+/// The SQL is prepared against the database named by the
+/// `BB8_RUSQLITE_DATABASE` environment variable, relative to the crate's
+/// manifest if it isn't absolute, which is opened read only. SQL that
+/// doesn't prepare, or a number of parameters that doesn't match the
+/// statement's, is a compile error. Queries are only checked when the
+/// calling crate is compiled, so touch it after changing the schema.
+///
+/// A statement that returns rows evaluates to a future of
+/// `Result<Vec<Record>, bb8_rusqlite::Error>`, where `Record` is a struct
+/// with a field for each column, named after it. Column types are inferred
+/// from their declared types, using SQLite's affinity rules: `i64` for
+/// integers, `f64` for reals, `String` for text, and `Vec<u8>` for blobs,
+/// each wrapped in an `Option` since SQLite doesn't say whether a column can
+/// be null. Naming a column with a trailing `!`, such as `id AS "id!"`,
+/// drops the `Option`. Expressions, which have no declared type, and columns
+/// with numeric affinity are `rusqlite::types::Value`s.
+///
+/// A statement that doesn't return rows evaluates to a future of
+/// `Result<usize, bb8_rusqlite::Error>`, with the number of rows changed.
+#[proc_macro]
+pub fn query(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as Query);
+    database()
+        .and_then(|conn| expand(&conn, &input))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `bb8_rusqlite::ToParams` for a struct with named fields, binding
+/// each field to the parameter of the same name.
+///
+/// A field can be bound to a parameter with another name with
+/// `#[param(rename = "name")]`, or left out with `#[param(skip)]`.
+#[proc_macro_derive(ToParams, attributes(param))]
+pub fn derive_to_params(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    derive(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct Query {
+    pool: Expr,
+    sql: LitStr,
+    params: Vec<Expr>,
+}
+
+impl Parse for Query {
+    fn parse(input: ParseStream<'_>) -> syn::Result<Self> {
+        let pool = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let sql = input.parse()?;
+        let params = if input.is_empty() {
+            Vec::new()
+        } else {
+            input.parse::<Token![,]>()?;
+            Punctuated::<Expr, Token![,]>::parse_terminated(input)?
+                .into_iter()
+                .collect()
+        };
+        Ok(Self { pool, sql, params })
+    }
+}
+
+/// Opens the database named by `BB8_RUSQLITE_DATABASE`.
+fn database() -> syn::Result<Connection> {
+    let error = |message: String| syn::Error::new(Span::call_site(), message);
+    let path = env::var_os(DATABASE_VAR).ok_or_else(|| {
+        error(format!(
+            "set {} to the path of a database to check queries against",
+            DATABASE_VAR
+        ))
+    })?;
+    let path = match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) => Path::new(&dir).join(path),
+        None => PathBuf::from(path),
+    };
+    Connection::open_with_flags(
+        &path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .map_err(|e| error(format!("can't open {}: {}", path.display(), e)))
+}
+
+/// Generates the code for `query`, checked against `conn`.
+fn expand(conn: &Connection, query: &Query) -> syn::Result<TokenStream> {
+    let sql = query.sql.value();
+    let stmt = conn
+        .prepare(&sql)
+        .map_err(|e| syn::Error::new(query.sql.span(), e))?;
+    if stmt.parameter_count() != query.params.len() {
+        return Err(syn::Error::new(
+            query.sql.span(),
+            format!(
+                "the query has {} parameters, but {} were given",
+                stmt.parameter_count(),
+                query.params.len()
+            ),
+        ));
+    }
+
+    let pool = &query.pool;
+    let params = query.params.iter().map(|param| {
+        quote_spanned! {param.span()=> ::bb8_rusqlite::__private::value(&#param) }
+    });
+    let columns = stmt.columns();
+    if columns.is_empty() {
+        return Ok(quote! {
+            ::bb8_rusqlite::__private::execute(&#pool, #sql, ::std::vec![#(#params),*])
+        });
+    }
+
+    let mut names = HashSet::new();
+    let mut fields = Vec::with_capacity(columns.len());
+    let mut types = Vec::with_capacity(columns.len());
+    for column in &columns {
+        let (name, required) = match column.name().strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (column.name(), false),
+        };
+        let field = syn::parse_str::<Ident>(name).map_err(|_| {
+            syn::Error::new(
+                query.sql.span(),
+                format!(
+                    "column `{}` can't be a field name: name it with AS",
+                    column.name()
+                ),
+            )
+        })?;
+        if !names.insert(name) {
+            return Err(syn::Error::new(
+                query.sql.span(),
+                format!("more than one column is named `{}`", name),
+            ));
+        }
+        fields.push(field);
+        types.push(column_type(column.decl_type(), required));
+    }
+    let indexes = 0..columns.len();
+
+    Ok(quote! {{
+        #[derive(Debug, Clone, PartialEq)]
+        struct Record {
+            #(#fields: #types,)*
+        }
+
+        ::bb8_rusqlite::__private::query(
+            &#pool,
+            #sql,
+            ::std::vec![#(#params),*],
+            |row| ::std::result::Result::Ok(Record {
+                #(#fields: row.get(#indexes)?,)*
+            }),
+        )
+    }})
+}
+
+/// Returns the Rust type for a column declared as `decl_type`, following
+/// SQLite's rules for column affinity.
+fn column_type(decl_type: Option<&str>, required: bool) -> TokenStream {
+    let decl_type = match decl_type {
+        Some(decl_type) => decl_type.to_ascii_uppercase(),
+        None => return quote! { ::bb8_rusqlite::__private::Value },
+    };
+    let inner = if decl_type.contains("INT") {
+        quote! { i64 }
+    } else if ["CHAR", "CLOB", "TEXT"]
+        .iter()
+        .any(|t| decl_type.contains(t))
+    {
+        quote! { ::std::string::String }
+    } else if decl_type.contains("BLOB") || decl_type.is_empty() {
+        quote! { ::std::vec::Vec<u8> }
+    } else if ["REAL", "FLOA", "DOUB"]
+        .iter()
+        .any(|t| decl_type.contains(t))
+    {
+        quote! { f64 }
+    } else {
+        return quote! { ::bb8_rusqlite::__private::Value };
+    };
+    if required {
+        inner
+    } else {
+        quote! { ::std::option::Option<#inner> }
+    }
+}
+
+/// Generates the `ToParams` implementation for `input`.
+fn derive(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.ident.span(),
+                    "ToParams can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "ToParams can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut params = Vec::with_capacity(fields.len());
+    for field in fields {
+        let ident = field.ident.as_ref().expect("fields are named");
+        let mut name = ident.to_string();
+        // Raw identifiers bind to the parameter without the prefix.
+        if let Some(stripped) = name.strip_prefix("r#") {
+            name = stripped.into();
+        }
+        let mut skip = false;
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("param"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else if meta.path.is_ident("rename") {
+                    name = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `rename` or `skip`"))
+                }
+            })?;
+        }
+        if !skip {
+            params.push(quote_spanned! {field.ty.span()=>
+                .set(#name, ::bb8_rusqlite::__private::value(&self.#ident)?)
+            });
+        }
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bb8_rusqlite::ToParams for #ident #ty_generics #where_clause {
+            fn to_params(&self) -> ::bb8_rusqlite::__private::Result<::bb8_rusqlite::NamedParams> {
+                ::std::result::Result::Ok(::bb8_rusqlite::NamedParams::new()#(#params)*)
+            }
+        }
+    })
+}
@@ -0,0 +1,101 @@
+use super::*;
+
+fn conn() -> Connection {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, score REAL, avatar BLOB, joined DATE)",
+    )
+    .unwrap();
+    conn
+}
+
+fn expand_str(input: &str) -> Result<String, String> {
+    let query = syn::parse_str::<Query>(input).map_err(|e| e.to_string())?;
+    expand(&conn(), &query)
+        .map(|tokens| tokens.to_string())
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn column_types() {
+    let expanded = expand_str(
+        r#"pool, "SELECT id AS \"id!\", name, score, avatar, joined, 1 + 1 AS two FROM users WHERE id = ?", id"#,
+    )
+    .unwrap();
+    let record = "struct Record {
+            id: i64,
+            name: ::std::option::Option<::std::string::String>,
+            score: ::std::option::Option<f64>,
+            avatar: ::std::option::Option<::std::vec::Vec<u8>>,
+            joined: ::bb8_rusqlite::__private::Value,
+            two: ::bb8_rusqlite::__private::Value,
+        }";
+    let squash = |s: &str| s.split_whitespace().collect::<String>();
+    assert!(squash(&expanded).contains(&squash(record)), "{}", expanded);
+    assert!(expanded.contains("__private :: query"), "{}", expanded);
+}
+
+#[test]
+fn execute() {
+    let expanded = expand_str(r#"&pool, "DELETE FROM users WHERE id = ?", 1"#).unwrap();
+    assert!(expanded.contains("__private :: execute"), "{}", expanded);
+}
+
+#[test]
+fn errors() {
+    assert!(expand_str(r#"pool, "SELECT * FROM missing""#)
+        .unwrap_err()
+        .contains("no such table"));
+    assert_eq!(
+        expand_str(r#"pool, "SELECT id FROM users WHERE id = ?""#).unwrap_err(),
+        "the query has 1 parameters, but 0 were given"
+    );
+    assert_eq!(
+        expand_str(r#"pool, "SELECT count(*) FROM users""#).unwrap_err(),
+        "column `count(*)` can't be a field name: name it with AS"
+    );
+    assert_eq!(
+        expand_str(r#"pool, "SELECT id, id FROM users""#).unwrap_err(),
+        "more than one column is named `id`"
+    );
+}
+
+fn derive_str(input: &str) -> Result<String, String> {
+    let input = syn::parse_str::<DeriveInput>(input).map_err(|e| e.to_string())?;
+    derive(&input)
+        .map(|tokens| tokens.to_string())
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn derive_fields() {
+    let expanded = derive_str(
+        r#"struct User<T> { id: i64, #[param(rename = "@name")] name: T, #[param(skip)] cache: (), r#type: u8 }"#,
+    )
+    .unwrap();
+    assert!(
+        expanded.contains("impl < T > :: bb8_rusqlite :: ToParams for User < T >"),
+        "{}",
+        expanded
+    );
+    assert!(expanded.contains(r#". set ("id""#), "{}", expanded);
+    assert!(expanded.contains(r#". set ("@name""#), "{}", expanded);
+    assert!(expanded.contains(r#". set ("type""#), "{}", expanded);
+    assert!(!expanded.contains("cache"), "{}", expanded);
+}
+
+#[test]
+fn derive_errors() {
+    assert_eq!(
+        derive_str("struct Pair(i64, i64);").unwrap_err(),
+        "ToParams can only be derived for structs with named fields"
+    );
+    assert_eq!(
+        derive_str("enum Either { A, B }").unwrap_err(),
+        "ToParams can only be derived for structs"
+    );
+    assert_eq!(
+        derive_str("struct User { #[param(flatten)] id: i64 }").unwrap_err(),
+        "expected `rename` or `skip`"
+    );
+}
@@ -0,0 +1,52 @@
+//! Binding lists of values as a single parameter, through the `rarray()`
+//! table valued function.
+//!
+//! With the `array` feature enabled, every connection in the pool can query
+//! `rarray(?)`, which returns the values of a [`ValueList`] bound to it as a
+//! table with a single `value` column. This keeps long `IN` lists out of the
+//! SQL text, and clear of SQLite's limit on the number of parameters:
+//!
+//! ```sql
+//! SELECT * FROM users WHERE id IN rarray(?)
+//! ```
+
+use std::rc::Rc;
+
+use rusqlite::types::{ToSql, ToSqlOutput, Value};
+
+#[cfg(test)]
+mod tests;
+
+/// A list of values to bind to `rarray(?)`.
+///
+/// Unlike `rusqlite::vtab::array::Array`, this is `Send`, so it can be moved
+/// into pooled operations. The values are copied each time the list is
+/// bound.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValueList(Vec<Value>);
+
+impl ValueList {
+    /// Creates a list from any values that can be converted to SQLite values.
+    pub fn new<I>(values: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Value>,
+    {
+        Self(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T> From<Vec<T>> for ValueList
+where
+    T: Into<Value>,
+{
+    fn from(values: Vec<T>) -> Self {
+        Self::new(values)
+    }
+}
+
+impl ToSql for ValueList {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Array(Rc::new(self.0.clone())))
+    }
+}
@@ -0,0 +1,38 @@
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager, ValueList};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rarray() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("array.db")))
+        .await?;
+    pool.get().await?.execute_batch(
+        "CREATE TABLE t (id INTEGER PRIMARY KEY);
+         WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+         INSERT INTO t SELECT i FROM n;",
+    )?;
+
+    // More values than SQLite's default limit on the number of parameters.
+    let ids = ValueList::new((0..2000).map(|i| i * 2));
+    let count: i64 = pool.get().await?.query_row(
+        "SELECT COUNT(*) FROM t WHERE id IN rarray(?)",
+        &[&ids],
+        |row| row.get(0),
+    )?;
+    assert_eq!(count, 1999);
+
+    let empty: i64 = pool.get().await?.query_row(
+        "SELECT COUNT(*) FROM t WHERE id IN rarray(?)",
+        &[&ValueList::default()],
+        |row| row.get(0),
+    )?;
+    assert_eq!(empty, 0);
+
+    // Lists can be moved into pooled operations.
+    let plan = pool
+        .explain_plan("SELECT * FROM t WHERE id IN rarray(?)", vec![ids])
+        .await?;
+    assert!(plan.iter().any(|step| step.detail.contains("rarray")));
+
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Databases attached to every connection, and transactions across them.
+//!
+//! SQLite commits a transaction that wrote to several attached databases
+//! atomically, using a super-journal that names each database's rollback
+//! journal, so after a crash either every database has the changes or none
+//! do. That only holds while every database involved uses a rollback
+//! journal: a database in WAL mode commits on its own, and one with
+//! `journal_mode = MEMORY` or `OFF` can't be rolled back after a crash at
+//! all. In those modes each database's part of the transaction is still
+//! atomic, but a crash during the commit can leave some databases with the
+//! changes and others without.
+//!
+//! [`PoolExt::attached_transaction()`](crate::PoolExt::attached_transaction)
+//! checks the journal modes before running anything, so that a transaction
+//! that's meant to span databases can't silently lose that guarantee when
+//! one of them is switched to WAL.
+
+use std::path::Path;
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use crate::{sql, Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// Attaches the database at `path` to `conn` as `schema`.
+pub(crate) fn attach(conn: &Connection, schema: &str, path: &Path) -> Result<(), Error> {
+    conn.execute(
+        &format!("ATTACH DATABASE ? AS {}", sql::quote_identifier(schema)),
+        &[path.to_string_lossy().as_ref()],
+    )?;
+    Ok(())
+}
+
+/// Runs `f` in a transaction on `conn`, after checking that a transaction
+/// across all of its databases would commit atomically.
+pub(crate) fn transaction<F, T>(conn: &mut RusqliteConnection, f: F) -> Result<T, Error>
+where
+    F: FnOnce(&Connection) -> Result<T, Error>,
+{
+    check_atomic(conn)?;
+    let tx = conn.transaction()?;
+    let value = f(&tx)?;
+    tx.commit()?;
+    Ok(value)
+}
+
+/// Fails with [`Error::NotAtomic`] if any of `conn`'s databases has a
+/// journal mode that would break the atomicity of a transaction across
+/// them. The temp database isn't checked, since it doesn't survive a crash
+/// anyway.
+fn check_atomic(conn: &Connection) -> Result<(), Error> {
+    let mut schemas: Vec<String> = Vec::new();
+    conn.pragma_query(None, "database_list", |row| {
+        schemas.push(row.get(1)?);
+        Ok(())
+    })?;
+    schemas.retain(|schema| schema != "temp");
+    if schemas.len() < 2 {
+        return Ok(());
+    }
+
+    for schema in schemas {
+        let journal_mode: String = conn.query_row(
+            &format!("PRAGMA {}.journal_mode", sql::quote_identifier(&schema)),
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        let journal_mode = journal_mode.to_ascii_lowercase();
+        if !matches!(journal_mode.as_str(), "delete" | "truncate" | "persist") {
+            return Err(Error::NotAtomic {
+                schema,
+                journal_mode,
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::path::PathBuf;
+
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    PoolExt, RusqliteConnectionManager,
+};
+
+/// Creates the main and archive databases with the given journal modes, and
+/// a manager with the archive attached.
+fn manager(
+    temp: &TempDir,
+    main_mode: &str,
+    archive_mode: &str,
+) -> Result<RusqliteConnectionManager, anyhow::Error> {
+    let (main, archive) = (temp.file("main.db"), temp.file("archive.db"));
+    for (path, mode) in [(&main, main_mode), (&archive, archive_mode)] {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", &mode)?;
+        conn.execute_batch("CREATE TABLE t (a)")?;
+    }
+    Ok(RusqliteConnectionManager::new(main).with_attached_database("archive", archive))
+}
+
+fn count(path: PathBuf) -> Result<i64, rusqlite::Error> {
+    Connection::open(path)?.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commits_across_databases() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(manager(&temp, "delete", "truncate")?, 10, "").await?;
+
+    let moved = pool
+        .attached_transaction(|tx| {
+            tx.execute_batch("INSERT INTO main.t VALUES (1), (2)")?;
+            let moved = tx.execute(
+                "INSERT INTO archive.t SELECT a FROM main.t WHERE a = 1",
+                NO_PARAMS,
+            )?;
+            tx.execute("DELETE FROM main.t WHERE a = 1", NO_PARAMS)?;
+            Ok(moved)
+        })
+        .await?;
+
+    assert_eq!(moved, 1);
+    assert_eq!(count(temp.file("main.db"))?, 1);
+    assert_eq!(count(temp.file("archive.db"))?, 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rolls_back_across_databases() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(manager(&temp, "delete", "persist")?, 10, "").await?;
+
+    let result: Result<(), Error> = pool
+        .attached_transaction(|tx| {
+            tx.execute_batch("INSERT INTO main.t VALUES (1); INSERT INTO archive.t VALUES (1);")?;
+            tx.execute_batch("INSERT INTO missing VALUES (1)")?;
+            Ok(())
+        })
+        .await;
+
+    assert!(matches!(result, Err(Error::Rusqlite(_))));
+    assert_eq!(count(temp.file("main.db"))?, 0);
+    assert_eq!(count(temp.file("archive.db"))?, 0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejects_wal() -> Result<(), anyhow::Error> {
+    // Either database being in WAL mode is enough to break atomicity.
+    for (main_mode, archive_mode, schema) in [
+        ("wal", "delete", "main"),
+        ("delete", "wal", "archive"),
+        ("wal", "wal", "main"),
+    ] {
+        let temp = TempDir::new()?;
+        let pool = pool(manager(&temp, main_mode, archive_mode)?, 10, "").await?;
+
+        let result = pool
+            .attached_transaction(|tx| Ok(tx.execute("INSERT INTO t VALUES (1)", NO_PARAMS)?))
+            .await;
+
+        match result {
+            Err(Error::NotAtomic {
+                schema: found,
+                journal_mode,
+            }) => {
+                assert_eq!(found, schema);
+                assert_eq!(journal_mode, "wal");
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(count(temp.file("main.db"))?, 0);
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn single_database() -> Result<(), anyhow::Error> {
+    // Without anything attached, the journal mode doesn't matter.
+    let temp = TempDir::new()?;
+    let path = temp.file("main.db");
+    let conn = Connection::open(&path)?;
+    conn.pragma_update(None, "journal_mode", &"wal")?;
+    conn.execute_batch("CREATE TABLE t (a)")?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(&path))
+        .await?;
+
+    pool.attached_transaction(|tx| Ok(tx.execute("INSERT INTO t VALUES (1)", NO_PARAMS)?))
+        .await?;
+
+    assert_eq!(count(path)?, 1);
+    Ok(())
+}
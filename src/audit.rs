@@ -0,0 +1,375 @@
+//! Row-level audit logging, built on `sqlite3_preupdate_hook()`.
+//!
+//! An [`AuditLog`] given to
+//! [`RusqliteConnectionManager::with_audit_log()`](crate::RusqliteConnectionManager::with_audit_log)
+//! is installed on every connection that can write, and records each insert,
+//! update, and delete made to the tables it's configured with, with the row's
+//! values before and after the change as JSON objects keyed by column name.
+//!
+//! Changes are kept with the connection that made them until its transaction
+//! ends: those that are rolled back, including by a `COMMIT` that fails, are
+//! discarded, and those that commit are recorded when the connection is next
+//! returned to the pool, either into an audit table in the same database or
+//! by passing them to a callback.
+//!
+//! Entries are written to the audit table in a transaction of their own,
+//! after the audited transaction has committed, since SQLite doesn't allow
+//! statements to run from its commit hook. They aren't atomic with the
+//! changes they record: a process that exits between a commit and the
+//! connection's return, or an audit table write that keeps failing, loses
+//! that transaction's entries, so this is a guardrail rather than a
+//! tamper-proof ledger. Changes undone by `ROLLBACK TO` a savepoint are still
+//! recorded, since SQLite doesn't report those.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use rusqlite::{params, types::Value, Connection, DatabaseName};
+use serde_json::{Map, Number};
+
+use crate::{Error, PreUpdate, PreUpdateAction};
+
+#[cfg(test)]
+mod tests;
+
+/// A recorded change to an audited row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// The ID of the connection that made the change, as returned by
+    /// [`RusqliteConnection::id()`](crate::RusqliteConnection::id).
+    pub connection: u64,
+
+    /// The kind of change.
+    pub action: PreUpdateAction,
+
+    /// The schema the table is in, which is `main` unless the connection has
+    /// attached other databases.
+    pub database: String,
+
+    /// The table that was changed.
+    pub table: String,
+
+    /// The rowid of the row: the old rowid for deletes, and the new one
+    /// otherwise.
+    pub rowid: i64,
+
+    /// The row's values before the change, for updates and deletes.
+    pub old: Option<serde_json::Value>,
+
+    /// The row's values after the change, for inserts and updates.
+    pub new: Option<serde_json::Value>,
+}
+
+type Sink = dyn Fn(&[AuditEntry]) + Send + Sync;
+type ErrorCallback = dyn Fn(&Error) + Send + Sync;
+
+#[derive(Clone)]
+enum Store {
+    Table(String),
+    Sink(Arc<Sink>),
+}
+
+/// Which tables to audit, and where to record their changes.
+#[derive(Clone)]
+pub struct AuditLog {
+    store: Store,
+    // Lowercased, since SQLite's names aren't case sensitive.
+    tables: HashSet<String>,
+    on_error: Option<Arc<ErrorCallback>>,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let store = match &self.store {
+            Store::Table(name) => name.as_str(),
+            Store::Sink(_) => "<sink>",
+        };
+        f.debug_struct("AuditLog")
+            .field("store", &store)
+            .field("tables", &self.tables)
+            .finish()
+    }
+}
+
+impl AuditLog {
+    /// Records changes as rows in the table `name`, which is created if it
+    /// doesn't exist, with the columns `id`, `recorded_at` (a UTC timestamp),
+    /// `connection`, `action` (`INSERT`, `UPDATE`, or `DELETE`),
+    /// `database_name`, `table_name`, `row_id`, `old_values`, and
+    /// `new_values`. Changes to the audit table itself are never recorded.
+    pub fn to_table(name: &str) -> Self {
+        Self::new(Store::Table(name.into()))
+    }
+
+    /// Passes each committed transaction's changes to `callback`, in the
+    /// order they were made. The callback runs on the connection's thread
+    /// as it's returned to the pool, so it should be quick.
+    pub fn to_sink<F>(callback: F) -> Self
+    where
+        F: Fn(&[AuditEntry]) + Send + Sync + 'static,
+    {
+        Self::new(Store::Sink(Arc::new(callback)))
+    }
+
+    fn new(store: Store) -> Self {
+        Self {
+            store,
+            tables: HashSet::new(),
+            on_error: None,
+        }
+    }
+
+    /// Audits changes to `table`, in any attached database.
+    pub fn table(mut self, table: &str) -> Self {
+        self.tables.insert(table.to_ascii_lowercase());
+        self
+    }
+
+    /// Sets a callback for errors recording changes into the audit table.
+    /// The changes are kept, and recording them is retried the next time the
+    /// connection is returned.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    fn audits(&self, table: &str) -> bool {
+        let table = table.to_ascii_lowercase();
+        match &self.store {
+            Store::Table(name) if name.eq_ignore_ascii_case(&table) => false,
+            _ => self.tables.contains(&table),
+        }
+    }
+}
+
+/// A change as captured by the preupdate hook, before its values are named.
+#[derive(Debug)]
+struct Change {
+    action: PreUpdateAction,
+    database: String,
+    table: String,
+    rowid: i64,
+    old: Option<Vec<Value>>,
+    new: Option<Vec<Value>>,
+}
+
+/// The changes made on a connection that haven't been recorded yet.
+#[derive(Debug)]
+pub(crate) struct Pending {
+    log: AuditLog,
+    id: u64,
+    // Changes made by the transaction in progress.
+    current: Mutex<Vec<Change>>,
+    // Changes made by a transaction that's committing, but may yet fail to.
+    committing: Mutex<Vec<Change>>,
+    // Changes made by committed transactions.
+    committed: Mutex<Vec<Change>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // These are locked from SQLite's callbacks, which mustn't panic.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Pending {
+    pub(crate) fn new(log: AuditLog, id: u64) -> Self {
+        Self {
+            log,
+            id,
+            current: Mutex::default(),
+            committing: Mutex::default(),
+            committed: Mutex::default(),
+        }
+    }
+
+    /// Captures `change` if its table is audited. This is called by the
+    /// preupdate hook.
+    pub(crate) fn record(&self, change: &PreUpdate<'_>) {
+        if !self.log.audits(change.table) {
+            return;
+        }
+        let rowid = match change.action {
+            PreUpdateAction::Delete => change.old_rowid,
+            _ => change.new_rowid,
+        };
+        lock(&self.current).push(Change {
+            action: change.action,
+            database: change.database.into(),
+            table: change.table.into(),
+            rowid: rowid.unwrap_or_default(),
+            old: change.old_values(),
+            new: change.new_values(),
+        });
+    }
+
+    /// Sets the transaction's changes aside until it's known whether it
+    /// committed. This is called by the commit hook.
+    pub(crate) fn commit(&self) {
+        let mut current = lock(&self.current);
+        if !current.is_empty() {
+            lock(&self.committing).append(&mut current);
+        }
+    }
+
+    /// Keeps the committed transaction's changes for recording, once the
+    /// commit has finished.
+    pub(crate) fn keep(&self) {
+        let mut committing = lock(&self.committing);
+        if !committing.is_empty() {
+            lock(&self.committed).append(&mut committing);
+        }
+    }
+
+    /// Discards the transaction's changes. This is called by the rollback
+    /// hook.
+    pub(crate) fn discard(&self) {
+        lock(&self.current).clear();
+        lock(&self.committing).clear();
+    }
+
+    /// Records the committed changes, using `conn` to name their columns and
+    /// to write them to the audit table. This is called as the connection is
+    /// returned to the pool.
+    pub(crate) fn flush(&self, conn: &Connection) {
+        let changes = std::mem::take(&mut *lock(&self.committed));
+        if changes.is_empty() {
+            return;
+        }
+        let result = entries(conn, self.id, &changes).and_then(|entries| match &self.log.store {
+            Store::Table(name) => insert(conn, name, &entries),
+            Store::Sink(sink) => {
+                sink(&entries);
+                Ok(())
+            }
+        });
+        if let Err(e) = result {
+            // Put the changes back to be retried on the next return.
+            lock(&self.committed).splice(0..0, changes);
+            if let Some(callback) = &self.log.on_error {
+                callback(&e);
+            }
+        }
+    }
+}
+
+/// Names the values of each change.
+fn entries(conn: &Connection, id: u64, changes: &[Change]) -> Result<Vec<AuditEntry>, Error> {
+    let mut columns: HashMap<(&str, &str), Vec<String>> = HashMap::new();
+    let mut entries = Vec::with_capacity(changes.len());
+    for change in changes {
+        let key = (change.database.as_str(), change.table.as_str());
+        let names = match columns.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut names = Vec::new();
+                conn.pragma(
+                    Some(DatabaseName::Attached(&change.database)),
+                    "table_xinfo",
+                    &change.table,
+                    |row| {
+                        names.push(row.get("name")?);
+                        Ok(())
+                    },
+                )?;
+                entry.insert(names)
+            }
+        };
+        let object = |values: &Option<Vec<Value>>| {
+            values.as_ref().map(|values| {
+                let object = values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        let name = names.get(i).cloned().unwrap_or_else(|| i.to_string());
+                        (name, json(value))
+                    })
+                    .collect::<Map<_, _>>();
+                serde_json::Value::Object(object)
+            })
+        };
+        entries.push(AuditEntry {
+            connection: id,
+            action: change.action,
+            database: change.database.clone(),
+            table: change.table.clone(),
+            rowid: change.rowid,
+            old: object(&change.old),
+            new: object(&change.new),
+        });
+    }
+    Ok(entries)
+}
+
+/// Converts a SQLite value to JSON. Blobs become lowercase hex strings, and
+/// non-finite reals become null.
+fn json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(i) => (*i).into(),
+        Value::Real(f) => Number::from_f64(*f).map_or(serde_json::Value::Null, Into::into),
+        Value::Text(text) => text.clone().into(),
+        Value::Blob(blob) => blob
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()
+            .into(),
+    }
+}
+
+fn insert(conn: &Connection, table: &str, entries: &[AuditEntry]) -> Result<(), Error> {
+    let tx = conn.unchecked_transaction()?;
+    // Checking first, rather than relying on IF NOT EXISTS, keeps this
+    // working under an authorizer that denies DDL once the table exists.
+    let exists: bool = tx.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ? COLLATE NOCASE)",
+        &[table],
+        |row| row.get(0),
+    )?;
+    let table = format!("\"{}\"", table.replace('"', "\"\""));
+    if !exists {
+        tx.execute_batch(&format!(
+            "CREATE TABLE {} (
+                id INTEGER PRIMARY KEY,
+                recorded_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ', 'now')),
+                connection INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                database_name TEXT NOT NULL,
+                table_name TEXT NOT NULL,
+                row_id INTEGER NOT NULL,
+                old_values TEXT,
+                new_values TEXT
+            )",
+            table
+        ))?;
+    }
+    {
+        let mut stmt = tx.prepare(&format!(
+            "INSERT INTO {} (connection, action, database_name, table_name, row_id, old_values, new_values)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            table
+        ))?;
+        for entry in entries {
+            let action = match entry.action {
+                PreUpdateAction::Insert => "INSERT",
+                PreUpdateAction::Update => "UPDATE",
+                PreUpdateAction::Delete => "DELETE",
+            };
+            stmt.execute(params![
+                entry.connection as i64,
+                action,
+                entry.database,
+                entry.table,
+                entry.rowid,
+                entry.old.as_ref().map(ToString::to_string),
+                entry.new.as_ref().map(ToString::to_string),
+            ])?;
+        }
+    }
+    Ok(tx.commit()?)
+}
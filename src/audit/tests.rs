@@ -0,0 +1,228 @@
+use std::time::Duration;
+
+use rusqlite::NO_PARAMS;
+use serde_json::json;
+
+use super::*;
+use crate::{tests::TempDir, RusqliteConnectionManager, ShutdownOptions};
+
+const SCHEMA: &str = "CREATE TABLE ledger (id INTEGER PRIMARY KEY, amount INTEGER, memo BLOB);
+                      CREATE TABLE scratch (a INTEGER);";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn to_table() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("audit.db"))
+        .with_audit_log(AuditLog::to_table("audit").table("LEDGER"));
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+
+    {
+        let conn = pool.get().await?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch(
+            "INSERT INTO ledger (amount, memo) VALUES (10, x'ff00');
+             UPDATE ledger SET amount = 20;
+             INSERT INTO scratch (a) VALUES (1);",
+        )?;
+        conn.execute_batch("BEGIN; DELETE FROM ledger; ROLLBACK;")?;
+        conn.execute_batch("DELETE FROM ledger")?;
+    }
+
+    let conn = pool.get().await?;
+    let mut stmt = conn.prepare(
+        "SELECT action, table_name, row_id, old_values, new_values FROM audit ORDER BY id",
+    )?;
+    let rows = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let parse = |json: &Option<String>| {
+        json.as_deref()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .transpose()
+            .unwrap()
+    };
+    assert_eq!(rows.len(), 3);
+    assert_eq!(
+        rows.iter().map(|row| row.0.as_str()).collect::<Vec<_>>(),
+        vec!["INSERT", "UPDATE", "DELETE"]
+    );
+    assert!(rows.iter().all(|row| row.1 == "ledger" && row.2 == 1));
+    assert_eq!(parse(&rows[0].3), None);
+    assert_eq!(
+        parse(&rows[1].4),
+        Some(json!({"id": 1, "amount": 20, "memo": "ff00"}))
+    );
+    assert_eq!(
+        parse(&rows[2].3),
+        Some(json!({"id": 1, "amount": 20, "memo": "ff00"}))
+    );
+    assert_eq!(parse(&rows[2].4), None);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deny_ddl() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("audit.db");
+    let write = |manager: RusqliteConnectionManager| async {
+        let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+        pool.get()
+            .await?
+            .execute("INSERT INTO ledger (amount) VALUES (1)", NO_PARAMS)?;
+        let conn = pool.get().await?;
+        Ok::<i64, anyhow::Error>(conn.query_row(
+            "SELECT COUNT(*) FROM audit",
+            NO_PARAMS,
+            |row| row.get(0),
+        )?)
+    };
+
+    // Once the first write has created the audit table, writes go ahead
+    // under a policy that denies DDL.
+    Connection::open(&path)?.execute_batch(SCHEMA)?;
+    let log = || AuditLog::to_table("audit").table("ledger");
+    assert_eq!(
+        write(RusqliteConnectionManager::new(&path).with_audit_log(log())).await?,
+        1
+    );
+    assert_eq!(
+        write(
+            RusqliteConnectionManager::new(&path)
+                .with_audit_log(log())
+                .with_authorizer(crate::AuthorizerPolicy::new().deny_ddl())
+        )
+        .await?,
+        2
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn to_sink() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let recorded = Arc::new(Mutex::new(Vec::new()));
+    let log = AuditLog::to_sink({
+        let recorded = recorded.clone();
+        move |entries| recorded.lock().unwrap().push(entries.to_vec())
+    })
+    .table("ledger");
+    let manager = RusqliteConnectionManager::new(temp.file("audit.db")).with_audit_log(log);
+    let pool = bb8::Pool::builder().max_size(2).build(manager).await?;
+
+    let id = {
+        let conn = pool.get().await?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch(
+            "BEGIN;
+             INSERT INTO ledger (amount) VALUES (1);
+             INSERT INTO ledger (amount) VALUES (2.5);
+             COMMIT;",
+        )?;
+        // Nothing is recorded until the connection is returned.
+        assert!(recorded.lock().unwrap().is_empty());
+        conn.id()
+    };
+
+    let recorded = recorded.lock().unwrap();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(
+        recorded[0],
+        vec![
+            AuditEntry {
+                connection: id,
+                action: PreUpdateAction::Insert,
+                database: "main".into(),
+                table: "ledger".into(),
+                rowid: 1,
+                old: None,
+                new: Some(json!({"id": 1, "amount": 1, "memo": null})),
+            },
+            AuditEntry {
+                connection: id,
+                action: PreUpdateAction::Insert,
+                database: "main".into(),
+                table: "ledger".into(),
+                rowid: 2,
+                old: None,
+                new: Some(json!({"id": 2, "amount": 2.5, "memo": null})),
+            },
+        ]
+    );
+    Ok(())
+}
+
+/// Returns an audit log passing its entries to the returned list.
+fn recording() -> (AuditLog, Arc<Mutex<Vec<AuditEntry>>>) {
+    let recorded = Arc::new(Mutex::new(Vec::new()));
+    let log = AuditLog::to_sink({
+        let recorded = recorded.clone();
+        move |entries| recorded.lock().unwrap().extend_from_slice(entries)
+    })
+    .table("ledger");
+    (log, recorded)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_commit() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let (log, recorded) = recording();
+    let manager = RusqliteConnectionManager::new(temp.file("audit.db")).with_audit_log(log);
+    let pool = bb8::Pool::builder().max_size(2).build(manager).await?;
+    pool.get().await?.execute_batch(SCHEMA)?;
+
+    // Outside WAL mode, a reader's shared lock keeps the writer from
+    // committing, after SQLite has run the commit hook.
+    let reader = pool.get().await?;
+    let writer = pool.get().await?;
+    reader.execute_batch("BEGIN; SELECT * FROM ledger;")?;
+    writer.execute_batch(
+        "PRAGMA busy_timeout = 0;
+         BEGIN;
+         INSERT INTO ledger (amount) VALUES (1);",
+    )?;
+    writer
+        .execute_batch("COMMIT")
+        .expect_err("the reader holds a shared lock");
+    writer.execute_batch("ROLLBACK")?;
+    drop(writer);
+    drop(reader);
+
+    assert!(recorded.lock().unwrap().is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let (log, recorded) = recording();
+    let manager = RusqliteConnectionManager::new(temp.file("audit.db")).with_audit_log(log);
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(manager.clone())
+        .await?;
+
+    let conn = pool.get().await?;
+    conn.execute_batch(SCHEMA)?;
+    conn.execute("INSERT INTO ledger (amount) VALUES (1)", NO_PARAMS)?;
+    let shutdown = tokio::spawn({
+        let manager = manager.clone();
+        let pool = pool.clone();
+        async move { manager.shutdown(pool, ShutdownOptions::new()).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The connection is closed as it's returned, but its entries are still
+    // recorded first.
+    drop(conn);
+    shutdown.await?;
+    assert_eq!(recorded.lock().unwrap().len(), 1);
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! Statement allow and deny lists, through `sqlite3_set_authorizer()`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CStr,
+    fmt,
+    os::raw::{c_char, c_int, c_void},
+    ptr,
+};
+
+use rusqlite::{ffi, Connection};
+
+#[cfg(test)]
+mod tests;
+
+/// Something a statement can do to a table, as allowed or denied by an
+/// [`AuthorizerPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TableAction {
+    /// Reading the table's columns, including through a view or trigger.
+    Read,
+    /// Inserting rows.
+    Insert,
+    /// Updating rows.
+    Update,
+    /// Deleting rows.
+    Delete,
+}
+
+impl TableAction {
+    const ALL: [TableAction; 4] = [
+        TableAction::Read,
+        TableAction::Insert,
+        TableAction::Update,
+        TableAction::Delete,
+    ];
+}
+
+/// Rules for which statements connections may run, as set with
+/// [`RusqliteConnectionManager::with_authorizer()`](crate::RusqliteConnectionManager::with_authorizer).
+///
+/// These are compiled into an authorizer callback, which SQLite consults as
+/// each statement is prepared: a statement that breaks a rule fails to
+/// prepare, with `SQLITE_AUTH`, before it has done anything. This guards
+/// against bugs in the application, not against hostile SQL, since a
+/// connection checked out of the pool can replace the authorizer.
+///
+/// Everything is allowed until denied. Rules apply to tables of the given
+/// name in every attached database, and to statements run by triggers.
+#[derive(Debug, Clone, Default)]
+pub struct AuthorizerPolicy {
+    // Keyed by lowercased table name, since SQLite's names aren't case
+    // sensitive.
+    denied: HashMap<String, HashSet<TableAction>>,
+    deny_ddl: bool,
+    deny_attach: bool,
+}
+
+impl AuthorizerPolicy {
+    /// Creates a policy that allows everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Denies `actions` on `table`.
+    pub fn deny(mut self, table: &str, actions: &[TableAction]) -> Self {
+        self.denied
+            .entry(table.to_ascii_lowercase())
+            .or_default()
+            .extend(actions);
+        self
+    }
+
+    /// Denies every action on `table` other than `actions`, such as to make
+    /// it read only with `&[TableAction::Read]`.
+    pub fn allow_only(self, table: &str, actions: &[TableAction]) -> Self {
+        let denied = TableAction::ALL
+            .iter()
+            .copied()
+            .filter(|action| !actions.contains(action))
+            .collect::<Vec<_>>();
+        self.deny(table, &denied)
+    }
+
+    /// Denies creating, dropping, and altering tables, indexes, triggers,
+    /// views, and virtual tables, including temporary ones. With the `audit`
+    /// feature, that includes the table `AuditLog::to_table()` creates when
+    /// it first writes to it, so it has to exist before connections are
+    /// opened with the policy; once it does, audited writes go ahead.
+    pub fn deny_ddl(mut self) -> Self {
+        self.deny_ddl = true;
+        self
+    }
+
+    /// Denies `ATTACH` and `DETACH`.
+    pub fn deny_attach(mut self) -> Self {
+        self.deny_attach = true;
+        self
+    }
+
+    /// Installs the authorizer on `conn`.
+    pub(crate) fn install(&self, conn: &Connection) -> Registration {
+        let context = Box::new(self.clone());
+        // Safety: the context is boxed, so its address is stable until the
+        // registration is dropped, which happens after the connection is
+        // closed, or after uninstall() has removed the authorizer.
+        unsafe {
+            ffi::sqlite3_set_authorizer(
+                conn.handle(),
+                Some(authorize),
+                &*context as *const AuthorizerPolicy as *mut c_void,
+            );
+        }
+        Registration { context }
+    }
+
+    fn allows(&self, code: c_int, table: Option<&str>) -> bool {
+        let action = match code {
+            ffi::SQLITE_READ => TableAction::Read,
+            ffi::SQLITE_INSERT => TableAction::Insert,
+            ffi::SQLITE_UPDATE => TableAction::Update,
+            ffi::SQLITE_DELETE => TableAction::Delete,
+            ffi::SQLITE_ATTACH | ffi::SQLITE_DETACH => return !self.deny_attach,
+            ffi::SQLITE_CREATE_INDEX..=ffi::SQLITE_CREATE_VIEW
+            | ffi::SQLITE_DROP_INDEX..=ffi::SQLITE_DROP_VIEW
+            | ffi::SQLITE_ALTER_TABLE
+            | ffi::SQLITE_CREATE_VTABLE
+            | ffi::SQLITE_DROP_VTABLE => return !self.deny_ddl,
+            _ => return true,
+        };
+        match table {
+            Some(table) => !self
+                .denied
+                .get(&table.to_ascii_lowercase())
+                .is_some_and(|denied| denied.contains(&action)),
+            None => true,
+        }
+    }
+}
+
+pub(crate) struct Registration {
+    context: Box<AuthorizerPolicy>,
+}
+
+impl fmt::Debug for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Registration").field(&self.context).finish()
+    }
+}
+
+impl Registration {
+    /// Removes the authorizer from `conn`, so the connection can outlive the
+    /// registration.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: clearing the authorizer can't fail on an open handle.
+        unsafe {
+            ffi::sqlite3_set_authorizer(conn.handle(), None, ptr::null_mut());
+        }
+    }
+}
+
+unsafe extern "C" fn authorize(
+    context: *mut c_void,
+    code: c_int,
+    table: *const c_char,
+    _column: *const c_char,
+    _database: *const c_char,
+    _trigger: *const c_char,
+) -> c_int {
+    let policy = &*(context as *const AuthorizerPolicy);
+    let table = (!table.is_null()).then(|| CStr::from_ptr(table).to_string_lossy());
+    if policy.allows(code, table.as_deref()) {
+        ffi::SQLITE_OK
+    } else {
+        ffi::SQLITE_DENY
+    }
+}
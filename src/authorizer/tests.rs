@@ -0,0 +1,56 @@
+use rusqlite::{ErrorCode, NO_PARAMS};
+
+use super::*;
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+fn denied(result: rusqlite::Result<()>) -> bool {
+    matches!(
+        result,
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::AuthorizationForStatementDenied
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn policy() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("authorizer.db");
+    Connection::open(&path)?.execute_batch(
+        "CREATE TABLE ledger (amount INTEGER);
+         CREATE TABLE config (key TEXT, value TEXT);
+         INSERT INTO config VALUES ('a', 'b');
+         CREATE TABLE scratch (a INTEGER);
+         CREATE TRIGGER purge AFTER INSERT ON scratch BEGIN DELETE FROM ledger; END;",
+    )?;
+    let manager = RusqliteConnectionManager::new(&path).with_authorizer(
+        AuthorizerPolicy::new()
+            .deny("Ledger", &[TableAction::Delete])
+            .allow_only("config", &[TableAction::Read])
+            .deny_ddl()
+            .deny_attach(),
+    );
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+    // Read connections set PRAGMA query_only, which is always allowed.
+    drop(pool.get_read().await?);
+    let conn = pool.get().await?;
+
+    conn.execute_batch("INSERT INTO ledger VALUES (1); UPDATE ledger SET amount = 2")?;
+    let value: String = conn.query_row("SELECT value FROM config", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(value, "b");
+    conn.execute_batch("PRAGMA user_version = 1")?;
+
+    for sql in &[
+        "DELETE FROM ledger",
+        "UPDATE config SET value = 'c'",
+        "CREATE TABLE other (a)",
+        "CREATE INDEX ledger_amount ON ledger (amount)",
+        "DROP TABLE ledger",
+        "ALTER TABLE ledger ADD COLUMN memo TEXT",
+        "ATTACH ':memory:' AS other",
+    ] {
+        assert!(denied(conn.execute_batch(sql)), "{} was allowed", sql);
+    }
+
+    // Triggers are held to the same rules.
+    assert!(denied(conn.execute_batch("INSERT INTO scratch VALUES (1)")));
+    Ok(())
+}
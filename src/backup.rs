@@ -0,0 +1,435 @@
+//! Online backups, with scheduling and retention.
+//!
+//! [`backup()`] copies a pooled database to a file using SQLite's online
+//! backup API, without blocking other connections for the duration. A
+//! [`BackupPlan`] takes backups into a directory on a [`Schedule`], and prunes
+//! old backups according to a [`Retention`] policy.
+//!
+//! Backups are named `backup-YYYYMMDDTHHMMSSZ.db` after the UTC time they were
+//! taken, so they sort chronologically. Other files in the directory are left
+//! alone.
+
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    fmt, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{
+    backup::{Backup, StepResult},
+    ffi, Connection,
+};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{
+    maintenance::{self, Schedule},
+    pool::{self, Operation},
+    task, Error, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// How many pages are copied in each step of a backup. Other connections can
+/// use the database between steps.
+const PAGES_PER_STEP: i32 = 1024;
+
+/// Copies every page in one step, holding a read transaction on the source
+/// throughout.
+const ALL_PAGES: i32 = -1;
+
+/// How long a backup keeps retrying a step that finds the source locked,
+/// before giving up.
+const LOCKED_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Copies the pool's main database to `dest`, replacing any existing file.
+///
+/// The backup is written to a temporary file alongside `dest`, and renamed
+/// into place once complete, so `dest` never holds a partial backup. If
+/// another connection keeps the database locked for five seconds between
+/// steps, the backup fails with `SQLITE_BUSY`.
+pub async fn backup<P>(pool: &bb8::Pool<RusqliteConnectionManager>, dest: P) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+{
+    let dest = dest.as_ref().to_path_buf();
+    pool::run(pool, Operation::new("backup"), move |conn| {
+        backup_to(conn, &dest)
+    })
+    .await
+}
+
+fn backup_to(conn: &Connection, dest: &Path) -> Result<(), Error> {
+    copy_to(conn, dest, PAGES_PER_STEP, LOCKED_TIMEOUT, |_| Ok(()))
+}
+
+/// Copies the database on `conn` to `dest` in a single step, so the copy is
+/// taken within one read transaction, then checks it with
+/// `PRAGMA quick_check` before moving it into place.
+pub(crate) fn export_snapshot(conn: &Connection, dest: &Path) -> Result<(), Error> {
+    copy_to(conn, dest, ALL_PAGES, LOCKED_TIMEOUT, |copy| {
+        maintenance::check_integrity(copy, "quick_check")
+    })
+}
+
+/// Copies the database on `conn` to `dest`, `pages` at a time, calling
+/// `verify` on the copy before it is renamed into place. A step that finds
+/// the source locked is retried until it's made no progress for `timeout`,
+/// and then the copy fails with `SQLITE_BUSY` or `SQLITE_LOCKED`.
+fn copy_to<F>(
+    conn: &Connection,
+    dest: &Path,
+    pages: i32,
+    timeout: Duration,
+    verify: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&Connection) -> Result<(), Error>,
+{
+    let mut tmp = dest.as_os_str().to_owned();
+    tmp.push(".partial");
+    let tmp = PathBuf::from(tmp);
+
+    let result = (|| -> Result<(), Error> {
+        let mut dst = Connection::open(&tmp)?;
+        let backup = Backup::new(conn, &mut dst)?;
+        // run_to_completion() would retry a locked source forever, and only
+        // takes a positive step.
+        let mut deadline = Instant::now() + timeout;
+        loop {
+            let code = match backup.step(pages)? {
+                StepResult::Done => break,
+                StepResult::More => {
+                    deadline = Instant::now() + timeout;
+                    continue;
+                }
+                StepResult::Locked => ffi::SQLITE_LOCKED,
+                _ => ffi::SQLITE_BUSY,
+            };
+            if Instant::now() >= deadline {
+                return Err(rusqlite::Error::SqliteFailure(
+                    ffi::Error::new(code),
+                    Some("the source database stayed locked".into()),
+                )
+                .into());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(backup);
+        verify(&dst)?;
+        dst.close().map_err(|(_, e)| e)?;
+        Ok(fs::rename(&tmp, dest)?)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Which backups to keep. A backup is kept if any of the rules would keep
+/// it, and everything else is deleted. The newest backup is always kept, so
+/// the default, which sets no rules, keeps only the backup just taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Keeps this many of the most recent backups.
+    pub last: usize,
+
+    /// Keeps the newest backup from each of this many of the most recent UTC
+    /// days that have backups.
+    pub daily: usize,
+
+    /// Keeps the newest backup from each of this many of the most recent
+    /// weeks, starting on Monday, that have backups.
+    pub weekly: usize,
+}
+
+impl Retention {
+    /// Returns the backups, given as the times they were taken, that should
+    /// be deleted.
+    fn prune(&self, mut taken: Vec<u64>) -> Vec<u64> {
+        taken.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut keep: HashSet<u64> = taken.iter().copied().take(self.last.max(1)).collect();
+        for (period, count) in &[(DAY, self.daily), (7 * DAY, self.weekly)] {
+            let mut seen = HashSet::new();
+            for &secs in &taken {
+                // The Unix epoch was a Thursday, so shift weeks to start on
+                // Monday. Days are unaffected, since they divide evenly.
+                let bucket = (secs + 3 * DAY) / period;
+                if seen.len() < *count && seen.insert(bucket) {
+                    keep.insert(secs);
+                }
+            }
+        }
+
+        taken.retain(|secs| !keep.contains(secs));
+        taken
+    }
+}
+
+/// A backup found in a backup directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupFile {
+    /// The path to the backup.
+    pub path: PathBuf,
+
+    /// When the backup was taken.
+    pub taken: SystemTime,
+}
+
+/// Returns the backups in `dir`, oldest first.
+pub fn list<P>(dir: P) -> Result<Vec<BackupFile>, Error>
+where
+    P: AsRef<Path>,
+{
+    let mut backups = Vec::new();
+    let entries = match fs::read_dir(dir.as_ref()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(backups),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if let Some(secs) = entry.file_name().to_str().and_then(parse_name) {
+            backups.push(BackupFile {
+                path: entry.path(),
+                taken: UNIX_EPOCH + Duration::from_secs(secs),
+            });
+        }
+    }
+
+    backups.sort_by_key(|backup| backup.taken);
+    Ok(backups)
+}
+
+type Callback<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+/// Scheduled backups into a directory.
+#[derive(Clone)]
+pub struct BackupPlan {
+    dir: PathBuf,
+    schedule: Schedule,
+    retention: Option<Retention>,
+    on_backup: Option<Callback<Path>>,
+    on_error: Option<Callback<Error>>,
+}
+
+impl fmt::Debug for BackupPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackupPlan")
+            .field("dir", &self.dir)
+            .field("schedule", &self.schedule)
+            .field("retention", &self.retention)
+            .finish()
+    }
+}
+
+impl BackupPlan {
+    /// Creates a plan that backs up into `dir` (creating it if necessary) on
+    /// `schedule`. By default, every backup is kept.
+    pub fn new<P>(dir: P, schedule: Schedule) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            dir: dir.as_ref().into(),
+            schedule,
+            retention: None,
+            on_backup: None,
+            on_error: None,
+        }
+    }
+
+    /// Prunes old backups after each new backup.
+    pub fn retain(mut self, retention: Retention) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Sets a callback that is given the path of each completed backup, such
+    /// as to upload it elsewhere.
+    pub fn on_backup<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Path) + Send + Sync + 'static,
+    {
+        self.on_backup = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback for failed backups. The plan keeps running after an
+    /// error, and tries again at the next scheduled time.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Takes a backup immediately, and prunes old backups, returning the new
+    /// backup's path.
+    pub async fn run(&self, pool: &bb8::Pool<RusqliteConnectionManager>) -> Result<PathBuf, Error> {
+        let dir = self.dir.clone();
+        let path = pool::run(pool, Operation::new("backup"), move |conn| {
+            fs::create_dir_all(&dir)?;
+
+            // Names only have a resolution of a second, so a backup taken
+            // within a second of the last one is named for the next second,
+            // keeping the names unique and in order.
+            let latest = list(&dir)?.last().map(|backup| secs(backup.taken));
+            let secs = latest.map_or(now(), |latest| now().max(latest + 1));
+            let path = dir.join(format_name(secs));
+            backup_to(conn, &path)?;
+            Ok(path)
+        })
+        .await?;
+
+        if let Some(callback) = &self.on_backup {
+            callback(&path);
+        }
+
+        if let Some(retention) = self.retention {
+            let dir = self.dir.clone();
+            task::spawn_blocking(
+                "bb8_rusqlite::backup::retention",
+                move || -> Result<(), Error> {
+                    let backups = list(&dir)?;
+                    let taken = backups.iter().map(|backup| secs(backup.taken)).collect();
+                    let prune: HashSet<u64> = retention.prune(taken).into_iter().collect();
+                    for backup in backups {
+                        if prune.contains(&secs(backup.taken)) {
+                            fs::remove_file(&backup.path)?;
+                        }
+                    }
+                    Ok(())
+                },
+            )
+            .await??;
+        }
+
+        Ok(path)
+    }
+
+    /// Starts taking backups in the background.
+    pub fn start(self, pool: bb8::Pool<RusqliteConnectionManager>) -> BackupHandle {
+        let (stop, mut stopped) = oneshot::channel();
+        let join = task::spawn("bb8_rusqlite::backup", async move {
+            loop {
+                let wait = self.schedule.next(SystemTime::now());
+                if tokio::time::timeout(wait, &mut stopped).await.is_ok() {
+                    return;
+                }
+                if let Err(e) = self.run(&pool).await {
+                    if let Some(callback) = &self.on_error {
+                        callback(&e);
+                    }
+                }
+            }
+        });
+
+        BackupHandle { stop, join }
+    }
+}
+
+/// A handle to a running backup plan. Dropping the handle also stops the
+/// plan.
+#[derive(Debug)]
+pub struct BackupHandle {
+    stop: oneshot::Sender<()>,
+    join: JoinHandle<()>,
+}
+
+impl BackupHandle {
+    /// Returns the ID of the plan's task.
+    pub fn task_id(&self) -> tokio::task::Id {
+        self.join.id()
+    }
+
+    /// Stops the plan, waiting for any backup in progress to finish.
+    pub async fn stop(self) -> Result<(), Error> {
+        let _ = self.stop.send(());
+        Ok(self.join.await?)
+    }
+}
+
+fn now() -> u64 {
+    secs(SystemTime::now())
+}
+
+fn secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Formats a backup file name from seconds since the Unix epoch.
+fn format_name(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / DAY) as i64);
+    let time = secs % DAY;
+    format!(
+        "backup-{:04}{:02}{:02}T{:02}{:02}{:02}Z.db",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Parses a backup file name back into seconds since the Unix epoch.
+fn parse_name(name: &str) -> Option<u64> {
+    let stamp = name.strip_prefix("backup-")?.strip_suffix("Z.db")?;
+    if stamp.len() != 15 || stamp.as_bytes()[8] != b'T' {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| -> Option<u64> {
+        let digits = stamp.get(range)?;
+        if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(9..11)?, field(11..13)?, field(13..15)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year as i64, month as u32, day as u32)).ok()?;
+    Some(days * DAY + hour * 3600 + minute * 60 + second)
+}
+
+// The two conversions below are Howard Hinnant's algorithms for the
+// proleptic Gregorian calendar; see
+// https://howardhinnant.github.io/date_algorithms.html.
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
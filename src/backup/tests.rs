@@ -0,0 +1,217 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::tests::TempDir;
+
+#[test]
+fn names() {
+    assert_eq!(format_name(0), "backup-19700101T000000Z.db");
+    assert_eq!(format_name(1_792_035_296), "backup-20261015T033456Z.db");
+    assert_eq!(format_name(951_782_400), "backup-20000229T000000Z.db");
+
+    for &secs in &[0, 951_782_400, 1_792_035_296, 4_102_444_799] {
+        assert_eq!(parse_name(&format_name(secs)), Some(secs));
+    }
+
+    assert_eq!(parse_name("backup-20261014T034136Z.db-journal"), None);
+    assert_eq!(parse_name("backup-2026-10-14T0341Z.db"), None);
+    assert_eq!(parse_name("backup-20261314T034136Z.db"), None);
+    assert_eq!(parse_name("other.db"), None);
+}
+
+#[test]
+fn prune() {
+    // Monday 2026-10-12, and the days around it.
+    let monday = 1_791_763_200;
+    let at = |day: i64, hour: u64| (monday as i64 + day * DAY as i64) as u64 + hour * 3600;
+    let taken = vec![
+        at(-8, 12),
+        at(-2, 12),
+        at(-1, 6),
+        at(-1, 18),
+        at(0, 6),
+        at(0, 12),
+        at(0, 18),
+        at(1, 6),
+    ];
+
+    let mut pruned = Retention {
+        last: 2,
+        daily: 0,
+        weekly: 0,
+    }
+    .prune(taken.clone());
+    pruned.sort_unstable();
+    assert_eq!(pruned, taken[..6].to_vec());
+
+    // The newest of each of the last three days: Tuesday, Monday, and
+    // Sunday.
+    let mut pruned = Retention {
+        last: 0,
+        daily: 3,
+        weekly: 0,
+    }
+    .prune(taken.clone());
+    pruned.sort_unstable();
+    assert_eq!(
+        pruned,
+        vec![at(-8, 12), at(-2, 12), at(-1, 6), at(0, 6), at(0, 12)]
+    );
+
+    // The newest backup is kept even without any rules.
+    let mut pruned = Retention::default().prune(taken.clone());
+    pruned.sort_unstable();
+    assert_eq!(pruned, taken[..7].to_vec());
+
+    // The newest of this week, last week, and the week before.
+    let mut pruned = Retention {
+        last: 0,
+        daily: 0,
+        weekly: 3,
+    }
+    .prune(taken.clone());
+    pruned.sort_unstable();
+    assert_eq!(
+        pruned,
+        vec![at(-2, 12), at(-1, 6), at(0, 6), at(0, 12), at(0, 18)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rotation() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+    pool.get().await?.execute_batch(
+        "CREATE TABLE t (a INTEGER);
+         INSERT INTO t (a) VALUES (1);",
+    )?;
+
+    let dir = temp.file("backups");
+    let plan =
+        BackupPlan::new(&dir, Schedule::Every(Duration::from_secs(3600))).retain(Retention {
+            last: 2,
+            daily: 0,
+            weekly: 0,
+        });
+    let mut paths = Vec::new();
+    for _ in 0..4 {
+        paths.push(plan.run(&pool).await?);
+    }
+
+    let backups = list(&dir)?;
+    assert_eq!(
+        backups
+            .iter()
+            .map(|backup| backup.path.clone())
+            .collect::<Vec<_>>(),
+        paths[2..].to_vec()
+    );
+
+    let a: i64 =
+        Connection::open(&paths[3])?.query_row("SELECT a FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(a, 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn default_retention() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+
+    let dir = temp.file("backups");
+    let plan = BackupPlan::new(&dir, Schedule::Every(Duration::from_secs(3600)))
+        .retain(Retention::default());
+    plan.run(&pool).await?;
+    let path = plan.run(&pool).await?;
+
+    let backups = list(&dir)?;
+    assert_eq!(
+        backups
+            .iter()
+            .map(|backup| backup.path.clone())
+            .collect::<Vec<_>>(),
+        vec![path]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scheduled() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+
+    let dir = temp.file("backups");
+    let handle = BackupPlan::new(&dir, Schedule::Every(Duration::from_millis(20)))
+        .on_error(|e| panic!("backup failed: {}", e))
+        .start(pool);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    handle.stop().await?;
+
+    assert!(!list(&dir)?.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_snapshot() -> Result<(), anyhow::Error> {
+    use crate::PoolExt;
+
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+    let writer = pool.get().await?;
+    writer.execute_batch(
+        "PRAGMA journal_mode = WAL;
+         CREATE TABLE t (a INTEGER);
+         INSERT INTO t (a) VALUES (1);",
+    )?;
+
+    // A write in progress isn't part of the snapshot.
+    writer.execute_batch("BEGIN; INSERT INTO t (a) VALUES (2);")?;
+    let dest = temp.file("snapshot.db");
+    pool.export_snapshot(&dest).await?;
+    writer.execute_batch("COMMIT")?;
+
+    let count: i64 =
+        Connection::open(&dest)?
+            .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(count, 1);
+    assert!(!temp.file("snapshot.db.partial").exists());
+    Ok(())
+}
+
+#[test]
+fn locked_source() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("db");
+    let conn = Connection::open(&path)?;
+    conn.busy_timeout(Duration::ZERO)?;
+    conn.execute_batch("CREATE TABLE t (a INTEGER)")?;
+    let writer = Connection::open(&path)?;
+    writer.execute_batch("BEGIN EXCLUSIVE; INSERT INTO t (a) VALUES (1);")?;
+
+    let dest = temp.file("copy.db");
+    for &pages in &[ALL_PAGES, PAGES_PER_STEP] {
+        match copy_to(&conn, &dest, pages, Duration::from_millis(50), |_| Ok(())) {
+            Err(Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _))) => {
+                assert_eq!(e.code, rusqlite::ErrorCode::DatabaseBusy)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!dest.exists());
+        assert!(!temp.file("copy.db.partial").exists());
+    }
+
+    writer.execute_batch("COMMIT")?;
+    copy_to(&conn, &dest, ALL_PAGES, Duration::from_millis(50), |_| {
+        Ok(())
+    })?;
+    assert!(dest.exists());
+    Ok(())
+}
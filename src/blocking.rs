@@ -0,0 +1,237 @@
+//! A synchronous pool, for command line tools and tests that want the
+//! manager's configuration without starting a Tokio runtime.
+//!
+//! [`Pool`] opens connections through a [`ConnectionFactory`], so they're
+//! configured, checked, and released exactly as they are in a bb8 pool of
+//! the same manager, with the same caveats about work that needs the runtime.
+//! It's deliberately simple: connections are opened as they're needed, up to
+//! the pool's size, and kept until the pool is dropped or they're found to be
+//! unusable. For more control over a synchronous pool, use r2d2 with the
+//! `r2d2` feature instead.
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{ConnectionFactory, Error, RusqliteConnection, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// A synchronous pool of connections, which can be cloned cheaply.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    factory: ConnectionFactory,
+    max_size: usize,
+    connection_timeout: Duration,
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    idle: Vec<RusqliteConnection>,
+    open: usize,
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.inner.state.lock().unwrap();
+        f.debug_struct("Pool")
+            .field("factory", &self.inner.factory)
+            .field("max_size", &self.inner.max_size)
+            .field("connection_timeout", &self.inner.connection_timeout)
+            .field("open", &state.open)
+            .field("idle", &state.idle.len())
+            .finish()
+    }
+}
+
+/// A builder for a [`Pool`].
+#[derive(Debug, Clone)]
+pub struct PoolBuilder {
+    max_size: usize,
+    connection_timeout: Duration,
+}
+
+impl Default for PoolBuilder {
+    fn default() -> Self {
+        Self {
+            max_size: 10,
+            connection_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PoolBuilder {
+    /// Sets the most connections the pool opens. Defaults to 10.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_size` is zero.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        assert!(max_size > 0, "max_size must be greater than zero");
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets how long [`Pool::get()`] waits for a connection. Defaults to 30
+    /// seconds.
+    pub fn with_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = timeout;
+        self
+    }
+
+    /// Creates the pool. Connections aren't opened until they're needed.
+    pub fn build(self, manager: RusqliteConnectionManager) -> Pool {
+        Pool {
+            inner: Arc::new(Inner {
+                factory: manager.connection_factory(),
+                max_size: self.max_size,
+                connection_timeout: self.connection_timeout,
+                state: Default::default(),
+                released: Condvar::new(),
+            }),
+        }
+    }
+}
+
+impl Pool {
+    /// Creates a pool from `manager` with the default settings, which are
+    /// the same as bb8's.
+    pub fn new(manager: RusqliteConnectionManager) -> Self {
+        Self::builder().build(manager)
+    }
+
+    /// Returns a builder for a pool with other settings.
+    pub fn builder() -> PoolBuilder {
+        PoolBuilder::default()
+    }
+
+    /// Returns the factory the pool opens connections with.
+    pub fn factory(&self) -> &ConnectionFactory {
+        &self.inner.factory
+    }
+
+    /// Returns the number of connections open.
+    pub fn connections(&self) -> usize {
+        self.inner.state.lock().unwrap().open
+    }
+
+    /// Returns the number of open connections that aren't checked out.
+    pub fn idle_connections(&self) -> usize {
+        self.inner.state.lock().unwrap().idle.len()
+    }
+
+    /// Checks out a connection, opening one if none are idle and the pool
+    /// isn't full, or returns [`Error::TimedOut`] if none becomes available
+    /// in time.
+    pub fn get(&self) -> Result<PooledConnection<'_>, Error> {
+        let inner = &self.inner;
+        let deadline = Instant::now().checked_add(inner.connection_timeout);
+        let mut state = inner.state.lock().unwrap();
+        loop {
+            if let Some(mut conn) = state.idle.pop() {
+                drop(state);
+                match inner.factory.is_valid(&mut conn) {
+                    Ok(()) => return Ok(self.wrap(conn)),
+                    Err(_) => {
+                        state = inner.state.lock().unwrap();
+                        state.open -= 1;
+                        continue;
+                    }
+                }
+            }
+            if state.open < inner.max_size {
+                state.open += 1;
+                drop(state);
+                return match inner.factory.connect() {
+                    Ok(mut conn) => {
+                        conn.checked_out(None);
+                        Ok(self.wrap(conn))
+                    }
+                    Err(e) => {
+                        inner.state.lock().unwrap().open -= 1;
+                        inner.released.notify_one();
+                        Err(e)
+                    }
+                };
+            }
+            state = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::TimedOut);
+                    }
+                    inner
+                        .released
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => inner.released.wait(state).unwrap(),
+            };
+        }
+    }
+
+    fn wrap(&self, conn: RusqliteConnection) -> PooledConnection<'_> {
+        PooledConnection {
+            pool: self,
+            conn: Some(conn),
+        }
+    }
+
+    fn put_back(&self, mut conn: RusqliteConnection) {
+        let broken = self.inner.factory.has_broken(&mut conn);
+        let mut state = self.inner.state.lock().unwrap();
+        if broken {
+            state.open -= 1;
+            drop(state);
+            drop(conn);
+        } else {
+            state.idle.push(conn);
+        }
+        self.inner.released.notify_one();
+    }
+}
+
+/// A connection checked out of a [`Pool`], which returns to it when dropped.
+pub struct PooledConnection<'a> {
+    pool: &'a Pool,
+    conn: Option<RusqliteConnection>,
+}
+
+impl fmt::Debug for PooledConnection<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PooledConnection").field(&**self).finish()
+    }
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = RusqliteConnection;
+
+    fn deref(&self) -> &RusqliteConnection {
+        self.conn.as_ref().expect("connection is checked out")
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut RusqliteConnection {
+        self.conn.as_mut().expect("connection is checked out")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.put_back(conn);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{tests::TempDir, PragmaCustomizer};
+
+#[test]
+fn reuses_connections() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = Pool::new(RusqliteConnectionManager::new(temp.file("blocking.db")));
+    assert_eq!(pool.connections(), 0);
+
+    let conn = pool.get()?;
+    conn.execute_batch("CREATE TABLE t (a INTEGER); INSERT INTO t (a) VALUES (1);")?;
+    let id = conn.id();
+    drop(conn);
+    assert_eq!((pool.connections(), pool.idle_connections()), (1, 1));
+
+    let conn = pool.get()?;
+    assert_eq!(conn.id(), id);
+    let other = pool.get()?;
+    let n: i64 = other.query_row("SELECT count(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(n, 1);
+    assert_eq!((pool.connections(), pool.idle_connections()), (2, 0));
+    Ok(())
+}
+
+#[test]
+fn waits_for_connections() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = Pool::builder()
+        .with_max_size(1)
+        .with_connection_timeout(Duration::from_millis(50))
+        .build(RusqliteConnectionManager::new(temp.file("wait.db")));
+
+    let conn = pool.get()?;
+    assert!(matches!(pool.get(), Err(Error::TimedOut)));
+
+    let waiting = {
+        let pool = pool.clone();
+        std::thread::spawn(move || pool.get().map(|conn| conn.id()))
+    };
+    std::thread::sleep(Duration::from_millis(10));
+    let id = conn.id();
+    drop(conn);
+    assert_eq!(waiting.join().unwrap()?, id);
+    Ok(())
+}
+
+#[test]
+fn drains_outdated_connections() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("reload.db"));
+    let pool = Pool::new(manager.clone());
+
+    let conn = pool.get()?;
+    let id = conn.id();
+    manager.reload_pragmas(PragmaCustomizer::new().pragma("cache_size", -2000));
+    drop(conn);
+    assert_eq!(pool.connections(), 0);
+
+    let conn = pool.get()?;
+    assert_ne!(conn.id(), id);
+    let size: i64 = conn.query_row("PRAGMA cache_size", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(size, -2000);
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Bulk insertion in chunked transactions.
+
+use std::{fmt, sync::Arc};
+
+use rusqlite::ToSql;
+
+use crate::{sql, Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+type ProgressCallback = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Options for [`PoolExt::bulk_insert()`](crate::PoolExt::bulk_insert).
+#[derive(Clone)]
+pub struct BulkInsertOptions {
+    batch_size: usize,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl Default for BulkInsertOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            on_progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for BulkInsertOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BulkInsertOptions")
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+impl BulkInsertOptions {
+    /// Creates the default options, which commit every 1000 rows.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many rows are inserted in each transaction. Each batch is
+    /// committed before the next begins, so a failed insert leaves every
+    /// earlier batch in place.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Sets a callback that is given the number of rows inserted so far after
+    /// each batch is committed.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(u64) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+}
+
+/// Inserts each row into `columns` of `table`, returning the number of rows
+/// inserted.
+pub(crate) fn insert<I, R>(
+    conn: &mut RusqliteConnection,
+    table: &str,
+    columns: &[&str],
+    rows: I,
+    options: &BulkInsertOptions,
+) -> Result<u64, Error>
+where
+    I: IntoIterator<Item = R>,
+    R: IntoIterator,
+    R::Item: ToSql,
+{
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        sql::quote_identifier(table),
+        columns
+            .iter()
+            .map(|column| sql::quote_identifier(column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        sql::placeholders(columns.len())
+    );
+
+    let mut rows = rows.into_iter().peekable();
+    let mut count = 0;
+    while rows.peek().is_some() {
+        let tx = conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(&sql)?;
+            for row in rows.by_ref().take(options.batch_size) {
+                stmt.execute(row)?;
+                count += 1;
+            }
+        }
+        tx.commit()?;
+
+        if let Some(callback) = &options.on_progress {
+            callback(count);
+        }
+    }
+    Ok(count)
+}
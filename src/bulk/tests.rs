@@ -0,0 +1,54 @@
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{types::Value, NO_PARAMS};
+
+use crate::{tests::TempDir, BulkInsertOptions, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bulk_insert() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("bulk.db")))
+        .await?;
+    pool.get().await?.execute(
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, \"the name\" TEXT)",
+        NO_PARAMS,
+    )?;
+
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let rows = (1..=5).map(|i| vec![Value::from(i), Value::from(format!("row {}", i))]);
+    let inserted = pool
+        .bulk_insert(
+            "t",
+            &["id", "the name"],
+            rows,
+            BulkInsertOptions::new().batch_size(2).on_progress({
+                let progress = progress.clone();
+                move |rows| progress.lock().unwrap().push(rows)
+            }),
+        )
+        .await?;
+    assert_eq!(inserted, 5);
+    assert_eq!(*progress.lock().unwrap(), vec![2, 4, 5]);
+
+    let name: String = pool.get().await?.query_row(
+        "SELECT \"the name\" FROM t WHERE id = 5",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    assert_eq!(name, "row 5");
+
+    // A failure rolls back its own batch, but not earlier ones.
+    let rows = vec![vec![6], vec![7], vec![8], vec![6]];
+    assert!(pool
+        .bulk_insert("t", &["id"], rows, BulkInsertOptions::new().batch_size(2))
+        .await
+        .is_err());
+    let count: i64 = pool
+        .get()
+        .await?
+        .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(count, 7);
+
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Pools over databases held in memory as bytes.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use rusqlite::OpenFlags;
+
+use crate::{replication, DatabaseFile, Error, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+const HEADER: &[u8] = b"SQLite format 3\0";
+
+impl RusqliteConnectionManager {
+    /// Creates a manager serving read-only connections to the database whose
+    /// file contents are `bytes`, such as a lookup dataset embedded in the
+    /// binary with `include_bytes!()`, or fetched from object storage.
+    ///
+    /// The bytes are written to a file in the system's temporary directory,
+    /// so that every connection can share them through the page cache rather
+    /// than holding its own copy. The file is removed once the manager, its
+    /// clones, and every connection it opened have been dropped.
+    ///
+    /// A database in WAL mode is switched to rollback journal mode on the
+    /// way, since a read-only connection can't create the WAL's shared
+    /// memory file. Bytes that don't start with SQLite's file header fail
+    /// with an `InvalidData` [`Error::Io`].
+    pub fn from_bytes<B>(bytes: B) -> Result<Self, Error>
+    where
+        B: AsRef<[u8]>,
+    {
+        let bytes = bytes.as_ref();
+        if !bytes.starts_with(HEADER) || bytes.len() < 100 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a SQLite database").into());
+        }
+
+        let path = temporary_path();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // Remove the file if anything goes wrong from here on.
+        let temporary = TemporaryFile::new(path.clone());
+
+        // Bytes 18 and 19 of the header are the file format versions, which
+        // are 2 in WAL mode and 1 otherwise.
+        let (header, rest) = bytes.split_at(100);
+        let mut header = header.to_vec();
+        for version in &mut header[18..20] {
+            if *version == 2 {
+                *version = 1;
+            }
+        }
+        file.write_all(&header)?;
+        file.write_all(rest)?;
+        file.sync_all()?;
+
+        Ok(
+            Self::new_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+                .with_temporary_file(temporary.keep()),
+        )
+    }
+
+    /// Makes the manager responsible for removing `path`, which must be the
+    /// file it was created for.
+    pub(crate) fn with_temporary_file(self, path: PathBuf) -> Self {
+        *self.files.current.write().unwrap() = Arc::new(DatabaseFile::new(path, true));
+        self
+    }
+}
+
+/// Returns a new path in the system's temporary directory for a database
+/// file that the manager will remove.
+pub(crate) fn temporary_path() -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    std::env::temp_dir().join(format!(
+        "bb8-rusqlite-{}-{}.db",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Removes the file it holds on drop, unless it's kept.
+pub(crate) struct TemporaryFile(Option<PathBuf>);
+
+impl TemporaryFile {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self(Some(path))
+    }
+
+    pub(crate) fn keep(mut self) -> PathBuf {
+        self.0.take().unwrap()
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Removes a temporary database file, along with any journal SQLite left
+/// beside it.
+pub(crate) fn remove(file: &DatabaseFile) {
+    let mut journal = file.path.as_os_str().to_owned();
+    journal.push("-journal");
+    for path in &[
+        file.path.clone(),
+        journal.into(),
+        replication::wal_path(&file.path),
+        replication::shm_path(&file.path),
+    ] {
+        let _ = std::fs::remove_file(path);
+    }
+}
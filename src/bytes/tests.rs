@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use super::*;
+use crate::tests::TempDir;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn from_bytes() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let source = temp.file("source.db");
+    {
+        let conn = Connection::open(&source)?;
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+        conn.execute_batch(
+            "CREATE TABLE countries (code TEXT PRIMARY KEY, name TEXT);
+             INSERT INTO countries VALUES ('NZ', 'New Zealand'), ('AU', 'Australia');",
+        )?;
+    }
+    let bytes = std::fs::read(&source)?;
+
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(RusqliteConnectionManager::from_bytes(&bytes)?)
+        .await?;
+    let path = {
+        let (a, b) = (pool.get().await?, pool.get().await?);
+        let name: String = a.query_row(
+            "SELECT name FROM countries WHERE code = 'NZ'",
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        assert_eq!(name, "New Zealand");
+        let count: i64 = b.query_row("SELECT COUNT(*) FROM countries", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+        assert_eq!(count, 2);
+        assert!(a.execute("DELETE FROM countries", NO_PARAMS).is_err());
+
+        let path: String = a.query_row("PRAGMA database_list", NO_PARAMS, |row| row.get(2))?;
+        PathBuf::from(path)
+    };
+    assert!(path.exists());
+
+    drop(pool);
+    // bb8 closes the connections in the background.
+    for _ in 0..100 {
+        if !path.exists() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert!(!path.exists());
+    Ok(())
+}
+
+#[test]
+fn not_a_database() {
+    assert!(matches!(
+        RusqliteConnectionManager::from_bytes(b"definitely not SQLite"),
+        Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::InvalidData
+    ));
+}
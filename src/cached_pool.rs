@@ -0,0 +1,213 @@
+//! Caching the results of chosen queries for a fixed time.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rusqlite::types::Value;
+
+use crate::{query_cache::Key, DynamicRow, Error, PoolExt, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// How the results of a query run through a [`CachedPool`] are cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachePolicy {
+    ttl: Duration,
+    max_entries: usize,
+    max_rows: Option<usize>,
+}
+
+impl CachePolicy {
+    /// Creates a policy that serves results for `ttl` after they were read,
+    /// keeping them for up to 64 different sets of parameters.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            max_entries: 64,
+            max_rows: None,
+        }
+    }
+
+    /// Sets how many different sets of parameters results are kept for.
+    /// Once that many are cached, expired results are dropped to make room,
+    /// and then the least recently used.
+    pub fn max_entries(mut self, entries: usize) -> Self {
+        self.max_entries = entries.max(1);
+        self
+    }
+
+    /// Sets the most rows a result can have and still be cached. Larger
+    /// results are still returned, but read again every time.
+    pub fn max_rows(mut self, rows: usize) -> Self {
+        self.max_rows = Some(rows);
+        self
+    }
+}
+
+/// A pool whose chosen read queries have their results cached for a fixed
+/// time, so that repeated queries, such as from dashboards that poll, are
+/// served without checking out a connection at all.
+///
+/// Unlike [`QueryCache`](crate::QueryCache), the cache doesn't check whether
+/// the database has changed: a result is served until its policy's time to
+/// live has passed, however much has been written since, unless it's
+/// [invalidated](Self::invalidate) first. Queries that haven't been given a
+/// policy with [`with_query()`](Self::with_query) aren't cached, and go
+/// straight to the pool.
+///
+/// Clones share the same cache.
+#[derive(Clone)]
+pub struct CachedPool {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    queries: Arc<HashMap<String, CachePolicy>>,
+    state: Arc<Mutex<HashMap<Key, Entry>>>,
+}
+
+impl fmt::Debug for CachedPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedPool")
+            .field("pool", &self.pool)
+            .field("queries", &self.queries)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    rows: Arc<Vec<DynamicRow>>,
+    expires: Instant,
+    used: Instant,
+}
+
+impl CachedPool {
+    /// Wraps `pool`, without caching any queries yet.
+    pub fn new(pool: bb8::Pool<RusqliteConnectionManager>) -> Self {
+        Self {
+            pool,
+            queries: Default::default(),
+            state: Default::default(),
+        }
+    }
+
+    /// Caches the results of `sql` according to `policy`. The SQL has to
+    /// match exactly, and results are cached separately for each set of
+    /// parameters it's run with.
+    ///
+    /// Only cache queries without side effects. Results that depend on when
+    /// the query is run, such as with `'now'`, are served as they were when
+    /// they were read until they expire.
+    pub fn with_query(mut self, sql: &str, policy: CachePolicy) -> Self {
+        Arc::make_mut(&mut self.queries).insert(sql.into(), policy);
+        self
+    }
+
+    /// Returns the wrapped pool.
+    pub fn pool(&self) -> &bb8::Pool<RusqliteConnectionManager> {
+        &self.pool
+    }
+
+    /// Runs `sql` with `params`, as for
+    /// [`PoolExt::query_rows_dynamic()`], unless a result for them is
+    /// cached and hasn't expired.
+    ///
+    /// Misses aren't coalesced: queries for the same parameters that miss at
+    /// the same time each run on the database, and the last to finish is the
+    /// one that's cached.
+    pub async fn query<P>(&self, sql: &str, params: P) -> Result<Arc<Vec<DynamicRow>>, Error>
+    where
+        P: IntoIterator,
+        P::Item: Into<Value>,
+    {
+        let params: Vec<Value> = params.into_iter().map(Into::into).collect();
+        let policy = match self.queries.get(sql) {
+            Some(policy) => *policy,
+            None => return Ok(Arc::new(self.pool.query_rows_dynamic(sql, params).await?)),
+        };
+
+        let key = Key {
+            sql: sql.into(),
+            params,
+        };
+        if let Some(rows) = self.get(&key) {
+            return Ok(rows);
+        }
+
+        let rows = Arc::new(self.pool.query_rows_dynamic(sql, key.params.iter()).await?);
+        // Option::is_none_or() needs Rust 1.82.
+        #[allow(clippy::unnecessary_map_or)]
+        if policy.max_rows.map_or(true, |max| rows.len() <= max) {
+            self.insert(key, rows.clone(), &policy);
+        }
+        Ok(rows)
+    }
+
+    /// Drops the cached results of `sql`, for every set of parameters, such
+    /// as after writing something they'd include.
+    pub fn invalidate(&self, sql: &str) {
+        self.state.lock().unwrap().retain(|key, _| key.sql != sql);
+    }
+
+    /// Drops every cached result.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().clear();
+    }
+
+    /// Returns the number of cached results, including any that have
+    /// expired but haven't been dropped yet.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().len()
+    }
+
+    /// Returns true if no results are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, key: &Key) -> Option<Arc<Vec<DynamicRow>>> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let entry = state.get_mut(key)?;
+        if entry.expires <= now {
+            state.remove(key);
+            return None;
+        }
+        entry.used = now;
+        Some(entry.rows.clone())
+    }
+
+    fn insert(&self, key: Key, rows: Arc<Vec<DynamicRow>>, policy: &CachePolicy) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let cached = |state: &HashMap<Key, Entry>| {
+            state.keys().filter(|cached| cached.sql == key.sql).count()
+        };
+        if cached(&state) >= policy.max_entries && !state.contains_key(&key) {
+            state.retain(|cached, entry| cached.sql != key.sql || entry.expires > now);
+        }
+        if cached(&state) >= policy.max_entries && !state.contains_key(&key) {
+            // As for QueryCache, a scan is cheaper than maintaining a list.
+            if let Some(oldest) = state
+                .iter()
+                .filter(|(cached, _)| cached.sql == key.sql)
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(cached, _)| cached.clone())
+            {
+                state.remove(&oldest);
+            }
+        }
+        state.insert(
+            key,
+            Entry {
+                rows,
+                expires: now + policy.ttl,
+                used: now,
+            },
+        );
+    }
+}
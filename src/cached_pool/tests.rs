@@ -0,0 +1,145 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    ExecutionContext,
+};
+
+const SETUP: &str = "CREATE TABLE t (a INTEGER);
+                    INSERT INTO t (a) VALUES (1), (2), (3);";
+
+const QUERY: &str = "SELECT a FROM t WHERE a > ? ORDER BY a";
+const COUNT: &str = "SELECT count(*) AS n FROM t";
+
+fn values(rows: &[DynamicRow]) -> Vec<Value> {
+    rows.iter()
+        .map(|row| row.values().next().unwrap().clone())
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_hits_without_a_connection() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("cached.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let cached =
+        CachedPool::new(pool.clone()).with_query(QUERY, CachePolicy::new(Duration::from_secs(60)));
+
+    let first = cached.query(QUERY, vec![1]).await?;
+    assert_eq!(values(&first), vec![Value::Integer(2), Value::Integer(3)]);
+
+    // With the only connection checked out, and written through, the cached
+    // result is still served.
+    let held = pool.get().await?;
+    held.execute_batch("DELETE FROM t")?;
+    let second = cached.query(QUERY, vec![1]).await?;
+    assert!(Arc::ptr_eq(&first, &second));
+
+    // Different parameters aren't cached yet, so they need the connection.
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_millis(50))
+        .scope(cached.query(QUERY, vec![2]))
+        .await;
+    assert!(matches!(result, Err(Error::DeadlineExceeded)));
+    drop(held);
+
+    cached.invalidate(QUERY);
+    assert!(cached.is_empty());
+    assert!(cached.query(QUERY, vec![1]).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn expires() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("cached.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let cached = CachedPool::new(pool.clone())
+        .with_query(COUNT, CachePolicy::new(Duration::from_millis(50)));
+
+    assert_eq!(
+        values(&cached.query(COUNT, Vec::<i64>::new()).await?),
+        vec![Value::Integer(3)]
+    );
+    pool.get()
+        .await?
+        .execute("DELETE FROM t WHERE a = 1", NO_PARAMS)?;
+    assert_eq!(
+        values(&cached.query(COUNT, Vec::<i64>::new()).await?),
+        vec![Value::Integer(3)]
+    );
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(
+        values(&cached.query(COUNT, Vec::<i64>::new()).await?),
+        vec![Value::Integer(2)]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn size_limits() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("cached.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let cached = CachedPool::new(pool.clone())
+        .with_query(
+            QUERY,
+            CachePolicy::new(Duration::from_secs(60))
+                .max_entries(2)
+                .max_rows(2),
+        )
+        .with_query(COUNT, CachePolicy::new(Duration::from_secs(60)));
+    cached.query(COUNT, Vec::<i64>::new()).await?;
+
+    // Results with too many rows aren't cached.
+    assert_eq!(cached.query(QUERY, vec![0]).await?.len(), 3);
+    assert_eq!(cached.len(), 1);
+
+    // Each query has its own limit on entries, and drops the least recently
+    // used once it's full.
+    let one = cached.query(QUERY, vec![1]).await?;
+    cached.query(QUERY, vec![2]).await?;
+    assert!(Arc::ptr_eq(&one, &cached.query(QUERY, vec![1]).await?));
+    cached.query(QUERY, vec![3]).await?;
+    assert_eq!(cached.len(), 3);
+    assert!(Arc::ptr_eq(&one, &cached.query(QUERY, vec![1]).await?));
+    let key = Key {
+        sql: QUERY.into(),
+        params: vec![Value::Integer(2)],
+    };
+    assert!(cached.get(&key).is_none());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn other_queries() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("cached.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let cached =
+        CachedPool::new(pool.clone()).with_query(QUERY, CachePolicy::new(Duration::from_secs(60)));
+
+    let first = cached.query(COUNT, Vec::<i64>::new()).await?;
+    let second = cached.query(COUNT, Vec::<i64>::new()).await?;
+    assert!(!Arc::ptr_eq(&first, &second));
+    assert!(cached.is_empty());
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Detection of what the linked SQLite library supports.
+
+use rusqlite::{Connection, NO_PARAMS};
+
+#[cfg(test)]
+mod tests;
+
+/// What the SQLite library the pool is linked against supports, as returned
+/// by [`PoolExt::capabilities()`](crate::PoolExt::capabilities).
+///
+/// Features that depend on the version are reported from the version, and
+/// functions from whether they can actually be called on the connection, so
+/// the math functions are reported as available when they're registered with
+/// [`RusqliteConnectionManager::with_math_functions()`](crate::RusqliteConnectionManager::with_math_functions).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The library's version, such as `3.40.1`.
+    pub version: String,
+
+    /// The library's version as a number, such as `3040001`.
+    pub version_number: i32,
+
+    /// True if tables can be declared `STRICT`, from SQLite 3.37.
+    pub strict_tables: bool,
+
+    /// True if `INSERT`, `UPDATE`, and `DELETE` support `RETURNING`, from
+    /// SQLite 3.35.
+    pub returning: bool,
+
+    /// True if `ALTER TABLE` supports `DROP COLUMN`, from SQLite 3.35.
+    pub drop_column: bool,
+
+    /// True if the JSON functions are available.
+    pub json: bool,
+
+    /// True if the math functions, such as `sqrt()`, are available.
+    pub math_functions: bool,
+
+    /// True if the library was built with the FTS5 full text search module.
+    pub fts5: bool,
+
+    /// True if the library was built with the R*Tree module.
+    pub rtree: bool,
+
+    /// The options the library was built with, as reported by
+    /// `PRAGMA compile_options`, without their `SQLITE_` prefix.
+    pub compile_options: Vec<String>,
+}
+
+impl Capabilities {
+    /// Detects the capabilities available on `conn`.
+    pub fn detect(conn: &Connection) -> Result<Self, rusqlite::Error> {
+        let mut compile_options: Vec<String> = Vec::new();
+        conn.pragma_query(None, "compile_options", |row| {
+            compile_options.push(row.get(0)?);
+            Ok(())
+        })?;
+        let compiled = |option: &str| compile_options.iter().any(|o| o == option);
+        let version_number = rusqlite::version_number();
+
+        Ok(Self {
+            version: rusqlite::version().into(),
+            version_number,
+            strict_tables: version_number >= 3_037_000,
+            returning: version_number >= 3_035_000,
+            drop_column: version_number >= 3_035_000,
+            json: callable(conn, "SELECT json('1')"),
+            math_functions: callable(conn, "SELECT sqrt(1)"),
+            fts5: compiled("ENABLE_FTS5"),
+            rtree: compiled("ENABLE_RTREE"),
+            compile_options,
+        })
+    }
+}
+
+/// Returns true if `sql`, which calls a function, runs, which it won't if the
+/// function doesn't exist.
+fn callable(conn: &Connection, sql: &str) -> bool {
+    conn.prepare(sql)
+        .and_then(|mut stmt| stmt.query_row(NO_PARAMS, |_| Ok(())))
+        .is_ok()
+}
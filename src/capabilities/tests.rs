@@ -0,0 +1,24 @@
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn capabilities() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(temp.file("db")).with_math_functions(true))
+        .await?;
+
+    let capabilities = pool.capabilities().await?;
+    assert_eq!(capabilities.version, rusqlite::version());
+    assert_eq!(
+        capabilities.strict_tables,
+        rusqlite::version_number() >= 3_037_000
+    );
+    assert!(capabilities.math_functions);
+    assert!(!capabilities.compile_options.is_empty());
+    assert!(capabilities
+        .compile_options
+        .iter()
+        .all(|option| !option.starts_with("SQLITE_")));
+    Ok(())
+}
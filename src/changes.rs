@@ -0,0 +1,115 @@
+//! Noticing when the database has been changed by someone else.
+
+use std::{path::PathBuf, time::Duration};
+
+use rusqlite::NO_PARAMS;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::{task, Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// A change to the database, yielded by [`ChangeStream::next()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    /// The stream's connection's `PRAGMA data_version`. This is only
+    /// meaningful when compared against earlier values from the same stream.
+    pub data_version: i64,
+
+    /// The number of frames in the WAL file, if tracking it has been enabled
+    /// with [`ChangeStream::with_wal_frames()`].
+    pub wal_frames: Option<u64>,
+}
+
+/// Polls the database for changes committed through any other connection,
+/// whether it belongs to this pool, another pool, or another process,
+/// created by [`PoolExt::changes_stream()`](crate::PoolExt::changes_stream).
+///
+/// The stream has its own connection, opened through the pool's manager
+/// but not counted against the pool's size. Changes are only noticed once per
+/// interval, so several commits between polls are reported as one change.
+#[derive(Debug)]
+pub struct ChangeStream {
+    conn: RusqliteConnection,
+    interval: Interval,
+    wal: Option<Wal>,
+    last: Change,
+}
+
+#[derive(Debug)]
+struct Wal {
+    path: PathBuf,
+    frame_size: u64,
+}
+
+impl ChangeStream {
+    pub(crate) fn new(conn: RusqliteConnection, interval: Duration) -> Result<Self, Error> {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut stream = Self {
+            conn,
+            interval,
+            wal: None,
+            last: Change {
+                data_version: 0,
+                wal_frames: None,
+            },
+        };
+        stream.last = task::block_in_place(|| stream.poll())?;
+        Ok(stream)
+    }
+
+    /// Also reports a change whenever the number of frames in the WAL file
+    /// changes, such as when a large transaction spills pages to the WAL
+    /// before it commits, or a checkpoint truncates it. This has no effect
+    /// outside WAL mode.
+    pub fn with_wal_frames(mut self) -> Result<Self, Error> {
+        let mut path = self.conn.file().path.clone().into_os_string();
+        path.push("-wal");
+        let page_size: u64 = task::block_in_place(|| {
+            self.conn
+                .query_row("PRAGMA page_size", NO_PARAMS, |row| row.get::<_, i64>(0))
+        })? as u64;
+        // Each frame is a page with a 24 byte header.
+        self.wal = Some(Wal {
+            path: path.into(),
+            frame_size: page_size + 24,
+        });
+        self.last.wal_frames = self.wal_frames();
+        Ok(self)
+    }
+
+    /// Waits for the next change.
+    ///
+    /// An error polling the database is returned, but doesn't end the
+    /// stream: calling `next()` again carries on polling.
+    pub async fn next(&mut self) -> Result<Change, Error> {
+        loop {
+            self.interval.tick().await;
+            let change = task::block_in_place(|| self.poll())?;
+            if change != self.last {
+                self.last = change;
+                return Ok(change);
+            }
+        }
+    }
+
+    fn poll(&self) -> Result<Change, Error> {
+        Ok(Change {
+            data_version: self
+                .conn
+                .query_row("PRAGMA data_version", NO_PARAMS, |row| row.get(0))?,
+            wal_frames: self.wal_frames(),
+        })
+    }
+
+    fn wal_frames(&self) -> Option<u64> {
+        let wal = self.wal.as_ref()?;
+        // The WAL file may not exist yet, or may have been removed when the
+        // last connection closed, which is the same as it being empty.
+        let size = std::fs::metadata(&wal.path).map_or(0, |meta| meta.len());
+        // The file starts with a 32 byte header.
+        Some(size.saturating_sub(32) / wal.frame_size)
+    }
+}
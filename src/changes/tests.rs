@@ -0,0 +1,75 @@
+use rusqlite::Connection;
+
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    PoolExt, RusqliteConnectionManager,
+};
+
+const SETUP: &str = "PRAGMA journal_mode = WAL; CREATE TABLE t (a INTEGER);";
+
+async fn no_change(stream: &mut ChangeStream) -> bool {
+    tokio::time::timeout(Duration::from_millis(100), stream.next())
+        .await
+        .is_err()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn changes() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("changes.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let mut stream = pool.changes_stream(Duration::from_millis(10)).await?;
+    assert!(no_change(&mut stream).await);
+
+    // Writes through the pool are changes, since they're on another
+    // connection.
+    pool.get()
+        .await?
+        .execute("INSERT INTO t (a) VALUES (1)", NO_PARAMS)?;
+    let first = stream.next().await?;
+    assert_eq!(first.wal_frames, None);
+    assert!(no_change(&mut stream).await);
+
+    // As are writes from other processes.
+    Connection::open(temp.file("changes.db"))?
+        .execute("INSERT INTO t (a) VALUES (2)", NO_PARAMS)?;
+    let second = stream.next().await?;
+    assert_ne!(first.data_version, second.data_version);
+    assert!(no_change(&mut stream).await);
+
+    // Reads aren't.
+    pool.get()
+        .await?
+        .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |_| Ok(()))?;
+    assert!(no_change(&mut stream).await);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wal_frames() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("changes.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let mut stream = pool
+        .changes_stream(Duration::from_millis(10))
+        .await?
+        .with_wal_frames()?;
+
+    let conn = pool.get().await?;
+    conn.execute("INSERT INTO t (a) VALUES (1)", NO_PARAMS)?;
+    let written = stream.next().await?;
+    assert!(written.wal_frames.unwrap() > 0);
+
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", NO_PARAMS, |_| Ok(()))?;
+    assert_eq!(stream.next().await?.wal_frames, Some(0));
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Fault injection, for exercising retry logic and timeouts in tests.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rusqlite::ffi;
+
+use crate::Error;
+
+#[cfg(test)]
+mod tests;
+
+type Delay = Arc<dyn Fn() -> Duration + Send + Sync>;
+
+/// Faults to inject into a pool, set with
+/// [`RusqliteConnectionManager::with_fault_injection()`](crate::RusqliteConnectionManager::with_fault_injection).
+///
+/// Each fault has a probability between 0 and 1 of being injected at each
+/// opportunity. The decisions come from a pseudo-random generator seeded by
+/// the caller, so a test that runs its operations in the same order sees the
+/// same faults every time.
+///
+/// Delays can also be added to every connect and checkout validation, to
+/// model a pool on a degraded disk in capacity planning tests.
+#[derive(Clone)]
+pub struct FaultInjection {
+    seed: u64,
+    connect_failure: f64,
+    busy: f64,
+    latency: f64,
+    delay: Duration,
+    connect_delay: Option<Delay>,
+    validation_delay: Option<Delay>,
+}
+
+impl fmt::Debug for FaultInjection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultInjection")
+            .field("seed", &self.seed)
+            .field("connect_failure", &self.connect_failure)
+            .field("busy", &self.busy)
+            .field("latency", &self.latency)
+            .field("delay", &self.delay)
+            .field("connect_delay", &self.connect_delay.is_some())
+            .field("validation_delay", &self.validation_delay.is_some())
+            .finish()
+    }
+}
+
+impl FaultInjection {
+    /// Creates a configuration that injects nothing until probabilities are
+    /// set, drawing from a generator seeded with `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            connect_failure: 0.0,
+            busy: 0.0,
+            latency: 0.0,
+            delay: Duration::ZERO,
+            connect_delay: None,
+            validation_delay: None,
+        }
+    }
+
+    /// Sets the probability that opening a connection fails with
+    /// `SQLITE_CANTOPEN`.
+    pub fn connect_failure(mut self, probability: f64) -> Self {
+        self.connect_failure = probability;
+        self
+    }
+
+    /// Sets the probability that an operation run through
+    /// [`PoolExt`](crate::PoolExt), or another of the crate's helpers, fails
+    /// with `SQLITE_BUSY` before it touches the database.
+    pub fn busy(mut self, probability: f64) -> Self {
+        self.busy = probability;
+        self
+    }
+
+    /// Sets the probability that opening a connection, or an operation run
+    /// through the crate's helpers, is delayed by `delay`. Delayed
+    /// operations block their thread, as a slow disk would.
+    pub fn latency(mut self, probability: f64, delay: Duration) -> Self {
+        self.latency = probability;
+        self.delay = delay;
+        self
+    }
+
+    /// Delays opening every connection by the duration `delay` returns,
+    /// which can sample from whatever distribution the test is modelling.
+    pub fn connect_delay<F>(mut self, delay: F) -> Self
+    where
+        F: Fn() -> Duration + Send + Sync + 'static,
+    {
+        self.connect_delay = Some(Arc::new(delay));
+        self
+    }
+
+    /// Delays validating every connection as it's checked out, when the
+    /// pool has `test_on_check_out` enabled, by the duration `delay`
+    /// returns.
+    pub fn validation_delay<F>(mut self, delay: F) -> Self
+    where
+        F: Fn() -> Duration + Send + Sync + 'static,
+    {
+        self.validation_delay = Some(Arc::new(delay));
+        self
+    }
+}
+
+/// Decides which faults to inject, shared by every connection of a manager.
+#[derive(Debug)]
+pub(crate) struct Injector {
+    config: FaultInjection,
+    state: Mutex<u64>,
+}
+
+impl Injector {
+    pub(crate) fn new(config: FaultInjection) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(config.seed),
+            config,
+        })
+    }
+
+    /// Returns true with the given probability.
+    fn roll(&self, probability: f64) -> bool {
+        if probability <= 0.0 {
+            return false;
+        }
+        // SplitMix64, which is plenty for choosing faults.
+        let mut state = self.state.lock().unwrap();
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    /// Injects faults into opening a connection.
+    pub(crate) async fn connect(&self) -> Result<(), Error> {
+        if let Some(delay) = &self.config.connect_delay {
+            tokio::time::sleep(delay()).await;
+        }
+        if self.roll(self.config.latency) {
+            tokio::time::sleep(self.config.delay).await;
+        }
+        if self.roll(self.config.connect_failure) {
+            return Err(injected(ffi::SQLITE_CANTOPEN));
+        }
+        Ok(())
+    }
+
+    /// Injects faults into validating a connection.
+    pub(crate) async fn validation(&self) {
+        if let Some(delay) = &self.config.validation_delay {
+            tokio::time::sleep(delay()).await;
+        }
+    }
+
+    /// Injects faults into an operation. This may block the thread.
+    pub(crate) fn operation(&self) -> Result<(), Error> {
+        if self.roll(self.config.latency) {
+            std::thread::sleep(self.config.delay);
+        }
+        if self.roll(self.config.busy) {
+            return Err(injected(ffi::SQLITE_BUSY));
+        }
+        Ok(())
+    }
+}
+
+fn injected(code: std::os::raw::c_int) -> Error {
+    rusqlite::Error::SqliteFailure(ffi::Error::new(code), Some("injected fault".into())).into()
+}
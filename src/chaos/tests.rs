@@ -0,0 +1,116 @@
+use std::time::Instant;
+
+use super::*;
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+fn code(e: &Error) -> Option<ffi::ErrorCode> {
+    match e {
+        Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _)) => Some(e.code),
+        _ => None,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connect_failure() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder().build_unchecked(
+        RusqliteConnectionManager::new(temp.file("chaos.db"))
+            .with_fault_injection(FaultInjection::new(1).connect_failure(1.0)),
+    );
+    let e = pool.dedicated_connection().await.unwrap_err();
+    assert_eq!(code(&e), Some(ffi::ErrorCode::CannotOpen));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let results = |seed| {
+        let path = temp.file("chaos.db");
+        async move {
+            let pool = bb8::Pool::builder()
+                .build(
+                    RusqliteConnectionManager::new(path)
+                        .with_fault_injection(FaultInjection::new(seed).busy(0.5)),
+                )
+                .await?;
+            let mut results = Vec::new();
+            for _ in 0..32 {
+                results.push(match pool.schema_version().await {
+                    Ok(_) => true,
+                    Err(e) => {
+                        assert_eq!(code(&e), Some(ffi::ErrorCode::DatabaseBusy));
+                        false
+                    }
+                });
+            }
+            Ok::<_, anyhow::Error>(results)
+        }
+    };
+
+    // The same seed injects the same faults.
+    let first = results(42).await?;
+    assert_eq!(first, results(42).await?);
+    assert_ne!(first, results(43).await?);
+    assert!(first.contains(&true) && first.contains(&false));
+
+    // Connections checked out directly aren't affected.
+    let pool = bb8::Pool::builder()
+        .build(
+            RusqliteConnectionManager::new(temp.file("chaos.db"))
+                .with_fault_injection(FaultInjection::new(1).busy(1.0)),
+        )
+        .await?;
+    pool.get().await?.execute_batch("SELECT 1")?;
+    assert!(pool.schema_version().await.is_err());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn latency() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let delay = Duration::from_millis(50);
+    let pool = bb8::Pool::builder().build_unchecked(
+        RusqliteConnectionManager::new(temp.file("chaos.db"))
+            .with_fault_injection(FaultInjection::new(1).latency(1.0, delay)),
+    );
+
+    let started = Instant::now();
+    pool.dedicated_connection().await?;
+    assert!(started.elapsed() >= delay);
+
+    let started = Instant::now();
+    pool.schema_version().await?;
+    // Both the connect and the operation are delayed.
+    assert!(started.elapsed() >= delay * 2);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn delays() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let delay = Duration::from_millis(30);
+    let manager = RusqliteConnectionManager::new(temp.file("chaos.db"))
+        .with_latency_samples(10)
+        .with_fault_injection(
+            FaultInjection::new(1)
+                .connect_delay(move || delay)
+                .validation_delay(move || delay),
+        );
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build_unchecked(manager.clone());
+
+    let started = Instant::now();
+    pool.dedicated_connection().await?;
+    assert!(started.elapsed() >= delay);
+
+    // The first checkout waits for a connection to be opened, the second for
+    // the idle one to be validated.
+    pool.acquire().await?;
+    pool.acquire().await?;
+    let latencies = manager.take_latency_samples();
+    assert_eq!(latencies.len(), 2);
+    assert!(latencies.samples().iter().all(|wait| *wait >= delay));
+    Ok(())
+}
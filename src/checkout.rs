@@ -0,0 +1,78 @@
+//! Checkouts that declare whether they intend to write.
+//!
+//! [`PoolExt::get_read()`](crate::PoolExt::get_read) and
+//! [`PoolExt::get_write()`](crate::PoolExt::get_write) both check out a
+//! connection from the same pool today, but a read checkout has
+//! `PRAGMA query_only` enabled for as long as it's held, so a read path can't
+//! write by accident. Code written against them won't need to change if reads
+//! and writes are later served by separate pools.
+
+use std::ops::{Deref, DerefMut};
+
+use crate::{RusqliteConnection, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+type Pooled<'a> = bb8::PooledConnection<'a, RusqliteConnectionManager>;
+
+/// A connection checked out for reading. Statements that would modify the
+/// database fail with `SQLITE_READONLY`.
+///
+/// `PRAGMA query_only` is turned back off when this is dropped, before the
+/// connection is returned to the pool.
+#[derive(Debug)]
+pub struct ReadConnection<'a>(Pooled<'a>);
+
+impl<'a> ReadConnection<'a> {
+    pub(crate) fn new(conn: Pooled<'a>) -> Result<Self, rusqlite::Error> {
+        conn.pragma_update(None, "query_only", &true)?;
+        Ok(Self(conn))
+    }
+}
+
+impl Deref for ReadConnection<'_> {
+    type Target = RusqliteConnection;
+
+    fn deref(&self) -> &RusqliteConnection {
+        &self.0
+    }
+}
+
+impl DerefMut for ReadConnection<'_> {
+    fn deref_mut(&mut self) -> &mut RusqliteConnection {
+        &mut self.0
+    }
+}
+
+impl Drop for ReadConnection<'_> {
+    fn drop(&mut self) {
+        // This only changes a flag on the connection, so it can't block, and
+        // won't fail on an open handle.
+        let _ = self.0.pragma_update(None, "query_only", &false);
+    }
+}
+
+/// A connection checked out for writing.
+#[derive(Debug)]
+pub struct WriteConnection<'a>(Pooled<'a>);
+
+impl<'a> WriteConnection<'a> {
+    pub(crate) fn new(conn: Pooled<'a>) -> Self {
+        Self(conn)
+    }
+}
+
+impl Deref for WriteConnection<'_> {
+    type Target = RusqliteConnection;
+
+    fn deref(&self) -> &RusqliteConnection {
+        &self.0
+    }
+}
+
+impl DerefMut for WriteConnection<'_> {
+    fn deref_mut(&mut self) -> &mut RusqliteConnection {
+        &mut self.0
+    }
+}
@@ -0,0 +1,42 @@
+use rusqlite::{ffi, ErrorCode, NO_PARAMS};
+
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_and_write() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(temp.file("checkout.db")))
+        .await?;
+
+    pool.get_write()
+        .await?
+        .execute_batch("CREATE TABLE t (a); INSERT INTO t VALUES (1);")?;
+
+    {
+        let conn = pool.get_read().await?;
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(count, 1);
+        match conn.execute("INSERT INTO t VALUES (2)", NO_PARAMS) {
+            Err(rusqlite::Error::SqliteFailure(
+                ffi::Error {
+                    code: ErrorCode::ReadOnly,
+                    ..
+                },
+                _,
+            )) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    // The same connection can write again once the read checkout is dropped.
+    pool.get_write()
+        .await?
+        .execute("INSERT INTO t VALUES (2)", NO_PARAMS)?;
+    let conn = pool.get().await?;
+    let query_only: bool = conn.query_row("PRAGMA query_only", NO_PARAMS, |row| row.get(0))?;
+    assert!(!query_only);
+
+    Ok(())
+}
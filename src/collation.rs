@@ -0,0 +1,136 @@
+//! Installing collations lazily, as SQLite finds it needs them.
+
+use std::{
+    cmp::Ordering,
+    ffi::{CStr, CString},
+    fmt,
+    os::raw::{c_char, c_int, c_void},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+    sync::Arc,
+};
+
+use rusqlite::{ffi, Connection};
+
+#[cfg(test)]
+mod tests;
+
+/// A collation's comparison function.
+pub type Collation = Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync>;
+
+type Resolve = dyn Fn(&str) -> Option<Collation> + Send + Sync;
+
+/// Looks up collations by name, for
+/// [`RusqliteConnectionManager::with_collation_needed()`](crate::RusqliteConnectionManager::with_collation_needed).
+#[derive(Clone)]
+pub(crate) struct Resolver(Arc<Resolve>);
+
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resolver").finish_non_exhaustive()
+    }
+}
+
+impl Resolver {
+    pub(crate) fn new<F>(resolve: F) -> Self
+    where
+        F: Fn(&str) -> Option<Collation> + Send + Sync + 'static,
+    {
+        Self(Arc::new(resolve))
+    }
+
+    /// Installs the resolver as the `collation_needed` callback on `conn`.
+    pub(crate) fn install(&self, conn: &Connection) -> Result<Registration, rusqlite::Error> {
+        let context = Box::new(self.clone());
+        // Safety: the context is boxed, so its address is stable until the
+        // registration is dropped, which happens after the connection is
+        // closed, or after uninstall() has removed the callback.
+        let rc = unsafe {
+            ffi::sqlite3_collation_needed(
+                conn.handle(),
+                &*context as *const Resolver as *mut c_void,
+                Some(needed),
+            )
+        };
+        if rc != ffi::SQLITE_OK {
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+        }
+        Ok(Registration { context })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Registration {
+    context: Box<Resolver>,
+}
+
+impl Registration {
+    /// Removes the callback from `conn`, so the connection can outlive the
+    /// registration. Collations that have already been installed stay.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: clearing the callback can't fail on an open handle.
+        unsafe {
+            ffi::sqlite3_collation_needed(conn.handle(), ptr::null_mut(), None);
+        }
+        drop(self.context);
+    }
+}
+
+unsafe extern "C" fn needed(
+    context: *mut c_void,
+    db: *mut ffi::sqlite3,
+    _encoding: c_int,
+    name: *const c_char,
+) {
+    let resolver = &*(context as *const Resolver);
+    let name = CStr::from_ptr(name);
+    let collation = match name.to_str() {
+        Ok(name) => catch_unwind(AssertUnwindSafe(|| (resolver.0)(name))).unwrap_or(None),
+        Err(_) => None,
+    };
+
+    // If nothing is installed, SQLite fails the statement with "no such
+    // collation sequence", as it would have without the callback.
+    if let Some(collation) = collation {
+        let name = CString::from(name);
+        let collation = Box::into_raw(Box::new(collation));
+        // SQLite calls destroy() if registering the collation fails, as well
+        // as when the connection is closed.
+        ffi::sqlite3_create_collation_v2(
+            db,
+            name.as_ptr(),
+            ffi::SQLITE_UTF8,
+            collation as *mut c_void,
+            Some(compare),
+            Some(destroy),
+        );
+    }
+}
+
+unsafe extern "C" fn compare(
+    collation: *mut c_void,
+    left_len: c_int,
+    left: *const c_void,
+    right_len: c_int,
+    right: *const c_void,
+) -> c_int {
+    let collation = &*(collation as *const Collation);
+    let text = |ptr: *const c_void, len: c_int| {
+        if len == 0 {
+            return String::new();
+        }
+        String::from_utf8_lossy(slice::from_raw_parts(ptr as *const u8, len as usize)).into_owned()
+    };
+    let (left, right) = (text(left, left_len), text(right, right_len));
+    match catch_unwind(AssertUnwindSafe(|| collation(&left, &right))) {
+        Ok(Ordering::Less) => -1,
+        Ok(Ordering::Greater) => 1,
+        // There's no way to report a panic from here, so the values compare
+        // as equal.
+        Ok(Ordering::Equal) | Err(_) => 0,
+    }
+}
+
+unsafe extern "C" fn destroy(collation: *mut c_void) {
+    drop(Box::from_raw(collation as *mut Collation));
+}
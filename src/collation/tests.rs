@@ -0,0 +1,52 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use bb8::ManageConnection;
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, Collation, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn collation_needed() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("collation.db");
+    let lookups = Arc::new(AtomicUsize::new(0));
+    let manager = RusqliteConnectionManager::new(&path).with_collation_needed({
+        let lookups = lookups.clone();
+        move |name| {
+            lookups.fetch_add(1, Ordering::SeqCst);
+            match name {
+                "reverse" => Some(Arc::new(|a: &str, b: &str| b.cmp(a)) as Collation),
+                _ => None,
+            }
+        }
+    });
+
+    // Set up a database whose index needs the collation, as an existing
+    // database would have.
+    manager.connect().await?.execute_batch(
+        "CREATE TABLE t (v TEXT);
+        CREATE INDEX t_v ON t (v COLLATE reverse);
+        INSERT INTO t VALUES ('a'), ('c'), ('b');",
+    )?;
+    assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+    let conn = pool.get().await?;
+    let values = conn
+        .prepare("SELECT v FROM t ORDER BY v COLLATE reverse")?
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    assert_eq!(values, ["c", "b", "a"]);
+    conn.execute("INSERT INTO t VALUES ('d')", NO_PARAMS)?;
+    assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+    let result = conn.query_row("SELECT 'a' < 'b' COLLATE missing", NO_PARAMS, |_| Ok(()));
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("no such collation sequence"));
+    Ok(())
+}
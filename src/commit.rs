@@ -0,0 +1,179 @@
+//! Tracking whether a connection has committed any transactions, and
+//! publishing changes to subscribers and the audit log when it does, through
+//! `sqlite3_commit_hook()` and `sqlite3_rollback_hook()`.
+//!
+//! The commit hook runs before the commit is durable, and a `COMMIT` can
+//! still fail after it: with `SQLITE_BUSY`, leaving the transaction open to
+//! be retried or rolled back, or with an error that rolls it back. So the
+//! hook only sets the transaction's changes aside, and they're published once
+//! the commit is known to have finished: when the connection has written
+//! pages to the database since the hook ran, which a commit that failed
+//! hasn't, or when it's out of the transaction as it's returned to the pool.
+//! A commit that's rolled back runs the rollback hook first, which discards
+//! them.
+
+use std::{
+    os::raw::{c_int, c_void},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Arc,
+    },
+};
+
+use rusqlite::{ffi, Connection};
+
+#[cfg(feature = "audit")]
+use crate::audit;
+use crate::ffi_ext::DBSTATUS_CACHE_WRITE;
+use crate::subscribe;
+
+#[derive(Debug)]
+struct Context {
+    // The connection's handle, as an address, to read its write count.
+    db: usize,
+    committed: AtomicBool,
+    // Set by the commit hook, until the commit is settled or rolled back,
+    // along with the number of pages the connection had written by then.
+    committing: AtomicBool,
+    writes: AtomicI32,
+    changes: Option<Arc<subscribe::Pending>>,
+    #[cfg(feature = "audit")]
+    audit: Option<Arc<audit::Pending>>,
+}
+
+impl Context {
+    /// Returns the number of pages the connection has written to its
+    /// databases.
+    fn writes(&self) -> c_int {
+        let (mut current, mut highwater) = (0, 0);
+        // Safety: the handle is open for as long as the hooks are installed.
+        unsafe {
+            ffi::sqlite3_db_status(
+                self.db as *mut ffi::sqlite3,
+                DBSTATUS_CACHE_WRITE,
+                &mut current,
+                &mut highwater,
+                0,
+            );
+        }
+        current
+    }
+
+    /// Publishes the changes of the transaction being committed, if
+    /// `finished`, or if the connection has written pages since the commit
+    /// hook ran, which only happens once SQLite has the locks to finish it.
+    fn settle(&self, finished: bool) {
+        if !self.committing.load(Ordering::Relaxed)
+            || !(finished || self.writes() != self.writes.load(Ordering::Relaxed))
+        {
+            return;
+        }
+        self.committing.store(false, Ordering::Relaxed);
+        if let Some(changes) = &self.changes {
+            changes.publish();
+        }
+        #[cfg(feature = "audit")]
+        if let Some(audit) = &self.audit {
+            audit.keep();
+        }
+    }
+}
+
+/// The commit and rollback hooks installed on a connection. SQLite only
+/// allows one of each per connection, so this does everything that needs to
+/// happen when a transaction ends.
+#[derive(Debug)]
+pub(crate) struct CommitHook(Box<Context>);
+
+impl CommitHook {
+    /// Installs the hooks on `conn`, publishing `changes` and keeping the
+    /// audit log's changes once each commit has finished, and discarding them
+    /// on each rollback, if given. `committed` carries over whether a
+    /// transaction had committed from a hook this replaces. The hook must be
+    /// kept alive for as long as it's installed.
+    pub(crate) fn install(
+        conn: &Connection,
+        committed: bool,
+        changes: Option<Arc<subscribe::Pending>>,
+        #[cfg(feature = "audit")] audit: Option<Arc<audit::Pending>>,
+    ) -> Self {
+        // Safety: the handle is only used while the hooks are installed.
+        let db = unsafe { conn.handle() } as usize;
+        let context = Box::new(Context {
+            db,
+            committed: AtomicBool::new(committed),
+            committing: AtomicBool::new(false),
+            writes: AtomicI32::new(0),
+            changes,
+            #[cfg(feature = "audit")]
+            audit,
+        });
+        let pointer = &*context as *const Context as *mut c_void;
+        // Safety: the context is boxed, so its address is stable until it's
+        // dropped, which happens after the connection is closed, or after
+        // uninstall() has removed the hook.
+        unsafe {
+            ffi::sqlite3_commit_hook(conn.handle(), Some(commit_hook), pointer);
+            ffi::sqlite3_rollback_hook(conn.handle(), Some(rollback_hook), pointer);
+        }
+        Self(context)
+    }
+
+    /// Returns true if a transaction has committed since the last call.
+    pub(crate) fn take(&self) -> bool {
+        self.0.committed.swap(false, Ordering::Relaxed)
+    }
+
+    /// Publishes the changes of a transaction that's committed on `conn`, if
+    /// the connection has left the transaction. If it hasn't, and hasn't
+    /// written anything since, the commit failed with `SQLITE_BUSY`, and the
+    /// changes wait for it to be retried or rolled back.
+    pub(crate) fn settle(&self, conn: &Connection) {
+        self.0.settle(conn.is_autocommit());
+    }
+
+    /// Removes the hooks from `conn`, so the connection can outlive them.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: clearing the hooks can't fail on an open handle.
+        unsafe {
+            ffi::sqlite3_commit_hook(conn.handle(), None, ptr::null_mut());
+            ffi::sqlite3_rollback_hook(conn.handle(), None, ptr::null_mut());
+        }
+    }
+}
+
+unsafe extern "C" fn commit_hook(context: *mut c_void) -> c_int {
+    let context = &*(context as *const Context);
+    // A commit retried after SQLITE_BUSY takes the changes from the first
+    // attempt with it, whereas those from an earlier commit that finished
+    // are published first.
+    context.settle(false);
+    context.committed.store(true, Ordering::Relaxed);
+    context.committing.store(true, Ordering::Relaxed);
+    context.writes.store(context.writes(), Ordering::Relaxed);
+    if let Some(changes) = &context.changes {
+        changes.commit();
+    }
+    #[cfg(feature = "audit")]
+    if let Some(audit) = &context.audit {
+        audit.commit();
+    }
+    // Returning zero lets the commit go ahead.
+    0
+}
+
+unsafe extern "C" fn rollback_hook(context: *mut c_void) {
+    let context = &*(context as *const Context);
+    // This may be a later transaction rolling back, after the last commit
+    // finished.
+    context.settle(false);
+    context.committing.store(false, Ordering::Relaxed);
+    if let Some(changes) = &context.changes {
+        changes.discard();
+    }
+    #[cfg(feature = "audit")]
+    if let Some(audit) = &context.audit {
+        audit.discard();
+    }
+}
@@ -0,0 +1,87 @@
+//! Transactions using `BEGIN CONCURRENT`, which is only available in SQLite
+//! builds from the `begin-concurrent` branch.
+//!
+//! A concurrent transaction doesn't take the write lock until it commits, so
+//! several connections in the pool can prepare writes at once. If another
+//! transaction has since modified a page this one read, the commit fails with
+//! `SQLITE_BUSY_SNAPSHOT`, and the whole transaction has to be run again.
+
+use rusqlite::{ffi, Connection};
+
+use crate::Error;
+
+#[cfg(test)]
+mod tests;
+
+/// The extended result code for a transaction that conflicted with another.
+const BUSY_SNAPSHOT: i32 = ffi::SQLITE_BUSY | (2 << 8);
+
+/// Options for
+/// [`PoolExt::concurrent_transaction()`](crate::PoolExt::concurrent_transaction).
+#[derive(Debug, Clone)]
+pub struct ConcurrentOptions {
+    max_attempts: u32,
+}
+
+impl Default for ConcurrentOptions {
+    fn default() -> Self {
+        Self { max_attempts: 10 }
+    }
+}
+
+impl ConcurrentOptions {
+    /// Creates the default options, which run the transaction up to 10
+    /// times.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many times the transaction is run before giving up and
+    /// returning the conflict.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+}
+
+/// Runs `f` within a transaction opened with `begin`, committing it if `f`
+/// succeeds. The transaction is run again from the start if it conflicts
+/// with another.
+pub(crate) fn transaction<F, T>(
+    conn: &Connection,
+    begin: &str,
+    options: &ConcurrentOptions,
+    mut f: F,
+) -> Result<T, Error>
+where
+    F: FnMut(&Connection) -> Result<T, Error>,
+{
+    let mut attempt = 1;
+    loop {
+        conn.execute_batch(begin)?;
+        let result = f(conn).and_then(|value| {
+            conn.execute_batch("COMMIT")?;
+            Ok(value)
+        });
+        match result {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                // A failed COMMIT leaves the transaction open.
+                if !conn.is_autocommit() {
+                    conn.execute_batch("ROLLBACK")?;
+                }
+                if !is_conflict(&e) || attempt >= options.max_attempts {
+                    return Err(e);
+                }
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn is_conflict(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _)) if e.extended_code == BUSY_SNAPSHOT
+    )
+}
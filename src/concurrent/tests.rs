@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{tests::TempDir, RusqliteConnectionManager};
+
+// Stock SQLite doesn't support BEGIN CONCURRENT, but a deferred transaction
+// in WAL mode fails the same way when it tries to write after another
+// connection already has.
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_conflicts() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(RusqliteConnectionManager::new(temp.file("concurrent.db")))
+        .await?;
+    let conn = pool.get().await?;
+    conn.execute_batch("PRAGMA journal_mode = WAL; CREATE TABLE t (a);")?;
+    let other = pool.get().await?;
+
+    let attempts = AtomicU32::new(0);
+    let run = |max_attempts| {
+        attempts.store(0, Ordering::SeqCst);
+        transaction(
+            &conn,
+            "BEGIN",
+            &ConcurrentOptions::new().max_attempts(max_attempts),
+            |tx| {
+                let count: i64 =
+                    tx.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    other.execute("INSERT INTO t VALUES (0)", NO_PARAMS)?;
+                }
+                tx.execute("INSERT INTO t VALUES (?)", [count + 1])?;
+                Ok(count)
+            },
+        )
+    };
+
+    assert_eq!(run(2)?, 1);
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    assert!(conn.is_autocommit());
+
+    assert!(is_conflict(&run(1).unwrap_err()));
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    assert!(conn.is_autocommit());
+
+    let rows: i64 = conn.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(rows, 3);
+    Ok(())
+}
@@ -0,0 +1,329 @@
+//! A record of how each new connection was configured.
+//!
+//! SQLite ignores some settings it can't apply, rather than failing: asking
+//! for WAL on a filesystem without shared memory leaves the database in its
+//! old journal mode, and unknown pragmas do nothing at all. The callback set
+//! with
+//! [`RusqliteConnectionManager::on_configured()`](crate::RusqliteConnectionManager::on_configured)
+//! is given every pragma the manager applied, along with the value SQLite
+//! reports for it afterwards, so those can be told apart from settings that
+//! took. Those that didn't can also be made to fail the connection, with
+//! [`PragmaVerification`].
+
+use std::{fmt, path::Path, sync::Arc};
+
+use rusqlite::{types::Value, Connection, OpenFlags, OptionalExtension, NO_PARAMS};
+
+use crate::{ConnectionOptions, Error};
+
+#[cfg(test)]
+mod tests;
+
+/// How a connection was configured by the manager, as passed to the callback
+/// set with
+/// [`RusqliteConnectionManager::on_configured()`](crate::RusqliteConnectionManager::on_configured).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectionConfig<'a> {
+    /// The connection's ID, as returned by
+    /// [`RusqliteConnection::id()`](crate::RusqliteConnection::id).
+    pub connection: u64,
+
+    /// The path of the database file the connection was opened on.
+    pub path: &'a Path,
+
+    /// The pragmas applied, in the order they were applied.
+    pub pragmas: &'a [AppliedPragma],
+
+    /// The extensions registered, by the name of the feature that enables
+    /// them, with `math` for the math functions.
+    pub extensions: &'a [&'static str],
+
+    /// The SQLite callbacks installed, by the name of the function that
+    /// installs them, such as `sqlite3_wal_hook`.
+    pub hooks: &'a [&'static str],
+}
+
+/// A pragma applied to a new connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppliedPragma {
+    /// The pragma's name, as it was given.
+    pub name: String,
+
+    /// The value the pragma was set to.
+    pub requested: Value,
+
+    /// The value SQLite reported for the pragma once the connection was
+    /// configured, or `None` if it couldn't be read back, or is a command
+    /// such as `wal_checkpoint` or `optimize`, which isn't run again to read
+    /// it. Pragmas don't
+    /// always read back as they were set: `synchronous = 'NORMAL'` reads back
+    /// as `1`, for example.
+    pub value: Option<Value>,
+}
+
+impl AppliedPragma {
+    /// Returns false if the pragma read back as something other than what it
+    /// was set to.
+    ///
+    /// Text is compared without regard to case, and the names SQLite gives
+    /// the values of `synchronous`, `temp_store`, `auto_vacuum`, and
+    /// `secure_delete`, and boolean words such as `ON`, match the numbers
+    /// they read back as. Pragmas that couldn't be read back are assumed to
+    /// have taken effect.
+    pub fn took_effect(&self) -> bool {
+        match &self.value {
+            Some(value) => normalize(&self.name, value) == normalize(&self.name, &self.requested),
+            None => true,
+        }
+    }
+}
+
+/// Returns `value` in a form that compares equal however SQLite would
+/// report it, for the pragma `name`.
+fn normalize(name: &str, value: &Value) -> Value {
+    let text = match value {
+        Value::Text(text) => text.trim().to_ascii_lowercase(),
+        value => return value.clone(),
+    };
+    // The name may be qualified with a schema.
+    let name = name.rsplit('.').next().unwrap_or(name).to_ascii_lowercase();
+    let number = match (name.as_str(), text.as_str()) {
+        ("synchronous", "off") | ("temp_store", "default") | ("auto_vacuum", "none") => Some(0),
+        ("synchronous", "normal") | ("temp_store", "file") => Some(1),
+        ("synchronous", "full") | ("temp_store", "memory") => Some(2),
+        ("auto_vacuum", "full") => Some(1),
+        ("auto_vacuum", "incremental") | ("secure_delete", "fast") => Some(2),
+        ("synchronous", "extra") => Some(3),
+        // journal_mode and locking_mode read back as words, so these are
+        // only booleans elsewhere.
+        ("journal_mode", _) | ("locking_mode", _) => None,
+        (_, "on") | (_, "yes") | (_, "true") => Some(1),
+        (_, "off") | (_, "no") | (_, "false") => Some(0),
+        (_, text) => text.parse().ok(),
+    };
+    match number {
+        Some(number) => Value::Integer(number),
+        None => Value::Text(text),
+    }
+}
+
+type WarningCallback = dyn Fn(&Path, &AppliedPragma) + Send + Sync;
+
+/// What to do when a pragma given to
+/// [`RusqliteConnectionManager::with_pragmas()`](crate::RusqliteConnectionManager::with_pragmas)
+/// doesn't take effect on a new connection, as set with
+/// [`RusqliteConnectionManager::with_pragma_verification()`](crate::RusqliteConnectionManager::with_pragma_verification).
+///
+/// Each pragma is read back once they've all been applied, and compared
+/// with [`AppliedPragma::took_effect()`].
+#[derive(Clone)]
+pub struct PragmaVerification(Verify);
+
+#[derive(Clone)]
+enum Verify {
+    Enforce,
+    Warn(Arc<WarningCallback>),
+}
+
+impl fmt::Debug for PragmaVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.0 {
+            Verify::Enforce => "Enforce",
+            Verify::Warn(_) => "Warn",
+        };
+        f.debug_tuple("PragmaVerification").field(&mode).finish()
+    }
+}
+
+impl PragmaVerification {
+    /// Fails `connect()` with [`Error::PragmaNotApplied`], and closes the
+    /// connection.
+    pub fn enforce() -> Self {
+        Self(Verify::Enforce)
+    }
+
+    /// Calls `callback` with the path of the database and the pragma, and
+    /// keeps the connection.
+    pub fn warn<F>(callback: F) -> Self
+    where
+        F: Fn(&Path, &AppliedPragma) + Send + Sync + 'static,
+    {
+        Self(Verify::Warn(Arc::new(callback)))
+    }
+
+    /// Checks the `pragmas` read back from a connection to `path`.
+    pub(crate) fn check(&self, path: &Path, pragmas: &[AppliedPragma]) -> Result<(), Error> {
+        for pragma in pragmas.iter().filter(|pragma| !pragma.took_effect()) {
+            match &self.0 {
+                Verify::Enforce => {
+                    return Err(Error::PragmaNotApplied {
+                        name: pragma.name.clone(),
+                        requested: pragma.requested.clone(),
+                        found: pragma.value.clone().unwrap_or(Value::Null),
+                    })
+                }
+                Verify::Warn(callback) => callback(path, pragma),
+            }
+        }
+        Ok(())
+    }
+}
+
+type Callback = dyn Fn(&ConnectionConfig<'_>) + Send + Sync;
+
+/// The callback set with `on_configured()`.
+#[derive(Clone)]
+pub(crate) struct Reporter(Arc<Callback>);
+
+impl fmt::Debug for Reporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reporter").finish()
+    }
+}
+
+impl Reporter {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&ConnectionConfig<'_>) + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+
+    /// Reports the configuration of connection `id`, given the pragmas read
+    /// back before its hooks were installed.
+    pub(crate) fn report(
+        &self,
+        id: u64,
+        path: &Path,
+        options: &ConnectionOptions,
+        pragmas: &[AppliedPragma],
+    ) {
+        let mut extensions = Vec::new();
+        #[cfg(feature = "array")]
+        extensions.push("array");
+        #[cfg(any(
+            feature = "regexp",
+            feature = "series",
+            feature = "sha3",
+            feature = "uuid"
+        ))]
+        extensions.extend(crate::extensions::names());
+        if options.math_functions {
+            extensions.push("math");
+        }
+
+        (self.0)(&ConnectionConfig {
+            connection: id,
+            path,
+            pragmas,
+            extensions: &extensions,
+            hooks: &hooks(options),
+        });
+    }
+}
+
+/// Returns the pragmas the manager applies itself with `options`, as opposed
+/// to those given to it in a [`PragmaCustomizer`].
+pub(crate) fn manager_pragmas(options: &ConnectionOptions) -> Vec<(&'static str, Value)> {
+    let mut pragmas = Vec::new();
+    if let Some(id) = options.application_id {
+        pragmas.push(("application_id", Value::Integer(id.into())));
+    }
+    if options.wal2
+        && !options
+            .mode
+            .flags()
+            .contains(OpenFlags::SQLITE_OPEN_READ_ONLY)
+    {
+        pragmas.push(("journal_mode", Value::Text("wal2".into())));
+    }
+    if let Some(limit) = options.soft_heap_limit {
+        pragmas.push(("soft_heap_limit", Value::Integer(limit)));
+    }
+    if let Some(limit) = options.hard_heap_limit {
+        pragmas.push(("hard_heap_limit", Value::Integer(limit)));
+    }
+    pragmas
+}
+
+/// Returns true if running the pragma `name` without a value does something
+/// other than read a setting, or checks the whole database.
+fn is_command(name: &str) -> bool {
+    // The name may be qualified with a schema.
+    let name = name.rsplit('.').next().unwrap_or(name);
+    [
+        "foreign_key_check",
+        "incremental_vacuum",
+        "integrity_check",
+        "optimize",
+        "quick_check",
+        "shrink_memory",
+        "wal_checkpoint",
+    ]
+    .iter()
+    .any(|command| name.eq_ignore_ascii_case(command))
+}
+
+/// Reads back `pragmas`, which have been applied to `conn`. Those that are
+/// commands, rather than settings, aren't run again, and so are reported
+/// without a value.
+///
+/// This has to run before any trace callback is installed, so that the reads
+/// aren't counted or profiled as the application's statements.
+pub(crate) fn read_back<'a, I>(conn: &Connection, pragmas: I) -> Vec<AppliedPragma>
+where
+    I: IntoIterator<Item = (&'a str, Value)>,
+{
+    pragmas
+        .into_iter()
+        .map(|(name, requested)| AppliedPragma {
+            name: name.into(),
+            requested,
+            value: if is_command(name) {
+                None
+            } else {
+                conn.query_row(&format!("PRAGMA {}", name), NO_PARAMS, |row| row.get(0))
+                    .optional()
+                    .ok()
+                    .flatten()
+            },
+        })
+        .collect()
+}
+
+/// Returns the SQLite callbacks the manager installs with `options`.
+fn hooks(options: &ConnectionOptions) -> Vec<&'static str> {
+    let mut hooks = Vec::new();
+    #[cfg(feature = "profiling")]
+    let profiling = options.profiler.is_some();
+    #[cfg(not(feature = "profiling"))]
+    let profiling = false;
+    if profiling || options.contention.is_some() || options.connection_stats {
+        hooks.push("sqlite3_trace_v2");
+    }
+    if options.contention.is_some() {
+        hooks.push("sqlite3_busy_handler");
+    }
+    if options.collation_needed.is_some() {
+        hooks.push("sqlite3_collation_needed");
+    }
+    if options.wal_hook.is_some() {
+        hooks.push("sqlite3_wal_hook");
+    }
+    #[cfg(feature = "preupdate-hook")]
+    if options.preupdate_installed() {
+        hooks.push("sqlite3_preupdate_hook");
+    }
+    if options.authorizer.is_some() {
+        hooks.push("sqlite3_set_authorizer");
+    }
+    if options.statement_timeout.is_some() {
+        hooks.push("sqlite3_progress_handler");
+    }
+    hooks.extend([
+        "sqlite3_update_hook",
+        "sqlite3_rollback_hook",
+        "sqlite3_commit_hook",
+    ]);
+    hooks
+}
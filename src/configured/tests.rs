@@ -0,0 +1,232 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bb8::ManageConnection;
+use rusqlite::{types::Value, NO_PARAMS};
+
+use crate::{
+    tests::TempDir, AppliedPragma, ConnectionConfig, Error, PragmaCustomizer, PragmaVerification,
+    RusqliteConnectionManager,
+};
+
+#[derive(Debug, PartialEq)]
+struct Record {
+    connection: u64,
+    pragmas: Vec<AppliedPragma>,
+    extensions: Vec<&'static str>,
+    hooks: Vec<&'static str>,
+}
+
+fn recorder() -> (
+    Arc<Mutex<Vec<Record>>>,
+    impl Fn(&ConnectionConfig<'_>) + Send + Sync + 'static,
+) {
+    let records = Arc::new(Mutex::new(Vec::new()));
+    let callback = {
+        let records = records.clone();
+        move |config: &ConnectionConfig<'_>| {
+            records.lock().unwrap().push(Record {
+                connection: config.connection,
+                pragmas: config.pragmas.to_vec(),
+                extensions: config.extensions.to_vec(),
+                hooks: config.hooks.to_vec(),
+            })
+        }
+    };
+    (records, callback)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reads_back_pragmas() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let (records, callback) = recorder();
+    let manager = RusqliteConnectionManager::new(temp.file("configured.db"))
+        .with_application_id(42)
+        .with_pragmas(
+            PragmaCustomizer::new()
+                .pragma("journal_mode", String::from("WAL"))
+                .pragma("locking_mode", String::from("sideways"))
+                .busy_timeout(Duration::from_millis(250))
+                .statement_cache_capacity(8),
+        )
+        .on_configured(callback);
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+
+    let id = pool.get().await?.id();
+    let records = records.lock().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].connection, id);
+    assert_eq!(
+        records[0].pragmas,
+        vec![
+            AppliedPragma {
+                name: "application_id".into(),
+                requested: Value::Integer(42),
+                value: Some(Value::Integer(42)),
+            },
+            AppliedPragma {
+                name: "journal_mode".into(),
+                requested: Value::Text("WAL".into()),
+                value: Some(Value::Text("wal".into())),
+            },
+            // SQLite ignores locking modes it doesn't know.
+            AppliedPragma {
+                name: "locking_mode".into(),
+                requested: Value::Text("sideways".into()),
+                value: Some(Value::Text("normal".into())),
+            },
+            AppliedPragma {
+                name: "busy_timeout".into(),
+                requested: Value::Integer(250),
+                value: Some(Value::Integer(250)),
+            },
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hooks_and_extensions() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let (records, callback) = recorder();
+    let manager = RusqliteConnectionManager::new(temp.file("configured.db"))
+        .with_math_functions(true)
+        .with_connection_stats(true)
+        .with_pragmas(PragmaCustomizer::new().pragma("foreign_keys", true))
+        .with_collation_needed(|_| None)
+        .on_configured(callback);
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .test_on_check_out(false)
+        .build(manager)
+        .await?;
+
+    let conn = pool.get().await?;
+    let records = records.lock().unwrap();
+    assert_eq!(records[0].pragmas[0].value, Some(Value::Integer(1)));
+    assert!(records[0].extensions.contains(&"math"));
+    for hook in &[
+        "sqlite3_trace_v2",
+        "sqlite3_collation_needed",
+        "sqlite3_commit_hook",
+    ] {
+        assert!(records[0].hooks.contains(hook), "{:?}", records[0].hooks);
+    }
+    assert!(!records[0].hooks.contains(&"sqlite3_busy_handler"));
+
+    // Reading the pragmas back isn't counted as the connection's use.
+    assert_eq!(conn.stats().statements, 0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn skips_commands() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("configured.db");
+    let setup = rusqlite::Connection::open(&path)?;
+    setup.execute_batch(
+        "PRAGMA auto_vacuum = INCREMENTAL;
+         CREATE TABLE t (a);
+         WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 10)
+         INSERT INTO t SELECT zeroblob(4096) FROM n;
+         DROP TABLE t;",
+    )?;
+    let free: i64 = setup.query_row("PRAGMA freelist_count", NO_PARAMS, |row| row.get(0))?;
+    assert!(free >= 10);
+
+    let (records, callback) = recorder();
+    let manager = RusqliteConnectionManager::new(&path)
+        .with_pragmas(PragmaCustomizer::new().pragma("incremental_vacuum", 1))
+        .on_configured(callback);
+    manager.connect().await?;
+
+    // Reading the pragma back would have run it again, freeing more pages.
+    let remaining: i64 = setup.query_row("PRAGMA freelist_count", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(remaining, free - 1);
+    let records = records.lock().unwrap();
+    assert_eq!(records[0].pragmas[0].name, "incremental_vacuum");
+    assert_eq!(records[0].pragmas[0].value, None);
+    Ok(())
+}
+
+#[test]
+fn took_effect() {
+    let pragma = |name: &str, requested: Value, value: Option<Value>| AppliedPragma {
+        name: name.into(),
+        requested,
+        value,
+    };
+    let text = |text: &str| Value::Text(text.into());
+
+    assert!(pragma("journal_mode", text("WAL"), Some(text("wal"))).took_effect());
+    assert!(pragma("main.synchronous", text("normal"), Some(Value::Integer(1))).took_effect());
+    assert!(pragma("foreign_keys", text("ON"), Some(Value::Integer(1))).took_effect());
+    assert!(pragma("cache_size", text("-4000"), Some(Value::Integer(-4000))).took_effect());
+    assert!(pragma("key", text("secret"), None).took_effect());
+
+    assert!(!pragma("journal_mode", text("WAL"), Some(text("delete"))).took_effect());
+    assert!(!pragma("journal_mode", text("off"), Some(text("delete"))).took_effect());
+    assert!(!pragma("synchronous", text("FULL"), Some(Value::Integer(1))).took_effect());
+}
+
+fn customizer() -> PragmaCustomizer {
+    PragmaCustomizer::new()
+        .pragma("synchronous", String::from("NORMAL"))
+        .pragma("locking_mode", String::from("sideways"))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn enforce() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("configured.db"))
+        .with_pragmas(customizer())
+        .with_pragma_verification(PragmaVerification::enforce());
+
+    match manager.connect().await {
+        Err(Error::PragmaNotApplied {
+            name,
+            requested,
+            found,
+        }) => {
+            assert_eq!(name, "locking_mode");
+            assert_eq!(requested, Value::Text("sideways".into()));
+            assert_eq!(found, Value::Text("normal".into()));
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    manager.reload_pragmas(
+        PragmaCustomizer::new()
+            .pragma("journal_mode", String::from("WAL"))
+            .busy_timeout(Duration::from_secs(1)),
+    );
+    manager.connect().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn warn() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("configured.db");
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let manager = RusqliteConnectionManager::new(&path)
+        .with_pragmas(customizer())
+        .with_pragma_verification(PragmaVerification::warn({
+            let warnings = warnings.clone();
+            move |path, pragma| {
+                warnings
+                    .lock()
+                    .unwrap()
+                    .push((path.to_path_buf(), pragma.name.clone()))
+            }
+        }));
+
+    manager.connect().await?;
+    assert_eq!(
+        *warnings.lock().unwrap(),
+        vec![(path, "locking_mode".into())]
+    );
+    Ok(())
+}
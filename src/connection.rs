@@ -0,0 +1,590 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use tokio::sync::{broadcast, Semaphore};
+
+#[cfg(feature = "audit")]
+use crate::audit::{self, AuditLog};
+#[cfg(feature = "chaos")]
+use crate::chaos::Injector;
+use crate::ffi_ext::sqlite3_close_v2;
+#[cfg(feature = "preupdate-hook")]
+use crate::preupdate;
+#[cfg(feature = "profiling")]
+use crate::profile::{Profiler, Registration};
+use crate::{
+    authorizer::{self, AuthorizerPolicy},
+    collation,
+    commit::CommitHook,
+    contention::{self, ContentionMonitor},
+    identity::FileIdentity,
+    leak::LeakDetector,
+    lifecycle::{Hooks, Lifecycle},
+    metrics::{ConnectionMetrics, Metrics},
+    params::{self, ToParams},
+    result_limit::ResultLimits,
+    statement_timeout::{self, StatementTimer},
+    subscribe::{self, Hub, RowChange},
+    transaction::DefaultBehavior,
+    usage::{self, ConnectionStats},
+    wal_hook, DatabaseFile,
+};
+
+/// A pooled `rusqlite::Connection`.
+///
+/// This derefs to the underlying `Connection`, so it can be used anywhere a
+/// `Connection` could, but also carries the state the connection manager
+/// tracks for each connection.
+#[derive(Debug)]
+pub struct RusqliteConnection {
+    // This is only None once the connection has been closed or unwrapped.
+    conn: Option<Connection>,
+    file: Arc<DatabaseFile>,
+    identity: Option<FileIdentity>,
+    metrics: ConnectionMetrics,
+    healthy_at: Instant,
+    generation: u64,
+    // These must be dropped after the connection is closed.
+    lifecycle: Lifecycle,
+    #[cfg(feature = "profiling")]
+    profiler: Option<Registration>,
+    contention: Option<contention::Registration>,
+    usage: Option<usage::Registration>,
+    hub: Option<Arc<Hub>>,
+    subscriptions: Option<subscribe::Registration>,
+    commits: Option<CommitHook>,
+    collations: Option<collation::Registration>,
+    wal_hook: Option<wal_hook::Registration>,
+    #[cfg(feature = "preupdate-hook")]
+    preupdate: Option<preupdate::Registration>,
+    #[cfg(feature = "audit")]
+    audit: Option<Arc<audit::Pending>>,
+    statement_timer: Option<Box<StatementTimer>>,
+    #[cfg(feature = "chaos")]
+    faults: Option<Arc<Injector>>,
+    leaks: Option<LeakDetector>,
+    execution: Option<Arc<Semaphore>>,
+    #[cfg(feature = "error-offset")]
+    redact_errors: bool,
+    #[cfg(feature = "log")]
+    slow_query_log: Option<Duration>,
+    result_limits: ResultLimits,
+    transaction_behavior: DefaultBehavior,
+    authorizer: Option<authorizer::Registration>,
+}
+
+impl RusqliteConnection {
+    pub(crate) fn new(
+        conn: Connection,
+        file: Arc<DatabaseFile>,
+        identity: Option<FileIdentity>,
+        metrics: Arc<Metrics>,
+        hooks: Hooks,
+        generation: u64,
+    ) -> Self {
+        let metrics = ConnectionMetrics::new(metrics);
+        let lifecycle = Lifecycle::new(hooks, file.path.clone(), metrics.id());
+        file.connections.fetch_add(1, Ordering::Relaxed);
+        Self {
+            conn: Some(conn),
+            file,
+            identity,
+            metrics,
+            healthy_at: Instant::now(),
+            generation,
+            lifecycle,
+            #[cfg(feature = "profiling")]
+            profiler: None,
+            contention: None,
+            usage: None,
+            hub: None,
+            subscriptions: None,
+            commits: None,
+            collations: None,
+            wal_hook: None,
+            #[cfg(feature = "preupdate-hook")]
+            preupdate: None,
+            #[cfg(feature = "audit")]
+            audit: None,
+            statement_timer: None,
+            #[cfg(feature = "chaos")]
+            faults: None,
+            leaks: None,
+            execution: None,
+            #[cfg(feature = "error-offset")]
+            redact_errors: false,
+            #[cfg(feature = "log")]
+            slow_query_log: None,
+            result_limits: ResultLimits::default(),
+            transaction_behavior: DefaultBehavior::default(),
+            authorizer: None,
+        }
+    }
+
+    #[cfg(feature = "profiling")]
+    pub(crate) fn with_profiler(mut self, profiler: &Profiler) -> Result<Self, rusqlite::Error> {
+        self.profiler = Some(profiler.install(&self)?);
+        Ok(self)
+    }
+
+    /// Installs `monitor`, which also counts statements and rows if
+    /// `count_statements` is set.
+    pub(crate) fn with_contention_monitor(
+        mut self,
+        monitor: &ContentionMonitor,
+        count_statements: bool,
+    ) -> Result<Self, rusqlite::Error> {
+        let usage = self.metrics.usage().clone();
+        self.contention = Some(monitor.install(&self, self.id(), usage, count_statements)?);
+        Ok(self)
+    }
+
+    /// Counts the statements and rows the connection runs, forwarding
+    /// statement profiles to `profiler`.
+    pub(crate) fn with_statement_counts(
+        mut self,
+        #[cfg(feature = "profiling")] profiler: Option<Profiler>,
+    ) -> Result<Self, rusqlite::Error> {
+        let usage = self.metrics.usage().clone();
+        self.usage = Some(usage::install(
+            &self,
+            usage,
+            #[cfg(feature = "profiling")]
+            profiler,
+        )?);
+        Ok(self)
+    }
+
+    pub(crate) fn with_collation_resolver(
+        mut self,
+        resolver: &collation::Resolver,
+    ) -> Result<Self, rusqlite::Error> {
+        self.collations = Some(resolver.install(&self)?);
+        Ok(self)
+    }
+
+    pub(crate) fn with_wal_hook(
+        mut self,
+        hook: &wal_hook::WalHook,
+    ) -> Result<Self, rusqlite::Error> {
+        self.wal_hook = Some(hook.install(&self, self.id())?);
+        Ok(self)
+    }
+
+    /// Installs the preupdate hook, calling `hook` and recording changes for
+    /// `audit`, if given. This must come before the commit hook.
+    #[cfg(feature = "preupdate-hook")]
+    pub(crate) fn with_preupdate_hook(
+        mut self,
+        hook: Option<&preupdate::PreUpdateHook>,
+        #[cfg(feature = "audit")] audit: Option<&AuditLog>,
+    ) -> Self {
+        #[cfg(feature = "audit")]
+        {
+            self.audit = audit.map(|log| Arc::new(audit::Pending::new(log.clone(), self.id())));
+        }
+        self.preupdate = Some(preupdate::install(
+            &self,
+            self.id(),
+            hook.cloned(),
+            #[cfg(feature = "audit")]
+            self.audit.clone(),
+        ));
+        self
+    }
+
+    /// Records the changes this connection has committed in the audit log.
+    #[cfg(feature = "audit")]
+    pub(crate) fn flush_audit(&self) {
+        if let Some(audit) = &self.audit {
+            audit.flush(self);
+        }
+    }
+
+    /// Connects the connection to the manager's subscribers, installing the
+    /// update hook if anyone is subscribed.
+    pub(crate) fn with_subscriptions(mut self, hub: Arc<Hub>) -> Self {
+        if hub.has_subscribers() {
+            self.subscriptions = Some(subscribe::install(&self, hub.clone()));
+        }
+        self.hub = Some(hub);
+        self
+    }
+
+    /// Installs the update hook if someone has subscribed since the
+    /// connection was opened, and the commit hook to publish its changes.
+    /// This is done between transactions, as the connection is checked out.
+    pub(crate) fn track_subscriptions(&mut self) {
+        let hub = match &self.hub {
+            Some(hub) if self.subscriptions.is_none() && hub.has_subscribers() => hub.clone(),
+            _ => return,
+        };
+        if !self.is_autocommit() {
+            return;
+        }
+        self.subscriptions = Some(subscribe::install(self, hub));
+        let committed = match self.commits.take() {
+            Some(commits) => {
+                let committed = commits.take();
+                commits.uninstall(self);
+                committed
+            }
+            None => false,
+        };
+        self.install_commit_hook(committed);
+    }
+
+    /// Sets the injector that adds faults to operations run on the
+    /// connection.
+    #[cfg(feature = "chaos")]
+    pub(crate) fn with_fault_injection(mut self, faults: Arc<Injector>) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Injects any configured faults into an operation about to run on the
+    /// connection.
+    pub(crate) fn inject_faults(&self) -> Result<(), crate::Error> {
+        #[cfg(feature = "chaos")]
+        if let Some(faults) = &self.faults {
+            return faults.operation();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn with_leak_detector(mut self, leaks: LeakDetector) -> Self {
+        self.leaks = Some(leaks);
+        self
+    }
+
+    pub(crate) fn with_statement_timeout(mut self, timeout: Duration) -> Self {
+        let timer = Box::new(StatementTimer::new(timeout));
+        statement_timeout::install(&self, &timer);
+        self.statement_timer = Some(timer);
+        self
+    }
+
+    /// Returns the timer interrupting the connection's long running
+    /// statements, if it has one.
+    pub(crate) fn statement_timer(&self) -> Option<&StatementTimer> {
+        self.statement_timer.as_deref()
+    }
+
+    pub(crate) fn with_execution_limit(mut self, permits: Arc<Semaphore>) -> Self {
+        self.execution = Some(permits);
+        self
+    }
+
+    #[cfg(feature = "error-offset")]
+    pub(crate) fn with_redacted_errors(mut self, redact: bool) -> Self {
+        self.redact_errors = redact;
+        self
+    }
+
+    /// Returns true if errors leave out the SQL of statements.
+    #[cfg(feature = "error-offset")]
+    pub(crate) fn redact_errors(&self) -> bool {
+        self.redact_errors
+    }
+
+    #[cfg(feature = "log")]
+    pub(crate) fn with_slow_query_log(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_query_log = threshold;
+        self
+    }
+
+    /// Returns how long an operation can take before it's logged as slow.
+    #[cfg(feature = "log")]
+    pub(crate) fn slow_query_log(&self) -> Option<Duration> {
+        self.slow_query_log
+    }
+
+    pub(crate) fn with_result_limits(mut self, limits: ResultLimits) -> Self {
+        self.result_limits = limits;
+        self
+    }
+
+    /// Returns the limits on how much of a query's result the helpers load.
+    pub(crate) fn result_limits(&self) -> ResultLimits {
+        self.result_limits
+    }
+
+    pub(crate) fn with_transaction_behavior(mut self, behavior: DefaultBehavior) -> Self {
+        self.transaction_behavior = behavior;
+        self
+    }
+
+    /// Returns the behavior the connection's transactions begin with unless
+    /// they choose one, as set with
+    /// [`RusqliteConnectionManager::with_transaction_behavior()`](crate::RusqliteConnectionManager::with_transaction_behavior).
+    pub fn transaction_behavior(&self) -> TransactionBehavior {
+        self.transaction_behavior.0
+    }
+
+    /// Begins a transaction with the connection's
+    /// [default behavior](Self::transaction_behavior).
+    ///
+    /// This shadows `Connection::transaction()`, which always begins a
+    /// deferred transaction, so `conn.transaction()` on a pooled connection
+    /// uses the manager's default. Use `transaction_with_behavior()` to
+    /// choose another.
+    pub fn transaction(&mut self) -> rusqlite::Result<Transaction<'_>> {
+        let behavior = self.transaction_behavior.0;
+        self.deref_mut().transaction_with_behavior(behavior)
+    }
+
+    /// Returns the semaphore that bounds how many of the pool's connections
+    /// run work from the pool helpers at once, if there is one.
+    pub(crate) fn execution_limit(&self) -> Option<&Arc<Semaphore>> {
+        self.execution.as_ref()
+    }
+
+    /// Installs the authorizer, which must come after anything else that
+    /// runs statements while the connection is opened.
+    pub(crate) fn with_authorizer(mut self, policy: &AuthorizerPolicy) -> Self {
+        self.authorizer = Some(policy.install(&self));
+        self
+    }
+
+    /// Installs the commit hook, which must come after anything it publishes
+    /// on commit. It's only installed if there's something to publish, or
+    /// `track_commits` is set, so that
+    /// [`take_committed()`](Self::take_committed) works.
+    pub(crate) fn with_commit_hook(mut self, track_commits: bool) -> Self {
+        #[cfg(feature = "audit")]
+        let track_commits = track_commits || self.audit.is_some();
+        if track_commits || self.subscriptions.is_some() {
+            self.install_commit_hook(false);
+        }
+        self
+    }
+
+    fn install_commit_hook(&mut self, committed: bool) {
+        let changes = self
+            .subscriptions
+            .as_ref()
+            .map(subscribe::Registration::pending);
+        self.commits = Some(CommitHook::install(
+            self,
+            committed,
+            changes,
+            #[cfg(feature = "audit")]
+            self.audit.clone(),
+        ));
+    }
+
+    /// Returns true if a transaction has committed on this connection since
+    /// the last call. This is always false unless the commit hook is
+    /// installed.
+    pub(crate) fn take_committed(&self) -> bool {
+        self.commits.as_ref().is_some_and(CommitHook::take)
+    }
+
+    /// Publishes the changes of transactions that have finished committing
+    /// to subscribers and the audit log.
+    pub(crate) fn settle_commits(&self) {
+        if let Some(commits) = &self.commits {
+            commits.settle(self);
+        }
+    }
+
+    /// Subscribes to changes committed to `table` through any connection
+    /// from the same manager.
+    pub(crate) fn subscribe(&self, table: &str) -> broadcast::Receiver<RowChange> {
+        self.hub
+            .as_ref()
+            .expect("connection is open")
+            .subscribe(table)
+    }
+
+    /// Returns how much the connection has been used since it was opened.
+    /// This is also reported for every open connection in the pool's
+    /// [metrics](crate::RusqliteConnectionManager::metrics).
+    pub fn stats(&self) -> ConnectionStats {
+        self.metrics.usage().stats(self.id())
+    }
+
+    /// Runs a statement with parameters bound from a [`ToParams`] value,
+    /// such as a struct deriving it, returning the number of rows changed.
+    pub fn execute_with<P>(&self, sql: &str, params: &P) -> Result<usize, crate::Error>
+    where
+        P: ToParams + ?Sized,
+    {
+        Ok(params::execute(self, sql, &params.to_params()?)?)
+    }
+
+    /// Returns an ID for this connection, unique among the connections opened
+    /// by its manager and any clones of it.
+    pub fn id(&self) -> u64 {
+        self.metrics.id()
+    }
+
+    pub(crate) fn file(&self) -> &Arc<DatabaseFile> {
+        &self.file
+    }
+
+    /// Returns the generation of the manager's reloadable settings that the
+    /// connection was opened with.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub(crate) fn metrics(&self) -> &ConnectionMetrics {
+        &self.metrics
+    }
+
+    /// Reports that the connection has been opened, which took `open`.
+    pub(crate) fn created(&self, open: Duration) {
+        self.lifecycle.created(open);
+    }
+
+    /// Records that the connection has been checked out of the pool, after
+    /// waiting for `wait` if the checkout was timed.
+    pub(crate) fn checked_out(&mut self, wait: Option<Duration>) {
+        if let Some(wait) = wait {
+            self.metrics.record_wait(wait);
+        }
+        self.lifecycle.acquired(wait);
+        if let Some(leaks) = &self.leaks {
+            leaks.checked_out(self.id(), &self.file.path);
+        }
+    }
+
+    /// Records that the connection has been returned to the pool.
+    pub(crate) fn released(&mut self) {
+        self.metrics.usage().record_checkout();
+        self.lifecycle.released();
+        if let Some(leaks) = &self.leaks {
+            leaks.released(self.id());
+        }
+    }
+
+    /// Records that the connection has just been shown to work.
+    pub(crate) fn mark_healthy(&mut self) {
+        self.healthy_at = Instant::now();
+    }
+
+    /// Returns true if the connection has been shown to work within `ttl`.
+    pub(crate) fn healthy_within(&self, ttl: Duration) -> bool {
+        self.healthy_at.elapsed() < ttl
+    }
+
+    /// Returns true if the database file this connection was opened on has
+    /// since been deleted, or replaced with a different file.
+    pub(crate) fn is_replaced(&self) -> bool {
+        self.identity
+            .is_some_and(|identity| identity.replaced(&self.file.path))
+    }
+
+    /// Closes the underlying connection, returning any error. The connection
+    /// must not be used afterwards, other than to drop it.
+    pub(crate) fn close(&mut self) -> Result<(), rusqlite::Error> {
+        match self.conn.take() {
+            Some(conn) => self.close_inner(conn),
+            None => Ok(()),
+        }
+    }
+
+    fn close_inner(&mut self, conn: Connection) -> Result<(), rusqlite::Error> {
+        let (conn, e) = match conn.close() {
+            Ok(()) => return Ok(()),
+            Err(failed) => failed,
+        };
+        self.metrics.record_close_error();
+        self.lifecycle.close_failed(&e);
+
+        // The handle outlives the callbacks' contexts, so they have to go.
+        self.uninstall(&conn);
+
+        // Dropping the connection would retry the close, and panic when that
+        // fails too. sqlite3_close_v2() instead leaves the handle to be freed
+        // once whatever is keeping it open has been finalized.
+        //
+        // Safety: the handle is forgotten by rusqlite, and never used again.
+        unsafe { sqlite3_close_v2(conn.handle()) };
+        std::mem::forget(conn);
+        Err(e)
+    }
+
+    /// Unwraps the underlying `Connection`.
+    ///
+    /// Any profiler, contention monitor, or other callback installed on the
+    /// connection is removed.
+    pub fn into_inner(mut self) -> Connection {
+        let conn = self.conn.take().expect("connection is open");
+        self.uninstall(&conn);
+        conn
+    }
+
+    /// Removes every callback installed on `conn`.
+    fn uninstall(&mut self, conn: &Connection) {
+        #[cfg(feature = "profiling")]
+        if let Some(profiler) = self.profiler.take() {
+            profiler.uninstall(conn);
+        }
+        if let Some(contention) = self.contention.take() {
+            contention.uninstall(conn);
+        }
+        if let Some(usage) = self.usage.take() {
+            usage.uninstall(conn);
+        }
+        if let Some(commits) = self.commits.take() {
+            commits.uninstall(conn);
+        }
+        if let Some(subscriptions) = self.subscriptions.take() {
+            subscriptions.uninstall(conn);
+        }
+        if let Some(collations) = self.collations.take() {
+            collations.uninstall(conn);
+        }
+        if let Some(authorizer) = self.authorizer.take() {
+            authorizer.uninstall(conn);
+        }
+        if let Some(wal_hook) = self.wal_hook.take() {
+            wal_hook.uninstall(conn);
+        }
+        #[cfg(feature = "preupdate-hook")]
+        if let Some(preupdate) = self.preupdate.take() {
+            preupdate.uninstall(conn);
+        }
+        if let Some(timer) = self.statement_timer.take() {
+            // The progress handler points at the timer until it's cleared.
+            statement_timeout::uninstall(conn);
+            drop(timer);
+        }
+    }
+}
+
+/// Closing the connection explicitly, rather than leaving it to rusqlite,
+/// means a failure to close can be reported through
+/// [`RusqliteConnectionManager::on_close_error()`](crate::RusqliteConnectionManager::on_close_error)
+/// and the pool's metrics, rather than panicking.
+impl Drop for RusqliteConnection {
+    fn drop(&mut self) {
+        if let Some(leaks) = &self.leaks {
+            leaks.released(self.id());
+        }
+        if let Some(conn) = self.conn.take() {
+            let _ = self.close_inner(conn);
+        }
+        self.file.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Deref for RusqliteConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection is open")
+    }
+}
+
+impl DerefMut for RusqliteConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("connection is open")
+    }
+}
@@ -0,0 +1,474 @@
+//! Diagnostics for lock contention between connections.
+//!
+//! SQLite allows one writer at a time, and a connection that can't get the
+//! lock it needs waits in its busy handler until the busy timeout expires,
+//! then fails with `SQLITE_BUSY`. Within a pool, it's rarely obvious which
+//! other connection was holding the lock. A [`ContentionMonitor`], installed
+//! with
+//! [`RusqliteConnectionManager::with_contention_monitor()`](crate::RusqliteConnectionManager::with_contention_monitor),
+//! replaces each connection's busy handler with one that raises
+//! [`ContentionEvent`]s for slow waits and timeouts, naming the connections
+//! that were holding a write transaction or running a statement at the time,
+//! and what they were running.
+//!
+//! Statements are tracked through `sqlite3_trace_v2()`, which requires
+//! SQLite 3.14. With the `txn-state` feature, write transactions are tracked
+//! through `sqlite3_txn_state()`, which requires SQLite 3.34. Without it, a
+//! connection is taken to have a write transaction open from the first
+//! statement in a transaction that writes, so one started with
+//! `BEGIN IMMEDIATE` only shows up once it's written something.
+
+use std::{
+    collections::HashMap,
+    ffi::CStr,
+    fmt,
+    os::raw::{c_char, c_int, c_uint, c_void},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rusqlite::{ffi, Connection};
+
+#[cfg(not(feature = "txn-state"))]
+use crate::ffi_ext::sqlite3_stmt_readonly;
+use crate::ffi_ext::{sqlite3_trace_v2, TRACE_PROFILE, TRACE_ROW, TRACE_STMT};
+#[cfg(feature = "txn-state")]
+use crate::ffi_ext::{sqlite3_txn_state, TXN_WRITE};
+#[cfg(feature = "profiling")]
+use crate::profile::{self, Profiler};
+use crate::usage::Usage;
+
+#[cfg(test)]
+mod tests;
+
+/// The delays SQLite's own busy handler sleeps for between retries, in
+/// milliseconds. The last is repeated until the timeout expires.
+const DELAYS: [u64; 12] = [1, 2, 5, 10, 15, 20, 25, 25, 25, 50, 50, 100];
+
+/// Contention seen by one of the pool's connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentionEvent {
+    /// A connection has been waiting for a lock for longer than the
+    /// configured threshold. This is raised once per wait, while the
+    /// connection is still waiting.
+    SlowWait {
+        /// The ID of the waiting connection, as returned by
+        /// [`RusqliteConnection::id()`](crate::RusqliteConnection::id).
+        connection: u64,
+
+        /// The statement the connection was running, if known.
+        statement: Option<String>,
+
+        /// How long the connection had been waiting.
+        waited: Duration,
+
+        /// The connections that may have been holding the lock.
+        holders: Vec<LockHolder>,
+    },
+
+    /// A connection gave up waiting for a lock, and its statement failed with
+    /// `SQLITE_BUSY`.
+    Busy {
+        /// The ID of the waiting connection.
+        connection: u64,
+
+        /// The statement the connection was running, if known.
+        statement: Option<String>,
+
+        /// How long the connection waited.
+        waited: Duration,
+
+        /// The number of waits in a row on this connection that have ended
+        /// in `SQLITE_BUSY`, including this one. A count that keeps climbing
+        /// means the connection is being starved.
+        consecutive: u32,
+
+        /// The connections that may have been holding the lock.
+        holders: Vec<LockHolder>,
+    },
+}
+
+/// Another of the pool's connections, which may have been holding the lock a
+/// connection was waiting for.
+///
+/// Only connections in the same pool are tracked: locks held by other pools
+/// or processes don't appear.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    /// The connection's ID.
+    pub connection: u64,
+
+    /// The statement the connection is running, if any.
+    pub running: Option<String>,
+
+    /// The last statement the connection finished running, if any.
+    pub last: Option<String>,
+
+    /// How long the connection has had a write transaction open, if it has
+    /// one.
+    pub write_transaction: Option<Duration>,
+}
+
+/// What each connection is doing, shared by every connection in the pool.
+#[derive(Debug, Default)]
+struct Registry {
+    connections: Mutex<HashMap<u64, Activity>>,
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    running: Option<String>,
+    last: Option<String>,
+    write_since: Option<Instant>,
+}
+
+impl Registry {
+    fn holders(&self, waiting: u64) -> Vec<LockHolder> {
+        let now = Instant::now();
+        let mut holders: Vec<_> = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(&id, activity)| {
+                id != waiting && (activity.running.is_some() || activity.write_since.is_some())
+            })
+            .map(|(&id, activity)| LockHolder {
+                connection: id,
+                running: activity.running.clone(),
+                last: activity.last.clone(),
+                write_transaction: activity.write_since.map(|since| now - since),
+            })
+            .collect();
+        holders.sort_by_key(|holder| holder.connection);
+        holders
+    }
+
+    fn running(&self, id: u64) -> Option<String> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .and_then(|activity| activity.running.clone())
+    }
+}
+
+type Callback = dyn Fn(&ContentionEvent) + Send + Sync;
+
+/// Reports lock contention among a pool's connections.
+#[derive(Clone)]
+pub struct ContentionMonitor {
+    callback: Arc<Callback>,
+    busy_timeout: Duration,
+    slow_wait: Option<Duration>,
+    registry: Arc<Registry>,
+    #[cfg(feature = "profiling")]
+    profiler: Option<Profiler>,
+}
+
+impl fmt::Debug for ContentionMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContentionMonitor")
+            .field("busy_timeout", &self.busy_timeout)
+            .field("slow_wait", &self.slow_wait)
+            .finish()
+    }
+}
+
+impl ContentionMonitor {
+    /// Creates a monitor that calls `callback` with each event. By default,
+    /// only [`ContentionEvent::Busy`] is raised.
+    ///
+    /// The callback runs synchronously on the waiting connection's thread,
+    /// so it should be quick.
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&ContentionEvent) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(callback),
+            busy_timeout: Duration::from_secs(5),
+            slow_wait: None,
+            registry: Arc::default(),
+            #[cfg(feature = "profiling")]
+            profiler: None,
+        }
+    }
+
+    /// Sets how long a connection waits for a lock before failing with
+    /// `SQLITE_BUSY`. This replaces the connection's busy timeout, and
+    /// defaults to 5 seconds, matching rusqlite.
+    ///
+    /// Calling `Connection::busy_timeout()` or `Connection::busy_handler()`
+    /// on a pooled connection removes the monitor's busy handler.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.busy_timeout = timeout;
+        self
+    }
+
+    /// Raises [`ContentionEvent::SlowWait`] when a connection has been
+    /// waiting for a lock for longer than `threshold`.
+    pub fn slow_wait(mut self, threshold: Duration) -> Self {
+        self.slow_wait = Some(threshold);
+        self
+    }
+
+    /// Forwards statement profiles to `profiler`, since a connection can only
+    /// have one trace callback installed.
+    #[cfg(feature = "profiling")]
+    pub(crate) fn forward_to(mut self, profiler: Profiler) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+
+    /// Installs the monitor on `conn`, which is identified by `id`. The
+    /// returned registration must be kept alive for as long as the monitor
+    /// is installed.
+    ///
+    /// Time spent waiting on locks is recorded in `usage`, as are statements
+    /// and rows if `count_statements` is set.
+    pub(crate) fn install(
+        &self,
+        conn: &Connection,
+        id: u64,
+        usage: Arc<Usage>,
+        count_statements: bool,
+    ) -> Result<Registration, rusqlite::Error> {
+        let context = Box::new(Context {
+            monitor: self.clone(),
+            id,
+            wait: Mutex::new(Wait::default()),
+            usage,
+            count_statements,
+        });
+        self.registry
+            .connections
+            .lock()
+            .unwrap()
+            .insert(id, Activity::default());
+
+        // Safety: the context is boxed, so its address is stable until the
+        // registration is dropped, which happens after the connection is
+        // closed, or after uninstall() has removed both callbacks.
+        let ptr = &*context as *const Context as *mut c_void;
+        let rc = unsafe { ffi::sqlite3_busy_handler(conn.handle(), Some(busy), ptr) };
+        if rc != ffi::SQLITE_OK {
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+        }
+        let mut mask = TRACE_STMT | TRACE_PROFILE;
+        if count_statements {
+            mask |= TRACE_ROW;
+        }
+        let rc = unsafe { sqlite3_trace_v2(conn.handle(), mask, Some(trace), ptr) };
+        if rc != ffi::SQLITE_OK {
+            unsafe { ffi::sqlite3_busy_handler(conn.handle(), None, ptr::null_mut()) };
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+        }
+        Ok(Registration { context })
+    }
+}
+
+struct Context {
+    monitor: ContentionMonitor,
+    id: u64,
+    // Only touched from the connection's own thread, but the connection can
+    // move between threads.
+    wait: Mutex<Wait>,
+    usage: Arc<Usage>,
+    count_statements: bool,
+}
+
+#[derive(Default)]
+struct Wait {
+    started: Option<Instant>,
+    slow_reported: bool,
+    gave_up: bool,
+    consecutive: u32,
+}
+
+impl Context {
+    fn report(&self, event: ContentionEvent) {
+        // Unwinding into SQLite would be undefined behaviour, so a panicking
+        // callback is ignored.
+        let _ = catch_unwind(AssertUnwindSafe(|| (self.monitor.callback)(&event)));
+    }
+
+    /// Handles a call to the busy handler, returning true to retry.
+    fn on_busy(&self, count: c_int) -> bool {
+        let now = Instant::now();
+        let mut wait = self.wait.lock().unwrap();
+        if count == 0 {
+            if !wait.gave_up {
+                wait.consecutive = 0;
+            }
+            wait.started = Some(now);
+            wait.slow_reported = false;
+            wait.gave_up = false;
+        }
+        let waited = now - *wait.started.get_or_insert(now);
+        let registry = &self.monitor.registry;
+
+        if waited >= self.monitor.busy_timeout {
+            wait.gave_up = true;
+            wait.consecutive += 1;
+            let consecutive = wait.consecutive;
+            drop(wait);
+            self.report(ContentionEvent::Busy {
+                connection: self.id,
+                statement: registry.running(self.id),
+                waited,
+                consecutive,
+                holders: registry.holders(self.id),
+            });
+            return false;
+        }
+
+        if self
+            .monitor
+            .slow_wait
+            .is_some_and(|threshold| waited >= threshold && !wait.slow_reported)
+        {
+            wait.slow_reported = true;
+            drop(wait);
+            self.report(ContentionEvent::SlowWait {
+                connection: self.id,
+                statement: registry.running(self.id),
+                waited,
+                holders: registry.holders(self.id),
+            });
+        } else {
+            drop(wait);
+        }
+
+        let delay = Duration::from_millis(DELAYS[(count as usize).min(DELAYS.len() - 1)]);
+        let slept = Instant::now();
+        thread::sleep(delay.min(self.monitor.busy_timeout - waited));
+        self.usage.record_busy_wait(slept.elapsed());
+        true
+    }
+
+    /// Records that a statement has started or finished running.
+    unsafe fn on_trace(&self, event: c_uint, stmt: *mut ffi::sqlite3_stmt) {
+        let sql = ffi::sqlite3_sql(stmt);
+        let sql = if sql.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(sql).to_string_lossy().into_owned())
+        };
+
+        let mut connections = self.monitor.registry.connections.lock().unwrap();
+        let activity = connections.entry(self.id).or_default();
+        if event == TRACE_STMT {
+            activity.running = sql;
+        } else {
+            activity.running = None;
+            activity.last = sql;
+            if in_write_transaction(stmt, activity.write_since.is_some()) {
+                activity.write_since.get_or_insert_with(Instant::now);
+            } else {
+                activity.write_since = None;
+            }
+        }
+    }
+}
+
+/// Returns whether the connection that just finished running `stmt` has a
+/// write transaction open.
+#[cfg(feature = "txn-state")]
+unsafe fn in_write_transaction(stmt: *mut ffi::sqlite3_stmt, _writing: bool) -> bool {
+    sqlite3_txn_state(ffi::sqlite3_db_handle(stmt), ptr::null()) == TXN_WRITE
+}
+
+/// Returns whether the connection that just finished running `stmt` has a
+/// write transaction open, given whether it had one before: that's so from
+/// the first statement that writes until the transaction ends.
+#[cfg(not(feature = "txn-state"))]
+unsafe fn in_write_transaction(stmt: *mut ffi::sqlite3_stmt, writing: bool) -> bool {
+    ffi::sqlite3_get_autocommit(ffi::sqlite3_db_handle(stmt)) == 0
+        && (writing || sqlite3_stmt_readonly(stmt) == 0)
+}
+
+unsafe extern "C" fn busy(context: *mut c_void, count: c_int) -> c_int {
+    let context = &*(context as *const Context);
+    catch_unwind(AssertUnwindSafe(|| context.on_busy(count))).unwrap_or(false) as c_int
+}
+
+unsafe extern "C" fn trace(
+    event: c_uint,
+    context: *mut c_void,
+    stmt: *mut c_void,
+    extra: *mut c_void,
+) -> c_int {
+    let monitor = &*(context as *const Context);
+    if monitor.count_statements {
+        monitor.usage.record_trace(event, stmt, extra);
+    }
+    if event == TRACE_ROW {
+        return 0;
+    }
+
+    // Statements run by triggers are reported with their SQL as a comment,
+    // and don't change what the connection as a whole is running.
+    let trigger = event == TRACE_STMT && {
+        let text = extra as *const c_char;
+        !text.is_null() && CStr::from_ptr(text).to_bytes().starts_with(b"--")
+    };
+    if !trigger {
+        let _ = catch_unwind(AssertUnwindSafe(|| {
+            monitor.on_trace(event, stmt as *mut ffi::sqlite3_stmt)
+        }));
+    }
+
+    #[cfg(feature = "profiling")]
+    if let Some(profiler) = &monitor.monitor.profiler {
+        profile::trace(
+            event,
+            profiler as *const Profiler as *mut c_void,
+            stmt,
+            extra,
+        );
+    }
+    0
+}
+
+/// Keeps a monitor's context alive while it is installed on a connection,
+/// and removes the connection from the monitor when dropped.
+pub(crate) struct Registration {
+    context: Box<Context>,
+}
+
+impl fmt::Debug for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registration")
+            .field("id", &self.context.id)
+            .finish()
+    }
+}
+
+impl Registration {
+    /// Removes the monitor from `conn`, so the connection can outlive the
+    /// registration. The connection is left with a plain busy timeout.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: neither call can fail on an open handle.
+        unsafe {
+            sqlite3_trace_v2(conn.handle(), 0, None, ptr::null_mut());
+            ffi::sqlite3_busy_handler(conn.handle(), None, ptr::null_mut());
+        }
+        let _ = conn.busy_timeout(self.context.monitor.busy_timeout);
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.context
+            .monitor
+            .registry
+            .connections
+            .lock()
+            .unwrap()
+            .remove(&self.context.id);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rusqlite::{ErrorCode, NO_PARAMS};
+
+use super::*;
+use crate::{tests::TempDir, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_holders() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let monitor = ContentionMonitor::new({
+        let events = events.clone();
+        move |event| events.lock().unwrap().push(event.clone())
+    })
+    .busy_timeout(Duration::from_millis(200))
+    .slow_wait(Duration::from_millis(20));
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(
+            RusqliteConnectionManager::new(temp.file("contention.db"))
+                .with_contention_monitor(monitor),
+        )
+        .await?;
+
+    let holder = pool.get().await?;
+    holder.execute_batch("CREATE TABLE t (a); BEGIN IMMEDIATE;")?;
+    holder.execute("INSERT INTO t VALUES (1)", NO_PARAMS)?;
+
+    let waiter = pool.get().await?;
+    for _ in 0..2 {
+        match waiter.execute("INSERT INTO t VALUES (2)", NO_PARAMS) {
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::DatabaseBusy => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    let seen = events.lock().unwrap().clone();
+    let check_holders = |holders: &[LockHolder]| {
+        assert_eq!(holders.len(), 1);
+        assert_eq!(holders[0].connection, holder.id());
+        assert_eq!(holders[0].running, None);
+        assert_eq!(holders[0].last.as_deref(), Some("INSERT INTO t VALUES (1)"));
+        assert!(holders[0].write_transaction.is_some());
+    };
+
+    // Each failed write raises a slow wait, then gives up.
+    assert_eq!(seen.len(), 4, "{:?}", seen);
+    for (pair, expected) in seen.chunks(2).zip(1..) {
+        match &pair[0] {
+            ContentionEvent::SlowWait {
+                connection,
+                statement,
+                holders,
+                ..
+            } => {
+                assert_eq!(*connection, waiter.id());
+                assert_eq!(statement.as_deref(), Some("INSERT INTO t VALUES (2)"));
+                check_holders(holders);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        match &pair[1] {
+            ContentionEvent::Busy {
+                connection,
+                waited,
+                consecutive,
+                holders,
+                ..
+            } => {
+                assert_eq!(*connection, waiter.id());
+                assert!(*waited >= Duration::from_millis(200));
+                assert_eq!(*consecutive, expected);
+                check_holders(holders);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    // Once the lock is released, writes go through without any events.
+    holder.execute_batch("COMMIT")?;
+    waiter.execute("INSERT INTO t VALUES (2)", NO_PARAMS)?;
+    assert_eq!(events.lock().unwrap().len(), 4);
+    Ok(())
+}
@@ -0,0 +1,175 @@
+//! CSV import and export.
+
+use std::io::{Read, Write};
+
+use rusqlite::{types::ValueRef, Connection, NO_PARAMS};
+
+use crate::{sql, Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// Options for [`PoolExt::import_csv()`](crate::PoolExt::import_csv).
+#[derive(Debug, Clone)]
+pub struct CsvImportOptions {
+    pub(crate) has_headers: bool,
+    pub(crate) columns: Option<Vec<String>>,
+    pub(crate) delimiter: u8,
+    pub(crate) batch_size: usize,
+    pub(crate) empty_as_null: bool,
+}
+
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        Self {
+            has_headers: true,
+            columns: None,
+            delimiter: b',',
+            batch_size: 1000,
+            empty_as_null: false,
+        }
+    }
+}
+
+impl CsvImportOptions {
+    /// Creates the default options: the first record is a header naming the
+    /// columns, fields are separated by commas, and rows are committed in
+    /// batches of 1000.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the first record is a header. If it is, and no columns
+    /// are given explicitly, the header names the columns to insert into.
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Sets the columns that each record's fields are inserted into, in
+    /// order. This is required if there is no header.
+    pub fn columns<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.columns = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets the field delimiter.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets how many rows are inserted in each transaction. Each batch is
+    /// committed before the next begins, so a failed import leaves every
+    /// earlier batch in place.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Inserts empty fields as `NULL`, rather than as empty strings.
+    pub fn empty_as_null(mut self, empty_as_null: bool) -> Self {
+        self.empty_as_null = empty_as_null;
+        self
+    }
+}
+
+/// Writes the results of `query` to `writer` as CSV, with a header of the
+/// column names, returning the number of rows written.
+///
+/// `NULL`s are written as empty fields, and blobs as their raw bytes.
+pub(crate) fn export<W>(conn: &Connection, query: &str, writer: W) -> Result<u64, Error>
+where
+    W: Write,
+{
+    let mut stmt = conn.prepare(query)?;
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(stmt.column_names())?;
+
+    let columns = stmt.column_count();
+    let mut rows = stmt.query(NO_PARAMS)?;
+    let mut count = 0;
+    let mut buf = Vec::new();
+    while let Some(row) = rows.next()? {
+        for i in 0..columns {
+            buf.clear();
+            match row.get_raw(i) {
+                ValueRef::Null => {}
+                ValueRef::Integer(v) => buf.extend(v.to_string().as_bytes()),
+                ValueRef::Real(v) => buf.extend(v.to_string().as_bytes()),
+                ValueRef::Text(v) | ValueRef::Blob(v) => buf.extend(v),
+            }
+            csv.write_field(&buf)?;
+        }
+        csv.write_record(None::<&[u8]>)?;
+        count += 1;
+    }
+
+    csv.flush()?;
+    Ok(count)
+}
+
+/// Inserts each CSV record in `reader` into `table`, returning the number of
+/// rows inserted.
+pub(crate) fn import<R>(
+    conn: &mut RusqliteConnection,
+    table: &str,
+    reader: R,
+    options: &CsvImportOptions,
+) -> Result<u64, Error>
+where
+    R: Read,
+{
+    let mut csv = csv::ReaderBuilder::new()
+        .has_headers(options.has_headers)
+        .delimiter(options.delimiter)
+        .from_reader(reader);
+
+    let columns = match &options.columns {
+        Some(columns) => columns.clone(),
+        None if options.has_headers => csv.headers()?.iter().map(String::from).collect(),
+        None => return Err(Error::CsvColumnsRequired),
+    };
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        sql::quote_identifier(table),
+        columns
+            .iter()
+            .map(|column| sql::quote_identifier(column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        sql::placeholders(columns.len())
+    );
+
+    let mut records = csv.into_records();
+    let mut count = 0;
+    loop {
+        let tx = conn.transaction()?;
+        let mut batch = 0;
+        {
+            let mut stmt = tx.prepare_cached(&sql)?;
+            while batch < options.batch_size {
+                let record = match records.next() {
+                    Some(record) => record?,
+                    None => break,
+                };
+                let values = record.iter().map(|field| match field {
+                    "" if options.empty_as_null => None,
+                    field => Some(field),
+                });
+                stmt.execute(values)?;
+                batch += 1;
+            }
+        }
+        tx.commit()?;
+
+        count += batch as u64;
+        if batch < options.batch_size {
+            return Ok(count);
+        }
+    }
+}
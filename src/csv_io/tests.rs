@@ -0,0 +1,106 @@
+use rusqlite::NO_PARAMS;
+
+use crate::{
+    tests::{pool, TempDir},
+    CsvImportOptions, Error, PoolExt, RusqliteConnectionManager,
+};
+
+const SETUP: &str = "CREATE TABLE t (id INTEGER, name TEXT, score REAL)";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn round_trip() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("csv.db")),
+        10,
+        SETUP,
+    )
+    .await?;
+
+    let input = "id,name,score\n1,alice,2.5\n2,\"bob, jr\",\n3,carol,10\n";
+    let imported = pool
+        .import_csv(
+            "t",
+            input.as_bytes(),
+            CsvImportOptions::new().batch_size(2).empty_as_null(true),
+        )
+        .await?;
+    assert_eq!(imported, 3);
+
+    let mut output = Vec::new();
+    let exported = pool
+        .export_csv("SELECT id, name, score FROM t ORDER BY id", &mut output)
+        .await?;
+    assert_eq!(exported, 3);
+    assert_eq!(
+        String::from_utf8(output)?,
+        "id,name,score\n1,alice,2.5\n2,\"bob, jr\",\n3,carol,10\n"
+    );
+
+    let nulls: i64 = pool.get().await?.query_row(
+        "SELECT COUNT(*) FROM t WHERE score IS NULL",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    assert_eq!(nulls, 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn headerless() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("csv.db")),
+        10,
+        SETUP,
+    )
+    .await?;
+
+    let result = pool
+        .import_csv(
+            "t",
+            "1\talice\n".as_bytes(),
+            CsvImportOptions::new().has_headers(false),
+        )
+        .await;
+    assert!(matches!(result, Err(Error::CsvColumnsRequired)));
+
+    let imported = pool
+        .import_csv(
+            "t",
+            "1\talice\n2\tbob\n".as_bytes(),
+            CsvImportOptions::new()
+                .has_headers(false)
+                .delimiter(b'\t')
+                .columns(vec!["id", "name"]),
+        )
+        .await?;
+    assert_eq!(imported, 2);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn batches() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("csv.db")),
+        10,
+        SETUP,
+    )
+    .await?;
+
+    // The malformed third record fails the second batch, but the first has
+    // already been committed.
+    let input = "id,name\n1,a\n2,b\n3,c,extra\n4,d\n";
+    let result = pool
+        .import_csv("t", input.as_bytes(), CsvImportOptions::new().batch_size(2))
+        .await;
+    assert!(matches!(result, Err(Error::Csv(_))));
+
+    let count: i64 = pool
+        .get()
+        .await?
+        .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(count, 2);
+    Ok(())
+}
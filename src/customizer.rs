@@ -0,0 +1,104 @@
+//! A `bb8::CustomizeConnection` that applies pragmas to new connections.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rusqlite::types::Value;
+
+use crate::{task, Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// Applies a list of pragmas and connection settings to each connection the
+/// pool opens, in the order they were added.
+///
+/// Pass it to `bb8::Builder::connection_customizer()` alongside a
+/// [`RusqliteConnectionManager`](crate::RusqliteConnectionManager). The
+/// customizer runs after the manager has opened the connection and applied
+/// its own options, so settings here take precedence. If a pragma fails, the
+/// error goes to the pool's error sink, and the connection is discarded.
+///
+/// Persistent settings, such as `journal_mode = WAL`, are harmless to repeat
+/// on every connection, but only need to be applied once.
+///
+/// A customizer can also be given to the manager itself, with
+/// [`RusqliteConnectionManager::with_pragmas()`](crate::RusqliteConnectionManager::with_pragmas),
+/// in which case it can be replaced while the pool is running.
+#[derive(Debug, Clone, Default)]
+pub struct PragmaCustomizer {
+    settings: Vec<Setting>,
+}
+
+#[derive(Debug, Clone)]
+enum Setting {
+    Pragma(String, Value),
+    BusyTimeout(Duration),
+    StatementCacheCapacity(usize),
+}
+
+impl PragmaCustomizer {
+    /// Creates a customizer that doesn't change anything yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `PRAGMA name = value`. Text values are quoted, so
+    /// `.pragma("journal_mode", String::from("WAL"))` runs
+    /// `PRAGMA journal_mode = 'WAL'`, which SQLite accepts.
+    pub fn pragma<V>(mut self, name: &str, value: V) -> Self
+    where
+        V: Into<Value>,
+    {
+        self.settings
+            .push(Setting::Pragma(name.into(), value.into()));
+        self
+    }
+
+    /// Sets how long statements wait for locks held by other connections
+    /// before failing with `SQLITE_BUSY`.
+    pub fn busy_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.push(Setting::BusyTimeout(timeout));
+        self
+    }
+
+    /// Sets how many prepared statements each connection caches.
+    pub fn statement_cache_capacity(mut self, capacity: usize) -> Self {
+        self.settings
+            .push(Setting::StatementCacheCapacity(capacity));
+        self
+    }
+
+    pub(crate) fn apply(&self, conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        for setting in &self.settings {
+            match setting {
+                Setting::Pragma(name, value) => conn.pragma_update(None, name, value)?,
+                Setting::BusyTimeout(timeout) => conn.busy_timeout(*timeout)?,
+                Setting::StatementCacheCapacity(capacity) => {
+                    conn.set_prepared_statement_cache_capacity(*capacity)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the pragmas this sets, in order, with the busy timeout as the
+    /// `busy_timeout` pragma it's equivalent to.
+    pub(crate) fn pragmas(&self) -> impl Iterator<Item = (&str, Value)> + '_ {
+        self.settings.iter().filter_map(|setting| match setting {
+            Setting::Pragma(name, value) => Some((name.as_str(), value.clone())),
+            Setting::BusyTimeout(timeout) => Some((
+                "busy_timeout",
+                Value::Integer(timeout.as_millis().min(i64::MAX as u128) as i64),
+            )),
+            Setting::StatementCacheCapacity(_) => None,
+        })
+    }
+}
+
+#[async_trait]
+impl bb8::CustomizeConnection<RusqliteConnection, Error> for PragmaCustomizer {
+    async fn on_acquire(&self, conn: &mut RusqliteConnection) -> Result<(), Error> {
+        Ok(task::block_in_place(|| self.apply(conn))?)
+    }
+}
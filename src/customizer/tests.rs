@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{tests::TempDir, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn applies_pragmas() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .connection_customizer(Box::new(
+            PragmaCustomizer::new()
+                .pragma("journal_mode", String::from("WAL"))
+                .pragma("foreign_keys", true)
+                .pragma("cache_size", -4096)
+                .busy_timeout(Duration::from_millis(250)),
+        ))
+        .build(RusqliteConnectionManager::new(temp.file("customizer.db")))
+        .await?;
+
+    let conn = pool.get().await?;
+    let pragma = |name: &str| -> rusqlite::Result<String> {
+        conn.query_row(&format!("PRAGMA {}", name), NO_PARAMS, |row| {
+            row.get::<_, rusqlite::types::Value>(0)
+                .map(|value| match value {
+                    rusqlite::types::Value::Text(text) => text,
+                    rusqlite::types::Value::Integer(n) => n.to_string(),
+                    other => format!("{:?}", other),
+                })
+        })
+    };
+    assert_eq!(pragma("journal_mode")?, "wal");
+    assert_eq!(pragma("foreign_keys")?, "1");
+    assert_eq!(pragma("cache_size")?, "-4096");
+    assert_eq!(pragma("busy_timeout")?, "250");
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bad_pragma() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let result = bb8::Pool::builder()
+        .min_idle(Some(1))
+        .connection_timeout(Duration::from_millis(500))
+        .connection_customizer(Box::new(
+            PragmaCustomizer::new().pragma("journal_mode", rusqlite::types::Value::Blob(vec![])),
+        ))
+        .build(RusqliteConnectionManager::new(temp.file("customizer.db")))
+        .await;
+    assert!(result.is_err());
+    Ok(())
+}
@@ -0,0 +1,325 @@
+//! Deadlines and cancellation for work run through [`PoolExt`](crate::PoolExt).
+//!
+//! An [`ExecutionContext`] is attached to a future with
+//! [`ExecutionContext::scope()`], and applies to every helper that future
+//! calls: waiting for a connection stops when the deadline passes or the
+//! token is cancelled, and so does any statement running on one, through a
+//! progress handler that interrupts it. A context can also hold the helpers
+//! to a [`RateLimiter`](crate::RateLimiter).
+
+use std::{
+    fmt,
+    future::{poll_fn, Future},
+    os::raw::{c_int, c_void},
+    pin::{pin, Pin},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+use rusqlite::{ffi, ErrorCode};
+use tokio::sync::Notify;
+
+use crate::{statement_timeout::StatementTimer, Error, RateLimiter, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// The number of virtual machine instructions between checks of the
+/// deadline. SQLite runs several million a second, so this checks well
+/// within a millisecond.
+pub(crate) const PROGRESS_INTERVAL: c_int = 1000;
+
+tokio::task_local! {
+    static CONTEXT: ExecutionContext;
+}
+
+/// A flag that cancels the work of every [`ExecutionContext`] it's attached
+/// to. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<Token>);
+
+#[derive(Debug, Default)]
+struct Token {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    /// Creates a token that hasn't been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token. Statements running under it are interrupted, and
+    /// waits for connections stop, failing with [`Error::Cancelled`].
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    /// Returns true if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    async fn cancelled(&self) {
+        loop {
+            // The notification is registered before checking the flag, so a
+            // cancel() in between isn't missed.
+            let notified = self.0.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// A deadline and cancellation tokens, applied to the future given to
+/// [`scope()`](Self::scope).
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionContext {
+    deadline: Option<Instant>,
+    tokens: Vec<CancellationToken>,
+    limiter: Option<RateLimiter>,
+    tag: Option<Arc<str>>,
+}
+
+impl ExecutionContext {
+    /// Creates a context without a deadline or cancellation token.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails work still running at `deadline` with
+    /// [`Error::DeadlineExceeded`].
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets the deadline to `timeout` from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Fails work still running when `token` is cancelled with
+    /// [`Error::Cancelled`].
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.tokens.push(token);
+        self
+    }
+
+    /// Holds each operation to the limits of `limiter`, waiting for a token
+    /// before checking out a connection. The wait counts towards the
+    /// deadline, and stops if the context is cancelled.
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Tags the work, for the limits set with
+    /// [`RateLimiter::limit_tag()`].
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Runs `f` with the context applied to the pool helpers it calls.
+    ///
+    /// Scopes nest: within another scope, the earlier of the two deadlines
+    /// applies, and cancelling either scope's tokens cancels the work. The
+    /// innermost rate limiter and tag apply.
+    pub async fn scope<F>(self, f: F) -> F::Output
+    where
+        F: Future,
+    {
+        let context = match current() {
+            Some(outer) => self.within(outer),
+            None => self,
+        };
+        CONTEXT.scope(context, f).await
+    }
+
+    fn within(mut self, outer: Self) -> Self {
+        self.deadline = match (self.deadline, outer.deadline) {
+            (Some(inner), Some(outer)) => Some(inner.min(outer)),
+            (inner, outer) => inner.or(outer),
+        };
+        self.tokens.extend(outer.tokens);
+        self.limiter = self.limiter.or(outer.limiter);
+        self.tag = self.tag.or(outer.tag);
+        self
+    }
+
+    /// Waits for the rate limiter, if there is one, to let an operation go
+    /// ahead.
+    pub(crate) async fn throttle(&self) -> Result<(), Error> {
+        match &self.limiter {
+            Some(limiter) => {
+                self.wait(async {
+                    limiter.acquire(self.tag.as_deref()).await;
+                    Ok(())
+                })
+                .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the error work in this context should fail with, if it's been
+    /// cancelled or its deadline has passed.
+    fn expired(&self) -> Option<Error> {
+        if self.tokens.iter().any(CancellationToken::is_cancelled) {
+            Some(Error::Cancelled)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(Error::DeadlineExceeded)
+        } else {
+            None
+        }
+    }
+
+    /// Runs `f`, stopping it early if the context expires first.
+    pub(crate) async fn wait<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        if let Some(e) = self.expired() {
+            return Err(e);
+        }
+
+        let mut f = pin!(f);
+        let mut cancelled: Vec<Pin<Box<dyn Future<Output = ()> + Send + '_>>> = self
+            .tokens
+            .iter()
+            .map(|token| Box::pin(token.cancelled()) as Pin<Box<dyn Future<Output = ()> + Send>>)
+            .collect();
+        let raced = poll_fn(|cx| {
+            if let Poll::Ready(result) = f.as_mut().poll(cx) {
+                return Poll::Ready(result);
+            }
+            for token in &mut cancelled {
+                if token.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Err(Error::Cancelled));
+                }
+            }
+            Poll::Pending
+        });
+
+        match self.deadline {
+            Some(deadline) => {
+                tokio::time::timeout_at(tokio::time::Instant::from_std(deadline), raced)
+                    .await
+                    .unwrap_or(Err(Error::DeadlineExceeded))
+            }
+            None => raced.await,
+        }
+    }
+
+    /// Runs `f` on `conn`, interrupting any statement still running when the
+    /// context expires.
+    pub(crate) fn run<F, T>(&self, conn: &mut RusqliteConnection, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut RusqliteConnection) -> Result<T, Error>,
+    {
+        if let Some(e) = self.expired() {
+            return Err(e);
+        }
+
+        let handler = ProgressHandler::install(conn, self);
+        let result = f(conn);
+        drop(handler);
+        match result {
+            Err(Error::Rusqlite(rusqlite::Error::SqliteFailure(e, message)))
+                if e.code == ErrorCode::OperationInterrupted =>
+            {
+                Err(self
+                    .expired()
+                    .unwrap_or(Error::Rusqlite(rusqlite::Error::SqliteFailure(e, message))))
+            }
+            result => result,
+        }
+    }
+}
+
+/// Returns the context of the current task, if it's in a scope.
+pub(crate) fn current() -> Option<ExecutionContext> {
+    CONTEXT.try_with(Clone::clone).ok()
+}
+
+/// A progress handler installed on a connection, which is removed when this
+/// is dropped, even if the work it was guarding panics. The connection's
+/// statement timer, if it has one, is checked by this handler while it's
+/// installed, and reinstalled once it's removed.
+struct ProgressHandler {
+    db: *mut ffi::sqlite3,
+    guard: Box<Guard>,
+}
+
+struct Guard {
+    context: ExecutionContext,
+    timer: Option<*const StatementTimer>,
+}
+
+impl fmt::Debug for ProgressHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressHandler")
+            .field("context", &self.guard.context)
+            .field("timer", &self.guard.timer.is_some())
+            .finish()
+    }
+}
+
+impl ProgressHandler {
+    /// Installs the handler on `conn`, which must outlive it.
+    fn install(conn: &RusqliteConnection, context: &ExecutionContext) -> Self {
+        let guard = Box::new(Guard {
+            context: context.clone(),
+            timer: conn
+                .statement_timer()
+                .map(|timer| timer as *const StatementTimer),
+        });
+        // Safety: the guard is boxed, so its address is stable until the
+        // handler is dropped, which removes it first.
+        let db = unsafe { conn.handle() };
+        unsafe {
+            ffi::sqlite3_progress_handler(
+                db,
+                PROGRESS_INTERVAL,
+                Some(progress),
+                &*guard as *const Guard as *mut c_void,
+            );
+        }
+        Self { db, guard }
+    }
+}
+
+impl Drop for ProgressHandler {
+    fn drop(&mut self) {
+        // Safety: the connection outlives the handler, and so does its
+        // timer, and setting the handler can't fail on an open handle.
+        unsafe {
+            match self.guard.timer {
+                Some(timer) => (*timer).install(self.db),
+                None => ffi::sqlite3_progress_handler(self.db, 0, None, ptr::null_mut()),
+            }
+        }
+    }
+}
+
+unsafe extern "C" fn progress(guard: *mut c_void) -> c_int {
+    let guard = &*(guard as *const Guard);
+    let timed_out = match guard.timer {
+        Some(timer) => (*timer).exceeded(),
+        None => false,
+    };
+    (guard.context.expired().is_some() || timed_out) as c_int
+}
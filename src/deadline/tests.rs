@@ -0,0 +1,155 @@
+use std::time::{Duration, Instant};
+
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    PoolExt, RusqliteConnectionManager,
+};
+
+/// A query that never finishes on its own.
+const FOREVER: &str =
+    "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn interrupts_statements() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("deadline.db")),
+        1,
+        "",
+    )
+    .await?;
+
+    let started = Instant::now();
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_millis(100))
+        .scope(pool.query_rows_dynamic(FOREVER, Vec::<i64>::new()))
+        .await;
+    assert!(
+        matches!(result, Err(Error::DeadlineExceeded)),
+        "{:?}",
+        result
+    );
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // The progress handler doesn't outlive the scope.
+    let rows = pool
+        .query_rows_dynamic("SELECT 1", Vec::<i64>::new())
+        .await?;
+    assert_eq!(rows.len(), 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_waiting_for_connections() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("deadline.db")),
+        1,
+        "",
+    )
+    .await?;
+    let held = pool.get().await?;
+
+    let started = Instant::now();
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_millis(50))
+        .scope(pool.schema_version())
+        .await;
+    assert!(
+        matches!(result, Err(Error::DeadlineExceeded)),
+        "{:?}",
+        result
+    );
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_millis(50))
+        .scope(pool.acquire())
+        .await;
+    assert!(matches!(result, Err(Error::DeadlineExceeded)));
+    drop(held);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancellation() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("deadline.db")),
+        1,
+        "",
+    )
+    .await?;
+    let token = CancellationToken::new();
+
+    let canceller = tokio::spawn({
+        let token = token.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            token.cancel();
+        }
+    });
+    let result = ExecutionContext::new()
+        .with_cancellation(token.clone())
+        .scope(pool.query_rows_dynamic(FOREVER, Vec::<i64>::new()))
+        .await;
+    assert!(matches!(result, Err(Error::Cancelled)), "{:?}", result);
+    canceller.await?;
+
+    // Waits for a connection are cancelled too, as is anything started
+    // after the token was cancelled.
+    let held = pool.get().await?;
+    let token = CancellationToken::new();
+    let waiting = tokio::spawn({
+        let pool = pool.clone();
+        let token = token.clone();
+        async move {
+            ExecutionContext::new()
+                .with_cancellation(token)
+                .scope(pool.schema_version())
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    token.cancel();
+    assert!(matches!(waiting.await?, Err(Error::Cancelled)));
+    drop(held);
+
+    let result = ExecutionContext::new()
+        .with_cancellation(token)
+        .scope(pool.schema_version())
+        .await;
+    assert!(matches!(result, Err(Error::Cancelled)));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nested_scopes() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("deadline.db")),
+        1,
+        "",
+    )
+    .await?;
+
+    // The outer deadline still applies within a more lenient inner scope.
+    let started = Instant::now();
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_millis(100))
+        .scope(async {
+            ExecutionContext::new()
+                .with_timeout(Duration::from_secs(60))
+                .scope(pool.query_rows_dynamic(FOREVER, Vec::<i64>::new()))
+                .await
+        })
+        .await;
+    assert!(matches!(result, Err(Error::DeadlineExceeded)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // Outside any scope, nothing is applied.
+    assert!(current().is_none());
+    assert_eq!(pool.schema_version().await?, 0);
+    Ok(())
+}
@@ -0,0 +1,261 @@
+//! An API mirroring deadpool-sqlite's, on top of bb8, for code that's moving
+//! between the two pools, or that supports both.
+//!
+//! [`Config`], [`Pool`], and [`Object::interact()`] are named and shaped as
+//! they are in deadpool-sqlite, so data access code written against it only
+//! needs its imports changed.
+//!
+//! Unlike deadpool-sqlite, the closure runs within
+//! `task::block_in_place()`, as [`PoolExt`](crate::PoolExt)'s helpers
+//! do, rather than on a blocking thread, so it needs the multi-threaded
+//! runtime. Checkouts and closures are bounded by the task's
+//! [`ExecutionContext`](crate::ExecutionContext) as the helpers are. To configure connections with everything
+//! [`RusqliteConnectionManager`] supports, build the bb8 pool as usual and
+//! convert it with [`Pool::from()`].
+
+use std::{
+    any::Any,
+    convert::TryFrom,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use rusqlite::Connection;
+use tokio::sync::Mutex;
+
+use crate::{deadline, task, Error, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// The async runtime a pool is created for. Only Tokio is supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    /// Tokio 1.x.
+    Tokio1,
+}
+
+/// How long to wait for a pool's connections. `None` waits indefinitely.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// How long [`Pool::get()`] waits for a connection, including opening
+    /// one.
+    pub wait: Option<Duration>,
+    /// Accepted for compatibility, but bb8 opens connections within the
+    /// `wait` timeout.
+    pub create: Option<Duration>,
+    /// Accepted for compatibility, but connections are checked as the
+    /// manager is configured to.
+    pub recycle: Option<Duration>,
+}
+
+/// The size and timeouts of a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// The most connections the pool opens. Defaults to four per CPU.
+    pub max_size: usize,
+    /// How long to wait for connections.
+    pub timeouts: Timeouts,
+}
+
+impl PoolConfig {
+    /// Creates a configuration for a pool of up to `max_size` connections.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            timeouts: Timeouts::default(),
+        }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+        Self::new(cpus * 4)
+    }
+}
+
+/// The configuration of a pool of connections to the database at `path`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// The path to the database.
+    pub path: PathBuf,
+    /// The pool's size and timeouts, or the defaults if `None`.
+    pub pool: Option<PoolConfig>,
+}
+
+impl Config {
+    /// Creates a configuration for a default pool of connections to `path`.
+    pub fn new<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self {
+            path: path.into(),
+            pool: None,
+        }
+    }
+
+    /// Returns the pool's size and timeouts.
+    pub fn get_pool_config(&self) -> PoolConfig {
+        self.pool.unwrap_or_default()
+    }
+
+    /// Creates the pool. Connections aren't opened until they're needed.
+    pub fn create_pool(&self, runtime: Runtime) -> Result<Pool, CreatePoolError> {
+        let Runtime::Tokio1 = runtime;
+        let config = self.get_pool_config();
+        let max_size = match u32::try_from(config.max_size) {
+            Ok(0) | Err(_) => return Err(CreatePoolError::MaxSize(config.max_size)),
+            Ok(max_size) => max_size,
+        };
+        let pool = bb8::Pool::builder()
+            .max_size(max_size)
+            .connection_timeout(config.timeouts.wait.unwrap_or(Duration::MAX))
+            .build_unchecked(RusqliteConnectionManager::new(&self.path));
+        Ok(Pool { pool })
+    }
+}
+
+/// An error creating a [`Pool`].
+#[derive(thiserror::Error, Debug)]
+pub enum CreatePoolError {
+    /// The pool's `max_size` was zero, or too large for bb8.
+    #[error("invalid pool size {0}")]
+    MaxSize(usize),
+}
+
+/// A pool of connections, which can be cloned cheaply.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+}
+
+impl From<bb8::Pool<RusqliteConnectionManager>> for Pool {
+    /// Wraps a bb8 pool, sharing its connections. Connections are waited for
+    /// as long as the bb8 pool's connection timeout.
+    fn from(pool: bb8::Pool<RusqliteConnectionManager>) -> Self {
+        Self { pool }
+    }
+}
+
+/// The number of connections in a [`Pool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status {
+    /// The number of connections open.
+    pub size: usize,
+    /// The number of open connections that aren't checked out.
+    pub available: usize,
+}
+
+impl Pool {
+    /// Checks out a connection, or returns [`PoolError::Timeout`] if none
+    /// becomes available in time.
+    pub async fn get(&self) -> Result<Object, PoolError> {
+        let context = deadline::current();
+        let waiting = Instant::now();
+        let conn = async { Ok(self.pool.get_owned().await?) };
+        let mut conn = match &context {
+            Some(context) => context.wait(conn).await,
+            None => conn.await,
+        }
+        .map_err(|e| match e {
+            Error::TimedOut => PoolError::Timeout(TimeoutType::Wait),
+            e => PoolError::Backend(e),
+        })?;
+        conn.checked_out(Some(waiting.elapsed()));
+        Ok(Object {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Returns the number of connections in the pool.
+    pub fn status(&self) -> Status {
+        let state = self.pool.state();
+        Status {
+            size: state.connections as usize,
+            available: state.idle_connections as usize,
+        }
+    }
+
+    /// Returns the bb8 pool, for code that uses [`PoolExt`](crate::PoolExt)
+    /// alongside this API.
+    pub fn bb8(&self) -> &bb8::Pool<RusqliteConnectionManager> {
+        &self.pool
+    }
+}
+
+/// The kind of timeout that a [`PoolError::Timeout`] came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutType {
+    /// Waiting for a connection. bb8 reports every timeout as this.
+    Wait,
+    /// Opening a connection.
+    Create,
+    /// Checking a connection before it was reused.
+    Recycle,
+}
+
+/// An error checking out a connection.
+#[derive(thiserror::Error, Debug)]
+pub enum PoolError {
+    /// No connection became available in time.
+    #[error("timed out waiting for a connection")]
+    Timeout(TimeoutType),
+    /// Opening a connection failed, or the checkout was cancelled by an
+    /// [`ExecutionContext`](crate::ExecutionContext).
+    #[error("error checking out a connection")]
+    Backend(#[source] Error),
+}
+
+/// An error running a closure with [`Object::interact()`].
+#[derive(thiserror::Error, Debug)]
+pub enum InteractError {
+    /// The closure panicked.
+    #[error("interact() panicked")]
+    Panic(Box<dyn Any + Send + 'static>),
+    /// The closure wasn't run, since the task's
+    /// [`ExecutionContext`](crate::ExecutionContext) had expired or been
+    /// cancelled.
+    #[error("interact() was aborted")]
+    Aborted,
+}
+
+/// A connection checked out of a [`Pool`], which returns to it when dropped.
+#[derive(Debug)]
+pub struct Object {
+    conn: Mutex<bb8::PooledConnection<'static, RusqliteConnectionManager>>,
+}
+
+impl Object {
+    /// Runs `f` with the connection, without starving the runtime. Calls on
+    /// the same object wait for each other.
+    ///
+    /// If the task is in an [`ExecutionContext`](crate::ExecutionContext),
+    /// `f` isn't run once the context has expired, and statements it's still
+    /// running when the context expires are interrupted, failing with
+    /// `SQLITE_INTERRUPT`.
+    pub async fn interact<F, R>(&self, f: F) -> Result<R, InteractError>
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let context = deadline::current();
+        if let Some(context) = &context {
+            context
+                .throttle()
+                .await
+                .map_err(|_| InteractError::Aborted)?;
+        }
+        let mut conn = self.conn.lock().await;
+        task::block_in_place(|| {
+            catch_unwind(AssertUnwindSafe(|| match &context {
+                Some(context) => context.run(&mut conn, |conn| Ok(f(conn))).ok(),
+                None => Some(f(&mut conn)),
+            }))
+        })
+        .map_err(InteractError::Panic)?
+        .ok_or(InteractError::Aborted)
+    }
+}
@@ -0,0 +1,154 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{tests::TempDir, PoolExt};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn interact() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = Config::new(temp.file("deadpool.db")).create_pool(Runtime::Tokio1)?;
+
+    let conn = pool.get().await?;
+    conn.interact(|conn| conn.execute_batch("CREATE TABLE t (a INTEGER)"))
+        .await
+        .unwrap()?;
+    let inserted = conn
+        .interact(|conn| {
+            let tx = conn.transaction()?;
+            tx.execute("INSERT INTO t (a) VALUES (1), (2)", NO_PARAMS)?;
+            tx.commit()?;
+            Ok::<_, rusqlite::Error>(2)
+        })
+        .await
+        .unwrap()?;
+    assert_eq!(inserted, 2);
+    assert_eq!(
+        pool.status(),
+        Status {
+            size: 1,
+            available: 0
+        }
+    );
+    drop(conn);
+    assert_eq!(
+        pool.status(),
+        Status {
+            size: 1,
+            available: 1
+        }
+    );
+
+    // The bb8 pool shares the same connections.
+    assert_eq!(pool.bb8().schema().await?.tables.len(), 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn panics() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = Config::new(temp.file("panics.db")).create_pool(Runtime::Tokio1)?;
+
+    let conn = pool.get().await?;
+    let result = conn.interact(|_| panic!("oops")).await;
+    match result {
+        Err(InteractError::Panic(payload)) => {
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"oops"))
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_: ()| ())),
+    }
+
+    // The connection can still be used.
+    let one: i64 = conn
+        .interact(|conn| conn.query_row("SELECT 1", NO_PARAMS, |row| row.get(0)))
+        .await
+        .unwrap()?;
+    assert_eq!(one, 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn config() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let mut config = Config::new(temp.file("config.db"));
+    assert!(config.get_pool_config().max_size >= 4);
+
+    config.pool = Some(PoolConfig::new(0));
+    assert!(matches!(
+        config.create_pool(Runtime::Tokio1),
+        Err(CreatePoolError::MaxSize(0))
+    ));
+
+    config.pool = Some(PoolConfig {
+        max_size: 1,
+        timeouts: Timeouts {
+            wait: Some(Duration::from_millis(50)),
+            ..Default::default()
+        },
+    });
+    let pool = config.create_pool(Runtime::Tokio1)?;
+    let held = pool.get().await?;
+    assert!(matches!(
+        pool.get().await,
+        Err(PoolError::Timeout(TimeoutType::Wait))
+    ));
+    drop(held);
+    pool.get().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn from_bb8() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool: Pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("bb8.db")).with_application_id(7))
+        .await?
+        .into();
+
+    let id: i32 = pool
+        .get()
+        .await?
+        .interact(|conn| conn.query_row("PRAGMA application_id", NO_PARAMS, |row| row.get(0)))
+        .await
+        .unwrap()?;
+    assert_eq!(id, 7);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn execution_context() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = Config::new(temp.file("context.db")).create_pool(Runtime::Tokio1)?;
+    let conn = pool.get().await?;
+
+    // Statements still running when the context expires are interrupted.
+    let result = crate::ExecutionContext::new()
+        .with_timeout(Duration::from_millis(100))
+        .scope(conn.interact(|conn| {
+            conn.query_row(
+                "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c)
+                 SELECT count(*) FROM c",
+                NO_PARAMS,
+                |row| row.get::<_, i64>(0),
+            )
+        }))
+        .await;
+    assert!(
+        matches!(
+            result,
+            Ok(Err(rusqlite::Error::SqliteFailure(ref e, _)))
+                if e.code == rusqlite::ErrorCode::OperationInterrupted
+        ),
+        "{:?}",
+        result
+    );
+
+    // And closures aren't run once it's cancelled.
+    let token = crate::CancellationToken::new();
+    token.cancel();
+    let result = crate::ExecutionContext::new()
+        .with_cancellation(token)
+        .scope(conn.interact(|_| unreachable!()))
+        .await;
+    assert!(matches!(result, Err(InteractError::Aborted)));
+    Ok(())
+}
@@ -0,0 +1,329 @@
+//! Textual SQL dumps, in the style of the `sqlite3` shell's `.dump` command.
+
+use std::{
+    ffi::CString,
+    fmt,
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    sync::Arc,
+};
+
+use rusqlite::{ffi, types::ValueRef, Connection, NO_PARAMS};
+
+use crate::{sql, transaction::DefaultBehavior, Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// Writes the schema and contents of the main database to `out` as a SQL
+/// script, which recreates the database when run against an empty one.
+///
+/// Everything is read within a single transaction, so the dump is consistent
+/// even while other connections are writing.
+pub(crate) fn dump<W>(conn: &mut Connection, mut out: W) -> Result<(), Error>
+where
+    W: Write,
+{
+    let tx = conn.transaction()?;
+    writeln!(out, "PRAGMA foreign_keys=OFF;")?;
+    writeln!(out, "BEGIN TRANSACTION;")?;
+
+    let tables: Vec<(String, String)> = {
+        let mut stmt = tx.prepare(
+            "SELECT name, sql FROM sqlite_master
+             WHERE sql NOT NULL AND type = 'table'
+             ORDER BY name = 'sqlite_sequence', rowid",
+        )?;
+        let rows = stmt
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        rows
+    };
+
+    let mut writable_schema = false;
+    for (name, create) in &tables {
+        if name == "sqlite_sequence" {
+            writeln!(out, "DELETE FROM sqlite_sequence;")?;
+        } else if name == "sqlite_stat1" {
+            // The statistics tables can't be created directly, but ANALYZE
+            // creates them.
+            writeln!(out, "ANALYZE sqlite_master;")?;
+        } else if name.starts_with("sqlite_") {
+            continue;
+        } else if create.starts_with("CREATE VIRTUAL TABLE") {
+            // Creating the virtual table would also create its shadow tables,
+            // which are dumped separately, so insert the definition directly
+            // instead.
+            if !writable_schema {
+                writeln!(out, "PRAGMA writable_schema=ON;")?;
+                writable_schema = true;
+            }
+            writeln!(
+                out,
+                "INSERT INTO sqlite_master(type,name,tbl_name,rootpage,sql)VALUES('table',{},{},0,{});",
+                quote_text(name),
+                quote_text(name),
+                quote_text(create),
+            )?;
+            continue;
+        } else {
+            writeln!(out, "{};", create)?;
+        }
+
+        dump_rows(&tx, name, &mut out)?;
+    }
+
+    let mut stmt = tx.prepare(
+        "SELECT sql FROM sqlite_master
+         WHERE sql NOT NULL AND type IN ('index', 'trigger', 'view')
+         ORDER BY type = 'trigger', rowid",
+    )?;
+    let mut rows = stmt.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        writeln!(out, "{};", row.get::<_, String>(0)?)?;
+    }
+
+    // RESET also reloads the schema, so the connection running the script
+    // sees the virtual tables inserted above. Older versions of SQLite treat
+    // it as OFF.
+    if writable_schema {
+        writeln!(out, "PRAGMA writable_schema=RESET;")?;
+    }
+    writeln!(out, "COMMIT;")?;
+    out.flush()?;
+    Ok(())
+}
+
+fn dump_rows<W>(conn: &Connection, table: &str, out: &mut W) -> Result<(), Error>
+where
+    W: Write,
+{
+    let table = sql::quote_identifier(table);
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {}", table))?;
+    let columns = stmt.column_count();
+    let mut rows = stmt.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        write!(out, "INSERT INTO {} VALUES(", table)?;
+        for i in 0..columns {
+            if i > 0 {
+                write!(out, ",")?;
+            }
+            write_value(out, row.get_raw(i))?;
+        }
+        writeln!(out, ");")?;
+    }
+    Ok(())
+}
+
+fn write_value<W>(out: &mut W, value: ValueRef<'_>) -> std::io::Result<()>
+where
+    W: Write,
+{
+    match value {
+        ValueRef::Null => write!(out, "NULL"),
+        ValueRef::Integer(v) => write!(out, "{}", v),
+        ValueRef::Real(v) if v.is_nan() => write!(out, "NULL"),
+        ValueRef::Real(v) if v.is_infinite() => {
+            write!(out, "{}", if v > 0.0 { "1e999" } else { "-1e999" })
+        }
+        // Debug formatting always includes a decimal point or exponent, so the
+        // value is read back as a REAL rather than an INTEGER.
+        ValueRef::Real(v) => write!(out, "{:?}", v),
+        // SQLite doesn't guarantee that text is valid UTF-8, or free of NULs,
+        // neither of which a quoted literal in the script can hold, so such
+        // text is written as its bytes.
+        ValueRef::Text(v) if v.contains(&0) || std::str::from_utf8(v).is_err() => {
+            write!(out, "CAST(")?;
+            write_hex(out, v)?;
+            write!(out, " AS TEXT)")
+        }
+        ValueRef::Text(v) => {
+            write!(out, "'")?;
+            for (i, part) in v.split(|&b| b == b'\'').enumerate() {
+                if i > 0 {
+                    write!(out, "''")?;
+                }
+                out.write_all(part)?;
+            }
+            write!(out, "'")
+        }
+        ValueRef::Blob(v) => write_hex(out, v),
+    }
+}
+
+fn write_hex<W>(out: &mut W, bytes: &[u8]) -> std::io::Result<()>
+where
+    W: Write,
+{
+    write!(out, "X'")?;
+    for byte in bytes {
+        write!(out, "{:02x}", byte)?;
+    }
+    write!(out, "'")
+}
+
+fn quote_text(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// How far a restore has got, as reported to
+/// [`SqlRestoreOptions::on_progress()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreProgress {
+    /// The number of statements executed and committed.
+    pub statements: u64,
+
+    /// The number of bytes of the script read.
+    pub bytes: u64,
+}
+
+type ProgressCallback = Arc<dyn Fn(&RestoreProgress) + Send + Sync>;
+
+/// Options for [`PoolExt::restore_from_sql()`](crate::PoolExt::restore_from_sql).
+#[derive(Clone)]
+pub struct SqlRestoreOptions {
+    batch_size: u64,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl Default for SqlRestoreOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            on_progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for SqlRestoreOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqlRestoreOptions")
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+impl SqlRestoreOptions {
+    /// Creates the default options, which commit every 1000 statements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many statements are executed in each transaction.
+    pub fn batch_size(mut self, batch_size: u64) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Sets a callback that is invoked after each batch is committed.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&RestoreProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+}
+
+/// Executes the SQL script in `reader` against an empty database.
+///
+/// The script's own transaction control statements are ignored, and the
+/// statements are instead committed in batches. `PRAGMA` statements are run
+/// between batches, since some (such as `foreign_keys`) have no effect within
+/// a transaction.
+pub(crate) fn restore<R>(
+    conn: &mut RusqliteConnection,
+    reader: R,
+    options: &SqlRestoreOptions,
+) -> Result<RestoreProgress, Error>
+where
+    R: Read,
+{
+    let empty: bool =
+        conn.query_row("SELECT COUNT(*) = 0 FROM sqlite_master", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+    if !empty {
+        return Err(Error::DatabaseNotEmpty);
+    }
+
+    let begin = DefaultBehavior(conn.transaction_behavior()).begin();
+    let mut reader = BufReader::new(reader);
+    let mut progress = RestoreProgress::default();
+    let mut batch = 0;
+    let mut statement = Vec::new();
+
+    let commit = |conn: &Connection, batch: &mut u64, progress: &mut RestoreProgress| {
+        if *batch > 0 {
+            conn.execute_batch("COMMIT")?;
+            progress.statements += *batch;
+            *batch = 0;
+            if let Some(callback) = &options.on_progress {
+                callback(progress);
+            }
+        }
+        Ok::<_, Error>(())
+    };
+
+    let result = (|| {
+        loop {
+            let read = reader.read_until(b'\n', &mut statement)?;
+            progress.bytes += read as u64;
+            if read > 0 && !is_complete(&statement)? {
+                continue;
+            }
+
+            let sql = std::str::from_utf8(&statement)
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?
+                .trim();
+            if sql.is_empty() {
+                if read == 0 {
+                    break;
+                }
+            } else if is_transaction_control(sql) {
+                // We manage transactions ourselves.
+            } else if starts_with_keyword(sql, "PRAGMA") {
+                commit(conn, &mut batch, &mut progress)?;
+                conn.execute_batch(sql)?;
+                progress.statements += 1;
+            } else {
+                if batch == 0 {
+                    conn.execute_batch(begin)?;
+                }
+                conn.execute_batch(sql)?;
+                batch += 1;
+                if batch >= options.batch_size {
+                    commit(conn, &mut batch, &mut progress)?;
+                }
+            }
+
+            statement.clear();
+            if read == 0 {
+                break;
+            }
+        }
+        commit(conn, &mut batch, &mut progress)
+    })();
+
+    if result.is_err() && !conn.is_autocommit() {
+        let _ = conn.execute_batch("ROLLBACK");
+    }
+    result.map(|()| progress)
+}
+
+fn is_complete(sql: &[u8]) -> Result<bool, Error> {
+    let sql = CString::new(sql).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    // Safety: the string is NUL terminated, and SQLite doesn't keep it.
+    Ok(unsafe { ffi::sqlite3_complete(sql.as_ptr()) } != 0)
+}
+
+fn starts_with_keyword(sql: &str, keyword: &str) -> bool {
+    sql.get(..keyword.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(keyword))
+        && !sql[keyword.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
+}
+
+fn is_transaction_control(sql: &str) -> bool {
+    ["BEGIN", "COMMIT", "END", "ROLLBACK"]
+        .iter()
+        .any(|keyword| starts_with_keyword(sql, keyword))
+}
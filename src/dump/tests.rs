@@ -0,0 +1,209 @@
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use crate::{tests::TempDir, Error, PoolExt, RusqliteConnectionManager, SqlRestoreOptions};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dump() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("dump.db")))
+        .await?;
+    pool.get().await?.execute_batch(
+        "CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, a, b);
+         CREATE INDEX t_a ON t (a);
+         CREATE VIEW v AS SELECT a FROM t;
+         INSERT INTO t (a, b) VALUES (1, 'it''s');
+         INSERT INTO t (a, b) VALUES (2.5, x'00ff');
+         INSERT INTO t (a, b) VALUES (NULL, 1e999);
+         INSERT INTO t (a, b) VALUES (3.0, 'multi
+line');",
+    )?;
+
+    let mut out = Vec::new();
+    pool.dump(&mut out).await?;
+    let script = String::from_utf8(out)?;
+    assert_eq!(
+        script,
+        r#"PRAGMA foreign_keys=OFF;
+BEGIN TRANSACTION;
+CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, a, b);
+INSERT INTO "t" VALUES(1,1,'it''s');
+INSERT INTO "t" VALUES(2,2.5,X'00ff');
+INSERT INTO "t" VALUES(3,NULL,1e999);
+INSERT INTO "t" VALUES(4,3.0,'multi
+line');
+DELETE FROM sqlite_sequence;
+INSERT INTO "sqlite_sequence" VALUES('t',4);
+CREATE INDEX t_a ON t (a);
+CREATE VIEW v AS SELECT a FROM t;
+COMMIT;
+"#
+    );
+
+    // Running the script recreates the database exactly.
+    let copy = Connection::open_in_memory()?;
+    copy.execute_batch(&script)?;
+    let mut original = Vec::new();
+    pool.dump(&mut original).await?;
+    let mut restored = Vec::new();
+    crate::dump::dump(&mut { copy }, &mut restored)?;
+    assert_eq!(original, restored);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn virtual_tables() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("dump.db")))
+        .await?;
+    let has_fts: bool = pool.get().await?.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_compile_options WHERE compile_options = 'ENABLE_FTS4'",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    if !has_fts {
+        return Ok(());
+    }
+
+    pool.get().await?.execute_batch(
+        "CREATE VIRTUAL TABLE docs USING fts4 (body);
+         INSERT INTO docs (body) VALUES ('hello world');",
+    )?;
+
+    let mut out = Vec::new();
+    pool.dump(&mut out).await?;
+    let copy = Connection::open_in_memory()?;
+    copy.execute_batch(std::str::from_utf8(&out)?)?;
+    let body: String = copy.query_row(
+        "SELECT body FROM docs WHERE docs MATCH 'hello'",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    assert_eq!(body, "hello world");
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let source = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("source.db")))
+        .await?;
+    source.get().await?.execute_batch(
+        "CREATE TABLE parent (id INTEGER PRIMARY KEY);
+         CREATE TABLE child (parent INTEGER REFERENCES parent (id));
+         INSERT INTO parent (id) VALUES (1), (2), (3);
+         INSERT INTO child (parent) VALUES (1), (2), (3), (3);
+         CREATE TRIGGER no_deletes BEFORE DELETE ON parent BEGIN SELECT RAISE(ABORT, 'no'); END;",
+    )?;
+    let mut script = Vec::new();
+    source.dump(&mut script).await?;
+
+    let dest = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("dest.db")))
+        .await?;
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let progress = dest
+        .restore_from_sql(
+            &script[..],
+            SqlRestoreOptions::new().batch_size(4).on_progress({
+                let reports = reports.clone();
+                move |progress| reports.lock().unwrap().push(*progress)
+            }),
+        )
+        .await?;
+
+    // The foreign_keys pragma, two tables, seven rows, and the trigger.
+    assert_eq!(progress.statements, 1 + 2 + 7 + 1);
+    assert_eq!(progress.bytes, script.len() as u64);
+    assert_eq!(
+        reports
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|progress| progress.statements)
+            .collect::<Vec<_>>(),
+        vec![5, 9, 11]
+    );
+
+    let mut restored = Vec::new();
+    dest.dump(&mut restored).await?;
+    assert_eq!(restored, script);
+
+    // Restoring again fails, since the database is no longer empty.
+    assert!(matches!(
+        dest.restore_from_sql(&script[..], SqlRestoreOptions::new())
+            .await,
+        Err(Error::DatabaseNotEmpty)
+    ));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn non_utf8_text() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let source = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("source.db")))
+        .await?;
+    source.get().await?.execute_batch(
+        "CREATE TABLE t (a TEXT);
+         INSERT INTO t (a) VALUES (CAST(X'ff41fe' AS TEXT)), (CAST(X'610062' AS TEXT));",
+    )?;
+    let mut script = Vec::new();
+    source.dump(&mut script).await?;
+    let script = String::from_utf8(script)?;
+    assert!(script.contains(r#"INSERT INTO "t" VALUES(CAST(X'ff41fe' AS TEXT));"#));
+    assert!(script.contains(r#"INSERT INTO "t" VALUES(CAST(X'610062' AS TEXT));"#));
+
+    let dest = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("dest.db")))
+        .await?;
+    dest.restore_from_sql(script.as_bytes(), SqlRestoreOptions::new())
+        .await?;
+    let mut values = Vec::new();
+    {
+        let conn = dest.get().await?;
+        let mut stmt = conn.prepare("SELECT typeof(a), hex(a) FROM t ORDER BY rowid")?;
+        let mut rows = stmt.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            values.push((row.get::<_, String>(0)?, row.get::<_, String>(1)?));
+        }
+    }
+    assert_eq!(
+        values,
+        vec![
+            ("text".to_string(), "FF41FE".to_string()),
+            ("text".to_string(), "610062".to_string()),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore_failure() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("dest.db")))
+        .await?;
+
+    let script = "CREATE TABLE t (a UNIQUE);
+INSERT INTO t VALUES(1);
+INSERT INTO t VALUES(2);
+INSERT INTO t VALUES(2);
+";
+    let result = pool
+        .restore_from_sql(script.as_bytes(), SqlRestoreOptions::new().batch_size(2))
+        .await;
+    assert!(result.is_err());
+
+    let count: i64 = pool
+        .get()
+        .await?
+        .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(count, 1);
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Rows as maps from column names to values, for tooling that doesn't know
+//! the schema at compile time.
+
+use indexmap::IndexMap;
+use rusqlite::{types::Value, Row, ToSql};
+
+use crate::{Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// A row, mapping each column name to its value, in the order the columns
+/// were returned.
+pub type DynamicRow = IndexMap<String, Value>;
+
+/// Converts `row` into a [`DynamicRow`].
+///
+/// If the query returns more than one column with the same name, such as
+/// from a join, only the last of them is kept; alias the columns apart to
+/// keep them all.
+pub fn row_to_map(row: &Row<'_>) -> rusqlite::Result<DynamicRow> {
+    (0..row.column_count())
+        .map(|i| Ok((row.column_name(i)?.to_string(), row.get(i)?)))
+        .collect()
+}
+
+pub(crate) fn query<P>(
+    conn: &RusqliteConnection,
+    sql: &str,
+    params: P,
+) -> Result<Vec<DynamicRow>, Error>
+where
+    P: IntoIterator,
+    P::Item: ToSql,
+{
+    let mut stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query(params)?;
+    conn.result_limits().collect(rows, row_to_map)
+}
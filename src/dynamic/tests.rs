@@ -0,0 +1,39 @@
+use rusqlite::{types::Value, NO_PARAMS};
+
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn query_rows_dynamic() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("dynamic.db")))
+        .await?;
+    pool.get().await?.execute_batch(
+        "CREATE TABLE t (id INTEGER, name TEXT, score REAL, data BLOB);
+         INSERT INTO t VALUES (1, 'alice', 2.5, x'01'), (2, NULL, NULL, NULL);",
+    )?;
+
+    let rows = pool
+        .query_rows_dynamic("SELECT * FROM t WHERE id >= ? ORDER BY id", vec![1])
+        .await?;
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        rows[0].keys().collect::<Vec<_>>(),
+        vec!["id", "name", "score", "data"]
+    );
+    assert_eq!(
+        rows[0].values().cloned().collect::<Vec<_>>(),
+        vec![
+            Value::Integer(1),
+            Value::Text("alice".into()),
+            Value::Real(2.5),
+            Value::Blob(vec![1]),
+        ]
+    );
+    assert_eq!(rows[1]["name"], Value::Null);
+
+    let conn = pool.get().await?;
+    let row = conn.query_row("SELECT 1 AS a, 'x' AS b", NO_PARAMS, crate::row_to_map)?;
+    assert_eq!(row["b"], Value::Text("x".into()));
+    Ok(())
+}
@@ -0,0 +1,137 @@
+//! Keys for encrypted databases, and the pragmas each encryption extension
+//! expects them in.
+
+use std::{fmt, fmt::Write};
+
+use rusqlite::{Connection, OptionalExtension, NO_PARAMS};
+
+#[cfg(test)]
+mod tests;
+
+/// The encryption extension SQLite was built with, which decides how keys
+/// are passed to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionBackend {
+    /// [SQLCipher](https://www.zetetic.net/sqlcipher/), which takes raw keys
+    /// as `PRAGMA key = "x'...'"`.
+    #[default]
+    SqlCipher,
+
+    /// The [SQLite Encryption
+    /// Extension](https://www.sqlite.org/see/doc/trunk/www/readme.wiki),
+    /// which takes raw keys through `PRAGMA hexkey` and `PRAGMA hexrekey`.
+    See,
+}
+
+/// A key for an encrypted database.
+///
+/// Passphrases are given to the encryption extension as is, and turned into
+/// a key however it sees fit. Raw keys are used directly, and are passed to
+/// the extension hex encoded. The `Debug` output never includes the key.
+#[derive(Clone, PartialEq, Eq)]
+pub enum EncryptionKey {
+    /// A passphrase.
+    Passphrase(String),
+
+    /// The bytes of the key itself.
+    Raw(Vec<u8>),
+}
+
+impl EncryptionKey {
+    /// Parses a raw key written in hex, returning `None` if `hex` isn't an
+    /// even number of hex digits.
+    // usize::is_multiple_of() needs Rust 1.87.
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() % 2 != 0 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<_>>>()
+            .map(Self::Raw)
+    }
+
+    /// Returns the pragma, and the value to give it, that sets this key with
+    /// `backend`, or changes an existing key to this one if `rekey` is set.
+    fn pragma(&self, backend: EncryptionBackend, rekey: bool) -> (&'static str, String) {
+        let hex = |raw: &[u8]| {
+            raw.iter().fold(String::new(), |mut hex, b| {
+                let _ = write!(hex, "{:02X}", b);
+                hex
+            })
+        };
+        match (self, backend, rekey) {
+            (Self::Passphrase(p), _, false) => ("key", p.clone()),
+            (Self::Passphrase(p), _, true) => ("rekey", p.clone()),
+            (Self::Raw(raw), EncryptionBackend::SqlCipher, false) => {
+                ("key", format!("x'{}'", hex(raw)))
+            }
+            (Self::Raw(raw), EncryptionBackend::SqlCipher, true) => {
+                ("rekey", format!("x'{}'", hex(raw)))
+            }
+            (Self::Raw(raw), EncryptionBackend::See, false) => ("hexkey", hex(raw)),
+            (Self::Raw(raw), EncryptionBackend::See, true) => ("hexrekey", hex(raw)),
+        }
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passphrase(_) => f.write_str("Passphrase(<redacted>)"),
+            Self::Raw(_) => f.write_str("Raw(<redacted>)"),
+        }
+    }
+}
+
+impl From<String> for EncryptionKey {
+    fn from(passphrase: String) -> Self {
+        Self::Passphrase(passphrase)
+    }
+}
+
+impl From<&str> for EncryptionKey {
+    fn from(passphrase: &str) -> Self {
+        Self::Passphrase(passphrase.into())
+    }
+}
+
+/// Sets the key on a newly opened connection.
+pub(crate) fn apply(
+    conn: &Connection,
+    backend: EncryptionBackend,
+    key: &EncryptionKey,
+) -> rusqlite::Result<()> {
+    let (pragma, value) = key.pragma(backend, false);
+    conn.pragma_update(None, pragma, &value)
+}
+
+/// Changes the key of the database `conn` is open on.
+pub(crate) fn rekey(
+    conn: &Connection,
+    backend: EncryptionBackend,
+    key: &EncryptionKey,
+) -> rusqlite::Result<()> {
+    let (pragma, value) = key.pragma(backend, true);
+    conn.pragma_update(None, pragma, &value)
+}
+
+/// Returns true if SQLite was built with `backend`. Builds without it ignore
+/// the key pragmas, rather than failing.
+pub(crate) fn supported(conn: &Connection, backend: EncryptionBackend) -> rusqlite::Result<bool> {
+    match backend {
+        EncryptionBackend::SqlCipher => Ok(conn
+            .query_row("PRAGMA cipher_version", NO_PARAMS, |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()?
+            .is_some()),
+        EncryptionBackend::See => conn.query_row(
+            "SELECT sqlite_compileoption_used('HAS_CODEC')",
+            NO_PARAMS,
+            |row| row.get(0),
+        ),
+    }
+}
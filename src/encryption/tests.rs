@@ -0,0 +1,72 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{tests::TempDir, Error, RusqliteConnectionManager};
+
+#[test]
+fn hex_keys() {
+    assert_eq!(
+        EncryptionKey::from_hex("00ff7A"),
+        Some(EncryptionKey::Raw(vec![0x00, 0xff, 0x7a]))
+    );
+    assert_eq!(EncryptionKey::from_hex("abc"), None);
+    assert_eq!(EncryptionKey::from_hex("zz"), None);
+}
+
+#[test]
+fn pragmas() {
+    let passphrase = EncryptionKey::from("secret");
+    let raw = EncryptionKey::Raw(vec![0x2d, 0xd2]);
+    for backend in [EncryptionBackend::SqlCipher, EncryptionBackend::See] {
+        assert_eq!(
+            passphrase.pragma(backend, false),
+            ("key", String::from("secret"))
+        );
+        assert_eq!(
+            passphrase.pragma(backend, true),
+            ("rekey", String::from("secret"))
+        );
+    }
+    assert_eq!(
+        raw.pragma(EncryptionBackend::SqlCipher, false),
+        ("key", String::from("x'2DD2'"))
+    );
+    assert_eq!(
+        raw.pragma(EncryptionBackend::SqlCipher, true),
+        ("rekey", String::from("x'2DD2'"))
+    );
+    assert_eq!(
+        raw.pragma(EncryptionBackend::See, false),
+        ("hexkey", String::from("2DD2"))
+    );
+    assert_eq!(
+        raw.pragma(EncryptionBackend::See, true),
+        ("hexrekey", String::from("2DD2"))
+    );
+}
+
+#[test]
+fn redacted() {
+    let key = EncryptionKey::from("secret");
+    assert_eq!(format!("{:?}", key), "Passphrase(<redacted>)");
+    let manager = RusqliteConnectionManager::new("redacted.db")
+        .with_encryption_backend(EncryptionBackend::See)
+        .with_key(EncryptionKey::Raw(vec![0xab; 16]));
+    assert!(!format!("{:?}", manager).contains("171"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn see_unsupported() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("see.db"))
+        .with_encryption_backend(EncryptionBackend::See)
+        .with_key(EncryptionKey::from_hex("00112233445566778899aabbccddeeff").unwrap());
+    let pool = bb8::Pool::builder().build(manager.clone()).await?;
+    pool.get()
+        .await?
+        .query_row("SELECT 1", NO_PARAMS, |_| Ok(()))?;
+
+    let result = manager.rekey(&pool, EncryptionKey::Raw(vec![1; 16])).await;
+    assert!(matches!(result, Err(Error::EncryptionUnsupported)));
+    Ok(())
+}
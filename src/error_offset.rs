@@ -0,0 +1,63 @@
+//! Locating where in its SQL a statement failed to prepare, through
+//! `sqlite3_error_offset()`.
+
+use crate::ffi_ext::sqlite3_error_offset;
+use crate::{Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// How much of the SQL either side of the offset an error shows.
+const CONTEXT: usize = 40;
+
+/// Turns `e`, if it's from `sql` failing to prepare on `conn` after `prefix`
+/// bytes of other SQL, into an [`Error::Prepare`] saying where. This has to
+/// be called before anything else runs on the connection, which would
+/// replace its error.
+///
+/// If errors are redacted, SQLite's message is dropped too, since it quotes
+/// the token the error is at, which can be a value.
+pub(crate) fn locate(conn: &RusqliteConnection, sql: &str, prefix: usize, e: Error) -> Error {
+    let source = match e {
+        Error::Rusqlite(rusqlite::Error::SqliteFailure(error, message)) => {
+            rusqlite::Error::SqliteFailure(error, message.filter(|_| !conn.redact_errors()))
+        }
+        e => return e,
+    };
+    // Safety: the handle is only used for the duration of the call.
+    let offset = unsafe { sqlite3_error_offset(conn.handle()) } as isize - prefix as isize;
+    // Offsets can be a byte past the end, for SQL that ends too early.
+    if offset < 0 || offset as usize > sql.len() {
+        return Error::Rusqlite(source);
+    }
+    Error::Prepare {
+        source,
+        offset: offset as usize,
+        sql: (!conn.redact_errors()).then(|| sql.to_string()),
+    }
+}
+
+/// Formats the SQL around `offset` on one line, marking where it is.
+pub(crate) fn snippet(sql: &str, offset: usize) -> String {
+    let boundary = |mut i: usize, forward: bool| {
+        while !sql.is_char_boundary(i) {
+            if forward {
+                i += 1;
+            } else {
+                i -= 1;
+            }
+        }
+        i
+    };
+    let start = boundary(offset.saturating_sub(CONTEXT), false);
+    let end = boundary((offset + CONTEXT).min(sql.len()), true);
+    let offset = boundary(offset, false);
+    let words = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+    format!(
+        "{}{} >>> {}{}",
+        if start > 0 { "..." } else { "" },
+        words(&sql[start..offset]),
+        words(&sql[offset..end]),
+        if end < sql.len() { "..." } else { "" },
+    )
+}
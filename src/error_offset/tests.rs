@@ -0,0 +1,148 @@
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    NamedParams, PoolExt, RusqliteConnectionManager,
+};
+
+const SETUP: &str = "CREATE TABLE IF NOT EXISTS t (a INTEGER UNIQUE)";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn syntax_error() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("offset.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+
+    let e = pool
+        .execute_named("SELECT * FORM t", NamedParams::new())
+        .await
+        .unwrap_err();
+    match &e {
+        Error::Prepare { offset, sql, .. } => {
+            assert_eq!(*offset, 9);
+            assert_eq!(sql.as_deref(), Some("SELECT * FORM t"));
+        }
+        e => panic!("unexpected error: {:?}", e),
+    }
+    assert_eq!(
+        e.to_string(),
+        "statement failed to prepare at offset 9: SELECT * >>> FORM t"
+    );
+    assert!(std::error::Error::source(&e).is_some());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redacted() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("redacted.db")).with_redacted_errors(true),
+        1,
+        SETUP,
+    )
+    .await?;
+
+    let e = pool
+        .execute_named("SELECT 'secret' FORM t", NamedParams::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        e,
+        Error::Prepare {
+            offset: 21,
+            sql: None,
+            ..
+        }
+    ));
+    assert_eq!(e.to_string(), "statement failed to prepare at offset 21");
+    assert!(!format!("{:?}", e).contains("secret"));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redacted_message() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("message.db")).with_redacted_errors(true),
+        1,
+        SETUP,
+    )
+    .await?;
+
+    // SQLite's message quotes the literal the error is at.
+    let e = pool
+        .execute_named("UPDATE t SET a = 'x' 'hunter2'", NamedParams::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(e, Error::Prepare { offset: 21, .. }), "{:?}", e);
+    assert!(!format!("{:?}", e).contains("hunter2"));
+    let mut source = std::error::Error::source(&e);
+    while let Some(e) = source {
+        assert!(!e.to_string().contains("hunter2"));
+        source = e.source();
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn explain_plan() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("explain.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+
+    let e = pool
+        .explain_plan("SELECT * FORM t", Vec::<i64>::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(e, Error::Prepare { offset: 9, .. }), "{:?}", e);
+    assert_eq!(
+        e.to_string(),
+        "statement failed to prepare at offset 9: SELECT * >>> FORM t"
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runtime_errors() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("runtime.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+
+    pool.execute_named("INSERT INTO t (a) VALUES (1)", NamedParams::new())
+        .await?;
+    let e = pool
+        .execute_named("INSERT INTO t (a) VALUES (1)", NamedParams::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(e, Error::Rusqlite(_)), "{:?}", e);
+    Ok(())
+}
+
+#[test]
+fn snippets() {
+    let sql = format!(
+        "SELECT {} FORM t WHERE\n    {}",
+        "a, ".repeat(20),
+        "b = 1 AND ".repeat(10)
+    );
+    let offset = sql.find("FORM").unwrap();
+    let marked = snippet(&sql, offset);
+    assert!(marked.starts_with("...a, a, "), "{}", marked);
+    assert!(marked.contains("a, >>> FORM t WHERE b = 1"), "{}", marked);
+    assert!(marked.ends_with("..."), "{}", marked);
+
+    // Offsets inside a character, or at the end, still make a snippet.
+    assert_eq!(snippet("SELECT 'é", 9), "SELECT ' >>> é");
+    assert_eq!(snippet("SELECT (", 8), "SELECT ( >>> ");
+}
@@ -0,0 +1,73 @@
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn registered() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("extensions.db")))
+        .await?;
+    let conn = pool.get().await?;
+
+    #[cfg(feature = "regexp")]
+    {
+        let matched: bool = conn.query_row(
+            "SELECT 'bb8-rusqlite' REGEXP '^bb[0-9]-'",
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        assert!(matched);
+    }
+
+    #[cfg(feature = "series")]
+    {
+        // A date spine, with gaps in the data filled with zeroes.
+        conn.execute_batch(
+            "CREATE TABLE sales (day TEXT, total INTEGER);
+            INSERT INTO sales VALUES ('2024-01-01', 3), ('2024-01-03', 5);",
+        )?;
+        let totals = conn
+            .prepare(
+                "SELECT spine.day, COALESCE(SUM(sales.total), 0)
+                FROM (
+                    SELECT date('2024-01-01', '+' || value || ' days') AS day
+                    FROM generate_series(0, 3)
+                ) AS spine
+                LEFT JOIN sales ON sales.day = spine.day
+                GROUP BY spine.day
+                ORDER BY spine.day",
+            )?
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, i64)>, _>>()?;
+        assert_eq!(
+            totals,
+            [
+                (String::from("2024-01-01"), 3),
+                (String::from("2024-01-02"), 0),
+                (String::from("2024-01-03"), 5),
+                (String::from("2024-01-04"), 0),
+            ]
+        );
+    }
+
+    #[cfg(feature = "sha3")]
+    {
+        let hash: String =
+            conn.query_row("SELECT hex(sha3('abc', 256))", NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(
+            hash,
+            "3A985DA74FE225B2045C172D6BD390BD855F086E3E9D525B46BFE24511431532"
+        );
+    }
+
+    #[cfg(feature = "uuid")]
+    {
+        let uuid: String = conn.query_row("SELECT uuid()", NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(uuid.len(), 36);
+        let roundtrip: String =
+            conn.query_row("SELECT uuid_str(uuid_blob(?))", &[&uuid], |row| row.get(0))?;
+        assert_eq!(roundtrip, uuid);
+    }
+    Ok(())
+}
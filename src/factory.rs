@@ -0,0 +1,79 @@
+//! Opening connections configured by a [`RusqliteConnectionManager`] without
+//! an async runtime, for synchronous pools such as r2d2.
+
+use bb8::ManageConnection;
+
+use crate::{Error, RusqliteConnection, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// Opens, checks, and releases connections exactly as a
+/// [`RusqliteConnectionManager`] does, but synchronously, so that code that
+/// isn't async can share the manager's configuration. With the `r2d2`
+/// feature, this implements `r2d2::ManageConnection`.
+///
+/// A factory shares its manager's state, so connections it opens are
+/// included in the manager's metrics, drain when the manager is
+/// [shut down](RusqliteConnectionManager::shutdown) or its settings are
+/// [reloaded](RusqliteConnectionManager::reload_pragmas), and so on. Work that needs
+/// the runtime isn't done, though: corrupt databases aren't
+/// [recovered](RusqliteConnectionManager::with_recovery), the
+/// [connect timeout](RusqliteConnectionManager::with_connect_timeout) doesn't
+/// apply, and connections are refused, rather than waited for, while the
+/// database is being rekeyed or swapped.
+#[derive(Clone, Debug)]
+pub struct ConnectionFactory {
+    manager: RusqliteConnectionManager,
+}
+
+impl ConnectionFactory {
+    pub(crate) fn new(manager: RusqliteConnectionManager) -> Self {
+        Self { manager }
+    }
+
+    /// Returns the manager the factory shares its configuration with.
+    pub fn manager(&self) -> &RusqliteConnectionManager {
+        &self.manager
+    }
+
+    /// Opens a connection, blocking until it's ready.
+    pub fn connect(&self) -> Result<RusqliteConnection, Error> {
+        let manager = &self.manager;
+        if manager.shutdown.is_closing() {
+            return Err(Error::ShutDown);
+        }
+        let _opening = manager.rekey.try_opening().ok_or(Error::Rekeying)?;
+        let _swapping = manager.swap.try_opening().ok_or(Error::Swapping)?;
+        manager.open_blocking(manager.current_file())
+    }
+
+    /// Checks that a connection can still be used, as it's checked out.
+    pub fn is_valid(&self, conn: &mut RusqliteConnection) -> Result<(), Error> {
+        conn.checked_out(None);
+        self.manager.check_connection(conn)
+    }
+
+    /// Returns true if a connection being released shouldn't be reused.
+    pub fn has_broken(&self, conn: &mut RusqliteConnection) -> bool {
+        self.manager.has_broken(conn)
+    }
+}
+
+#[cfg(feature = "r2d2")]
+impl r2d2::ManageConnection for ConnectionFactory {
+    type Connection = RusqliteConnection;
+    type Error = Error;
+
+    fn connect(&self) -> Result<RusqliteConnection, Error> {
+        ConnectionFactory::connect(self)
+    }
+
+    fn is_valid(&self, conn: &mut RusqliteConnection) -> Result<(), Error> {
+        ConnectionFactory::is_valid(self, conn)
+    }
+
+    fn has_broken(&self, conn: &mut RusqliteConnection) -> bool {
+        ConnectionFactory::has_broken(self, conn)
+    }
+}
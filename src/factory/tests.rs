@@ -0,0 +1,56 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{tests::TempDir, PragmaCustomizer};
+
+fn cache_size(conn: &RusqliteConnection) -> Result<i64, rusqlite::Error> {
+    conn.query_row("PRAGMA cache_size", NO_PARAMS, |row| row.get(0))
+}
+
+#[test]
+fn without_a_runtime() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("factory.db"))
+        .with_application_id(42)
+        .with_pragmas(PragmaCustomizer::new().pragma("cache_size", -4000));
+    let factory = manager.connection_factory();
+
+    let mut conn = factory.connect()?;
+    let id: i32 = conn.query_row("PRAGMA application_id", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(id, 42);
+    assert_eq!(cache_size(&conn)?, -4000);
+    factory.is_valid(&mut conn)?;
+    assert!(!factory.has_broken(&mut conn));
+
+    // Reloading the manager's settings reaches the factory's connections,
+    // and the connections it opens from then on.
+    manager.reload_pragmas(PragmaCustomizer::new().pragma("cache_size", -2000));
+    assert!(matches!(factory.is_valid(&mut conn), Err(Error::Reloaded)));
+    assert!(factory.has_broken(&mut conn));
+    assert_eq!(cache_size(&factory.connect()?)?, -2000);
+    Ok(())
+}
+
+#[cfg(feature = "r2d2")]
+#[tokio::test(flavor = "multi_thread")]
+async fn shared_with_r2d2() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("r2d2.db"));
+    let pool = bb8::Pool::builder().build(manager.clone()).await?;
+    let factory = manager.connection_factory();
+
+    let sync = std::thread::spawn(move || -> Result<i64, anyhow::Error> {
+        let pool = r2d2::Pool::builder().max_size(2).build(factory)?;
+        let conn = pool.get()?;
+        conn.execute_batch("CREATE TABLE t (a INTEGER); INSERT INTO t (a) VALUES (1);")?;
+        Ok(conn.query_row("SELECT count(*) FROM t", NO_PARAMS, |row| row.get(0))?)
+    });
+    assert_eq!(sync.join().unwrap()?, 1);
+
+    let n: i64 = pool
+        .get()
+        .await?
+        .query_row("SELECT count(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(n, 1);
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! SQLite functions and constants that aren't in every version of the
+//! bindings rusqlite 0.24 is built with, declared here once for the modules
+//! that use them. Each notes the SQLite version it first appeared in; those
+//! that need a newer SQLite than the rest of the crate, or a compile-time
+//! option, are behind features.
+
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+use rusqlite::ffi;
+
+// The `SQLITE_TRACE_*` event codes, since 3.14.
+pub(crate) const TRACE_STMT: c_uint = 0x01;
+pub(crate) const TRACE_PROFILE: c_uint = 0x02;
+pub(crate) const TRACE_ROW: c_uint = 0x04;
+
+/// `SQLITE_STMTSTATUS_RUN`, since 3.20.
+pub(crate) const STMTSTATUS_RUN: c_int = 6;
+
+/// `SQLITE_TXN_WRITE`, since 3.34.
+#[cfg(feature = "txn-state")]
+pub(crate) const TXN_WRITE: c_int = 2;
+
+// The `sqlite3_file_control()` opcodes.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) const FCNTL_WIN32_AV_RETRY: c_int = 9;
+pub(crate) const FCNTL_PERSIST_WAL: c_int = 10;
+
+// The extended `SQLITE_READONLY` codes for a WAL index that can't be used.
+pub(crate) const READONLY_RECOVERY: c_int = ffi::SQLITE_READONLY | (1 << 8);
+pub(crate) const READONLY_CANTLOCK: c_int = ffi::SQLITE_READONLY | (2 << 8);
+pub(crate) const READONLY_CANTINIT: c_int = ffi::SQLITE_READONLY | (5 << 8);
+
+// The `sqlite3_db_status()` codes.
+pub(crate) const DBSTATUS_LOOKASIDE_USED: c_int = 0;
+pub(crate) const DBSTATUS_CACHE_USED: c_int = 1;
+pub(crate) const DBSTATUS_SCHEMA_USED: c_int = 2;
+pub(crate) const DBSTATUS_STMT_USED: c_int = 3;
+pub(crate) const DBSTATUS_LOOKASIDE_HIT: c_int = 4;
+pub(crate) const DBSTATUS_LOOKASIDE_MISS_SIZE: c_int = 5;
+pub(crate) const DBSTATUS_LOOKASIDE_MISS_FULL: c_int = 6;
+pub(crate) const DBSTATUS_CACHE_WRITE: c_int = 9;
+
+// The `sqlite3_status()` codes.
+pub(crate) const STATUS_MEMORY_USED: c_int = 0;
+pub(crate) const STATUS_PAGECACHE_OVERFLOW: c_int = 2;
+pub(crate) const STATUS_MALLOC_SIZE: c_int = 5;
+pub(crate) const STATUS_MALLOC_COUNT: c_int = 9;
+
+pub(crate) type TraceCallback =
+    unsafe extern "C" fn(c_uint, *mut c_void, *mut c_void, *mut c_void) -> c_int;
+
+pub(crate) type WalHook =
+    unsafe extern "C" fn(*mut c_void, *mut ffi::sqlite3, *const c_char, c_int) -> c_int;
+
+#[cfg(feature = "preupdate-hook")]
+pub(crate) type PreUpdateHook = unsafe extern "C" fn(
+    *mut c_void,
+    *mut ffi::sqlite3,
+    c_int,
+    *const c_char,
+    *const c_char,
+    i64,
+    i64,
+);
+
+extern "C" {
+    // Since 3.7.0.
+    pub(crate) fn sqlite3_wal_hook(
+        db: *mut ffi::sqlite3,
+        hook: Option<WalHook>,
+        context: *mut c_void,
+    ) -> *mut c_void;
+    pub(crate) fn sqlite3_wal_autocheckpoint(db: *mut ffi::sqlite3, pages: c_int) -> c_int;
+    pub(crate) fn sqlite3_wal_checkpoint(db: *mut ffi::sqlite3, schema: *const c_char) -> c_int;
+
+    // Since 3.7.4.
+    #[cfg(not(feature = "txn-state"))]
+    pub(crate) fn sqlite3_stmt_readonly(stmt: *mut ffi::sqlite3_stmt) -> c_int;
+
+    // Since 3.7.10.
+    pub(crate) fn sqlite3_stmt_busy(stmt: *mut ffi::sqlite3_stmt) -> c_int;
+
+    // Since 3.7.14.
+    pub(crate) fn sqlite3_close_v2(db: *mut ffi::sqlite3) -> c_int;
+
+    // Since 3.13, in builds with `SQLITE_ENABLE_PREUPDATE_HOOK`.
+    #[cfg(feature = "preupdate-hook")]
+    pub(crate) fn sqlite3_preupdate_hook(
+        db: *mut ffi::sqlite3,
+        hook: Option<PreUpdateHook>,
+        context: *mut c_void,
+    ) -> *mut c_void;
+    #[cfg(feature = "preupdate-hook")]
+    pub(crate) fn sqlite3_preupdate_old(
+        db: *mut ffi::sqlite3,
+        column: c_int,
+        value: *mut *mut ffi::sqlite3_value,
+    ) -> c_int;
+    #[cfg(feature = "preupdate-hook")]
+    pub(crate) fn sqlite3_preupdate_new(
+        db: *mut ffi::sqlite3,
+        column: c_int,
+        value: *mut *mut ffi::sqlite3_value,
+    ) -> c_int;
+    #[cfg(feature = "preupdate-hook")]
+    pub(crate) fn sqlite3_preupdate_count(db: *mut ffi::sqlite3) -> c_int;
+    #[cfg(feature = "preupdate-hook")]
+    pub(crate) fn sqlite3_preupdate_depth(db: *mut ffi::sqlite3) -> c_int;
+
+    // Since 3.14.
+    pub(crate) fn sqlite3_trace_v2(
+        db: *mut ffi::sqlite3,
+        mask: c_uint,
+        callback: Option<TraceCallback>,
+        context: *mut c_void,
+    ) -> c_int;
+    #[cfg(feature = "profiling")]
+    pub(crate) fn sqlite3_expanded_sql(stmt: *mut ffi::sqlite3_stmt) -> *mut c_char;
+
+    // Since 3.34.
+    #[cfg(feature = "txn-state")]
+    pub(crate) fn sqlite3_txn_state(db: *mut ffi::sqlite3, schema: *const c_char) -> c_int;
+
+    // Since 3.38.
+    #[cfg(feature = "error-offset")]
+    pub(crate) fn sqlite3_error_offset(db: *mut ffi::sqlite3) -> c_int;
+}
@@ -0,0 +1,52 @@
+use std::{
+    fs::{self, Metadata},
+    io::ErrorKind,
+    path::Path,
+};
+
+/// Identifies a file on disk independently of its path, so we can tell when
+/// the file at a path has been deleted or replaced.
+///
+/// This is only tracked on Unix, where it's the device and inode numbers.
+/// Elsewhere, no identity is ever returned, and so files are never considered
+/// replaced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FileIdentity {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileIdentity {
+    /// Returns the identity of the file currently at `path`, if it exists and
+    /// identities are supported on this platform.
+    pub(crate) fn of(path: &Path) -> Option<Self> {
+        fs::metadata(path)
+            .ok()
+            .and_then(|meta| Self::from_metadata(&meta))
+    }
+
+    /// Returns true if the file at `path` is no longer this file. Errors
+    /// other than the file not existing are assumed to be transient, and the
+    /// file to be unchanged.
+    pub(crate) fn replaced(&self, path: &Path) -> bool {
+        match fs::metadata(path) {
+            Ok(meta) => Self::from_metadata(&meta) != Some(*self),
+            Err(e) => e.kind() == ErrorKind::NotFound,
+        }
+    }
+
+    #[cfg(unix)]
+    fn from_metadata(meta: &Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn from_metadata(_meta: &Metadata) -> Option<Self> {
+        None
+    }
+}
@@ -0,0 +1,75 @@
+//! Electing a single leader among the processes sharing a database.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rusqlite::{Connection, ErrorCode};
+
+use crate::{replication, Error};
+
+#[cfg(test)]
+mod tests;
+
+/// The leadership of the processes sharing a database, as taken with
+/// [`PoolExt::try_acquire_leadership()`](crate::PoolExt::try_acquire_leadership).
+/// Leadership is held until this is dropped, or the process exits.
+///
+/// Leadership is an exclusive lock on a separate coordination database next
+/// to the pool's database, named after it with a `-leader` suffix. The lock
+/// is SQLite's own, so it works wherever SQLite's locking does, and is
+/// released by the operating system if the process dies. In-memory databases
+/// have no file to put it next to, so their pools fail with
+/// [`Error::InvalidPath`].
+#[derive(Debug)]
+pub struct Leadership {
+    // The lock is held by an exclusive transaction, which is rolled back when
+    // the connection closes.
+    conn: Connection,
+    path: PathBuf,
+}
+
+impl Leadership {
+    /// Returns the path of the coordination database holding the lock.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gives up leadership, letting another process take it.
+    pub fn release(self) -> Result<(), Error> {
+        self.conn.close().map_err(|(_, e)| e.into())
+    }
+}
+
+/// Returns the path of the coordination database for `database`.
+fn lock_path(database: &Path) -> PathBuf {
+    let mut path = OsString::from(database.as_os_str());
+    path.push("-leader");
+    path.into()
+}
+
+/// Takes leadership for `database`, which `conn` is open on, unless another
+/// connection holds it.
+pub(crate) fn try_acquire(conn: &Connection, database: &Path) -> Result<Option<Leadership>, Error> {
+    // SQLite reports no file for in-memory and temporary databases.
+    if replication::main_database_path(conn)?
+        .as_os_str()
+        .is_empty()
+    {
+        return Err(Error::InvalidPath {
+            path: database.into(),
+            reason: "an in-memory database can't have a leader".into(),
+        });
+    }
+
+    let path = lock_path(database);
+    let conn = Connection::open(&path)?;
+    conn.busy_timeout(Duration::ZERO)?;
+    match conn.execute_batch("BEGIN EXCLUSIVE") {
+        Ok(()) => Ok(Some(Leadership { conn, path })),
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::DatabaseBusy => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
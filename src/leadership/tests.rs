@@ -0,0 +1,41 @@
+use crate::{tests::TempDir, Error, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn single_leader() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("shared.db");
+    let build = || {
+        bb8::Pool::builder()
+            .max_size(1)
+            .build(RusqliteConnectionManager::new(&path))
+    };
+    let (first, second) = (build().await?, build().await?);
+
+    let leadership = first.try_acquire_leadership().await?.unwrap();
+    assert_eq!(leadership.path(), temp.file("shared.db-leader"));
+    assert!(second.try_acquire_leadership().await?.is_none());
+    assert!(first.try_acquire_leadership().await?.is_none());
+
+    leadership.release()?;
+    let leadership = second.try_acquire_leadership().await?;
+    assert!(leadership.is_some());
+    assert!(first.try_acquire_leadership().await?.is_none());
+
+    drop(leadership);
+    assert!(first.try_acquire_leadership().await?.is_some());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn in_memory() -> Result<(), anyhow::Error> {
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(":memory:"))
+        .await?;
+    assert!(matches!(
+        pool.try_acquire_leadership().await,
+        Err(Error::InvalidPath { .. })
+    ));
+    assert!(!std::path::Path::new(":memory:-leader").exists());
+    Ok(())
+}
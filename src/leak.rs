@@ -0,0 +1,210 @@
+//! Detection of connections held for too long.
+//!
+//! A pooled connection is only returned to the pool when its guard is
+//! dropped, so a guard accidentally kept alive across a long `.await` starves
+//! every other task of that connection. A [`LeakDetector`], installed with
+//! [`RusqliteConnectionManager::with_leak_detector()`](crate::RusqliteConnectionManager::with_leak_detector),
+//! watches the pool's checkouts from a background task, and reports each one
+//! that's been held for longer than a threshold, optionally with a backtrace
+//! of where it was checked out.
+//!
+//! Checkouts are seen when a connection is validated on checkout, which
+//! `bb8` does unless `test_on_check_out` is disabled, and when one is checked
+//! out through [`PoolExt`](crate::PoolExt).
+
+use std::{
+    backtrace::Backtrace,
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::task;
+
+#[cfg(test)]
+mod tests;
+
+/// A connection that has been checked out for longer than a
+/// [`LeakDetector`]'s threshold.
+#[derive(Debug, Clone, Copy)]
+pub struct HeldConnection<'a> {
+    /// The connection's ID, as returned by
+    /// [`RusqliteConnection::id()`](crate::RusqliteConnection::id).
+    pub connection: u64,
+
+    /// The path of the database file the connection was opened on.
+    pub path: &'a Path,
+
+    /// How long the connection has been checked out.
+    pub held: Duration,
+
+    /// Where the connection was checked out, if
+    /// [`LeakDetector::capture_backtraces()`] is enabled.
+    pub backtrace: Option<&'a Backtrace>,
+}
+
+type Callback = dyn Fn(&HeldConnection<'_>) + Send + Sync;
+
+/// Reports connections that have been checked out of the pool for too long.
+#[derive(Clone)]
+pub struct LeakDetector {
+    callback: Arc<Callback>,
+    threshold: Duration,
+    backtraces: bool,
+    registry: Arc<Registry>,
+}
+
+impl fmt::Debug for LeakDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LeakDetector")
+            .field("threshold", &self.threshold)
+            .field("backtraces", &self.backtraces)
+            .finish()
+    }
+}
+
+impl LeakDetector {
+    /// Creates a detector that calls `callback` once for each checkout held
+    /// for longer than the threshold, which defaults to 30 seconds.
+    ///
+    /// The callback runs on the detector's background task, while the
+    /// connection is still checked out.
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&HeldConnection<'_>) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(callback),
+            threshold: Duration::from_secs(30),
+            backtraces: false,
+            registry: Arc::default(),
+        }
+    }
+
+    /// Sets how long a connection can be held before it's reported.
+    pub fn threshold(mut self, threshold: Duration) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Captures a backtrace on every checkout, to be reported if the
+    /// connection is then held for too long. This is expensive, so is best
+    /// left to development and tests.
+    pub fn capture_backtraces(mut self, enabled: bool) -> Self {
+        self.backtraces = enabled;
+        self
+    }
+
+    /// Records that connection `id`, opened on `path`, has been checked out.
+    /// A connection that's already checked out keeps its original checkout.
+    pub(crate) fn checked_out(&self, id: u64, path: &Path) {
+        {
+            let mut checkouts = self.registry.checkouts.lock().unwrap();
+            if checkouts.contains_key(&id) {
+                return;
+            }
+            checkouts.insert(
+                id,
+                Checkout {
+                    since: Instant::now(),
+                    path: path.to_path_buf(),
+                    backtrace: self
+                        .backtraces
+                        .then(|| Arc::new(Backtrace::force_capture())),
+                    reported: false,
+                },
+            );
+        }
+
+        // The watching task is started by the first checkout, since the
+        // manager can be created outside a runtime, and by the first after
+        // it's stopped, such as along with its runtime.
+        if !self.registry.watching.swap(true, Ordering::SeqCst) {
+            task::spawn(
+                "bb8_rusqlite::leak_detector",
+                watch(
+                    Arc::downgrade(&self.registry),
+                    self.threshold,
+                    self.callback.clone(),
+                ),
+            );
+        }
+    }
+
+    /// Records that connection `id` has been returned to the pool, or
+    /// closed.
+    pub(crate) fn released(&self, id: u64) {
+        self.registry.checkouts.lock().unwrap().remove(&id);
+    }
+}
+
+/// The checkouts being watched, shared by every connection from a manager.
+#[derive(Debug, Default)]
+struct Registry {
+    checkouts: Mutex<HashMap<u64, Checkout>>,
+    watching: AtomicBool,
+}
+
+#[derive(Debug)]
+struct Checkout {
+    since: Instant,
+    path: PathBuf,
+    backtrace: Option<Arc<Backtrace>>,
+    reported: bool,
+}
+
+/// Clears the registry's `watching` flag when the watching task exits,
+/// however it does.
+struct Watching(Weak<Registry>);
+
+impl Drop for Watching {
+    fn drop(&mut self) {
+        if let Some(registry) = self.0.upgrade() {
+            registry.watching.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+async fn watch(registry: Weak<Registry>, threshold: Duration, callback: Arc<Callback>) {
+    let _watching = Watching(registry.clone());
+    // Checking four times per threshold reports a checkout within a quarter
+    // of a threshold of it being held too long.
+    let interval = (threshold / 4).max(Duration::from_millis(10));
+    loop {
+        tokio::time::sleep(interval).await;
+        let registry = match registry.upgrade() {
+            Some(registry) => registry,
+            None => return,
+        };
+
+        // The callback runs without the registry locked, so that it can use
+        // the pool.
+        let mut held = Vec::new();
+        for (&id, checkout) in registry.checkouts.lock().unwrap().iter_mut() {
+            let duration = checkout.since.elapsed();
+            if !checkout.reported && duration >= threshold {
+                checkout.reported = true;
+                held.push((
+                    id,
+                    checkout.path.clone(),
+                    duration,
+                    checkout.backtrace.clone(),
+                ));
+            }
+        }
+        drop(registry);
+        for (connection, path, held, backtrace) in held {
+            callback(&HeldConnection {
+                connection,
+                path: &path,
+                held,
+                backtrace: backtrace.as_deref(),
+            });
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::*;
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reports_held_connections() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let detector = LeakDetector::new({
+        let reports = reports.clone();
+        move |held| {
+            reports.lock().unwrap().push((
+                held.connection,
+                held.path.to_path_buf(),
+                held.held,
+                held.backtrace.map(ToString::to_string),
+            ))
+        }
+    })
+    .threshold(Duration::from_millis(50))
+    .capture_backtraces(true);
+    let path = temp.file("leak.db");
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(RusqliteConnectionManager::new(&path).with_leak_detector(detector))
+        .await?;
+
+    // Connections returned promptly aren't reported.
+    for _ in 0..3 {
+        pool.acquire().await?;
+    }
+    let held = pool.get().await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // The connection is only reported once, however long it's held.
+    let seen = reports.lock().unwrap().clone();
+    assert_eq!(seen.len(), 1, "{:?}", seen);
+    let (connection, reported_path, duration, backtrace) = &seen[0];
+    assert_eq!(*connection, held.id());
+    assert_eq!(*reported_path, path);
+    assert!(*duration >= Duration::from_millis(50));
+    assert!(backtrace.is_some());
+
+    // A new checkout of the same connection is watched afresh.
+    drop(held);
+    let held = pool.get().await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(reports.lock().unwrap().len(), 2);
+    drop(held);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn without_backtraces() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let backtraces = Arc::new(Mutex::new(Vec::new()));
+    let detector = LeakDetector::new({
+        let backtraces = backtraces.clone();
+        move |held| backtraces.lock().unwrap().push(held.backtrace.is_some())
+    })
+    .threshold(Duration::from_millis(20));
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("leak.db")).with_leak_detector(detector))
+        .await?;
+
+    let _held = pool.acquire().await?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(*backtraces.lock().unwrap(), vec![false]);
+    Ok(())
+}
+
+#[test]
+fn restarts_watching() -> Result<(), anyhow::Error> {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let detector = LeakDetector::new({
+        let reports = reports.clone();
+        move |held| reports.lock().unwrap().push(held.connection)
+    })
+    .threshold(Duration::from_millis(20));
+    let path = Path::new("leak.db");
+
+    // The watching task stops along with its runtime, and the next checkout
+    // starts another on the runtime it's made on.
+    tokio::runtime::Runtime::new()?.block_on(async {
+        detector.checked_out(1, path);
+        detector.released(1);
+    });
+    tokio::runtime::Runtime::new()?.block_on(async {
+        detector.checked_out(2, path);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    });
+    assert_eq!(*reports.lock().unwrap(), vec![2]);
+    Ok(())
+}
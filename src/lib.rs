@@ -1,32 +1,603 @@
-//! rusqlite support for the `bb8` connection pool. Note that in-memory
-//! databases aren't supported, since they are always per-connection, and
-//! therefore don't make sense in a pool environment.
+//! rusqlite support for the `bb8` connection pool.
+//!
+//! A database held in memory can be pooled with
+//! [`RusqliteConnectionManager::from_bytes()`], whose connections all share
+//! it. Private `:memory:` databases still aren't supported, since each
+//! connection would get an empty database of its own, which doesn't make
+//! sense in a pool environment.
 #![deny(missing_docs, missing_debug_implementations)]
 
 use std::{
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
 use async_trait::async_trait;
 use bb8::ManageConnection;
-use rusqlite::{Connection, OpenFlags, NO_PARAMS};
+use rusqlite::{OpenFlags, NO_PARAMS};
+
+#[cfg(feature = "array")]
+mod array;
+mod attach;
+#[cfg(feature = "audit")]
+pub mod audit;
+mod authorizer;
+pub mod backup;
+pub mod blocking;
+mod bulk;
+mod bytes;
+mod cached_pool;
+mod capabilities;
+mod changes;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod checkout;
+mod collation;
+mod commit;
+#[cfg(feature = "begin-concurrent")]
+mod concurrent;
+mod configured;
+mod connection;
+pub mod contention;
+#[cfg(feature = "csv")]
+mod csv_io;
+mod customizer;
+mod deadline;
+pub mod deadpool;
+mod dump;
+mod dynamic;
+mod encryption;
+#[cfg(feature = "error-offset")]
+mod error_offset;
+#[cfg(any(
+    feature = "regexp",
+    feature = "series",
+    feature = "sha3",
+    feature = "uuid"
+))]
+mod extensions;
+mod factory;
+mod ffi_ext;
+mod identity;
+mod leadership;
+pub mod leak;
+mod lifecycle;
+#[cfg(feature = "log")]
+mod logging;
+pub mod maintenance;
+mod math;
+mod memory;
+mod metrics;
+#[cfg(feature = "otel")]
+mod otel;
+mod params;
+mod path_policy;
+mod pipeline;
+mod plan;
+mod pool;
+#[cfg(feature = "preupdate-hook")]
+mod preupdate;
+mod priority;
+#[cfg(feature = "profiling")]
+pub mod profile;
+mod query_cache;
+#[cfg(feature = "macros")]
+mod query_macro;
+mod rate_limit;
+pub mod recovery;
+mod rekey;
+mod reload;
+pub mod replacement;
+pub mod replica;
+pub mod replication;
+mod result_limit;
+mod rotation;
+pub mod schema;
+mod shared_wal;
+mod shutdown;
+mod sql;
+mod statement_timeout;
+mod subscribe;
+mod swap;
+mod task;
+mod temp_dir;
+pub mod tenant;
+mod testing;
+mod transaction;
+mod unit_of_work;
+mod unsafe_fs;
+mod upsert;
+mod uri;
+mod usage;
+mod validate;
+mod wal_hook;
+pub mod watchdog;
+mod windows;
+pub mod write_behind;
+
+#[cfg(feature = "array")]
+pub use array::ValueList;
+pub use authorizer::{AuthorizerPolicy, TableAction};
+#[cfg(feature = "macros")]
+pub use bb8_rusqlite_macros::{query, ToParams};
+pub use bulk::BulkInsertOptions;
+pub use cached_pool::{CachePolicy, CachedPool};
+pub use capabilities::Capabilities;
+pub use changes::{Change, ChangeStream};
+#[cfg(feature = "chaos")]
+pub use chaos::FaultInjection;
+pub use checkout::{ReadConnection, WriteConnection};
+pub use collation::Collation;
+#[cfg(feature = "begin-concurrent")]
+pub use concurrent::ConcurrentOptions;
+pub use configured::{AppliedPragma, ConnectionConfig, PragmaVerification};
+pub use connection::RusqliteConnection;
+#[cfg(feature = "csv")]
+pub use csv_io::CsvImportOptions;
+pub use customizer::PragmaCustomizer;
+pub use deadline::{CancellationToken, ExecutionContext};
+pub use dump::{RestoreProgress, SqlRestoreOptions};
+pub use dynamic::{row_to_map, DynamicRow};
+pub use encryption::{EncryptionBackend, EncryptionKey};
+pub use factory::ConnectionFactory;
+pub use leadership::Leadership;
+pub use lifecycle::LifecycleEvent;
+pub use memory::{MemoryStats, ProcessMemoryStats};
+pub use metrics::{LatencyDistribution, PoolMetricsSnapshot, WaitHistogram};
+pub use params::{NamedParams, ToParams};
+pub use path_policy::PathPolicy;
+pub use pipeline::{PipelineOutput, PipelineStatement};
+pub use plan::{PlanStep, QueryPlan};
+pub use pool::PoolExt;
+#[cfg(feature = "preupdate-hook")]
+pub use preupdate::{PreUpdate, PreUpdateAction};
+pub use priority::{Priority, PriorityPool};
+pub use query_cache::QueryCache;
+pub use rate_limit::{RateLimit, RateLimiter};
+pub use recovery::RecoveryPolicy;
+pub use result_limit::ResultLimit;
+pub use rotation::RetiredFile;
+pub use shutdown::{ShutdownOptions, ShutdownReport};
+pub use subscribe::{RowAction, RowChange};
+pub use testing::{TestPool, TestPoolBuilder};
+pub use transaction::WriteOptions;
+pub use unit_of_work::{UnitOfWork, Work};
+pub use unsafe_fs::UnsafeFsOptions;
+pub use upsert::Upsert;
+pub use usage::ConnectionStats;
+pub use wal_hook::WalCommit;
+pub use windows::WindowsOptions;
+
+// Used by the code the macros generate, which can only name public items.
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::query_macro::{execute, query, value};
+    pub use rusqlite::{types::Value, Result};
+}
 
 #[cfg(test)]
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod tests;
 
+// The code the macros generate names the crate, including in its own tests.
+#[cfg(all(test, feature = "macros"))]
+extern crate self as bb8_rusqlite;
+
 /// A `bb8::ManageConnection` implementation for `rusqlite::Connection`
 /// instances.
 #[derive(Clone, Debug)]
-pub struct RusqliteConnectionManager(Arc<ConnectionOptions>);
+pub struct RusqliteConnectionManager {
+    options: Arc<ConnectionOptions>,
+    files: Arc<Files>,
+    metrics: Arc<metrics::Metrics>,
+    heap_limits_applied: Arc<AtomicBool>,
+    shutdown: Arc<shutdown::State>,
+    settings: Arc<reload::Settings>,
+    subscriptions: Arc<subscribe::Hub>,
+    rekey: Arc<rekey::State>,
+    swap: Arc<swap::State>,
+}
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct ConnectionOptions {
     mode: OpenMode,
-    path: PathBuf,
+    connect_timeout: Option<Duration>,
+    create_dirs: bool,
+    #[cfg(unix)]
+    dir_mode: Option<u32>,
+    #[cfg(unix)]
+    file_mode: Option<u32>,
+    #[cfg_attr(not(windows), allow(dead_code))]
+    windows: WindowsOptions,
+    temp_dir: Option<PathBuf>,
+    immutable: bool,
+    wal_reader: bool,
+    persistent_wal: bool,
+    unsafe_fs: UnsafeFsOptions,
+    path_policy: PathPolicy,
+    max_schema_version: Option<i32>,
+    strict_tables: bool,
+    application_id: Option<i32>,
+    attached: Vec<(String, PathBuf)>,
+    recovery: Option<RecoveryPolicy>,
+    replacement_check: bool,
+    encryption: EncryptionBackend,
+    validation_ttl: Option<Duration>,
+    checkpoint_on_release: bool,
+    wal2: bool,
+    math_functions: bool,
+    soft_heap_limit: Option<i64>,
+    hard_heap_limit: Option<i64>,
+    #[cfg(feature = "profiling")]
+    profiler: Option<profile::Profiler>,
+    #[cfg(feature = "chaos")]
+    faults: Option<Arc<chaos::Injector>>,
+    contention: Option<contention::ContentionMonitor>,
+    connection_stats: bool,
+    leaks: Option<leak::LeakDetector>,
+    execution_limit: Option<Arc<tokio::sync::Semaphore>>,
+    statement_timeout: Option<Duration>,
+    #[cfg(feature = "error-offset")]
+    redact_errors: bool,
+    #[cfg(feature = "log")]
+    slow_query_log: Option<Duration>,
+    result_limits: result_limit::ResultLimits,
+    transaction_behavior: transaction::DefaultBehavior,
+    collation_needed: Option<collation::Resolver>,
+    wal_hook: Option<wal_hook::WalHook>,
+    #[cfg(feature = "preupdate-hook")]
+    preupdate_hook: Option<preupdate::PreUpdateHook>,
+    #[cfg(feature = "audit")]
+    audit: Option<audit::AuditLog>,
+    authorizer: Option<AuthorizerPolicy>,
+    lifecycle: lifecycle::Hooks,
+    configured: Option<configured::Reporter>,
+    verification: Option<PragmaVerification>,
+}
+
+impl ConnectionOptions {
+    fn new(mode: OpenMode) -> Self {
+        Self {
+            mode,
+            connect_timeout: None,
+            create_dirs: false,
+            #[cfg(unix)]
+            dir_mode: None,
+            #[cfg(unix)]
+            file_mode: None,
+            windows: WindowsOptions::default(),
+            temp_dir: None,
+            immutable: false,
+            wal_reader: false,
+            persistent_wal: false,
+            unsafe_fs: UnsafeFsOptions::default(),
+            path_policy: PathPolicy::default(),
+            max_schema_version: None,
+            strict_tables: false,
+            application_id: None,
+            attached: Vec::new(),
+            recovery: None,
+            replacement_check: true,
+            encryption: EncryptionBackend::default(),
+            validation_ttl: None,
+            checkpoint_on_release: false,
+            wal2: false,
+            math_functions: false,
+            soft_heap_limit: None,
+            hard_heap_limit: None,
+            #[cfg(feature = "profiling")]
+            profiler: None,
+            #[cfg(feature = "chaos")]
+            faults: None,
+            contention: None,
+            connection_stats: false,
+            leaks: None,
+            execution_limit: None,
+            statement_timeout: None,
+            #[cfg(feature = "error-offset")]
+            redact_errors: false,
+            #[cfg(feature = "log")]
+            slow_query_log: None,
+            result_limits: result_limit::ResultLimits::default(),
+            transaction_behavior: transaction::DefaultBehavior::default(),
+            collation_needed: None,
+            wal_hook: None,
+            #[cfg(feature = "preupdate-hook")]
+            preupdate_hook: None,
+            #[cfg(feature = "audit")]
+            audit: None,
+            authorizer: None,
+            lifecycle: lifecycle::Hooks::default(),
+            configured: None,
+            verification: None,
+        }
+    }
+
+    fn open(
+        &self,
+        path: &Path,
+        key: Option<&EncryptionKey>,
+    ) -> Result<rusqlite::Connection, Error> {
+        validate::check_flags(self.mode.flags())?;
+        self.path_policy.check(path)?;
+        self.prepare_dirs(path)?;
+        #[cfg(unix)]
+        self.prepare_file(path)?;
+
+        #[cfg(windows)]
+        let windows_path = self.windows.normalize(path)?;
+        #[cfg(windows)]
+        let path = windows_path.path.as_path();
+
+        let mut flags = self.mode.flags();
+        if self.path_policy.refuse_symlinks {
+            flags |= OpenFlags::SQLITE_OPEN_NOFOLLOW;
+        }
+        let params = self.uri_params();
+        let uri;
+        let path = if params.is_empty() {
+            path
+        } else {
+            uri = PathBuf::from(uri::file_uri(path, &params));
+            flags |= OpenFlags::SQLITE_OPEN_URI;
+            &uri
+        };
+        let mut vfs = match &self.mode {
+            OpenMode::WithFlagsAndVFS { vfs, .. } => Some(vfs.clone()),
+            _ => None,
+        };
+        #[cfg(windows)]
+        if vfs.is_none() && windows_path.long {
+            vfs = Some(windows::LONG_PATH_VFS.into());
+        }
+        if let Some(dir) = &self.temp_dir {
+            vfs = Some(temp_dir::vfs_for(vfs.as_deref(), dir)?);
+        }
+
+        let conn = match vfs {
+            Some(vfs) => rusqlite::Connection::open_with_flags_and_vfs(path, flags, &vfs),
+            None => rusqlite::Connection::open_with_flags(path, flags),
+        }?;
+
+        // The key has to be given before anything reads the database.
+        if let Some(key) = key {
+            encryption::apply(&conn, self.encryption, key)?;
+        }
+
+        #[cfg(windows)]
+        self.windows.apply(&conn)?;
+
+        if self.persistent_wal {
+            shared_wal::persist(&conn)?;
+        }
+        if self.wal_reader {
+            shared_wal::probe(&conn)?;
+        }
+
+        #[cfg(feature = "array")]
+        rusqlite::vtab::array::load_module(&conn)?;
+
+        #[cfg(any(
+            feature = "regexp",
+            feature = "series",
+            feature = "sha3",
+            feature = "uuid"
+        ))]
+        extensions::register(&conn)?;
+
+        if self.math_functions && !math::built_in(&conn)? {
+            math::register(&conn)?;
+        }
+
+        if self.recovery.is_some() {
+            recovery::probe(&conn)?;
+        }
+
+        if self.wal2 && !self.mode.flags().contains(OpenFlags::SQLITE_OPEN_READ_ONLY) {
+            let found: String =
+                conn.query_row("PRAGMA journal_mode = wal2", NO_PARAMS, |row| row.get(0))?;
+            if !found.eq_ignore_ascii_case("wal2") {
+                return Err(Error::Wal2Unsupported { found });
+            }
+        }
+
+        if let Some(expected) = self.application_id {
+            self.check_application_id(&conn, expected)?;
+        }
+
+        if let Some(supported) = self.max_schema_version {
+            let found = pool::schema_version(&conn)?;
+            if found > supported {
+                return Err(Error::SchemaTooNew { found, supported });
+            }
+        }
+
+        if self.strict_tables {
+            let tables = schema::non_strict_tables(&conn)?;
+            if !tables.is_empty() {
+                return Err(Error::NotStrict { tables });
+            }
+        }
+
+        for (schema, path) in &self.attached {
+            attach::attach(&conn, schema, path)?;
+        }
+
+        Ok(conn)
+    }
+
+    /// Returns true if the preupdate hook is installed on new connections,
+    /// which it is for the callback or the audit log, unless connections are
+    /// read only, and can't change anything.
+    #[cfg(feature = "preupdate-hook")]
+    fn preupdate_installed(&self) -> bool {
+        #[cfg(feature = "audit")]
+        let audit = self.audit.is_some();
+        #[cfg(not(feature = "audit"))]
+        let audit = false;
+        (self.preupdate_hook.is_some() || audit)
+            && !self.mode.flags().contains(OpenFlags::SQLITE_OPEN_READ_ONLY)
+    }
+
+    /// Returns the URI parameters the database has to be opened with.
+    fn uri_params(&self) -> Vec<(&'static str, &'static str)> {
+        let mut params = Vec::new();
+        if self.immutable {
+            params.extend([("immutable", "1"), ("mode", "ro")]);
+        }
+        if self.wal_reader {
+            params.push(("readonly_shm", "1"));
+        }
+        params.extend(self.unsafe_fs.uri_params());
+        params
+    }
+
+    /// Applies the configured heap limits, which are process wide, through
+    /// `conn`.
+    fn apply_heap_limits(&self, conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+        // The pragmas are used rather than the C functions, since versions of
+        // SQLite before 3.31 ignore the unknown hard_heap_limit pragma, where
+        // the missing function would fail to link.
+        if let Some(limit) = self.soft_heap_limit {
+            conn.query_row(
+                &format!("PRAGMA soft_heap_limit = {}", limit),
+                NO_PARAMS,
+                |_| Ok(()),
+            )?;
+        }
+        if let Some(limit) = self.hard_heap_limit {
+            let mut stmt = conn.prepare(&format!("PRAGMA hard_heap_limit = {}", limit))?;
+            let mut rows = stmt.query(NO_PARAMS)?;
+            while rows.next()?.is_some() {}
+        }
+        Ok(())
+    }
+
+    /// Verifies the application ID, stamping it onto empty databases.
+    fn check_application_id(
+        &self,
+        conn: &rusqlite::Connection,
+        expected: i32,
+    ) -> Result<(), Error> {
+        let found: i32 = conn.query_row("PRAGMA application_id", NO_PARAMS, |row| row.get(0))?;
+        if found == expected {
+            return Ok(());
+        }
+        if found != 0 {
+            return Err(Error::WrongApplication { found, expected });
+        }
+
+        // An unset ID on a database that already has a schema could belong to
+        // anyone, including older versions of this application, so we only
+        // claim databases we can tell are brand new.
+        let empty: bool =
+            conn.query_row("SELECT COUNT(*) = 0 FROM sqlite_master", NO_PARAMS, |row| {
+                row.get(0)
+            })?;
+        if empty && !self.mode.flags().contains(OpenFlags::SQLITE_OPEN_READ_ONLY) {
+            conn.pragma_update(None, "application_id", &expected)?;
+        }
+        Ok(())
+    }
+
+    /// Creates any missing parent directories of `path`, if configured to.
+    fn prepare_dirs(&self, path: &Path) -> std::io::Result<()> {
+        let parent = match path.parent() {
+            Some(parent) if self.create_dirs && !parent.as_os_str().is_empty() => parent,
+            _ => return Ok(()),
+        };
+
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.dir_mode {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(mode);
+        }
+        builder.create(parent)
+    }
+
+    /// Creates the database file with the configured permissions, if it
+    /// doesn't already exist and we'd otherwise let SQLite create it.
+    ///
+    /// SQLite gives the WAL, shared memory, and journal files the same
+    /// permissions as the database file, so this is all we need to cover
+    /// those too.
+    #[cfg(unix)]
+    fn prepare_file(&self, path: &Path) -> std::io::Result<()> {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let mode = match self.file_mode {
+            Some(mode) => mode,
+            None => return Ok(()),
+        };
+        let flags = self.mode.flags();
+        if !flags.contains(OpenFlags::SQLITE_OPEN_CREATE)
+            || flags.contains(OpenFlags::SQLITE_OPEN_READ_ONLY)
+        {
+            return Ok(());
+        }
+
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(mode)
+            .open(path)
+        {
+            // The umask may have masked off some of the bits we asked for.
+            Ok(file) => file.set_permissions(std::fs::Permissions::from_mode(mode)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The database file new connections are opened on, along with any files
+/// that have been rotated away from.
+#[derive(Debug)]
+struct Files {
+    current: RwLock<Arc<DatabaseFile>>,
+    retired: Mutex<Vec<Arc<DatabaseFile>>>,
+    recovering: tokio::sync::Mutex<()>,
 }
 
+/// A database file. Each connection holds a reference to the file it was
+/// opened on, which lets us tell when a retired file has been drained.
 #[derive(Debug)]
+pub(crate) struct DatabaseFile {
+    path: PathBuf,
+    // Set if the file was created by the manager, and should be removed once
+    // nothing uses it.
+    temporary: bool,
+    // The established connections to the file, as opposed to every
+    // reference, which includes connections still being opened.
+    connections: AtomicUsize,
+}
+
+impl DatabaseFile {
+    pub(crate) fn new(path: PathBuf, temporary: bool) -> Self {
+        Self {
+            path,
+            temporary,
+            connections: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Drop for DatabaseFile {
+    fn drop(&mut self) {
+        if self.temporary {
+            bytes::remove(self);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 enum OpenMode {
     Plain,
     WithFlags {
@@ -38,16 +609,264 @@ enum OpenMode {
     },
 }
 
-/// Error wraps errors from both rusqlite and tokio.
+/// Error wraps errors from rusqlite, tokio, and the filesystem.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     /// A rusqlite error.
     #[error("rusqlite error")]
     Rusqlite(#[from] rusqlite::Error),
 
+    /// A statement run by one of the [`PoolExt`] helpers failed to prepare,
+    /// such as because of a syntax error, at `offset` bytes into its SQL.
+    #[cfg(feature = "error-offset")]
+    #[error(
+        "statement failed to prepare at offset {offset}{}",
+        sql.as_deref()
+            .map(|sql| format!(": {}", error_offset::snippet(sql, *offset)))
+            .unwrap_or_default()
+    )]
+    Prepare {
+        /// The error from SQLite.
+        #[source]
+        source: rusqlite::Error,
+        /// Where in the SQL preparing it failed, in bytes.
+        offset: usize,
+        /// The statement's SQL, unless errors are
+        /// [redacted](RusqliteConnectionManager::with_redacted_errors).
+        sql: Option<String>,
+    },
+
     /// A tokio join handle error.
     #[error("tokio join error")]
     TokioJoin(#[from] tokio::task::JoinError),
+
+    /// An I/O error.
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+
+    /// An error serializing named parameters.
+    #[cfg(feature = "serde")]
+    #[error("serialization error")]
+    Serialize(#[from] serde_json::Error),
+
+    /// A CSV error.
+    #[cfg(feature = "csv")]
+    #[error("CSV error")]
+    Csv(#[from] csv::Error),
+
+    /// A CSV import without a header didn't specify which columns to insert
+    /// into.
+    #[cfg(feature = "csv")]
+    #[error("columns must be given when importing CSV without a header")]
+    CsvColumnsRequired,
+
+    /// The pool timed out while waiting for a connection.
+    #[error("timed out waiting for a pooled connection")]
+    TimedOut,
+
+    /// The operation requires the database to be in WAL mode.
+    #[error("database is not in WAL mode")]
+    NotWalMode,
+
+    /// `wal2` journal mode was requested, but SQLite left the database in
+    /// another mode, which means it was built without wal2 support.
+    #[error("wal2 journal mode is not supported; the database is in {found} mode")]
+    Wal2Unsupported {
+        /// The journal mode the database is in.
+        found: String,
+    },
+
+    /// There is no replicated database state to restore from.
+    #[error("no replica is available")]
+    NoReplica,
+
+    /// The tenant ID can't be used to name a database file. See
+    /// [`TenantPools::pool()`](tenant::TenantPools::pool).
+    #[error("invalid tenant ID {0:?}")]
+    InvalidTenant(String),
+
+    /// Opening another tenant's pool would exceed the connection budget, and
+    /// every open tenant has connections in use, or evicted tenants' pools
+    /// are still shutting down.
+    #[error("tenant connection budget exhausted")]
+    TenantBudgetExhausted,
+
+    /// The tenant has connections checked out, or its pool is held, so it
+    /// can't be evicted. See
+    /// [`TenantPools::evict()`](tenant::TenantPools::evict).
+    #[error("tenant {0:?} is in use")]
+    TenantInUse(String),
+
+    /// The database path failed validation.
+    #[error("invalid database path {}: {reason}", path.display())]
+    InvalidPath {
+        /// The path that was validated.
+        path: PathBuf,
+
+        /// Why the path is invalid.
+        reason: String,
+    },
+
+    /// The manager's [`OpenFlags`] can't be used together, or can't be used
+    /// in a pool.
+    #[error("invalid open flags {flags:?}: {reason}")]
+    InvalidFlags {
+        /// The flags that were checked.
+        flags: OpenFlags,
+
+        /// Why the flags are invalid.
+        reason: &'static str,
+    },
+
+    /// The database's application ID doesn't match the configured ID.
+    #[error("database belongs to application ID {found}, not {expected}")]
+    WrongApplication {
+        /// The application ID in the database.
+        found: i32,
+
+        /// The configured application ID.
+        expected: i32,
+    },
+
+    /// The database's schema version is newer than the application supports.
+    #[error("database schema version {found} is newer than the supported version {supported}")]
+    SchemaTooNew {
+        /// The schema version in the database.
+        found: i32,
+
+        /// The newest schema version the application supports.
+        supported: i32,
+    },
+
+    /// The database has tables that aren't declared `STRICT`, and
+    /// [`RusqliteConnectionManager::with_strict_tables()`] is set.
+    #[error("tables are not STRICT: {}", tables.join(", "))]
+    NotStrict {
+        /// The tables that aren't strict, ordered by name.
+        tables: Vec<String>,
+    },
+
+    /// A connection opened with [`RusqliteConnectionManager::read_only_wal()`]
+    /// couldn't use the database's WAL index, usually because no writer has
+    /// the database open to keep its `-shm` file in place.
+    #[error("the WAL index is missing or unreadable")]
+    WalIndexUnavailable {
+        /// The error SQLite reported.
+        source: rusqlite::Error,
+    },
+
+    /// Opening a connection took longer than the configured connect timeout.
+    #[error("timed out opening a connection")]
+    ConnectTimeout,
+
+    /// The operation requires an empty database.
+    #[error("database is not empty")]
+    DatabaseNotEmpty,
+
+    /// The connection is open on a database file that has been rotated away
+    /// from.
+    #[error("connection belongs to a retired database file")]
+    Retired,
+
+    /// The database file the connection was opened on has been deleted or
+    /// replaced.
+    #[error("database file has been deleted or replaced")]
+    Replaced,
+
+    /// The connection was opened with settings that have since been replaced
+    /// with [`RusqliteConnectionManager::reload_pragmas()`].
+    #[error("connection was opened with settings that have since been reloaded")]
+    Reloaded,
+
+    /// The connection was checked out while the database was being rekeyed
+    /// with [`RusqliteConnectionManager::rekey()`].
+    #[error("database is being rekeyed")]
+    Rekeying,
+
+    /// The connection was checked out while the database file was being
+    /// swapped with [`RusqliteConnectionManager::swap_database()`].
+    #[error("database file is being swapped")]
+    Swapping,
+
+    /// The database can't be encrypted, because SQLite wasn't built with the
+    /// configured encryption extension.
+    #[error("SQLite was built without encryption support")]
+    EncryptionUnsupported,
+
+    /// The pool has been shut down with
+    /// [`RusqliteConnectionManager::shutdown()`].
+    #[error("pool has been shut down")]
+    ShutDown,
+
+    /// The [`write_behind`] writer has been stopped, so no more writes can be
+    /// queued.
+    #[error("write-behind writer has stopped")]
+    WriteBehindStopped,
+
+    /// The deadline of the task's [`ExecutionContext`] passed before the
+    /// work finished.
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+
+    /// A cancellation token of the task's [`ExecutionContext`] was cancelled
+    /// before the work finished.
+    #[error("cancelled")]
+    Cancelled,
+
+    /// A pragma given to [`RusqliteConnectionManager::with_pragmas()`]
+    /// didn't take effect, and [`PragmaVerification::enforce()`] is set.
+    #[error("pragma {name} was set to {requested:?}, but is {found:?}")]
+    PragmaNotApplied {
+        /// The name of the pragma.
+        name: String,
+
+        /// The value the pragma was set to.
+        requested: rusqlite::types::Value,
+
+        /// The value the pragma read back as.
+        found: rusqlite::types::Value,
+    },
+
+    /// `PRAGMA integrity_check` found problems with the database.
+    #[error("integrity check failed: {}", problems.join("; "))]
+    IntegrityCheck {
+        /// The problems reported by SQLite.
+        problems: Vec<String>,
+    },
+
+    /// `PRAGMA foreign_key_check` found rows that break their foreign keys.
+    #[error("foreign key check found {} violations", violations.len())]
+    ForeignKeyCheck {
+        /// The rows that break their foreign keys.
+        violations: Vec<schema::ForeignKeyViolation>,
+    },
+
+    /// A transaction across attached databases wouldn't be atomic, because
+    /// one of the databases doesn't use a rollback journal.
+    #[error("transactions involving {schema} aren't atomic with journal_mode = {journal_mode}")]
+    NotAtomic {
+        /// The database's schema name.
+        schema: String,
+        /// The database's journal mode.
+        journal_mode: String,
+    },
+
+    /// A query returned more than the manager's limit on rows or bytes,
+    /// and was stopped before loading the rest.
+    #[error("the query's result is larger than the limit of {limit}")]
+    ResultTooLarge {
+        /// The limit the result went over.
+        limit: ResultLimit,
+    },
+}
+
+impl From<bb8::RunError<Error>> for Error {
+    fn from(e: bb8::RunError<Error>) -> Self {
+        match e {
+            bb8::RunError::User(e) => e,
+            bb8::RunError::TimedOut => Error::TimedOut,
+        }
+    }
 }
 
 impl RusqliteConnectionManager {
@@ -56,59 +875,965 @@ impl RusqliteConnectionManager {
     where
         P: AsRef<Path>,
     {
-        Self(Arc::new(ConnectionOptions {
-            mode: OpenMode::Plain,
-            path: path.as_ref().into(),
-        }))
+        Self::with_mode(path.as_ref(), OpenMode::Plain)
     }
 
-    /// Analogous to `rusqlite::Connection::open_with_flags()`.
+    /// Analogous to `rusqlite::Connection::open_with_flags()`. Flags that
+    /// conflict fail with [`Error::InvalidFlags`] when connections are opened,
+    /// or in [`validate()`](Self::validate);
+    /// [`try_new_with_flags()`](Self::try_new_with_flags) checks them here.
     pub fn new_with_flags<P>(path: P, flags: OpenFlags) -> Self
     where
         P: AsRef<Path>,
     {
-        Self(Arc::new(ConnectionOptions {
-            mode: OpenMode::WithFlags { flags },
-            path: path.as_ref().into(),
-        }))
+        Self::with_mode(path.as_ref(), OpenMode::WithFlags { flags })
     }
 
-    /// Analogous to `rusqlite::Connection::open_with_flags_and_vfs()`.
+    /// Analogous to `rusqlite::Connection::open_with_flags_and_vfs()`. As
+    /// with [`new_with_flags()`](Self::new_with_flags), flags that conflict
+    /// fail when connections are opened.
     pub fn new_with_flags_and_vfs<P>(path: P, flags: OpenFlags, vfs: &str) -> Self
     where
         P: AsRef<Path>,
     {
-        Self(Arc::new(ConnectionOptions {
-            mode: OpenMode::WithFlagsAndVFS {
+        Self::with_mode(
+            path.as_ref(),
+            OpenMode::WithFlagsAndVFS {
                 flags,
                 vfs: vfs.into(),
             },
-            path: path.as_ref().into(),
-        }))
+        )
+    }
+
+    /// Like [`new_with_flags()`](Self::new_with_flags), but fails with
+    /// [`Error::InvalidFlags`] if the flags conflict, rather than when
+    /// connections are opened.
+    ///
+    /// Flags conflict if they're both read only and read write, neither, or
+    /// read only and `CREATE`; if they ask for both `NO_MUTEX` and
+    /// `FULL_MUTEX`, or both a shared and private cache; or if they include
+    /// `MEMORY`, since each connection would get a database of its own.
+    /// `NO_MUTEX` on its own is fine, and is rusqlite's default, since a
+    /// pooled connection is only used by one task at a time.
+    pub fn try_new_with_flags<P>(path: P, flags: OpenFlags) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        validate::check_flags(flags)?;
+        Ok(Self::new_with_flags(path, flags))
+    }
+
+    /// Like [`new_with_flags_and_vfs()`](Self::new_with_flags_and_vfs), but
+    /// fails with [`Error::InvalidFlags`] if the flags conflict, as
+    /// [`try_new_with_flags()`](Self::try_new_with_flags) does.
+    pub fn try_new_with_flags_and_vfs<P>(
+        path: P,
+        flags: OpenFlags,
+        vfs: &str,
+    ) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        validate::check_flags(flags)?;
+        Ok(Self::new_with_flags_and_vfs(path, flags, vfs))
+    }
+
+    /// Opens a database that nothing changes while the pool is using it, such
+    /// as one shipped in a container image or on a squashfs mount.
+    ///
+    /// Connections are opened read only, with SQLite's `immutable=1` URI
+    /// option, which skips locking and change detection, and never looks for
+    /// a journal or WAL. That lets the database be pooled from a read-only
+    /// filesystem, where SQLite would otherwise fail to create the WAL's
+    /// shared memory file, and [`validate()`](Self::validate) doesn't check
+    /// that the directory is writable.
+    ///
+    /// Changing the file while it's open, even from another process, can
+    /// give wrong query results or corruption errors.
+    pub fn read_only_immutable<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let mut manager = Self::new_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        );
+        manager.options_mut().immutable = true;
+        manager
+    }
+
+    /// Opens read only connections to a WAL database written by another
+    /// process, which may be running as another user, so that the database's
+    /// directory and `-shm` file can't be written to.
+    ///
+    /// Connections are opened with the `readonly_shm=1` URI option, so SQLite
+    /// only ever reads the `-shm` file, the WAL's index. The index and WAL
+    /// have to exist for that, which they only do while the writer has the
+    /// database open, unless it keeps them with
+    /// [`with_persistent_wal()`](Self::with_persistent_wal). If they don't,
+    /// or can't be read, `connect()` fails with
+    /// [`Error::WalIndexUnavailable`].
+    pub fn read_only_wal<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        let mut manager = Self::new_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        );
+        manager.options_mut().wal_reader = true;
+        manager
+    }
+
+    fn with_mode(path: &Path, mode: OpenMode) -> Self {
+        Self {
+            options: Arc::new(ConnectionOptions::new(mode)),
+            files: Arc::new(Files {
+                current: RwLock::new(Arc::new(DatabaseFile::new(path.into(), false))),
+                retired: Mutex::new(Vec::new()),
+                recovering: tokio::sync::Mutex::new(()),
+            }),
+            metrics: Arc::default(),
+            heap_limits_applied: Arc::default(),
+            shutdown: Arc::default(),
+            settings: Arc::default(),
+            subscriptions: Arc::default(),
+            rekey: Arc::default(),
+            swap: Arc::default(),
+        }
+    }
+
+    /// Sets a deadline for opening each new connection. If it passes,
+    /// `connect()` fails with [`Error::ConnectTimeout`], and the connection is
+    /// closed in the background once the slow open eventually finishes.
+    ///
+    /// This guards against opens that hang indefinitely, such as on a dead
+    /// network filesystem.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.options_mut().connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Creates any missing parent directories of the database path before
+    /// opening connections, instead of failing with `SQLITE_CANTOPEN`.
+    pub fn with_create_dirs(mut self, create_dirs: bool) -> Self {
+        self.options_mut().create_dirs = create_dirs;
+        self
+    }
+
+    /// Sets the permissions used for directories created by
+    /// [`with_create_dirs()`](Self::with_create_dirs), before the umask is
+    /// applied. Defaults to `0o777`.
+    #[cfg(unix)]
+    pub fn with_dir_mode(mut self, mode: u32) -> Self {
+        self.options_mut().dir_mode = Some(mode);
+        self
+    }
+
+    /// Sets the permissions of database files created by the pool, such as
+    /// `0o600` to keep other users on the host from reading them. This also
+    /// applies to the WAL, shared memory, and journal files, since SQLite
+    /// copies the database file's permissions to those.
+    ///
+    /// Existing files are left untouched.
+    #[cfg(unix)]
+    pub fn with_file_mode(mut self, mode: u32) -> Self {
+        self.options_mut().file_mode = Some(mode);
+        self
+    }
+
+    /// Sets options that control how databases are opened on Windows. These
+    /// are ignored on other platforms.
+    pub fn with_windows_options(mut self, options: WindowsOptions) -> Self {
+        self.options_mut().windows = options;
+        self
+    }
+
+    /// Turns off some of SQLite's safeguards, so that databases can be used
+    /// on network filesystems that don't support them. Read the
+    /// [`UnsafeFsOptions`] documentation before using this: each option risks
+    /// corrupting the database if its conditions aren't met.
+    pub fn with_unsafe_fs_options(mut self, options: UnsafeFsOptions) -> Self {
+        self.options_mut().unsafe_fs = options;
+        self
+    }
+
+    /// Sets how the database path is resolved, and whether it may go through
+    /// symbolic links. With [`PathPolicy::canonicalize()`], the path is
+    /// resolved here, and [`path()`](Self::path) returns the result.
+    pub fn with_path_policy(mut self, policy: PathPolicy) -> Self {
+        let file = self.current_file();
+        // Files the manager created itself are removed once they're
+        // replaced, and were never anywhere but where it put them.
+        if policy.canonicalize && !file.temporary {
+            *self.files.current.write().unwrap() =
+                Arc::new(DatabaseFile::new(policy.resolve(&file.path), false));
+        }
+        self.options_mut().path_policy = policy;
+        self
+    }
+
+    /// Places the temporary files SQLite creates for this pool's connections
+    /// (such as temporary tables and indices that spill out of memory, and
+    /// the scratch copy made by `VACUUM`) in `dir`, instead of the process
+    /// wide temporary directory. The directory must already exist.
+    pub fn with_temp_dir<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.options_mut().temp_dir = Some(dir.as_ref().into());
+        self
+    }
+
+    /// Refuses to open connections to databases with a schema version (as
+    /// stored in `PRAGMA user_version`) newer than `version`, failing with
+    /// [`Error::SchemaTooNew`] instead. This cheaply protects against old
+    /// binaries running against data that has already been migrated forward.
+    ///
+    /// Combine this with `bb8::Builder::min_idle()` to have building the pool
+    /// fail, rather than the first checkout.
+    pub fn with_max_schema_version(mut self, version: i32) -> Self {
+        self.options_mut().max_schema_version = Some(version);
+        self
+    }
+
+    /// Refuses to open connections to databases with tables that aren't
+    /// declared `STRICT`, failing with [`Error::NotStrict`] instead, so that
+    /// every column's type is known to be enforced. Strict tables need SQLite
+    /// 3.37 or later; before that, only databases without any tables can be
+    /// opened.
+    pub fn with_strict_tables(mut self, strict: bool) -> Self {
+        self.options_mut().strict_tables = strict;
+        self
+    }
+
+    /// Identifies the database as belonging to this application, via
+    /// `PRAGMA application_id`.
+    ///
+    /// New, empty databases are stamped with `id` when first opened. Opening
+    /// a database stamped with any other ID fails with
+    /// [`Error::WrongApplication`]; existing databases without an ID are
+    /// accepted, but left unstamped.
+    pub fn with_application_id(mut self, id: i32) -> Self {
+        self.options_mut().application_id = Some(id);
+        self
+    }
+
+    /// Attaches the database at `path` to every connection as `schema`, so
+    /// that its tables can be queried as `schema.table`, and written in the
+    /// same transactions as the main database's: see
+    /// [`PoolExt::attached_transaction()`].
+    ///
+    /// Databases are attached in the order they're added, after the checks
+    /// on the main database. A relative `path` is relative to the working
+    /// directory, and settings such as the journal mode only apply to the
+    /// main database unless they name the schema.
+    pub fn with_attached_database(mut self, schema: &str, path: impl AsRef<Path>) -> Self {
+        self.options_mut()
+            .attached
+            .push((schema.into(), path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Recovers from corruption detected while opening connections by
+    /// quarantining and rebuilding the database. See the
+    /// [`recovery`](crate::recovery) module for details.
+    pub fn with_recovery(mut self, policy: RecoveryPolicy) -> Self {
+        self.options_mut().recovery = Some(policy);
+        self
+    }
+
+    /// Checks, each time a connection is checked out, whether the database
+    /// file it was opened on has since been deleted or replaced (such as by a
+    /// volume being remounted), and if so, reopens it on whatever is at the
+    /// path now. Without this, the connection would carry on reading and
+    /// writing the unlinked file.
+    ///
+    /// This is enabled by default, and is only supported on Unix, where
+    /// files are identified by their device and inode numbers.
+    pub fn with_replacement_check(mut self, check: bool) -> Self {
+        self.options_mut().replacement_check = check;
+        self
+    }
+
+    /// Skips the `SELECT 1` that validates connections on checkout (when
+    /// `bb8::Builder::test_on_check_out()` is enabled) if the connection was
+    /// opened or last validated within the last `ttl`. Connections that have
+    /// gone longer are validated again, however recently they were in use,
+    /// since returning a connection to the pool doesn't show that it works.
+    ///
+    /// The checks for retired and replaced database files still run on every
+    /// checkout.
+    pub fn with_validation_ttl(mut self, ttl: Duration) -> Self {
+        self.options_mut().validation_ttl = Some(ttl);
+        self
+    }
+
+    /// Runs a `PASSIVE` checkpoint whenever a connection that has committed a
+    /// transaction since it was checked out is returned to the pool. This
+    /// keeps the WAL small under bursts of writes, without scheduling
+    /// checkpoints separately.
+    ///
+    /// A passive checkpoint never waits for other connections, but it does
+    /// copy pages into the database, synchronously on the thread returning
+    /// the connection. It does nothing if the database isn't in WAL mode.
+    pub fn with_checkpoint_on_release(mut self, checkpoint: bool) -> Self {
+        self.options_mut().checkpoint_on_release = checkpoint;
+        self
+    }
+
+    /// Sets how many changes to each table are buffered for subscribers from
+    /// [`PoolExt::subscribe()`]. Subscribers that fall further behind than
+    /// this miss the oldest changes. The default is 1,024.
+    ///
+    /// This must be set before anyone subscribes.
+    pub fn with_subscription_capacity(mut self, capacity: usize) -> Self {
+        self.subscriptions = Arc::new(subscribe::Hub::new(capacity));
+        self
+    }
+
+    /// Ensures SQLite's math functions, such as `sqrt()`, `ln()`, and
+    /// `pow()`, are available on every connection. If SQLite was built
+    /// without them, Rust implementations are registered in their place, so
+    /// queries using them don't depend on how SQLite was built.
+    pub fn with_math_functions(mut self, math_functions: bool) -> Self {
+        self.options_mut().math_functions = math_functions;
+        self
+    }
+
+    /// Puts the database in `wal2` journal mode, which alternates between two
+    /// WAL files so that one can be checkpointed while the other is written,
+    /// and so a steady stream of writes never stalls waiting for a
+    /// checkpoint to reset the WAL.
+    ///
+    /// This requires SQLite to be built from the `wal2` branch. With other
+    /// builds, connections fail to open with [`Error::Wal2Unsupported`].
+    /// The [`WalWatchdog`](crate::watchdog::WalWatchdog) and
+    /// [`metrics()`](Self::metrics) account for both WAL files.
+    pub fn with_wal2(mut self, wal2: bool) -> Self {
+        self.options_mut().wal2 = wal2;
+        self
+    }
+
+    /// Leaves the `-wal` and `-shm` files in place when the last connection
+    /// to a WAL database closes, rather than checkpointing and deleting them,
+    /// so that readers opened with [`read_only_wal()`](Self::read_only_wal)
+    /// can still use the database while no writer has it open.
+    pub fn with_persistent_wal(mut self, persistent: bool) -> Self {
+        self.options_mut().persistent_wal = persistent;
+        self
+    }
+
+    /// Logs operations run through the [`PoolExt`] helpers that take longer
+    /// than `threshold`, from when they have a connection until their work
+    /// is done, at `warn` level through the `log` facade.
+    ///
+    /// Connections log the stages of their life with the `log` feature
+    /// whether or not this is set, under the `bb8_rusqlite::lifecycle`
+    /// target: opening and closing at `debug`, checkouts and returns at
+    /// `trace`, and failures to close at `warn`.
+    #[cfg(feature = "log")]
+    pub fn with_slow_query_log(mut self, threshold: Duration) -> Self {
+        self.options_mut().slow_query_log = Some(threshold);
+        self
+    }
+
+    /// Calls `callback` each time a statement finishes running on any of the
+    /// pool's connections, with the statement's SQL and how long it took.
+    ///
+    /// The callback runs synchronously on the thread running the statement,
+    /// so it should be quick: hand anything expensive off to another task.
+    #[cfg(feature = "profiling")]
+    pub fn with_profiler<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&profile::StatementProfile<'_>) + Send + Sync + 'static,
+    {
+        self.options_mut().profiler = Some(profile::Profiler::new(callback));
+        self
+    }
+
+    /// Injects faults into opening connections and running operations, so
+    /// that retry logic and timeouts can be tested. See [`FaultInjection`].
+    #[cfg(feature = "chaos")]
+    pub fn with_fault_injection(mut self, faults: FaultInjection) -> Self {
+        self.options_mut().faults = Some(chaos::Injector::new(faults));
+        self
+    }
+
+    /// Reports lock contention between the pool's connections through
+    /// `monitor`, which replaces each connection's busy timeout. See the
+    /// [`contention`](crate::contention) module for details.
+    pub fn with_contention_monitor(mut self, monitor: contention::ContentionMonitor) -> Self {
+        self.options_mut().contention = Some(monitor);
+        self
+    }
+
+    /// Counts the statements each connection runs and the rows they return,
+    /// for the [`ConnectionStats`] in the pool's [metrics](Self::metrics).
+    /// Checkouts are counted regardless, and time spent waiting on locks only
+    /// with a [`ContentionMonitor`](contention::ContentionMonitor).
+    ///
+    /// This installs a trace callback on each connection, which costs a
+    /// little for every row returned.
+    pub fn with_connection_stats(mut self, enabled: bool) -> Self {
+        self.options_mut().connection_stats = enabled;
+        self
+    }
+
+    /// Reports connections that have been checked out for too long through
+    /// `detector`. See the [`leak`] module for details.
+    ///
+    /// Connections are told apart by their IDs, so a detector shouldn't be
+    /// shared with other managers, other than clones of this one.
+    pub fn with_leak_detector(mut self, detector: leak::LeakDetector) -> Self {
+        self.options_mut().leaks = Some(detector);
+        self
+    }
+
+    /// Limits how many of the pool's connections can be running work from
+    /// the [`PoolExt`] helpers at once to `limit`, separately from the size
+    /// of the pool. This keeps more connections open, with their statement
+    /// caches warm, than there are threads allowed to block on SQLite.
+    ///
+    /// Helpers wait for their turn after checking out a connection, and
+    /// within an [`ExecutionContext`] scope stop waiting when it expires.
+    /// Connections used directly, through `bb8::Pool::get()`, aren't
+    /// limited. Clones of the manager share the limit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    pub fn with_max_concurrent_queries(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "limit must be at least one");
+        self.options_mut().execution_limit = Some(Arc::new(tokio::sync::Semaphore::new(limit)));
+        self
+    }
+
+    /// Interrupts any statement that runs for longer than `timeout`, on
+    /// every connection, through a progress handler. Interrupted statements
+    /// fail with `SQLITE_INTERRUPT`, and as with `sqlite3_interrupt()`, an
+    /// interrupted write within a transaction rolls back the whole
+    /// transaction.
+    ///
+    /// This is a guardrail against runaway queries, separate from
+    /// [`ExecutionContext`] deadlines, which apply to a whole operation and
+    /// only to work done through the [`PoolExt`] helpers. A statement is
+    /// timed from when it starts running, including any time the caller
+    /// spends between steps, such as reading the rows it's returned so far.
+    pub fn with_statement_timeout(mut self, timeout: Duration) -> Self {
+        self.options_mut().statement_timeout = Some(timeout);
+        self
+    }
+
+    /// Leaves the SQL out of [`Error::Prepare`], keeping only the offset, for
+    /// statements whose SQL can include values that shouldn't be logged.
+    #[cfg(feature = "error-offset")]
+    pub fn with_redacted_errors(mut self, redact: bool) -> Self {
+        self.options_mut().redact_errors = redact;
+        self
+    }
+
+    /// Limits the queries run by [`PoolExt::query_named()`],
+    /// [`PoolExt::query_rows_dynamic()`], [`QueryCache`], and the `query!`
+    /// macro to returning `rows` rows, failing with
+    /// [`Error::ResultTooLarge`] as soon as a query returns more, rather than
+    /// loading the rest into memory. Queries run on connections directly
+    /// aren't limited.
+    pub fn with_max_result_rows(mut self, rows: usize) -> Self {
+        self.options_mut().result_limits.rows = Some(rows);
+        self
+    }
+
+    /// Limits the same queries as
+    /// [`with_max_result_rows()`](Self::with_max_result_rows) to returning
+    /// `bytes` bytes of values, counting integers and reals as eight bytes
+    /// each, text and blobs as their length, and nulls as nothing.
+    pub fn with_max_result_bytes(mut self, bytes: u64) -> Self {
+        self.options_mut().result_limits.bytes = Some(bytes);
+        self
+    }
+
+    /// Sets the behavior transactions begin with unless they choose one,
+    /// which is `Deferred` by default, as it is in SQLite.
+    ///
+    /// A deferred transaction that reads before it writes can fail with
+    /// `SQLITE_BUSY` at its first write, without waiting for the busy
+    /// timeout, if another connection has written since it started. Making
+    /// transactions `Immediate` takes the write lock when they begin
+    /// instead, where the busy timeout applies.
+    ///
+    /// This applies to [`RusqliteConnection::transaction()`] on pooled
+    /// connections, and to the transactions the [`PoolExt`] helpers write in,
+    /// such as [`bulk_insert()`](PoolExt::bulk_insert). It doesn't apply to
+    /// [`write_transaction()`](PoolExt::write_transaction), which is always
+    /// immediate, or to transactions begun with SQL.
+    pub fn with_transaction_behavior(mut self, behavior: rusqlite::TransactionBehavior) -> Self {
+        self.options_mut().transaction_behavior = transaction::DefaultBehavior(behavior);
+        self
+    }
+
+    /// Installs collations on each connection as SQLite finds it needs them,
+    /// such as when a query uses an index declared with a custom collation.
+    ///
+    /// `resolve` is given the name of the missing collation, and returns its
+    /// comparison function, or `None` to fail the statement with SQLite's
+    /// usual "no such collation sequence" error. It may be called more than
+    /// once for the same name, on different connections.
+    pub fn with_collation_needed<F>(mut self, resolve: F) -> Self
+    where
+        F: Fn(&str) -> Option<Collation> + Send + Sync + 'static,
+    {
+        self.options_mut().collation_needed = Some(collation::Resolver::new(resolve));
+        self
+    }
+
+    /// Restricts the statements each connection may run to those `policy`
+    /// allows, once the manager has finished configuring it. Statements that
+    /// break the policy fail to prepare with `SQLITE_AUTH`.
+    ///
+    /// The manager's own statements on checked out connections, such as
+    /// validation queries, checkpoints, and `PRAGMA query_only` for
+    /// [`PoolExt::get_read()`], don't touch any tables, and so are never
+    /// denied.
+    pub fn with_authorizer(mut self, policy: AuthorizerPolicy) -> Self {
+        self.options_mut().authorizer = Some(policy);
+        self
+    }
+
+    /// Calls `callback` each time the pool opens a connection, with how long
+    /// the open took.
+    ///
+    /// This and the other lifecycle callbacks run synchronously, on whichever
+    /// thread is handling the connection, so they should be quick.
+    pub fn on_create<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&LifecycleEvent<'_>) + Send + Sync + 'static,
+    {
+        self.options_mut().lifecycle.on_create = Some(Arc::new(callback));
+        self
+    }
+
+    /// Calls `callback` each time a connection is checked out through
+    /// [`PoolExt`], with how long the checkout waited. Checkouts made
+    /// directly through `bb8::Pool::get()` aren't timed, and so aren't
+    /// reported.
+    pub fn on_acquire<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&LifecycleEvent<'_>) + Send + Sync + 'static,
+    {
+        self.options_mut().lifecycle.on_acquire = Some(Arc::new(callback));
+        self
+    }
+
+    /// Calls `callback` each time a connection is returned to the pool, with
+    /// how long it was checked out.
+    pub fn on_release<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&LifecycleEvent<'_>) + Send + Sync + 'static,
+    {
+        self.options_mut().lifecycle.on_release = Some(Arc::new(callback));
+        self
+    }
+
+    /// Calls `callback` each time a connection is closed, or taken out of the
+    /// pool for good, with how long it was open.
+    pub fn on_destroy<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&LifecycleEvent<'_>) + Send + Sync + 'static,
+    {
+        self.options_mut().lifecycle.on_destroy = Some(Arc::new(callback));
+        self
+    }
+
+    /// Calls `callback` each time the pool opens a connection, with the
+    /// pragmas, extensions, and hooks the manager applied to it, and the
+    /// value of each pragma read back afterwards, so that settings SQLite
+    /// quietly ignored can be spotted.
+    ///
+    /// Settings applied by a customizer given to the pool builder come after
+    /// this, and so aren't included.
+    pub fn on_configured<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&ConnectionConfig<'_>) + Send + Sync + 'static,
+    {
+        self.options_mut().configured = Some(configured::Reporter::new(callback));
+        self
+    }
+
+    /// Calls `callback` when a connection fails to close, such as because a
+    /// statement prepared through the raw handle was never finalized. The
+    /// failure is also counted in the pool's [metrics](Self::metrics).
+    ///
+    /// Without this, the failure only shows up in the metrics: the handle is
+    /// left for SQLite to free once it can, rather than panicking, as
+    /// rusqlite does when dropping a connection fails to close it.
+    pub fn on_close_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&LifecycleEvent<'_>, &rusqlite::Error) + Send + Sync + 'static,
+    {
+        self.options_mut().lifecycle.on_close_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Calls `callback` after each commit to the WAL, with the number of
+    /// pages the WAL now holds, for replication and checkpointing decisions.
+    ///
+    /// The callback runs synchronously, on the committing connection's
+    /// thread, while the transaction is still being committed, so it should
+    /// be quick, and mustn't use the database.
+    ///
+    /// SQLite's automatic checkpoints are run from its own WAL hook, which
+    /// this replaces, so automatic checkpoints are run after the callback
+    /// with the `wal_autocheckpoint` setting each connection had when it was
+    /// opened. Setting `wal_autocheckpoint` on a connection after that
+    /// removes the callback from it, so set it with
+    /// [`with_pragmas()`](Self::with_pragmas), rather than a pool
+    /// customizer.
+    pub fn on_wal_commit<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&WalCommit<'_>) + Send + Sync + 'static,
+    {
+        self.options_mut().wal_hook = Some(wal_hook::WalHook::new(callback));
+        self
+    }
+
+    /// Calls `callback` before each change to a row, with the row's values
+    /// before and after the change, as the building block for change data
+    /// capture and audit logs. The callback isn't installed on read only
+    /// connections, which can't change anything.
+    ///
+    /// The callback runs synchronously, on the changing connection's thread,
+    /// in the middle of the statement making the change, so it should be
+    /// quick, and mustn't use the database. It's also called for changes
+    /// that are later rolled back.
+    #[cfg(feature = "preupdate-hook")]
+    pub fn on_preupdate<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&PreUpdate<'_>) + Send + Sync + 'static,
+    {
+        self.options_mut().preupdate_hook = Some(preupdate::PreUpdateHook::new(callback));
+        self
+    }
+
+    /// Records changes to the tables configured in `log` on every connection
+    /// that can write. See the [`audit`](crate::audit) module for details.
+    #[cfg(feature = "audit")]
+    pub fn with_audit_log(mut self, log: audit::AuditLog) -> Self {
+        self.options_mut().audit = Some(log);
+        self
+    }
+
+    /// Sets SQLite's soft heap limit, in bytes. Once SQLite's allocations
+    /// reach the limit, it tries to free memory (chiefly by shrinking page
+    /// caches) before allocating more, but allocations still succeed.
+    ///
+    /// The limit applies to the whole process, not just this pool, and is
+    /// set once, when the pool creates its first connection.
+    pub fn with_soft_heap_limit(mut self, bytes: i64) -> Self {
+        self.options_mut().soft_heap_limit = Some(bytes);
+        self
+    }
+
+    /// Sets SQLite's hard heap limit, in bytes. Allocations that would take
+    /// SQLite past the limit fail with `SQLITE_NOMEM`.
+    ///
+    /// As with [`with_soft_heap_limit()`](Self::with_soft_heap_limit), the
+    /// limit applies to the whole process and is set when the first
+    /// connection is created. It requires SQLite 3.31 or later, and is
+    /// ignored by older versions. A hard limit can only be lowered, so this
+    /// has no effect if the process already has a lower one.
+    pub fn with_hard_heap_limit(mut self, bytes: i64) -> Self {
+        self.options_mut().hard_heap_limit = Some(bytes);
+        self
+    }
+
+    /// Applies `pragmas` to each connection the manager opens, after the
+    /// manager's other options.
+    ///
+    /// Unlike a customizer given to the pool builder, these can be replaced
+    /// with [`reload_pragmas()`](Self::reload_pragmas) while the pool is
+    /// running.
+    pub fn with_pragmas(mut self, pragmas: PragmaCustomizer) -> Self {
+        self.settings = Arc::new(self.settings.with(|current| {
+            current.pragmas = Arc::new(pragmas);
+        }));
+        self
+    }
+
+    /// Reads back the pragmas given to [`with_pragmas()`](Self::with_pragmas)
+    /// on each new connection, and either fails to connect or warns when one
+    /// didn't take effect, such as `journal_mode = WAL` on a filesystem that
+    /// doesn't support it. Without this, such pragmas are only visible
+    /// through [`on_configured()`](Self::on_configured).
+    pub fn with_pragma_verification(mut self, verification: PragmaVerification) -> Self {
+        self.options_mut().verification = Some(verification);
+        self
+    }
+
+    /// Replaces the pragmas applied to new connections, for this manager and
+    /// any clones of it, such as the one owned by the pool.
+    ///
+    /// Existing connections aren't changed. Instead, each one is recycled the
+    /// next time it's checked out or returned to the pool, so the new
+    /// settings roll out as the pool turns over. Connections that are checked
+    /// out keep their settings until they're returned.
+    pub fn reload_pragmas(&self, pragmas: PragmaCustomizer) {
+        self.settings
+            .update(|current| current.pragmas = Arc::new(pragmas));
+    }
+
+    /// Opens connections on an encrypted database, by giving SQLite the key
+    /// before anything else touches the database. Strings are used as
+    /// passphrases.
+    ///
+    /// This requires SQLite to be built with an encryption extension, which
+    /// is SQLCipher unless set otherwise with
+    /// [`with_encryption_backend()`](Self::with_encryption_backend). Stock
+    /// builds ignore the key, and open the database unencrypted.
+    ///
+    /// The key can be changed later with [`rekey()`](Self::rekey).
+    pub fn with_key<K>(mut self, key: K) -> Self
+    where
+        K: Into<EncryptionKey>,
+    {
+        let key = Arc::new(key.into());
+        self.settings = Arc::new(self.settings.with(|current| current.key = Some(key)));
+        self
+    }
+
+    /// Sets which encryption extension SQLite was built with, and so how keys
+    /// are passed to it. The default is SQLCipher.
+    pub fn with_encryption_backend(mut self, backend: EncryptionBackend) -> Self {
+        self.options_mut().encryption = backend;
+        self
+    }
+
+    /// Returns a [`ConnectionFactory`] that opens connections as this manager
+    /// does, but synchronously, such as for an r2d2 pool sharing the same
+    /// configuration.
+    pub fn connection_factory(&self) -> ConnectionFactory {
+        ConnectionFactory::new(self.clone())
+    }
+
+    /// Returns true if `conn` was opened with settings that have since been
+    /// reloaded.
+    fn is_outdated(&self, conn: &RusqliteConnection) -> bool {
+        conn.generation() != self.settings.generation()
+    }
+
+    fn options_mut(&mut self) -> &mut ConnectionOptions {
+        Arc::make_mut(&mut self.options)
+    }
+
+    fn current_file(&self) -> Arc<DatabaseFile> {
+        self.files.current.read().unwrap().clone()
+    }
+
+    /// Opens and configures a connection to `file`, blocking until it's
+    /// ready.
+    pub(crate) fn open_blocking(
+        &self,
+        file: Arc<DatabaseFile>,
+    ) -> Result<RusqliteConnection, Error> {
+        let options = &self.options;
+        let metrics = self.metrics.clone();
+        let heap_limits_applied = &self.heap_limits_applied;
+        let settings = self.settings.current();
+        let subscriptions = self.subscriptions.clone();
+
+        let started = Instant::now();
+        let conn = options.open(&file.path, settings.key.as_deref())?;
+        if !heap_limits_applied.swap(true, Ordering::SeqCst) {
+            if let Err(e) = options.apply_heap_limits(&conn) {
+                heap_limits_applied.store(false, Ordering::SeqCst);
+                return Err(e.into());
+            }
+        }
+        let identity = if options.replacement_check {
+            identity::FileIdentity::of(&file.path)
+        } else {
+            None
+        };
+        settings.pragmas.apply(&conn)?;
+        let customized = if options.configured.is_some() || options.verification.is_some() {
+            configured::read_back(&conn, settings.pragmas.pragmas())
+        } else {
+            Vec::new()
+        };
+        if let Some(verification) = &options.verification {
+            verification.check(&file.path, &customized)?;
+        }
+        let configured = options.configured.as_ref().map(|reporter| {
+            let mut pragmas = configured::read_back(&conn, configured::manager_pragmas(options));
+            pragmas.extend(customized);
+            (reporter, file.path.clone(), pragmas)
+        });
+        let conn = RusqliteConnection::new(
+            conn,
+            file,
+            identity,
+            metrics,
+            options.lifecycle.clone(),
+            settings.generation,
+        );
+        conn.metrics().sample_memory(conn.memory_stats()?);
+        #[cfg(feature = "profiling")]
+        let conn = match &options.profiler {
+            // The contention monitor and statement counting take over the
+            // connection's trace callback, and forward profiles
+            // themselves.
+            Some(profiler) if options.contention.is_none() && !options.connection_stats => {
+                conn.with_profiler(profiler)?
+            }
+            _ => conn,
+        };
+        let conn = match &options.contention {
+            Some(monitor) => {
+                #[cfg(feature = "profiling")]
+                let monitor = &match &options.profiler {
+                    Some(profiler) => monitor.clone().forward_to(profiler.clone()),
+                    None => monitor.clone(),
+                };
+                conn.with_contention_monitor(monitor, options.connection_stats)?
+            }
+            None if options.connection_stats => conn.with_statement_counts(
+                #[cfg(feature = "profiling")]
+                options.profiler.clone(),
+            )?,
+            None => conn,
+        };
+        let conn = match &options.collation_needed {
+            Some(resolver) => conn.with_collation_resolver(resolver)?,
+            None => conn,
+        };
+        let conn = match &options.wal_hook {
+            Some(hook) => conn.with_wal_hook(hook)?,
+            None => conn,
+        };
+        #[cfg(feature = "preupdate-hook")]
+        let conn = if options.preupdate_installed() {
+            conn.with_preupdate_hook(
+                options.preupdate_hook.as_ref(),
+                #[cfg(feature = "audit")]
+                options.audit.as_ref(),
+            )
+        } else {
+            conn
+        };
+        let conn = conn
+            .with_subscriptions(subscriptions)
+            .with_commit_hook(options.checkpoint_on_release);
+        #[cfg(feature = "chaos")]
+        let conn = match &options.faults {
+            Some(faults) => conn.with_fault_injection(faults.clone()),
+            None => conn,
+        };
+        let conn = match &options.leaks {
+            Some(leaks) => conn.with_leak_detector(leaks.clone()),
+            None => conn,
+        };
+        let conn = match &options.execution_limit {
+            Some(permits) => conn.with_execution_limit(permits.clone()),
+            None => conn,
+        };
+        let conn = match options.statement_timeout {
+            Some(timeout) => conn.with_statement_timeout(timeout),
+            None => conn,
+        };
+        #[cfg(feature = "error-offset")]
+        let conn = conn.with_redacted_errors(options.redact_errors);
+        #[cfg(feature = "log")]
+        let conn = conn.with_slow_query_log(options.slow_query_log);
+        let conn = conn
+            .with_result_limits(options.result_limits)
+            .with_transaction_behavior(options.transaction_behavior);
+        let conn = match &options.authorizer {
+            Some(policy) => conn.with_authorizer(policy),
+            None => conn,
+        };
+        if let Some((reporter, path, pragmas)) = configured {
+            reporter.report(conn.id(), &path, options, &pragmas);
+        }
+        conn.created(started.elapsed());
+        Ok(conn)
+    }
+
+    /// Checks that a connection being checked out can still be used, blocking
+    /// on SQLite if it has to.
+    pub(crate) fn check_connection(&self, conn: &mut RusqliteConnection) -> Result<(), Error> {
+        if self.shutdown.refuses_checkout() {
+            self.shutdown.close(conn);
+            return Err(Error::ShutDown);
+        }
+        if self.rekey.refuses_checkout() {
+            return Err(Error::Rekeying);
+        }
+        if self.swap.refuses_checkout() {
+            return Err(Error::Swapping);
+        }
+        if self.is_retired(conn) {
+            return Err(Error::Retired);
+        }
+        if self.is_outdated(conn) {
+            return Err(Error::Reloaded);
+        }
+        if conn.is_replaced() {
+            return Err(Error::Replaced);
+        }
+        conn.track_subscriptions();
+        if let Some(ttl) = self.options.validation_ttl {
+            if conn.healthy_within(ttl) {
+                return Ok(());
+            }
+        }
+        conn.query_row("SELECT 1", NO_PARAMS, |_| Ok(()))?;
+        conn.mark_healthy();
+        Ok(())
+    }
+
+    async fn open(&self, file: Arc<DatabaseFile>) -> Result<RusqliteConnection, Error> {
+        // Technically, we don't need to use spawn_blocking() here, but doing so
+        // means we won't inadvertantly block this task for any length of time,
+        // since rusqlite is inherently synchronous.
+        let manager = self.clone();
+        let open =
+            task::spawn_blocking("bb8_rusqlite::connect", move || manager.open_blocking(file));
+
+        // If the timeout elapses, dropping the JoinHandle detaches the blocking
+        // task, which will drop (and therefore close) the connection whenever
+        // it does finish opening.
+        match self.options.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, open)
+                .await
+                .map_err(|_| Error::ConnectTimeout)??,
+            None => open.await?,
+        }
     }
 }
 
 #[async_trait]
 impl ManageConnection for RusqliteConnectionManager {
-    type Connection = Connection;
+    type Connection = RusqliteConnection;
     type Error = Error;
 
     async fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        let options = self.0.clone();
-
-        // Technically, we don't need to use spawn_blocking() here, but doing so
-        // means we won't inadvertantly block this task for any length of time,
-        // since rusqlite is inherently synchronous.
-        Ok(tokio::task::spawn_blocking(move || match &options.mode {
-            OpenMode::Plain => rusqlite::Connection::open(&options.path),
-            OpenMode::WithFlags { flags } => {
-                rusqlite::Connection::open_with_flags(&options.path, *flags)
+        if self.shutdown.is_closing() {
+            return Err(Error::ShutDown);
+        }
+        let _opening = self.rekey.opening().await;
+        let _swapping = self.swap.opening().await;
+        #[cfg(feature = "chaos")]
+        if let Some(faults) = &self.options.faults {
+            faults.connect().await?;
+        }
+        let file = self.current_file();
+        match self.open(file.clone()).await {
+            Err(e) if e.is_corruption() && self.options.recovery.is_some() => {
+                self.recover_file(file, e).await?;
+                self.open(self.current_file()).await
             }
-            OpenMode::WithFlagsAndVFS { flags, vfs } => {
-                rusqlite::Connection::open_with_flags_and_vfs(&options.path, *flags, &vfs)
-            }
-        })
-        .await??)
+            result => result,
+        }
     }
 
     async fn is_valid(
@@ -120,15 +1845,46 @@ impl ManageConnection for RusqliteConnectionManager {
         // some reason, but means that we depend on the tokio multi-threaded
         // runtime being active. (We can't use spawn_blocking() here because
         // Connection isn't Sync.)
-        tokio::task::block_in_place(|| conn.execute("SELECT 1", NO_PARAMS))?;
-        Ok(())
+        conn.checked_out(None);
+        #[cfg(feature = "chaos")]
+        if let Some(faults) = &self.options.faults {
+            faults.validation().await;
+        }
+        task::block_in_place(|| self.check_connection(conn))
     }
 
-    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
         // There's no real concept of a "broken" connection in SQLite: if the
         // handle is still open, then we're good. (And we know the handle is
         // still open, because Connection::close() consumes the Connection, in
-        // which case we're definitely not here.)
+        // which case we're definitely not here.) We do want connections to
+        // retired files to drain out of the pool, though.
+        //
+        // This is also a convenient, exclusive point at which to sample the
+        // connection's memory usage for the pool's metrics.
+        conn.released();
+        conn.settle_commits();
+        #[cfg(feature = "audit")]
+        {
+            conn.flush_audit();
+            // Writing the audit table commits too.
+            conn.settle_commits();
+        }
+        if self.shutdown.is_closing() {
+            self.shutdown.close(conn);
+            return true;
+        }
+        if conn.take_committed() && self.options.checkpoint_on_release {
+            // Passive checkpoints don't wait on anything, and a failure here
+            // leaves the WAL to the next checkpoint.
+            let _ = conn.query_row("PRAGMA wal_checkpoint(PASSIVE)", NO_PARAMS, |_| Ok(()));
+        }
+        if let Ok(stats) = conn.memory_stats() {
+            conn.metrics().sample_memory(stats);
+        }
+        if self.is_retired(conn) || self.is_outdated(conn) {
+            return true;
+        }
         false
     }
 }
@@ -0,0 +1,158 @@
+//! Callbacks for each stage of a pooled connection's life.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// A stage in the life of one of the pool's connections, as passed to the
+/// callbacks set with
+/// [`RusqliteConnectionManager::on_create()`](crate::RusqliteConnectionManager::on_create)
+/// and friends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LifecycleEvent<'a> {
+    /// The connection's ID, as returned by
+    /// [`RusqliteConnection::id()`](crate::RusqliteConnection::id).
+    pub connection: u64,
+
+    /// The path of the database file the connection was opened on.
+    pub path: &'a Path,
+
+    /// How long the stage took: opening the connection for `on_create`,
+    /// waiting for it for `on_acquire`, holding it for `on_release`, and its
+    /// whole life for `on_destroy` and `on_close_error`.
+    ///
+    /// This is `None` for `on_release` if the checkout wasn't timed, which
+    /// happens when a connection is checked out with `bb8::Pool::get()` and
+    /// `test_on_check_out` disabled.
+    pub duration: Option<Duration>,
+}
+
+type Callback = Arc<dyn Fn(&LifecycleEvent<'_>) + Send + Sync>;
+type ErrorCallback = Arc<dyn Fn(&LifecycleEvent<'_>, &rusqlite::Error) + Send + Sync>;
+
+/// The lifecycle callbacks configured on a manager.
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) on_create: Option<Callback>,
+    pub(crate) on_acquire: Option<Callback>,
+    pub(crate) on_release: Option<Callback>,
+    pub(crate) on_destroy: Option<Callback>,
+    pub(crate) on_close_error: Option<ErrorCallback>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_create", &self.on_create.is_some())
+            .field("on_acquire", &self.on_acquire.is_some())
+            .field("on_release", &self.on_release.is_some())
+            .field("on_destroy", &self.on_destroy.is_some())
+            .field("on_close_error", &self.on_close_error.is_some())
+            .finish()
+    }
+}
+
+/// Tracks a connection's lifecycle, calling `on_destroy` when dropped.
+#[derive(Debug)]
+pub(crate) struct Lifecycle {
+    hooks: Hooks,
+    // This is a copy, rather than a reference to the DatabaseFile, so it
+    // doesn't count as an open connection to a retired file.
+    path: PathBuf,
+    id: u64,
+    created: Instant,
+    acquired: Option<Instant>,
+}
+
+impl Lifecycle {
+    pub(crate) fn new(hooks: Hooks, path: PathBuf, id: u64) -> Self {
+        Self {
+            hooks,
+            path,
+            id,
+            created: Instant::now(),
+            acquired: None,
+        }
+    }
+
+    fn event(&self, duration: Option<Duration>) -> LifecycleEvent<'_> {
+        LifecycleEvent {
+            connection: self.id,
+            path: &self.path,
+            duration,
+        }
+    }
+
+    fn call(&self, callback: &Option<Callback>, duration: Option<Duration>) {
+        if let Some(callback) = callback {
+            callback(&self.event(duration));
+        }
+    }
+
+    pub(crate) fn created(&self, open: Duration) {
+        #[cfg(feature = "log")]
+        log::debug!(
+            "opened connection {} to {} in {:?}",
+            self.id,
+            self.path.display(),
+            open
+        );
+        self.call(&self.hooks.on_create, Some(open));
+    }
+
+    /// Records that the connection has been checked out. `wait` is only
+    /// known for checkouts made through `PoolExt`.
+    pub(crate) fn acquired(&mut self, wait: Option<Duration>) {
+        self.acquired = Some(Instant::now());
+        #[cfg(feature = "log")]
+        match wait {
+            Some(wait) => log::trace!("checked out connection {} after {:?}", self.id, wait),
+            None => log::trace!("checked out connection {}", self.id),
+        }
+        if let Some(wait) = wait {
+            self.call(&self.hooks.on_acquire, Some(wait));
+        }
+    }
+
+    pub(crate) fn close_failed(&self, e: &rusqlite::Error) {
+        #[cfg(feature = "log")]
+        log::warn!(
+            "failed to close connection {} to {}: {}",
+            self.id,
+            self.path.display(),
+            e
+        );
+        if let Some(callback) = &self.hooks.on_close_error {
+            callback(&self.event(Some(self.created.elapsed())), e);
+        }
+    }
+
+    pub(crate) fn released(&mut self) {
+        let held = self.acquired.take().map(|acquired| acquired.elapsed());
+        #[cfg(feature = "log")]
+        match held {
+            Some(held) => log::trace!("returned connection {} after {:?}", self.id, held),
+            None => log::trace!("returned connection {}", self.id),
+        }
+        self.call(&self.hooks.on_release, held);
+    }
+}
+
+impl Drop for Lifecycle {
+    fn drop(&mut self) {
+        #[cfg(feature = "log")]
+        log::debug!(
+            "closed connection {} to {} after {:?}",
+            self.id,
+            self.path.display(),
+            self.created.elapsed()
+        );
+        self.call(&self.hooks.on_destroy, Some(self.created.elapsed()));
+    }
+}
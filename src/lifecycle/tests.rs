@@ -0,0 +1,97 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hooks() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("lifecycle.db");
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let record = |stage: &'static str| {
+        let events = events.clone();
+        let path = path.clone();
+        move |event: &crate::LifecycleEvent<'_>| {
+            assert_eq!(event.path, path);
+            events
+                .lock()
+                .unwrap()
+                .push((stage, event.connection, event.duration.is_some()))
+        }
+    };
+    let manager = RusqliteConnectionManager::new(&path)
+        .on_create(record("create"))
+        .on_acquire(record("acquire"))
+        .on_release(record("release"))
+        .on_destroy(record("destroy"));
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+
+    let id = {
+        let conn = pool.acquire().await?;
+        conn.id()
+    };
+    // Untimed checkouts are still reported when they're returned.
+    pool.get().await?;
+    pool.dedicated_connection().await?.into_inner();
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            ("create", id, true),
+            ("acquire", id, true),
+            ("release", id, true),
+            ("release", id, true),
+            ("create", id + 1, true),
+            ("destroy", id + 1, true),
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn close_error() -> Result<(), anyhow::Error> {
+    use rusqlite::ffi;
+
+    let temp = TempDir::new()?;
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let manager = RusqliteConnectionManager::new(temp.file("lifecycle.db")).on_close_error({
+        let errors = errors.clone();
+        move |event, e| {
+            errors
+                .lock()
+                .unwrap()
+                .push((event.connection, e.to_string()))
+        }
+    });
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(manager.clone())
+        .await?;
+
+    // A statement prepared through the raw handle, and never finalized, keeps
+    // the connection from closing.
+    let conn = pool.dedicated_connection().await?;
+    let id = conn.id();
+    let mut stmt = std::ptr::null_mut();
+    let rc = unsafe {
+        ffi::sqlite3_prepare_v2(
+            conn.handle(),
+            b"SELECT 1\0".as_ptr() as *const _,
+            -1,
+            &mut stmt,
+            std::ptr::null_mut(),
+        )
+    };
+    assert_eq!(rc, ffi::SQLITE_OK);
+    drop(conn);
+
+    {
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, id);
+    }
+    assert_eq!(manager.metrics(&pool).close_errors, 1);
+
+    // Finalizing the statement lets SQLite free the connection.
+    unsafe { ffi::sqlite3_finalize(stmt) };
+    Ok(())
+}
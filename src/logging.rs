@@ -0,0 +1,35 @@
+//! Slow operations, logged through the `log` facade with the `log` feature.
+//! Lifecycle events are logged by [`Lifecycle`](crate::lifecycle::Lifecycle)
+//! itself.
+
+use std::time::Duration;
+
+use crate::{pool::Operation, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// Logs `op` if it took longer than `conn`'s slow query threshold.
+pub(crate) fn operation_finished(conn: &RusqliteConnection, op: &Operation<'_>, took: Duration) {
+    let threshold = match conn.slow_query_log() {
+        Some(threshold) if took >= threshold => threshold,
+        _ => return,
+    };
+    match op.statement {
+        Some(sql) => log::warn!(
+            "{} on connection {} took {:?}, longer than {:?}: {}",
+            op.name,
+            conn.id(),
+            took,
+            threshold,
+            sql
+        ),
+        None => log::warn!(
+            "{} on connection {} took {:?}, longer than {:?}",
+            op.name,
+            conn.id(),
+            took,
+            threshold
+        ),
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    sync::{Mutex, Once},
+    time::Duration,
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+/// Records every message logged in the test binary, since a logger can only
+/// be set once.
+struct Capture;
+
+static MESSAGES: Mutex<Vec<(String, Level, String)>> = Mutex::new(Vec::new());
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        MESSAGES.lock().unwrap().push((
+            record.target().into(),
+            record.level(),
+            record.args().to_string(),
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+fn capture() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&Capture).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+/// Returns the messages logged so far that contain `needle`.
+fn logged(needle: &str) -> Vec<(String, Level, String)> {
+    MESSAGES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, _, message)| message.contains(needle))
+        .cloned()
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lifecycle() -> Result<(), anyhow::Error> {
+    capture();
+    let temp = TempDir::new()?;
+    let path = temp.file("lifecycle-log.db");
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(&path))
+        .await?;
+    let id = pool.acquire().await?.id();
+    drop(pool);
+
+    let needle = path.display().to_string();
+    let opened = logged(&needle);
+    assert!(
+        opened.iter().any(
+            |(target, level, message)| target == "bb8_rusqlite::lifecycle"
+                && *level == Level::Debug
+                && message.starts_with(&format!("opened connection {} ", id))
+        ),
+        "{:?}",
+        opened
+    );
+    assert!(opened
+        .iter()
+        .any(|(_, _, message)| message.starts_with(&format!("closed connection {} ", id))));
+
+    let checkouts = logged(&format!("checked out connection {} after", id));
+    assert!(checkouts.iter().all(|(_, level, _)| *level == Level::Trace));
+    assert!(!checkouts.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_operations() -> Result<(), anyhow::Error> {
+    capture();
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(
+            RusqliteConnectionManager::new(temp.file("slow-log.db"))
+                .with_slow_query_log(Duration::ZERO),
+        )
+        .await?;
+
+    pool.query_rows_dynamic("SELECT 'slow operation'", Vec::<i64>::new())
+        .await?;
+    let slow = logged("SELECT 'slow operation'");
+    assert_eq!(slow.len(), 1, "{:?}", slow);
+    assert_eq!(slow[0].1, Level::Warn);
+    assert!(slow[0].2.starts_with("query_rows_dynamic on connection "));
+
+    // Without a threshold, nothing is logged.
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("fast-log.db")))
+        .await?;
+    pool.query_rows_dynamic("SELECT 'fast operation'", Vec::<i64>::new())
+        .await?;
+    assert!(logged("SELECT 'fast operation'").is_empty());
+    Ok(())
+}
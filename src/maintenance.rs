@@ -0,0 +1,348 @@
+//! Scheduled database maintenance.
+//!
+//! A [`MaintenancePlan`] lists maintenance [`Task`]s and when each should run.
+//! Once started, the plan runs as a background task against the pool, checking
+//! out a connection for each task as it comes due. Tasks can optionally be
+//! deferred while the pool is busy, so that a checkpoint or integrity check
+//! doesn't compete with a burst of application queries.
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{Connection, NO_PARAMS};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{
+    pool::{self, Operation},
+    schema, task, Error, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+const DAY: u64 = 24 * 60 * 60;
+const WEEK: u64 = 7 * DAY;
+
+/// A maintenance operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    /// Checkpoints the WAL with `PRAGMA wal_checkpoint`.
+    Checkpoint(CheckpointMode),
+
+    /// Runs `PRAGMA optimize`, which refreshes query planner statistics where
+    /// SQLite thinks they would help.
+    Optimize,
+
+    /// Runs `PRAGMA incremental_vacuum`, freeing up to the given number of
+    /// pages, or every free page if `None`. This only has an effect on
+    /// databases with `auto_vacuum = INCREMENTAL`.
+    IncrementalVacuum(Option<u32>),
+
+    /// Runs `PRAGMA integrity_check`. Problems are reported as
+    /// [`Error::IntegrityCheck`].
+    IntegrityCheck,
+
+    /// Runs `PRAGMA foreign_key_check`. Violations are reported as
+    /// [`Error::ForeignKeyCheck`].
+    ForeignKeyCheck,
+}
+
+/// The `PRAGMA wal_checkpoint` modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    /// Checkpoints as many frames as possible without waiting on readers or
+    /// writers.
+    Passive,
+
+    /// Waits for writers, then checkpoints every frame.
+    Full,
+
+    /// As `Full`, and then waits for readers so the next writer restarts the
+    /// WAL from the beginning.
+    Restart,
+
+    /// As `Restart`, and also truncates the WAL file to zero bytes.
+    Truncate,
+}
+
+impl Task {
+    fn name(self) -> &'static str {
+        match self {
+            Task::Checkpoint(_) => "wal_checkpoint",
+            Task::Optimize => "optimize",
+            Task::IncrementalVacuum(_) => "incremental_vacuum",
+            Task::IntegrityCheck => "integrity_check",
+            Task::ForeignKeyCheck => "foreign_key_check",
+        }
+    }
+}
+
+impl CheckpointMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Full => "FULL",
+            CheckpointMode::Restart => "RESTART",
+            CheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+/// When a task runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Runs repeatedly, with the given interval between runs, which must be
+    /// greater than zero. The first run happens one interval after the plan
+    /// starts.
+    Every(Duration),
+
+    /// Runs once a day, at the given UTC time.
+    Daily {
+        /// The hour, from 0 to 23.
+        hour: u8,
+
+        /// The minute, from 0 to 59.
+        minute: u8,
+    },
+
+    /// Runs once a week, on the given day at the given UTC time.
+    Weekly {
+        /// The day of the week, from 0 for Monday to 6 for Sunday.
+        weekday: u8,
+
+        /// The hour, from 0 to 23.
+        hour: u8,
+
+        /// The minute, from 0 to 59.
+        minute: u8,
+    },
+}
+
+impl Schedule {
+    /// Returns how long from `now` until the task should next run.
+    pub(crate) fn next(&self, now: SystemTime) -> Duration {
+        let time = |hour: u8, minute: u8| (u64::from(hour) * 60 + u64::from(minute)) * 60;
+        match *self {
+            Schedule::Every(interval) => interval,
+            Schedule::Daily { hour, minute } => until(now, DAY, time(hour, minute)),
+            Schedule::Weekly {
+                weekday,
+                hour,
+                minute,
+            } => {
+                // The epoch was a Thursday, three days into its week.
+                let offset = (u64::from(weekday) + 7 - 3) % 7;
+                until(now, WEEK, offset * DAY + time(hour, minute))
+            }
+        }
+    }
+}
+
+/// Returns how long from `now` until `target` seconds into the next period of
+/// `period` seconds, counting periods from the epoch.
+fn until(now: SystemTime, period: u64, target: u64) -> Duration {
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let into_period = Duration::from_secs(now.as_secs() % period)
+        + Duration::from_nanos(now.subsec_nanos().into());
+    let target = Duration::from_secs(target % period);
+    if target > into_period {
+        target - into_period
+    } else {
+        Duration::from_secs(period) - (into_period - target)
+    }
+}
+
+type ErrorCallback = Arc<dyn Fn(Task, &Error) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy)]
+struct Deferral {
+    busy: u32,
+    retry: Duration,
+    max: Duration,
+}
+
+/// A set of maintenance tasks and their schedules.
+#[derive(Clone, Default)]
+pub struct MaintenancePlan {
+    jobs: Vec<(Task, Schedule)>,
+    deferral: Option<Deferral>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl fmt::Debug for MaintenancePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MaintenancePlan")
+            .field("jobs", &self.jobs)
+            .field("deferral", &self.deferral)
+            .finish()
+    }
+}
+
+impl MaintenancePlan {
+    /// Creates an empty plan.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a task to the plan. The same task may be added more than once
+    /// with different schedules.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `schedule` is `Schedule::Every(Duration::ZERO)`, which would
+    /// run the task in a busy loop.
+    pub fn task(mut self, task: Task, schedule: Schedule) -> Self {
+        assert!(
+            schedule != Schedule::Every(Duration::ZERO),
+            "the interval must be greater than zero"
+        );
+        self.jobs.push((task, schedule));
+        self
+    }
+
+    /// Defers tasks while more than `busy` connections are checked out of the
+    /// pool, checking again every `retry`. A task that has been deferred for
+    /// `max` runs regardless, so maintenance can't be starved forever.
+    pub fn defer_when_busy(mut self, busy: u32, retry: Duration, max: Duration) -> Self {
+        self.deferral = Some(Deferral { busy, retry, max });
+        self
+    }
+
+    /// Sets a callback for tasks that fail. The plan keeps running after an
+    /// error, and the task runs again at its next scheduled time.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Task, &Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Starts running the plan in the background against `pool`.
+    pub fn start(self, pool: bb8::Pool<RusqliteConnectionManager>) -> MaintenanceHandle {
+        let (stop, mut stopped) = oneshot::channel();
+        let now = (Instant::now(), SystemTime::now());
+        let mut jobs: Vec<Job> = self
+            .jobs
+            .into_iter()
+            .map(|(task, schedule)| Job {
+                task,
+                schedule,
+                due: now.0 + schedule.next(now.1),
+                deferred_since: None,
+            })
+            .collect();
+        let deferral = self.deferral;
+        let on_error = self.on_error;
+
+        let join = task::spawn("bb8_rusqlite::maintenance", async move {
+            loop {
+                let next = match jobs.iter().map(|job| job.due).min() {
+                    Some(next) => next,
+                    None => return (&mut stopped).await.unwrap_or(()),
+                };
+                let wait = next.saturating_duration_since(Instant::now());
+                if tokio::time::timeout(wait, &mut stopped).await.is_ok() {
+                    return;
+                }
+
+                for job in jobs.iter_mut().filter(|job| job.due <= Instant::now()) {
+                    if let Some(deferral) = &deferral {
+                        let state = pool.state();
+                        let busy = state.connections - state.idle_connections;
+                        let since = *job.deferred_since.get_or_insert_with(Instant::now);
+                        if busy > deferral.busy && since.elapsed() < deferral.max {
+                            job.due = Instant::now() + deferral.retry;
+                            continue;
+                        }
+                    }
+
+                    if let Err(e) = run(&pool, job.task).await {
+                        if let Some(callback) = &on_error {
+                            callback(job.task, &e);
+                        }
+                    }
+                    job.deferred_since = None;
+                    job.due = Instant::now() + job.schedule.next(SystemTime::now());
+                }
+            }
+        });
+
+        MaintenanceHandle { stop, join }
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    task: Task,
+    schedule: Schedule,
+    due: Instant,
+    deferred_since: Option<Instant>,
+}
+
+/// A handle to a running maintenance plan. Dropping the handle also stops the
+/// plan.
+#[derive(Debug)]
+pub struct MaintenanceHandle {
+    stop: oneshot::Sender<()>,
+    join: JoinHandle<()>,
+}
+
+impl MaintenanceHandle {
+    /// Returns the ID of the plan's task.
+    pub fn task_id(&self) -> tokio::task::Id {
+        self.join.id()
+    }
+
+    /// Stops the plan, waiting for any task that is currently running to
+    /// finish.
+    pub async fn stop(self) -> Result<(), Error> {
+        let _ = self.stop.send(());
+        Ok(self.join.await?)
+    }
+}
+
+/// Runs a single maintenance task immediately.
+pub async fn run(pool: &bb8::Pool<RusqliteConnectionManager>, task: Task) -> Result<(), Error> {
+    pool::run(pool, Operation::new(task.name()), move |conn| match task {
+        Task::Checkpoint(mode) => {
+            let sql = format!("PRAGMA wal_checkpoint({})", mode.as_str());
+            Ok(conn.query_row(&sql, NO_PARAMS, |_| Ok(()))?)
+        }
+        Task::Optimize => Ok(conn.execute_batch("PRAGMA optimize")?),
+        Task::IncrementalVacuum(pages) => {
+            let sql = match pages {
+                Some(pages) => format!("PRAGMA incremental_vacuum({})", pages),
+                None => "PRAGMA incremental_vacuum".into(),
+            };
+            Ok(conn.execute_batch(&sql)?)
+        }
+        Task::IntegrityCheck => check_integrity(conn, "integrity_check"),
+        Task::ForeignKeyCheck => {
+            let violations = schema::foreign_key_check(conn)?;
+            if violations.is_empty() {
+                Ok(())
+            } else {
+                Err(Error::ForeignKeyCheck { violations })
+            }
+        }
+    })
+    .await
+}
+
+/// Runs `PRAGMA integrity_check` or `PRAGMA quick_check`, failing with
+/// [`Error::IntegrityCheck`] if it finds any problems.
+pub(crate) fn check_integrity(conn: &Connection, pragma: &str) -> Result<(), Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA {}", pragma))?;
+    let problems = stmt
+        .query_map(NO_PARAMS, |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if problems.len() == 1 && problems[0] == "ok" {
+        Ok(())
+    } else {
+        Err(Error::IntegrityCheck { problems })
+    }
+}
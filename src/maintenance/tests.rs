@@ -0,0 +1,179 @@
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::tests::TempDir;
+
+#[test]
+fn daily() {
+    let schedule = Schedule::Daily {
+        hour: 3,
+        minute: 30,
+    };
+    let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+    assert_eq!(schedule.next(at(0)), Duration::from_secs(3 * 3600 + 1800));
+    assert_eq!(schedule.next(at(DAY + 3 * 3600)), Duration::from_secs(1800));
+    assert_eq!(
+        schedule.next(at(DAY + 3 * 3600 + 1800)),
+        Duration::from_secs(DAY)
+    );
+    assert_eq!(
+        schedule.next(at(DAY + 4 * 3600)),
+        Duration::from_secs(DAY - 1800)
+    );
+}
+
+#[test]
+fn weekly() {
+    // Sunday, at 02:00.
+    let schedule = Schedule::Weekly {
+        weekday: 6,
+        hour: 2,
+        minute: 0,
+    };
+    let at = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+    // The epoch was a Thursday at midnight.
+    assert_eq!(
+        schedule.next(at(0)),
+        Duration::from_secs(3 * DAY + 2 * 3600)
+    );
+    assert_eq!(schedule.next(at(3 * DAY + 3600)), Duration::from_secs(3600));
+    assert_eq!(
+        schedule.next(at(3 * DAY + 2 * 3600)),
+        Duration::from_secs(WEEK)
+    );
+    assert_eq!(
+        schedule.next(at(4 * DAY)),
+        Duration::from_secs(WEEK - DAY + 2 * 3600)
+    );
+}
+
+#[test]
+#[should_panic(expected = "the interval must be greater than zero")]
+fn zero_interval() {
+    MaintenancePlan::new().task(Task::Optimize, Schedule::Every(Duration::ZERO));
+}
+
+fn wal_len(path: &std::path::Path) -> u64 {
+    fs::metadata(path.with_extension("db-wal"))
+        .map(|meta| meta.len())
+        .unwrap_or(0)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn checkpoints() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("maintenance.db");
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(&path))
+        .await?;
+    {
+        let conn = pool.get().await?;
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+        conn.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+    }
+    assert!(wal_len(&path) > 0);
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let handle = MaintenancePlan::new()
+        .task(
+            Task::Checkpoint(CheckpointMode::Truncate),
+            Schedule::Every(Duration::from_millis(10)),
+        )
+        .task(
+            Task::IntegrityCheck,
+            Schedule::Every(Duration::from_millis(10)),
+        )
+        .on_error({
+            let errors = errors.clone();
+            move |task, e| errors.lock().unwrap().push((task, e.to_string()))
+        })
+        .start(pool.clone());
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    handle.stop().await?;
+
+    assert_eq!(wal_len(&path), 0);
+    assert!(errors.lock().unwrap().is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn defers_when_busy() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("maintenance.db");
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(RusqliteConnectionManager::new(&path))
+        .await?;
+    let conn = pool.get().await?;
+    conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+    conn.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+
+    let handle = MaintenancePlan::new()
+        .task(
+            Task::Checkpoint(CheckpointMode::Truncate),
+            Schedule::Every(Duration::from_millis(10)),
+        )
+        .defer_when_busy(0, Duration::from_millis(10), Duration::from_secs(3600))
+        .start(pool.clone());
+
+    // While a connection is checked out, the checkpoint keeps being put off.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(wal_len(&path) > 0);
+
+    drop(conn);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(wal_len(&path), 0);
+
+    handle.stop().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn integrity_check() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+    pool.get()
+        .await?
+        .execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+
+    run(&pool, Task::IntegrityCheck).await?;
+    run(&pool, Task::Optimize).await?;
+    run(&pool, Task::IncrementalVacuum(None)).await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn foreign_key_check() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+    pool.get().await?.execute_batch(
+        "CREATE TABLE parents (id INTEGER PRIMARY KEY);
+         CREATE TABLE children (parent_id REFERENCES parents);",
+    )?;
+    run(&pool, Task::ForeignKeyCheck).await?;
+
+    pool.get()
+        .await?
+        .execute("INSERT INTO children VALUES (1)", NO_PARAMS)?;
+    match run(&pool, Task::ForeignKeyCheck).await {
+        Err(Error::ForeignKeyCheck { violations }) => {
+            assert_eq!(violations.len(), 1);
+            assert_eq!(violations[0].table, "children");
+            assert_eq!(violations[0].parent, "parents");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//! Implementations of SQLite's built-in math functions, for builds of SQLite
+//! without `SQLITE_ENABLE_MATH_FUNCTIONS`.
+//!
+//! These follow SQLite's own implementations: arguments that aren't
+//! numbers, and results that aren't, give `NULL`, and `ceil()`, `floor()`,
+//! and `trunc()` return integers unchanged.
+
+use std::{
+    f64::consts::PI,
+    os::raw::{c_char, c_int},
+    slice,
+};
+
+use rusqlite::{ffi, Connection, NO_PARAMS};
+
+#[cfg(test)]
+mod tests;
+
+struct Function {
+    // NUL terminated.
+    name: &'static str,
+    args: usize,
+    // Integer arguments are returned as is, rather than converted to reals.
+    keeps_integers: bool,
+    eval: fn(&[f64]) -> f64,
+}
+
+macro_rules! functions {
+    ($($name:literal, $args:literal, $keeps_integers:literal, $eval:expr;)*) => {
+        &[$(Function {
+            name: concat!($name, "\0"),
+            args: $args,
+            keeps_integers: $keeps_integers,
+            eval: $eval,
+        }),*]
+    };
+}
+
+/// Returns NaN, which becomes `NULL`, unless `x` is positive.
+fn positive(x: f64, f: fn(f64) -> f64) -> f64 {
+    if x > 0.0 {
+        f(x)
+    } else {
+        f64::NAN
+    }
+}
+
+static FUNCTIONS: &[Function] = functions![
+    "acos", 1, false, |x| x[0].acos();
+    "acosh", 1, false, |x| x[0].acosh();
+    "asin", 1, false, |x| x[0].asin();
+    "asinh", 1, false, |x| x[0].asinh();
+    "atan", 1, false, |x| x[0].atan();
+    "atan2", 2, false, |x| x[0].atan2(x[1]);
+    "atanh", 1, false, |x| x[0].atanh();
+    "ceil", 1, true, |x| x[0].ceil();
+    "ceiling", 1, true, |x| x[0].ceil();
+    "cos", 1, false, |x| x[0].cos();
+    "cosh", 1, false, |x| x[0].cosh();
+    "degrees", 1, false, |x| x[0].to_degrees();
+    "exp", 1, false, |x| x[0].exp();
+    "floor", 1, true, |x| x[0].floor();
+    "ln", 1, false, |x| positive(x[0], f64::ln);
+    "log", 1, false, |x| positive(x[0], f64::log10);
+    "log", 2, false, |x| {
+        if x[0] <= 0.0 || x[0] == 1.0 {
+            f64::NAN
+        } else {
+            positive(x[1], f64::ln) / x[0].ln()
+        }
+    };
+    "log10", 1, false, |x| positive(x[0], f64::log10);
+    "log2", 1, false, |x| positive(x[0], f64::log2);
+    "mod", 2, false, |x| x[0] % x[1];
+    "pi", 0, false, |_| PI;
+    "pow", 2, false, |x| x[0].powf(x[1]);
+    "power", 2, false, |x| x[0].powf(x[1]);
+    "radians", 1, false, |x| x[0].to_radians();
+    "sin", 1, false, |x| x[0].sin();
+    "sinh", 1, false, |x| x[0].sinh();
+    "sqrt", 1, false, |x| x[0].sqrt();
+    "tan", 1, false, |x| x[0].tan();
+    "tanh", 1, false, |x| x[0].tanh();
+    "trunc", 1, true, |x| x[0].trunc();
+];
+
+/// Returns true if SQLite was built with its own math functions.
+pub(crate) fn built_in(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT sqlite_compileoption_used('ENABLE_MATH_FUNCTIONS')",
+        NO_PARAMS,
+        |row| row.get(0),
+    )
+}
+
+/// Registers the math functions on `conn`, replacing any built in ones.
+pub(crate) fn register(conn: &Connection) -> rusqlite::Result<()> {
+    for function in FUNCTIONS {
+        // Safety: the functions are static, so their user data pointers are
+        // valid for as long as the connection is.
+        let rc = unsafe {
+            ffi::sqlite3_create_function(
+                conn.handle(),
+                function.name.as_ptr() as *const c_char,
+                function.args as c_int,
+                ffi::SQLITE_UTF8 | ffi::SQLITE_DETERMINISTIC,
+                function as *const Function as *mut _,
+                Some(call),
+                None,
+                None,
+            )
+        };
+        if rc != ffi::SQLITE_OK {
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+        }
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn call(
+    ctx: *mut ffi::sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut ffi::sqlite3_value,
+) {
+    let function = &*(ffi::sqlite3_user_data(ctx) as *const Function);
+    let args = slice::from_raw_parts(argv, argc as usize);
+    let mut values = [0.0; 2];
+    for (value, &arg) in values.iter_mut().zip(args) {
+        match ffi::sqlite3_value_numeric_type(arg) {
+            ffi::SQLITE_INTEGER if function.keeps_integers => {
+                return ffi::sqlite3_result_value(ctx, arg)
+            }
+            ffi::SQLITE_INTEGER | ffi::SQLITE_FLOAT => *value = ffi::sqlite3_value_double(arg),
+            _ => return ffi::sqlite3_result_null(ctx),
+        }
+    }
+    match (function.eval)(&values[..function.args]) {
+        result if result.is_nan() => ffi::sqlite3_result_null(ctx),
+        result => ffi::sqlite3_result_double(ctx, result),
+    }
+}
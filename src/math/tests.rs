@@ -0,0 +1,55 @@
+use rusqlite::{types::Value, NO_PARAMS};
+
+use super::*;
+use crate::{tests::TempDir, RusqliteConnectionManager};
+
+#[test]
+fn fallbacks() -> Result<(), anyhow::Error> {
+    let conn = Connection::open_in_memory()?;
+    register(&conn)?;
+    let eval = |sql: &str| -> rusqlite::Result<Value> {
+        conn.query_row(&format!("SELECT {}", sql), NO_PARAMS, |row| row.get(0))
+    };
+
+    assert_eq!(eval("pi()")?, Value::Real(PI));
+    assert_eq!(eval("sqrt(16)")?, Value::Real(4.0));
+    assert_eq!(eval("sqrt('16')")?, Value::Real(4.0));
+    assert_eq!(eval("power(2, 10)")?, Value::Real(1024.0));
+    assert_eq!(eval("mod(7, 4)")?, Value::Real(3.0));
+    assert_eq!(eval("log(100)")?, Value::Real(2.0));
+    assert_eq!(eval("log(2, 8)")?, Value::Real(3.0));
+    assert_eq!(eval("degrees(pi())")?, Value::Real(180.0));
+
+    // Integers are kept as such, where SQLite keeps them.
+    assert_eq!(eval("ceil(3)")?, Value::Integer(3));
+    assert_eq!(eval("ceil(2.5)")?, Value::Real(3.0));
+    assert_eq!(eval("trunc(-2.5)")?, Value::Real(-2.0));
+
+    // Non-numeric arguments and domain errors give NULL.
+    for sql in &[
+        "sin(NULL)",
+        "sin('abc')",
+        "sqrt(-1)",
+        "ln(0)",
+        "log(1, 8)",
+        "mod(1, 0)",
+        "acos(2)",
+    ] {
+        assert_eq!(eval(sql)?, Value::Null, "{}", sql);
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn math_functions() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("math.db")).with_math_functions(true))
+        .await?;
+    let cos: f64 = pool
+        .get()
+        .await?
+        .query_row("SELECT cos(0)", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(cos, 1.0);
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! SQLite memory statistics, from `sqlite3_db_status()` and
+//! `sqlite3_status()`.
+
+use std::{
+    ops::{Add, AddAssign},
+    os::raw::c_int,
+};
+
+use rusqlite::{ffi, Connection};
+
+use crate::ffi_ext::{
+    DBSTATUS_CACHE_USED, DBSTATUS_LOOKASIDE_HIT, DBSTATUS_LOOKASIDE_MISS_FULL,
+    DBSTATUS_LOOKASIDE_MISS_SIZE, DBSTATUS_LOOKASIDE_USED, DBSTATUS_SCHEMA_USED,
+    DBSTATUS_STMT_USED, STATUS_MALLOC_COUNT, STATUS_MALLOC_SIZE, STATUS_MEMORY_USED,
+    STATUS_PAGECACHE_OVERFLOW,
+};
+use crate::RusqliteConnection;
+
+#[cfg(test)]
+mod tests;
+
+/// Memory used by a connection, or summed across the connections in a pool.
+///
+/// Memory sizes are in bytes. The lookaside counters count allocations since
+/// the connection was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Memory used by the page cache.
+    pub cache_used: u64,
+
+    /// Memory used to hold the schema of every attached database.
+    pub schema_used: u64,
+
+    /// Memory used by prepared statements, including cached statements.
+    pub stmt_used: u64,
+
+    /// The number of lookaside memory slots in use.
+    pub lookaside_used: u64,
+
+    /// The number of allocations satisfied from lookaside memory.
+    pub lookaside_hits: u64,
+
+    /// The number of allocations too large for lookaside memory.
+    pub lookaside_misses_size: u64,
+
+    /// The number of allocations that missed because lookaside memory was
+    /// full.
+    pub lookaside_misses_full: u64,
+}
+
+impl MemoryStats {
+    pub(crate) fn read(conn: &Connection) -> Result<Self, rusqlite::Error> {
+        // The hit and miss counters are reported as the high water mark.
+        let current = |op| db_status(conn, op).map(|(current, _)| current);
+        let highwater = |op| db_status(conn, op).map(|(_, highwater)| highwater);
+        Ok(Self {
+            cache_used: current(DBSTATUS_CACHE_USED)?,
+            schema_used: current(DBSTATUS_SCHEMA_USED)?,
+            stmt_used: current(DBSTATUS_STMT_USED)?,
+            lookaside_used: current(DBSTATUS_LOOKASIDE_USED)?,
+            lookaside_hits: highwater(DBSTATUS_LOOKASIDE_HIT)?,
+            lookaside_misses_size: highwater(DBSTATUS_LOOKASIDE_MISS_SIZE)?,
+            lookaside_misses_full: highwater(DBSTATUS_LOOKASIDE_MISS_FULL)?,
+        })
+    }
+}
+
+impl Add for MemoryStats {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        self += other;
+        self
+    }
+}
+
+impl AddAssign for MemoryStats {
+    fn add_assign(&mut self, other: Self) {
+        self.cache_used += other.cache_used;
+        self.schema_used += other.schema_used;
+        self.stmt_used += other.stmt_used;
+        self.lookaside_used += other.lookaside_used;
+        self.lookaside_hits += other.lookaside_hits;
+        self.lookaside_misses_size += other.lookaside_misses_size;
+        self.lookaside_misses_full += other.lookaside_misses_full;
+    }
+}
+
+fn db_status(conn: &Connection, op: c_int) -> Result<(u64, u64), rusqlite::Error> {
+    let (mut current, mut highwater) = (0, 0);
+    // Safety: the handle is valid for the lifetime of the connection, and the
+    // connection isn't shared between threads.
+    let rc = unsafe { ffi::sqlite3_db_status(conn.handle(), op, &mut current, &mut highwater, 0) };
+    if rc != ffi::SQLITE_OK {
+        return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+    }
+    Ok((current.max(0) as u64, highwater.max(0) as u64))
+}
+
+/// Process wide SQLite memory statistics, shared by every connection in every
+/// pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessMemoryStats {
+    /// Memory currently allocated by SQLite, in bytes.
+    pub memory_used: u64,
+
+    /// The most memory SQLite has had allocated at once, in bytes.
+    pub memory_highwater: u64,
+
+    /// The number of outstanding allocations.
+    pub malloc_count: u64,
+
+    /// The largest single allocation requested, in bytes.
+    pub largest_malloc: u64,
+
+    /// Page cache memory that couldn't be satisfied from a configured page
+    /// cache buffer and fell back to the general allocator, in bytes.
+    pub pagecache_overflow: u64,
+}
+
+impl ProcessMemoryStats {
+    /// Reads the current statistics.
+    pub fn read() -> Self {
+        let status = |op| {
+            let (mut current, mut highwater) = (0, 0);
+            // Safety: sqlite3_status() is thread safe, and only fails for
+            // unknown codes, which leave both values at zero.
+            unsafe { ffi::sqlite3_status(op, &mut current, &mut highwater, 0) };
+            (current.max(0) as u64, highwater.max(0) as u64)
+        };
+        let (memory_used, memory_highwater) = status(STATUS_MEMORY_USED);
+        Self {
+            memory_used,
+            memory_highwater,
+            malloc_count: status(STATUS_MALLOC_COUNT).0,
+            largest_malloc: status(STATUS_MALLOC_SIZE).1,
+            pagecache_overflow: status(STATUS_PAGECACHE_OVERFLOW).0,
+        }
+    }
+}
+
+impl RusqliteConnection {
+    /// Returns how much memory this connection is using.
+    pub fn memory_stats(&self) -> Result<MemoryStats, rusqlite::Error> {
+        MemoryStats::read(self)
+    }
+}
@@ -0,0 +1,34 @@
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, ProcessMemoryStats, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn memory_stats() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("memory.db"));
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(manager.clone())
+        .await?;
+
+    let (a, b) = (pool.get().await?, pool.get().await?);
+    a.execute_batch("CREATE TABLE t (a INTEGER, b TEXT); CREATE INDEX t_a ON t (a);")?;
+    a.prepare_cached("SELECT * FROM t WHERE a = ?")?;
+    b.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |_| Ok(()))?;
+
+    let stats = a.memory_stats()?;
+    assert!(stats.cache_used > 0);
+    assert!(stats.schema_used > 0);
+    assert!(stats.stmt_used > 0);
+    let total = stats + b.memory_stats()?;
+    drop((a, b));
+
+    // The pool's totals are sampled as connections are returned.
+    assert_eq!(manager.metrics(&pool).memory, total);
+
+    let process = ProcessMemoryStats::read();
+    assert!(process.memory_used > 0);
+    assert!(process.memory_highwater >= process.memory_used);
+    assert!(process.malloc_count > 0);
+    Ok(())
+}
@@ -0,0 +1,444 @@
+//! Pool metrics, gathered on demand.
+//!
+//! [`RusqliteConnectionManager::metrics()`] returns a [`PoolMetricsSnapshot`]
+//! without checking out a connection, so it's cheap enough to call on every
+//! scrape of a metrics endpoint. [`PoolMetricsSnapshot::prometheus()`] renders
+//! the snapshot in the Prometheus text exposition format.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    replication,
+    usage::{ConnectionStats, Usage},
+    MemoryStats, ProcessMemoryStats, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// The upper bounds of the wait time histogram buckets, in microseconds.
+/// These match the Prometheus client libraries' default buckets.
+const BUCKETS: [u64; 11] = [
+    5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000, 2_500_000, 5_000_000,
+    10_000_000,
+];
+
+/// Metrics recorded as the pool is used, shared by every clone of a manager.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    next_connection: AtomicU64,
+    close_errors: AtomicU64,
+    memory: Mutex<HashMap<u64, MemoryStats>>,
+    usage: Mutex<HashMap<u64, Arc<Usage>>>,
+    samples: Mutex<Samples>,
+}
+
+/// The most recent checkout waits, kept when the manager is configured to.
+#[derive(Debug, Default)]
+struct Samples {
+    limit: usize,
+    waits: VecDeque<Duration>,
+}
+
+impl Metrics {
+    /// Creates metrics that also keep the last `limit` checkout waits.
+    pub(crate) fn with_samples(limit: usize) -> Self {
+        Self {
+            samples: Mutex::new(Samples {
+                limit,
+                waits: VecDeque::with_capacity(limit),
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Records how long a checkout waited for a connection.
+    pub(crate) fn record_wait(&self, wait: Duration) {
+        {
+            let mut samples = self.samples.lock().unwrap();
+            if samples.limit > 0 {
+                if samples.waits.len() == samples.limit {
+                    samples.waits.pop_front();
+                }
+                samples.waits.push_back(wait);
+            }
+        }
+        let micros = wait.as_micros().min(u128::from(u64::MAX)) as u64;
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| micros <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    fn memory(&self) -> MemoryStats {
+        self.memory
+            .lock()
+            .unwrap()
+            .values()
+            .fold(MemoryStats::default(), |sum, stats| sum + *stats)
+    }
+
+    fn connection_stats(&self) -> Vec<ConnectionStats> {
+        let mut stats: Vec<_> = self
+            .usage
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&id, usage)| usage.stats(id))
+            .collect();
+        stats.sort_by_key(|stats| stats.connection);
+        stats
+    }
+
+    fn wait_histogram(&self) -> WaitHistogram {
+        let mut cumulative = 0;
+        let buckets = BUCKETS
+            .iter()
+            .zip(&self.buckets)
+            .map(|(&bound, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (Duration::from_micros(bound), cumulative)
+            })
+            .collect();
+
+        WaitHistogram {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// A connection's entry in the pool's metrics, which is removed when the
+/// connection is closed.
+#[derive(Debug)]
+pub(crate) struct ConnectionMetrics {
+    metrics: Arc<Metrics>,
+    id: u64,
+    usage: Arc<Usage>,
+}
+
+impl ConnectionMetrics {
+    pub(crate) fn new(metrics: Arc<Metrics>) -> Self {
+        let id = metrics.next_connection.fetch_add(1, Ordering::Relaxed);
+        let usage = Arc::new(Usage::default());
+        metrics.usage.lock().unwrap().insert(id, usage.clone());
+        Self { metrics, id, usage }
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn usage(&self) -> &Arc<Usage> {
+        &self.usage
+    }
+
+    pub(crate) fn record_wait(&self, wait: Duration) {
+        self.metrics.record_wait(wait);
+    }
+
+    /// Records that closing the connection failed.
+    pub(crate) fn record_close_error(&self) {
+        self.metrics.close_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the connection's latest memory statistics.
+    pub(crate) fn sample_memory(&self, stats: MemoryStats) {
+        self.metrics.memory.lock().unwrap().insert(self.id, stats);
+    }
+}
+
+impl Drop for ConnectionMetrics {
+    fn drop(&mut self) {
+        self.metrics.memory.lock().unwrap().remove(&self.id);
+        self.metrics.usage.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// How long checkouts have waited for a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitHistogram {
+    /// Each bucket's upper bound, and the number of waits no longer than it.
+    /// As in Prometheus, the counts are cumulative.
+    pub buckets: Vec<(Duration, u64)>,
+
+    /// The total number of waits, including those longer than every bucket.
+    pub count: u64,
+
+    /// The total time spent waiting.
+    pub sum: Duration,
+}
+
+/// Every checkout wait kept by
+/// [`RusqliteConnectionManager::with_latency_samples()`], for computing exact
+/// percentiles rather than the estimates a [`WaitHistogram`] allows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyDistribution {
+    // Sorted, shortest first.
+    samples: Vec<Duration>,
+}
+
+impl LatencyDistribution {
+    /// Returns the number of samples.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns true if there are no samples.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Returns the samples, shortest first.
+    pub fn samples(&self) -> &[Duration] {
+        &self.samples
+    }
+
+    /// Returns the shortest wait no shorter than `quantile` of the samples,
+    /// where `quantile` is between 0 and 1: 0.99 returns the 99th percentile.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let rank = (quantile.clamp(0.0, 1.0) * self.samples.len() as f64).ceil() as usize;
+        Some(self.samples[rank.max(1) - 1])
+    }
+
+    /// Returns the longest wait.
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.last().copied()
+    }
+
+    /// Returns the mean wait.
+    pub fn mean(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32)
+    }
+}
+
+/// A point in time view of a pool, for exporting to a metrics system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolMetricsSnapshot {
+    /// The path new connections are opened on, as resolved by the manager's
+    /// [`PathPolicy`](crate::PathPolicy).
+    pub path: PathBuf,
+
+    /// The number of open connections.
+    pub connections: u32,
+
+    /// The number of open connections that are checked out.
+    pub in_use: u32,
+
+    /// The number of open connections that are idle in the pool.
+    pub idle: u32,
+
+    /// How long checkouts through [`PoolExt`](crate::PoolExt) and
+    /// [`PoolExt::acquire()`](crate::PoolExt::acquire) have waited.
+    /// Checkouts made directly through `bb8::Pool::get()` aren't timed.
+    pub wait: WaitHistogram,
+
+    /// Memory used by the pool's connections, summed as of when each was
+    /// last returned to the pool.
+    pub memory: MemoryStats,
+
+    /// The memory currently allocated by SQLite, in bytes. This is process
+    /// wide, rather than specific to the pool.
+    pub sqlite_memory_used: u64,
+
+    /// The most memory SQLite has had allocated at once, in bytes. This is
+    /// also process wide.
+    pub sqlite_memory_highwater: u64,
+
+    /// The number of connections that failed to close cleanly, since the
+    /// manager was created.
+    pub close_errors: u64,
+
+    /// The size of the database's WAL file in bytes, or `None` if there is
+    /// no WAL file. In wal2 mode, this is the combined size of both WAL
+    /// files.
+    pub wal_size: Option<u64>,
+
+    /// How much each open connection has been used, ordered by connection
+    /// ID. A pool where one connection does most of the work may be larger
+    /// than it needs to be.
+    pub connection_stats: Vec<ConnectionStats>,
+}
+
+impl PoolMetricsSnapshot {
+    /// Renders the snapshot in the Prometheus text exposition format, with
+    /// each metric name prefixed by `namespace` and an underscore.
+    pub fn prometheus(&self, namespace: &str) -> String {
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {}_{} {}", namespace, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} gauge", namespace, name);
+            let _ = writeln!(out, "{}_{} {}", namespace, name, value);
+        };
+        gauge("connections", "Open connections.", self.connections.into());
+        gauge(
+            "connections_in_use",
+            "Checked out connections.",
+            self.in_use.into(),
+        );
+        gauge("connections_idle", "Idle connections.", self.idle.into());
+        gauge(
+            "sqlite_memory_used_bytes",
+            "Memory allocated by SQLite.",
+            self.sqlite_memory_used,
+        );
+        gauge(
+            "sqlite_memory_highwater_bytes",
+            "Most memory allocated by SQLite at once.",
+            self.sqlite_memory_highwater,
+        );
+        gauge(
+            "cache_used_bytes",
+            "Memory used by connections' page caches.",
+            self.memory.cache_used,
+        );
+        gauge(
+            "schema_used_bytes",
+            "Memory used by connections' schemas.",
+            self.memory.schema_used,
+        );
+        gauge(
+            "stmt_used_bytes",
+            "Memory used by connections' prepared statements.",
+            self.memory.stmt_used,
+        );
+        gauge(
+            "lookaside_hits",
+            "Allocations satisfied from lookaside memory by open connections.",
+            self.memory.lookaside_hits,
+        );
+        if let Some(size) = self.wal_size {
+            gauge("wal_size_bytes", "Size of the WAL file.", size);
+        }
+
+        let name = format!("{}_close_errors_total", namespace);
+        let _ = writeln!(out, "# HELP {} Connections that failed to close.", name);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, self.close_errors);
+
+        let name = format!("{}_wait_seconds", namespace);
+        let _ = writeln!(out, "# HELP {} Time spent waiting for a connection.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bound, count) in &self.wait.buckets {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                bound.as_secs_f64(),
+                count
+            );
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.wait.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.wait.sum.as_secs_f64());
+        let _ = writeln!(out, "{}_count {}", name, self.wait.count);
+
+        let mut per_connection =
+            |name: &str, help: &str, value: &dyn Fn(&ConnectionStats) -> String| {
+                let name = format!("{}_{}", namespace, name);
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                for stats in &self.connection_stats {
+                    let _ = writeln!(
+                        out,
+                        "{}{{connection=\"{}\"}} {}",
+                        name,
+                        stats.connection,
+                        value(stats)
+                    );
+                }
+            };
+        per_connection(
+            "connection_checkouts_total",
+            "Checkouts of each connection.",
+            &|stats| stats.checkouts.to_string(),
+        );
+        per_connection(
+            "connection_statements_total",
+            "Statements run by each connection.",
+            &|stats| stats.statements.to_string(),
+        );
+        per_connection(
+            "connection_rows_total",
+            "Rows returned by each connection.",
+            &|stats| stats.rows.to_string(),
+        );
+        per_connection(
+            "connection_busy_wait_seconds_total",
+            "Time each connection has spent waiting on locks, with a contention monitor.",
+            &|stats| stats.busy_wait.as_secs_f64().to_string(),
+        );
+        out
+    }
+}
+
+impl RusqliteConnectionManager {
+    /// Returns a snapshot of `pool`, which must use this manager (or a clone
+    /// of it), without checking out a connection.
+    pub fn metrics(&self, pool: &bb8::Pool<Self>) -> PoolMetricsSnapshot {
+        let state = pool.state();
+        let wal_size = replication::wal_paths(&self.current_file().path)
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|meta| meta.len())
+            .fold(None, |sum, len| Some(sum.unwrap_or(0) + len));
+
+        let process = ProcessMemoryStats::read();
+
+        PoolMetricsSnapshot {
+            path: self.path(),
+            connections: state.connections,
+            in_use: state.connections - state.idle_connections,
+            idle: state.idle_connections,
+            wait: self.metrics.wait_histogram(),
+            memory: self.metrics.memory(),
+            sqlite_memory_used: process.memory_used,
+            sqlite_memory_highwater: process.memory_highwater,
+            close_errors: self.metrics.close_errors.load(Ordering::Relaxed),
+            wal_size,
+            connection_stats: self.metrics.connection_stats(),
+        }
+    }
+
+    /// Keeps the last `limit` checkout waits, in addition to the histogram in
+    /// the [metrics](Self::metrics), so that load tests can model the full
+    /// latency distribution. As for the histogram, only checkouts through
+    /// [`PoolExt`](crate::PoolExt) are timed.
+    ///
+    /// This replaces the manager's metrics, so must be called before any
+    /// are recorded.
+    pub fn with_latency_samples(mut self, limit: usize) -> Self {
+        self.metrics = Arc::new(Metrics::with_samples(limit));
+        self
+    }
+
+    /// Returns the checkout waits kept since the last call, clearing them.
+    /// This is always empty unless
+    /// [`with_latency_samples()`](Self::with_latency_samples) was set.
+    pub fn take_latency_samples(&self) -> LatencyDistribution {
+        let mut samples: Vec<_> =
+            std::mem::take(&mut self.metrics.samples.lock().unwrap().waits).into();
+        samples.sort();
+        LatencyDistribution { samples }
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+use super::{LatencyDistribution, Metrics};
+
+#[test]
+fn histogram() {
+    let metrics = Metrics::default();
+    metrics.record_wait(Duration::from_millis(1));
+    metrics.record_wait(Duration::from_millis(20));
+    metrics.record_wait(Duration::from_secs(60));
+
+    let histogram = metrics.wait_histogram();
+    assert_eq!(histogram.count, 3);
+    assert_eq!(histogram.sum, Duration::from_millis(60_021));
+    assert_eq!(histogram.buckets[0], (Duration::from_millis(5), 1));
+    assert_eq!(histogram.buckets[1], (Duration::from_millis(10), 1));
+    assert_eq!(histogram.buckets[2], (Duration::from_millis(25), 2));
+    assert_eq!(
+        histogram.buckets.last(),
+        Some(&(Duration::from_secs(10), 2))
+    );
+}
+
+#[test]
+fn latency_samples() {
+    let metrics = Metrics::with_samples(3);
+    for millis in &[40, 10, 30, 20] {
+        metrics.record_wait(Duration::from_millis(*millis));
+    }
+    // Only the most recent samples are kept.
+    let waits: Vec<_> = metrics
+        .samples
+        .lock()
+        .unwrap()
+        .waits
+        .iter()
+        .copied()
+        .collect();
+    assert_eq!(
+        waits,
+        vec![
+            Duration::from_millis(10),
+            Duration::from_millis(30),
+            Duration::from_millis(20)
+        ]
+    );
+
+    let distribution = LatencyDistribution {
+        samples: (1..=100).map(Duration::from_millis).collect(),
+    };
+    assert_eq!(distribution.quantile(0.0), Some(Duration::from_millis(1)));
+    assert_eq!(distribution.quantile(0.5), Some(Duration::from_millis(50)));
+    assert_eq!(distribution.quantile(0.99), Some(Duration::from_millis(99)));
+    assert_eq!(distribution.quantile(1.0), Some(Duration::from_millis(100)));
+    assert_eq!(distribution.max(), Some(Duration::from_millis(100)));
+    assert_eq!(distribution.mean(), Some(Duration::from_micros(50_500)));
+    assert_eq!(LatencyDistribution::default().quantile(0.5), None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn take_latency_samples() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("metrics.db")).with_latency_samples(10);
+    let pool = bb8::Pool::builder().build(manager.clone()).await?;
+    for _ in 0..3 {
+        pool.acquire().await?;
+    }
+
+    let distribution = manager.take_latency_samples();
+    assert_eq!(distribution.len(), 3);
+    assert!(distribution.samples().windows(2).all(|w| w[0] <= w[1]));
+    assert!(manager.take_latency_samples().is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn snapshot() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("metrics.db"));
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(manager.clone())
+        .await?;
+
+    let conn = pool.acquire().await?;
+    conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+    conn.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+    pool.schema_version().await?;
+
+    let snapshot = manager.metrics(&pool);
+    assert_eq!(snapshot.connections, 2);
+    assert_eq!(snapshot.in_use, 1);
+    assert_eq!(snapshot.idle, 1);
+    assert_eq!(snapshot.wait.count, 2);
+    assert!(snapshot.sqlite_memory_used > 0);
+    assert!(snapshot.sqlite_memory_highwater >= snapshot.sqlite_memory_used);
+    assert!(snapshot.wal_size.is_some_and(|size| size > 0));
+
+    let text = snapshot.prometheus("app_db");
+    assert!(text.contains("# TYPE app_db_connections gauge\napp_db_connections 2\n"));
+    assert!(text.contains("app_db_connections_in_use 1\n"));
+    assert!(text.contains("app_db_wait_seconds_bucket{le=\"0.005\"}"));
+    assert!(text.contains("app_db_wait_seconds_bucket{le=\"+Inf\"} 2\n"));
+    assert!(text.contains("app_db_wait_seconds_count 2\n"));
+    Ok(())
+}
@@ -0,0 +1,67 @@
+//! OpenTelemetry spans for pooled operations.
+//!
+//! Spans are created with the global tracer provider, as a child of the
+//! current context, and follow the OpenTelemetry semantic conventions for
+//! database client calls where they apply.
+
+use std::{error::Error as _, path::Path, time::Duration};
+
+use opentelemetry::{
+    global::{self, BoxedSpan},
+    trace::{Span, SpanKind, Status, Tracer},
+    KeyValue,
+};
+
+use crate::{pool::Operation, Error};
+
+#[cfg(test)]
+mod tests;
+
+const TRACER: &str = env!("CARGO_PKG_NAME");
+
+/// The span covering a single operation, from waiting for a connection until
+/// the operation returns.
+pub(crate) struct OperationSpan(BoxedSpan);
+
+impl OperationSpan {
+    pub(crate) fn start(op: &Operation<'_>) -> Self {
+        let tracer = global::tracer(TRACER);
+        let mut attributes = vec![
+            KeyValue::new("db.system", "sqlite"),
+            KeyValue::new("db.operation", op.name),
+        ];
+        if let Some(sql) = op.statement {
+            attributes.push(KeyValue::new("db.statement", sql.to_string()));
+        }
+
+        Self(
+            tracer
+                .span_builder(op.name)
+                .with_kind(SpanKind::Client)
+                .with_attributes(attributes)
+                .start(&tracer),
+        )
+    }
+
+    /// Records that a connection to `path` was checked out after `wait`.
+    pub(crate) fn acquired(&mut self, wait: Duration, path: &Path) {
+        self.0.set_attributes([
+            KeyValue::new("db.name", path.display().to_string()),
+            KeyValue::new(
+                "db.client.connections.wait_time",
+                wait.as_secs_f64() * 1000.0,
+            ),
+        ]);
+    }
+
+    pub(crate) fn finish<T>(mut self, result: &Result<T, Error>) {
+        if let Err(e) = result {
+            let message = match e.source() {
+                Some(source) => format!("{}: {}", e, source),
+                None => e.to_string(),
+            };
+            self.0.set_status(Status::error(message));
+        }
+        self.0.end();
+    }
+}
@@ -0,0 +1,56 @@
+use opentelemetry::{trace::Status, KeyValue, Value};
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans() -> Result<(), anyhow::Error> {
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    opentelemetry::global::set_tracer_provider(provider);
+
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("otel.db")))
+        .await?;
+    pool.get()
+        .await?
+        .execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+
+    pool.explain_plan("SELECT * FROM t", Vec::<i64>::new())
+        .await?;
+    assert!(pool
+        .explain_plan("SELECT * FROM missing", Vec::<i64>::new())
+        .await
+        .is_err());
+
+    let spans: Vec<_> = exporter
+        .get_finished_spans()?
+        .into_iter()
+        .filter(|span| span.name == "explain_plan")
+        .collect();
+    assert_eq!(spans.len(), 2);
+
+    let attribute = |span: &opentelemetry_sdk::trace::SpanData, key: &str| {
+        span.attributes
+            .iter()
+            .find(|kv: &&KeyValue| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    };
+    assert_eq!(
+        attribute(&spans[0], "db.system"),
+        Some(Value::from("sqlite"))
+    );
+    assert_eq!(
+        attribute(&spans[0], "db.statement"),
+        Some(Value::from("SELECT * FROM t"))
+    );
+    assert!(attribute(&spans[0], "db.client.connections.wait_time").is_some());
+    assert_eq!(spans[0].status, Status::Unset);
+    assert!(matches!(spans[1].status, Status::Error { .. }));
+
+    Ok(())
+}
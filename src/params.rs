@@ -0,0 +1,194 @@
+//! Named parameters built from maps and, with the `serde` feature, from any
+//! serializable struct.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::BuildHasher,
+    iter::FromIterator,
+};
+
+use rusqlite::{types::Value, Row, Statement, ToSql};
+
+use crate::{Error, RusqliteConnection};
+
+#[cfg(test)]
+mod tests;
+
+/// A set of named parameters, owned so they can be moved into pooled
+/// operations.
+///
+/// Names may be given with or without SQLite's `:`, `@`, or `$` prefix; names
+/// without one are given a `:` prefix. Parameters the statement doesn't use
+/// are ignored, so a struct can be bound to a statement that only uses some
+/// of its fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NamedParams(Vec<(String, Value)>);
+
+impl NamedParams {
+    /// Creates an empty set of parameters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a parameter.
+    pub fn set<K, V>(mut self, name: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<Value>,
+    {
+        self.push(name.into(), value.into());
+        self
+    }
+
+    fn push(&mut self, mut name: String, value: Value) {
+        if !name.starts_with([':', '@', '$']) {
+            name.insert(0, ':');
+        }
+        self.0.push((name, value));
+    }
+
+    /// Creates parameters from the fields of a struct, or the entries of a
+    /// map, serialized with serde.
+    ///
+    /// Strings, numbers, booleans, and `None` are bound directly. Any other
+    /// value, such as a nested struct or a `Vec`, is bound as JSON text, for
+    /// use with SQLite's JSON functions. Integers that don't fit in an `i64`
+    /// are an error.
+    #[cfg(feature = "serde")]
+    pub fn from_serialize<T>(value: &T) -> Result<Self, serde_json::Error>
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        use serde::ser::Error as _;
+
+        let fields = match serde_json::to_value(value)? {
+            serde_json::Value::Object(fields) => fields,
+            _ => {
+                return Err(serde_json::Error::custom(
+                    "named parameters must be serialized from a struct or map",
+                ))
+            }
+        };
+
+        let mut params = Self::new();
+        for (name, value) in fields {
+            let value = match value {
+                serde_json::Value::Null => Value::Null,
+                serde_json::Value::Bool(v) => Value::Integer(v.into()),
+                serde_json::Value::Number(v) => match (v.as_i64(), v.as_f64()) {
+                    (Some(v), _) => Value::Integer(v),
+                    (None, Some(f)) if v.is_f64() => Value::Real(f),
+                    _ => {
+                        return Err(serde_json::Error::custom(format!(
+                            "parameter {} is out of range: {}",
+                            name, v
+                        )))
+                    }
+                },
+                serde_json::Value::String(v) => Value::Text(v),
+                value => Value::Text(value.to_string()),
+            };
+            params.push(name, value);
+        }
+        Ok(params)
+    }
+
+    /// Returns the parameters that `stmt` uses, as rusqlite expects them.
+    fn bind(&self, stmt: &Statement<'_>) -> rusqlite::Result<Vec<(&str, &dyn ToSql)>> {
+        let mut bound = Vec::new();
+        for (name, value) in &self.0 {
+            if stmt.parameter_index(name)?.is_some() {
+                bound.push((name.as_str(), value as &dyn ToSql));
+            }
+        }
+        Ok(bound)
+    }
+}
+
+/// Types that can be bound as named parameters, such as with
+/// [`PoolExt::execute_with()`](crate::PoolExt::execute_with).
+///
+/// With the `macros` feature, this can be derived for structs with named
+/// fields, which bind each field to the parameter of the same name. A field
+/// can be bound to a parameter with another name with
+/// `#[param(rename = "name")]`, or left out with `#[param(skip)]`. Field
+/// values are converted with `rusqlite::ToSql`.
+pub trait ToParams {
+    /// Returns the parameters, failing if a value can't be converted.
+    fn to_params(&self) -> rusqlite::Result<NamedParams>;
+}
+
+impl ToParams for NamedParams {
+    fn to_params(&self) -> rusqlite::Result<NamedParams> {
+        Ok(self.clone())
+    }
+}
+
+impl<T> ToParams for &T
+where
+    T: ToParams + ?Sized,
+{
+    fn to_params(&self) -> rusqlite::Result<NamedParams> {
+        (**self).to_params()
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for NamedParams
+where
+    K: Into<String>,
+    V: Into<Value>,
+{
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        iter.into_iter()
+            .fold(Self::new(), |params, (name, value)| params.set(name, value))
+    }
+}
+
+impl<K, V, S> From<HashMap<K, V, S>> for NamedParams
+where
+    K: Into<String>,
+    V: Into<Value>,
+    S: BuildHasher,
+{
+    fn from(map: HashMap<K, V, S>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for NamedParams
+where
+    K: Into<String>,
+    V: Into<Value>,
+{
+    fn from(map: BTreeMap<K, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+pub(crate) fn execute(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: &NamedParams,
+) -> rusqlite::Result<usize> {
+    let mut stmt = conn.prepare_cached(sql)?;
+    let params = params.bind(&stmt)?;
+    stmt.execute_named(&params)
+}
+
+pub(crate) fn query<T, F>(
+    conn: &RusqliteConnection,
+    sql: &str,
+    params: &NamedParams,
+    f: F,
+) -> Result<Vec<T>, Error>
+where
+    F: FnMut(&Row<'_>) -> rusqlite::Result<T>,
+{
+    let mut stmt = conn.prepare_cached(sql)?;
+    let params = params.bind(&stmt)?;
+    let rows = stmt.query_named(&params)?;
+    conn.result_limits().collect(rows, f)
+}
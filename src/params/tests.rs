@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use rusqlite::types::Value;
+
+use crate::{
+    tests::{pool, TempDir},
+    NamedParams, PoolExt, RusqliteConnectionManager,
+};
+
+const SETUP: &str = "CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT, email TEXT, tags TEXT)";
+
+#[tokio::test(flavor = "multi_thread")]
+async fn maps() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("params.db")),
+        10,
+        SETUP,
+    )
+    .await?;
+
+    let mut user = HashMap::new();
+    user.insert("id", Value::from(1));
+    user.insert(":name", Value::from("alice".to_string()));
+    user.insert("@email", Value::Null);
+    // Unused parameters are ignored.
+    user.insert("unused", Value::from(0));
+    let changed = pool
+        .execute_named(
+            "INSERT INTO users (id, name, email) VALUES (:id, :name, @email)",
+            user.into(),
+        )
+        .await?;
+    assert_eq!(changed, 1);
+
+    let rows = pool
+        .query_named(
+            "SELECT name, email FROM users WHERE id = $id",
+            NamedParams::new().set("$id", 1),
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+        )
+        .await?;
+    assert_eq!(rows, vec![("alice".to_string(), None)]);
+
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test(flavor = "multi_thread")]
+async fn structs() -> Result<(), anyhow::Error> {
+    #[derive(serde::Serialize)]
+    struct User<'a> {
+        id: i64,
+        name: &'a str,
+        email: Option<&'a str>,
+        tags: Vec<&'a str>,
+    }
+
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("params.db")),
+        10,
+        SETUP,
+    )
+    .await?;
+
+    let user = User {
+        id: 2,
+        name: "bob",
+        email: Some("bob@example.com"),
+        tags: vec!["admin"],
+    };
+    pool.execute_named(
+        "INSERT INTO users VALUES (:id, :name, :email, :tags)",
+        NamedParams::from_serialize(&user)?,
+    )
+    .await?;
+
+    let rows = pool
+        .query_named(
+            "SELECT email, json_extract(tags, '$[0]') FROM users WHERE name = :name",
+            NamedParams::from_serialize(&user)?,
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+        )
+        .await?;
+    assert_eq!(
+        rows,
+        vec![("bob@example.com".to_string(), "admin".to_string())]
+    );
+
+    assert!(NamedParams::from_serialize(&42).is_err());
+    assert!(NamedParams::from_serialize(&HashMap::from([("big", u64::MAX)])).is_err());
+    Ok(())
+}
+
+#[cfg(feature = "macros")]
+#[tokio::test(flavor = "multi_thread")]
+async fn derived() -> Result<(), anyhow::Error> {
+    use crate::ToParams;
+
+    #[derive(ToParams)]
+    struct User<'a> {
+        id: i64,
+        #[param(rename = "@name")]
+        display_name: &'a str,
+        email: Option<String>,
+        #[param(skip)]
+        _session: Vec<String>,
+    }
+
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("params.db")),
+        10,
+        SETUP,
+    )
+    .await?;
+    let user = User {
+        id: 1,
+        display_name: "alice",
+        email: None,
+        _session: Default::default(),
+    };
+    assert_eq!(
+        user.to_params()?,
+        NamedParams::new()
+            .set("id", 1)
+            .set("@name", "alice".to_string())
+            .set("email", Value::Null)
+    );
+
+    let sql = "INSERT INTO users (id, name, email) VALUES (:id, @name, :email)";
+    assert_eq!(pool.execute_with(sql, &user).await?, 1);
+    let user = User { id: 2, ..user };
+    assert_eq!(pool.get().await?.execute_with(sql, &user)?, 1);
+
+    let rows = pool
+        .query_named(
+            "SELECT id, name FROM users ORDER BY id",
+            NamedParams::new(),
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+        )
+        .await?;
+    assert_eq!(
+        rows,
+        vec![(1, "alice".to_string()), (2, "alice".to_string())]
+    );
+    Ok(())
+}
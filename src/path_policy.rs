@@ -0,0 +1,121 @@
+//! Resolving the database path once, and refusing symbolic links, for
+//! databases in directories that other users can write to.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::Error;
+
+#[cfg(all(test, unix))]
+mod tests;
+
+/// How the database path is resolved, and whether it may go through
+/// symbolic links.
+///
+/// In a directory other users can write to, such as a shared `/tmp`, another
+/// user can replace the database, or a directory leading to it, with a
+/// symbolic link between the pool being configured and a connection being
+/// opened, so that the pool writes somewhere it shouldn't. Canonicalizing
+/// the path pins down the file the pool uses when it's configured, and
+/// refusing symbolic links stops it following one swapped in later.
+///
+/// Set this with
+/// [`RusqliteConnectionManager::with_path_policy()`](crate::RusqliteConnectionManager::with_path_policy).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathPolicy {
+    pub(crate) canonicalize: bool,
+    pub(crate) refuse_symlinks: bool,
+}
+
+impl PathPolicy {
+    /// Creates a policy that uses paths as they're given, following any
+    /// symbolic links.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolves the database path to an absolute one, without symbolic
+    /// links, as soon as the policy is set, and likewise for paths the pool
+    /// is [rotated](crate::RusqliteConnectionManager::rotate) or
+    /// [swapped](crate::RusqliteConnectionManager::swap_database) to. The
+    /// [manager's path](crate::RusqliteConnectionManager::path), errors, and
+    /// metrics then report the resolved path. Parts of the path that don't
+    /// exist yet are kept as they are.
+    pub fn canonicalize(mut self, canonicalize: bool) -> Self {
+        self.canonicalize = canonicalize;
+        self
+    }
+
+    /// Refuses to open the database if it, or any directory leading to it,
+    /// is a symbolic link, failing with [`Error::InvalidPath`]. SQLite is
+    /// also told not to follow a link if the file is swapped for one after
+    /// that check.
+    ///
+    /// System directories can be links too, such as `/tmp` on macOS, so this
+    /// is best combined with [`canonicalize()`](Self::canonicalize), which
+    /// resolves those before anything can be swapped.
+    pub fn refuse_symlinks(mut self, refuse_symlinks: bool) -> Self {
+        self.refuse_symlinks = refuse_symlinks;
+        self
+    }
+
+    /// Returns `path` as the pool should use it.
+    pub(crate) fn resolve(&self, path: &Path) -> PathBuf {
+        if !self.canonicalize {
+            return path.into();
+        }
+
+        // Canonicalize the deepest part of the path that exists, and put the
+        // rest back on the end.
+        let mut missing = Vec::new();
+        let mut existing = path;
+        loop {
+            let dir = if existing.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                existing
+            };
+            if let Ok(resolved) = fs::canonicalize(dir) {
+                return missing
+                    .iter()
+                    .rev()
+                    .fold(resolved, |resolved, name| resolved.join(name));
+            }
+            match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name);
+                    existing = parent;
+                }
+                _ => return path.into(),
+            }
+        }
+    }
+
+    /// Checks `path` before a connection is opened on it.
+    pub(crate) fn check(&self, path: &Path) -> Result<(), Error> {
+        if !self.refuse_symlinks {
+            return Ok(());
+        }
+        for ancestor in path.ancestors() {
+            if ancestor.as_os_str().is_empty() {
+                continue;
+            }
+            match fs::symlink_metadata(ancestor) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    return Err(Error::InvalidPath {
+                        path: path.into(),
+                        reason: if ancestor == path {
+                            "database is a symbolic link".into()
+                        } else {
+                            format!("{} is a symbolic link", ancestor.display())
+                        },
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
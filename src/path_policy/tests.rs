@@ -0,0 +1,89 @@
+use std::{fs, os::unix::fs::symlink};
+
+use super::*;
+use crate::{tests::TempDir, RusqliteConnectionManager};
+
+/// Returns a directory containing `real/`, and `link`, which points to it.
+fn linked(temp: &TempDir) -> Result<(PathBuf, PathBuf), anyhow::Error> {
+    let real = temp.file("real");
+    fs::create_dir(&real)?;
+    let link = temp.file("link");
+    symlink(&real, &link)?;
+    Ok((fs::canonicalize(real)?, link))
+}
+
+#[test]
+fn resolve() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let (real, link) = linked(&temp)?;
+    let policy = PathPolicy::new().canonicalize(true);
+
+    fs::write(real.join("app.db"), "")?;
+    assert_eq!(policy.resolve(&link.join("app.db")), real.join("app.db"));
+    assert_eq!(
+        policy.resolve(&link.join("missing/app.db")),
+        real.join("missing/app.db")
+    );
+    assert_eq!(
+        PathPolicy::new().resolve(&link.join("app.db")),
+        link.join("app.db")
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn canonicalize() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let (real, link) = linked(&temp)?;
+    let manager = RusqliteConnectionManager::new(link.join("app.db"))
+        .with_path_policy(PathPolicy::new().canonicalize(true));
+    assert_eq!(manager.path(), real.join("app.db"));
+
+    let pool = bb8::Pool::builder().build(manager.clone()).await?;
+    pool.get().await?;
+    assert_eq!(manager.metrics(&pool).path, real.join("app.db"));
+
+    manager.rotate(link.join("next.db"));
+    assert_eq!(manager.path(), real.join("next.db"));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refuse_symlinks() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let (real, link) = linked(&temp)?;
+    let refuse = PathPolicy::new().refuse_symlinks(true);
+
+    // Through a linked directory.
+    let manager =
+        RusqliteConnectionManager::new(link.join("app.db")).with_path_policy(refuse.clone());
+    match manager.connection_factory().connect() {
+        Err(Error::InvalidPath { path, reason }) => {
+            assert_eq!(path, link.join("app.db"));
+            assert_eq!(reason, format!("{} is a symbolic link", link.display()));
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    assert!(matches!(
+        manager.validate().await,
+        Err(Error::InvalidPath { .. })
+    ));
+
+    // Resolving the path first leaves no links to refuse.
+    let manager = RusqliteConnectionManager::new(link.join("app.db"))
+        .with_path_policy(refuse.clone().canonicalize(true));
+    let factory = manager.connection_factory();
+    drop(factory.connect()?);
+
+    // Until the file is swapped for one.
+    fs::rename(real.join("app.db"), real.join("moved.db"))?;
+    symlink(real.join("moved.db"), real.join("app.db"))?;
+    match factory.connect() {
+        Err(Error::InvalidPath { path, reason }) => {
+            assert_eq!(path, real.join("app.db"));
+            assert_eq!(reason, "database is a symbolic link");
+        }
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Running several independent statements in one hop to the blocking pool.
+
+use rusqlite::{types::Value, Connection};
+
+#[cfg(test)]
+mod tests;
+
+/// A statement to run as part of a
+/// [`PoolExt::pipeline()`](crate::PoolExt::pipeline).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineStatement {
+    pub(crate) sql: String,
+    pub(crate) params: Vec<Value>,
+}
+
+impl PipelineStatement {
+    /// Creates a statement without parameters.
+    pub fn new<S>(sql: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            sql: sql.into(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Sets the statement's positional parameters.
+    pub fn params<I>(mut self, params: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Value>,
+    {
+        self.params = params.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+/// The outcome of a statement run as part of a pipeline.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PipelineOutput {
+    /// The number of rows changed, for statements that don't return rows.
+    pub changes: usize,
+
+    /// The rows returned, for statements that do.
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Runs each statement in turn, carrying on after failures.
+pub(crate) fn run(
+    conn: &Connection,
+    statements: Vec<PipelineStatement>,
+) -> Vec<Result<PipelineOutput, rusqlite::Error>> {
+    statements
+        .into_iter()
+        .map(|statement| run_one(conn, &statement))
+        .collect()
+}
+
+pub(crate) fn run_one(
+    conn: &Connection,
+    statement: &PipelineStatement,
+) -> Result<PipelineOutput, rusqlite::Error> {
+    let mut stmt = conn.prepare_cached(&statement.sql)?;
+    let columns = stmt.column_count();
+    if columns == 0 {
+        return Ok(PipelineOutput {
+            changes: stmt.execute(&statement.params)?,
+            rows: Vec::new(),
+        });
+    }
+
+    let rows = stmt
+        .query_map(&statement.params, |row| {
+            (0..columns).map(|i| row.get(i)).collect()
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(PipelineOutput { changes: 0, rows })
+}
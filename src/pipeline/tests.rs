@@ -0,0 +1,40 @@
+use rusqlite::types::Value;
+
+use crate::{tests::TempDir, PipelineStatement, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pipeline() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("pipeline.db")))
+        .await?;
+
+    let results = pool
+        .pipeline(vec![
+            PipelineStatement::new("CREATE TABLE t (a INTEGER, b TEXT)"),
+            PipelineStatement::new("INSERT INTO t VALUES (?, ?), (?, ?)").params(vec![
+                Value::from(1),
+                Value::from("one".to_string()),
+                Value::from(2),
+                Value::Null,
+            ]),
+            PipelineStatement::new("INSERT INTO missing VALUES (1)"),
+            PipelineStatement::new("SELECT a, b FROM t WHERE a >= ? ORDER BY a").params(vec![1]),
+        ])
+        .await?;
+
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].as_ref().unwrap().changes, 0);
+    assert_eq!(results[1].as_ref().unwrap().changes, 2);
+    // A failure doesn't stop later statements from running.
+    assert!(results[2].is_err());
+    assert_eq!(
+        results[3].as_ref().unwrap().rows,
+        vec![
+            vec![Value::Integer(1), Value::Text("one".into())],
+            vec![Value::Integer(2), Value::Null],
+        ]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use std::fmt;
+
+use rusqlite::{Connection, ToSql};
+
+use crate::RusqliteConnection;
+
+#[cfg(test)]
+mod tests;
+
+/// The plan SQLite would use to run a query, as reported by
+/// `EXPLAIN QUERY PLAN`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryPlan {
+    /// The top level steps of the plan, in order.
+    pub steps: Vec<PlanStep>,
+}
+
+/// A step within a [`QueryPlan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanStep {
+    /// The step's ID, which is unique within the plan.
+    pub id: i64,
+
+    /// The ID of the parent step, or 0 for top level steps.
+    pub parent: i64,
+
+    /// SQLite's description of the step, such as
+    /// `SEARCH t USING INDEX t_a (a=?)`. The exact wording varies between
+    /// SQLite versions.
+    pub detail: String,
+
+    /// The steps nested within this one.
+    pub children: Vec<PlanStep>,
+}
+
+impl QueryPlan {
+    /// Returns every step in the plan, depth first.
+    pub fn iter(&self) -> impl Iterator<Item = &PlanStep> {
+        let mut stack: Vec<&PlanStep> = self.steps.iter().rev().collect();
+        std::iter::from_fn(move || {
+            let step = stack.pop()?;
+            stack.extend(step.children.iter().rev());
+            Some(step)
+        })
+    }
+
+    /// Returns true if any step uses the named index, including as a
+    /// covering index.
+    pub fn uses_index(&self, index: &str) -> bool {
+        let needle = format!("INDEX {}", index);
+        self.iter().any(|step| {
+            step.detail
+                .match_indices(&needle)
+                .any(|(at, _)| !continues_identifier(&step.detail[at + needle.len()..]))
+        })
+    }
+
+    /// Returns true if any step scans the named table in full, rather than
+    /// searching it through an index.
+    pub fn scans_table(&self, table: &str) -> bool {
+        self.iter().any(|step| {
+            // Older versions of SQLite say "SCAN TABLE t", newer ones "SCAN t".
+            let rest = step.detail.strip_prefix("SCAN ");
+            let rest = rest.map(|rest| rest.strip_prefix("TABLE ").unwrap_or(rest));
+            rest.and_then(|rest| rest.strip_prefix(table))
+                .is_some_and(|rest| !continues_identifier(rest))
+        })
+    }
+
+    fn from_rows(rows: Vec<(i64, i64, String)>) -> Self {
+        fn children(rows: &[(i64, i64, String)], parent: i64) -> Vec<PlanStep> {
+            rows.iter()
+                .filter(|(_, p, _)| *p == parent)
+                .map(|(id, parent, detail)| PlanStep {
+                    id: *id,
+                    parent: *parent,
+                    detail: detail.clone(),
+                    children: children(rows, *id),
+                })
+                .collect()
+        }
+
+        Self {
+            steps: children(&rows, 0),
+        }
+    }
+}
+
+fn continues_identifier(rest: &str) -> bool {
+    rest.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+}
+
+/// Formats the plan as a tree, in the same style as the `sqlite3` shell.
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_steps(
+            f: &mut fmt::Formatter<'_>,
+            steps: &[PlanStep],
+            prefix: &str,
+        ) -> fmt::Result {
+            for (i, step) in steps.iter().enumerate() {
+                let last = i + 1 == steps.len();
+                writeln!(
+                    f,
+                    "{}{}{}",
+                    prefix,
+                    if last { "`--" } else { "|--" },
+                    step.detail
+                )?;
+                let prefix = format!("{}{}", prefix, if last { "   " } else { "|  " });
+                write_steps(f, &step.children, &prefix)?;
+            }
+            Ok(())
+        }
+
+        writeln!(f, "QUERY PLAN")?;
+        write_steps(f, &self.steps, "")
+    }
+}
+
+/// What's prepared ahead of the SQL whose plan is explained.
+pub(crate) const PREFIX: &str = "EXPLAIN QUERY PLAN ";
+
+/// Returns the plan for `sql`. Parameters only need to be given if the plan
+/// may depend on them, which is rare.
+pub(crate) fn explain<P>(
+    conn: &Connection,
+    sql: &str,
+    params: P,
+) -> Result<QueryPlan, rusqlite::Error>
+where
+    P: IntoIterator,
+    P::Item: ToSql,
+{
+    let mut stmt = conn.prepare(&format!("{}{}", PREFIX, sql))?;
+    let rows = stmt
+        .query_map(params, |row| Ok((row.get(0)?, row.get(1)?, row.get(3)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(QueryPlan::from_rows(rows))
+}
+
+impl RusqliteConnection {
+    /// Returns the plan SQLite would use to run `sql`, so tests can assert
+    /// that queries use the indexes they are expected to. Parameters only
+    /// need to be given if the plan may depend on them, which is rare.
+    pub fn explain_plan<P>(&self, sql: &str, params: P) -> Result<QueryPlan, rusqlite::Error>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+    {
+        explain(self, sql, params)
+    }
+}
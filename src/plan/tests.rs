@@ -0,0 +1,51 @@
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn explain() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("plan.db")))
+        .await?;
+    pool.get().await?.execute_batch(
+        "CREATE TABLE t (a INTEGER, b TEXT);
+         CREATE INDEX t_a ON t (a);
+         CREATE TABLE u (c INTEGER);",
+    )?;
+
+    let plan = pool
+        .explain_plan("SELECT b FROM t WHERE a = ?", vec![1])
+        .await?;
+    assert!(plan.uses_index("t_a"));
+    assert!(!plan.uses_index("t"));
+    assert!(!plan.scans_table("t"));
+
+    let plan = pool
+        .get()
+        .await?
+        .explain_plan("SELECT b FROM t WHERE b = 'x'", NO_PARAMS)?;
+    assert!(!plan.uses_index("t_a"));
+    assert!(plan.scans_table("t"));
+
+    // Subqueries nest within the step that uses them.
+    let plan = pool
+        .explain_plan(
+            "SELECT * FROM u WHERE c IN (SELECT a FROM t WHERE b = 'x')",
+            Vec::<i64>::new(),
+        )
+        .await?;
+    assert!(plan.scans_table("u"));
+    let subquery = plan
+        .iter()
+        .find(|step| !step.children.is_empty())
+        .expect("a step with children");
+    assert!(subquery
+        .children
+        .iter()
+        .all(|child| child.parent == subquery.id));
+    assert!(plan.to_string().starts_with("QUERY PLAN\n"));
+    assert!(plan.to_string().contains("`--"));
+
+    Ok(())
+}
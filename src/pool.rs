@@ -0,0 +1,751 @@
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use rusqlite::{Connection, Row, ToSql, NO_PARAMS};
+use tokio::sync::broadcast;
+
+#[cfg(feature = "error-offset")]
+use crate::error_offset;
+#[cfg(feature = "log")]
+use crate::logging;
+#[cfg(feature = "otel")]
+use crate::otel;
+use crate::{
+    attach, backup, bulk, deadline, dump, dynamic, leadership, params, pipeline, plan,
+    schema::{self, Column, ForeignKey, ForeignKeyViolation, Index, Schema},
+    task, transaction, BulkInsertOptions, Capabilities, ChangeStream, DynamicRow, Error,
+    Leadership, NamedParams, PipelineOutput, PipelineStatement, QueryPlan, ReadConnection,
+    RestoreProgress, RowChange, RusqliteConnection, RusqliteConnectionManager, SqlRestoreOptions,
+    ToParams, UnitOfWork, Upsert, WriteConnection, WriteOptions,
+};
+#[cfg(feature = "begin-concurrent")]
+use crate::{concurrent, ConcurrentOptions};
+#[cfg(feature = "csv")]
+use crate::{csv_io, CsvImportOptions};
+
+/// Helpers that are available on pools of rusqlite connections.
+///
+/// Each method checks out a connection for the duration of the call, and runs
+/// any SQLite work within `task::block_in_place()`.
+///
+/// Within an [`ExecutionContext`](crate::ExecutionContext) scope, both the
+/// checkout and the work stop when the context's deadline passes or it's
+/// cancelled.
+///
+/// With
+/// [`RusqliteConnectionManager::with_max_concurrent_queries()`](crate::RusqliteConnectionManager::with_max_concurrent_queries),
+/// the work also waits until fewer than that many connections are running
+/// work of their own.
+#[async_trait]
+pub trait PoolExt {
+    /// Checks out a connection, as `bb8::Pool::get()` does, but also records
+    /// how long the checkout waited in the pool's
+    /// [metrics](crate::RusqliteConnectionManager::metrics).
+    async fn acquire(&self) -> Result<bb8::PooledConnection<'_, RusqliteConnectionManager>, Error>;
+
+    /// Checks out a connection for reading, with `PRAGMA query_only` enabled
+    /// until it's returned to the pool. The wait is recorded as for
+    /// [`acquire()`](Self::acquire).
+    async fn get_read(&self) -> Result<ReadConnection<'_>, Error>;
+
+    /// Checks out a connection for writing. The wait is recorded as for
+    /// [`acquire()`](Self::acquire).
+    async fn get_write(&self) -> Result<WriteConnection<'_>, Error>;
+
+    /// Checks out a connection for a [`UnitOfWork`], whose nested
+    /// transactions become savepoints. The wait is recorded as for
+    /// [`acquire()`](Self::acquire).
+    async fn unit_of_work(&self) -> Result<UnitOfWork<'_>, Error>;
+
+    /// Tries to become the leader of the processes sharing the database, such
+    /// as to elect a single process to run migrations or checkpoints.
+    /// Returns `None` without waiting if another process, or another caller
+    /// in this one, is already the leader. See [`Leadership`] for how the
+    /// lock works.
+    async fn try_acquire_leadership(&self) -> Result<Option<Leadership>, Error>;
+
+    /// Returns the database's schema version, as stored in
+    /// `PRAGMA user_version`.
+    async fn schema_version(&self) -> Result<i32, Error>;
+
+    /// Sets the database's schema version.
+    async fn set_schema_version(&self, version: i32) -> Result<(), Error>;
+
+    /// Describes the tables, columns, and indexes in the database. See the
+    /// [`schema`](crate::schema) module for comparing this against an
+    /// expected schema.
+    async fn schema(&self) -> Result<Schema, Error>;
+
+    /// Reports what the linked SQLite library supports, such as strict
+    /// tables and the JSON functions.
+    async fn capabilities(&self) -> Result<Capabilities, Error>;
+
+    /// Returns the columns of `table`, in declaration order.
+    async fn table_info(&self, table: &str) -> Result<Vec<Column>, Error>;
+
+    /// Returns the indexes on `table`, ordered by name.
+    async fn index_list(&self, table: &str) -> Result<Vec<Index>, Error>;
+
+    /// Returns the foreign keys on `table`, ordered by ID.
+    async fn foreign_key_list(&self, table: &str) -> Result<Vec<ForeignKey>, Error>;
+
+    /// Returns every row that breaks a foreign key constraint, as found by
+    /// `PRAGMA foreign_key_check`. This is useful after bulk imports made
+    /// with `PRAGMA foreign_keys` disabled, which SQLite doesn't check
+    /// retroactively when it's enabled again.
+    async fn check_foreign_keys(&self) -> Result<Vec<ForeignKeyViolation>, Error>;
+
+    /// Executes a statement with named parameters, returning the number of
+    /// rows changed. See [`NamedParams`] for building the parameters from a
+    /// map or struct.
+    async fn execute_named(&self, sql: &str, params: NamedParams) -> Result<usize, Error>;
+
+    /// Runs a statement with parameters bound from a [`ToParams`] value,
+    /// such as a struct deriving it, returning the number of rows changed.
+    async fn execute_with<P>(&self, sql: &str, params: &P) -> Result<usize, Error>
+    where
+        P: ToParams + Sync + ?Sized;
+
+    /// Runs a query with named parameters, returning the result of `f` for
+    /// each row.
+    async fn query_named<T, F>(
+        &self,
+        sql: &str,
+        params: NamedParams,
+        f: F,
+    ) -> Result<Vec<T>, Error>
+    where
+        F: FnMut(&Row<'_>) -> rusqlite::Result<T> + Send,
+        T: Send;
+
+    /// Runs a query, returning each row as a map from column names to values.
+    /// This suits generic tooling, such as admin interfaces and exporters,
+    /// that doesn't know the schema ahead of time.
+    async fn query_rows_dynamic<P>(&self, sql: &str, params: P) -> Result<Vec<DynamicRow>, Error>
+    where
+        P: IntoIterator + Send,
+        P::Item: ToSql;
+
+    /// Runs several independent statements on one connection, in a single
+    /// hop to the blocking thread, returning each statement's outcome in
+    /// order. This saves the overhead of a checkout and hop per statement,
+    /// which dominates workloads of many small queries.
+    ///
+    /// The statements aren't run in a transaction, and a failed statement
+    /// doesn't stop the rest from running, so some statements can be applied
+    /// and others not. That's still the case with `BEGIN` and `COMMIT` as the
+    /// first and last statements, since the `COMMIT` runs after a failure
+    /// too. Statements that have to be applied together need
+    /// [`write_transaction()`](Self::write_transaction) instead.
+    async fn pipeline(
+        &self,
+        statements: Vec<PipelineStatement>,
+    ) -> Result<Vec<Result<PipelineOutput, rusqlite::Error>>, Error>;
+
+    /// Inserts each of `rows` into `columns` of `table`, returning the number
+    /// of rows inserted. Rows are inserted in batches, each in its own
+    /// transaction, through a single cached prepared statement.
+    ///
+    /// The connection is held until every row has been inserted.
+    async fn bulk_insert<I, R>(
+        &self,
+        table: &str,
+        columns: &[&str],
+        rows: I,
+        options: BulkInsertOptions,
+    ) -> Result<u64, Error>
+    where
+        I: IntoIterator<Item = R> + Send,
+        I::IntoIter: Send,
+        R: IntoIterator,
+        R::Item: ToSql;
+
+    /// Runs `upsert` with `params` as the values of its columns, returning
+    /// the number of rows inserted or updated.
+    async fn upsert<P>(&self, upsert: &Upsert, params: P) -> Result<usize, Error>
+    where
+        P: IntoIterator + Send,
+        P::Item: ToSql;
+
+    /// Returns the plan SQLite would use to run `sql`. See
+    /// [`RusqliteConnection::explain_plan()`](crate::RusqliteConnection::explain_plan).
+    async fn explain_plan<P>(&self, sql: &str, params: P) -> Result<QueryPlan, Error>
+    where
+        P: IntoIterator + Send,
+        P::Item: ToSql;
+
+    /// Runs `f` within a `BEGIN CONCURRENT` transaction, committing it if `f`
+    /// succeeds. Concurrent transactions on different connections only
+    /// serialize at commit, and one that conflicts with another that
+    /// committed first is rolled back and run again, up to the configured
+    /// number of attempts.
+    ///
+    /// `f` may be called more than once, so it shouldn't have side effects
+    /// outside the database. This requires SQLite to be built from the
+    /// `begin-concurrent` branch: stock builds fail with a syntax error.
+    #[cfg(feature = "begin-concurrent")]
+    async fn concurrent_transaction<F, T>(
+        &self,
+        options: ConcurrentOptions,
+        f: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut(&Connection) -> Result<T, Error> + Send,
+        T: Send;
+
+    /// Runs `f` within a `BEGIN IMMEDIATE` transaction, committing it if `f`
+    /// succeeds, with the default [`WriteOptions`]. See
+    /// [`write_transaction_with()`](Self::write_transaction_with).
+    async fn write_transaction<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnMut(&Connection) -> Result<T, Error> + Send,
+        T: Send;
+
+    /// Runs `f` within a `BEGIN IMMEDIATE` transaction, committing it if `f`
+    /// succeeds. The transaction takes the write lock before `f` runs, so it
+    /// can't fail with `SQLITE_BUSY` partway through, as a deferred one can.
+    ///
+    /// If the database is busy, once the connection's busy timeout has
+    /// expired, or `f` itself fails with `SQLITE_BUSY`, the transaction is
+    /// rolled back and run again after a backoff, until the options' maximum
+    /// duration would pass. `f` may therefore be called more than once, so
+    /// it shouldn't have side effects outside the database.
+    async fn write_transaction_with<F, T>(&self, options: WriteOptions, f: F) -> Result<T, Error>
+    where
+        F: FnMut(&Connection) -> Result<T, Error> + Send,
+        T: Send;
+
+    /// Runs `f` within a transaction, with the default behavior, spanning
+    /// the main database and the ones attached with
+    /// [`RusqliteConnectionManager::with_attached_database()`], committing
+    /// it if `f` succeeds.
+    ///
+    /// SQLite only commits a transaction across databases atomically if
+    /// every one of them uses a rollback journal, with a `journal_mode` of
+    /// `DELETE`, `TRUNCATE` or `PERSIST`. A database in WAL mode, or with an
+    /// in-memory or no journal, can be left out of step with the others by
+    /// a crash during the commit, so this fails with [`Error::NotAtomic`]
+    /// before running `f` if any database, other than the temp database,
+    /// uses one of those modes.
+    async fn attached_transaction<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Connection) -> Result<T, Error> + Send,
+        T: Send;
+
+    /// Writes the schema and contents of the database to `writer` as a SQL
+    /// script, like the `sqlite3` shell's `.dump` command. Running the script
+    /// against an empty database recreates this one.
+    ///
+    /// The dump is read within a single transaction, so it's consistent even
+    /// while other connections are writing.
+    async fn dump<W>(&self, writer: W) -> Result<(), Error>
+    where
+        W: std::io::Write + Send;
+
+    /// Copies the database to `path` while the pool stays in use, replacing
+    /// any existing file.
+    ///
+    /// The copy is made with SQLite's backup API in a single step, so it's
+    /// consistent even while other connections are writing, although outside
+    /// WAL mode they wait for it to finish. It's then checked with
+    /// `PRAGMA quick_check`, failing with [`Error::IntegrityCheck`] if that
+    /// finds problems, and only renamed into place once it passes, so `path`
+    /// never holds a partial or unverified copy. If another connection keeps
+    /// the database locked for five seconds, the copy fails with
+    /// `SQLITE_BUSY`.
+    async fn export_snapshot<P>(&self, path: P) -> Result<(), Error>
+    where
+        P: AsRef<std::path::Path> + Send;
+
+    /// Executes a SQL script, such as one written by [`dump()`](Self::dump),
+    /// against the database, which must be empty. Statements are committed in
+    /// batches, all on the same connection.
+    ///
+    /// If a statement fails, the batch it's in is rolled back, but earlier
+    /// batches remain.
+    async fn restore_from_sql<R>(
+        &self,
+        reader: R,
+        options: SqlRestoreOptions,
+    ) -> Result<RestoreProgress, Error>
+    where
+        R: std::io::Read + Send;
+
+    /// Writes the results of `query` to `writer` as CSV, with a header of the
+    /// column names, returning the number of rows written. `NULL`s are
+    /// written as empty fields, and blobs as their raw bytes.
+    ///
+    /// The connection is held until every row has been written.
+    #[cfg(feature = "csv")]
+    async fn export_csv<W>(&self, query: &str, writer: W) -> Result<u64, Error>
+    where
+        W: std::io::Write + Send;
+
+    /// Inserts each record read from `reader` as CSV into `table`, returning
+    /// the number of rows inserted. Rows are inserted in batches, each in its
+    /// own transaction.
+    #[cfg(feature = "csv")]
+    async fn import_csv<R>(
+        &self,
+        table: &str,
+        reader: R,
+        options: CsvImportOptions,
+    ) -> Result<u64, Error>
+    where
+        R: std::io::Read + Send;
+
+    /// Returns a stream of changes committed to the database by other
+    /// connections, polling `PRAGMA data_version` every `interval`. This lets
+    /// a process notice writes by other processes sharing the database file.
+    async fn changes_stream(&self, interval: Duration) -> Result<ChangeStream, Error>;
+
+    /// Subscribes to the rows changed in `table` by transactions committed
+    /// through any of the pool's connections. This checks out a connection
+    /// just long enough to reach the manager's subscribers.
+    ///
+    /// Changes are buffered for each table, and never hold up the writer:
+    /// a receiver that falls behind by more than
+    /// [`with_subscription_capacity()`](crate::RusqliteConnectionManager::with_subscription_capacity)
+    /// changes gets `RecvError::Lagged`, then carries on from the oldest
+    /// change still buffered, so it should treat a lag as "anything may have
+    /// changed".
+    ///
+    /// A transaction's changes are sent once its commit has finished, which
+    /// SQLite doesn't announce, so they may not be sent until the connection
+    /// that made them is returned to the pool. Connections only track
+    /// changes once someone has subscribed, starting from their next
+    /// checkout.
+    ///
+    /// Changes come from SQLite's update hook, so they aren't reported for
+    /// `WITHOUT ROWID` tables, or for a `DELETE` without a `WHERE` clause,
+    /// which SQLite runs by truncating the table. Changes rolled back to a
+    /// savepoint are still reported if the transaction commits, and changes
+    /// made by other processes are never reported; see
+    /// [`changes_stream()`](Self::changes_stream) for those.
+    async fn subscribe(&self, table: &str) -> Result<broadcast::Receiver<RowChange>, Error>;
+}
+
+#[async_trait]
+impl PoolExt for bb8::Pool<RusqliteConnectionManager> {
+    async fn acquire(&self) -> Result<bb8::PooledConnection<'_, RusqliteConnectionManager>, Error> {
+        let waiting = Instant::now();
+        let mut conn = get(self, deadline::current().as_ref()).await?;
+        conn.checked_out(Some(waiting.elapsed()));
+        Ok(conn)
+    }
+
+    async fn get_read(&self) -> Result<ReadConnection<'_>, Error> {
+        Ok(ReadConnection::new(self.acquire().await?)?)
+    }
+
+    async fn get_write(&self) -> Result<WriteConnection<'_>, Error> {
+        Ok(WriteConnection::new(self.acquire().await?))
+    }
+
+    async fn unit_of_work(&self) -> Result<UnitOfWork<'_>, Error> {
+        Ok(UnitOfWork::new(self.acquire().await?))
+    }
+
+    async fn try_acquire_leadership(&self) -> Result<Option<Leadership>, Error> {
+        // The pool doesn't expose its manager, so the path comes from one of
+        // its connections.
+        let conn = get(self, deadline::current().as_ref()).await?;
+        task::block_in_place(|| leadership::try_acquire(&conn, &conn.file().path))
+    }
+
+    async fn schema_version(&self) -> Result<i32, Error> {
+        run(self, Operation::new("schema_version"), |conn| {
+            Ok(schema_version(conn)?)
+        })
+        .await
+    }
+
+    async fn set_schema_version(&self, version: i32) -> Result<(), Error> {
+        run(self, Operation::new("set_schema_version"), move |conn| {
+            Ok(conn.pragma_update(None, "user_version", &version)?)
+        })
+        .await
+    }
+
+    async fn schema(&self) -> Result<Schema, Error> {
+        run(self, Operation::new("schema"), |conn| {
+            Ok(Schema::read(conn)?)
+        })
+        .await
+    }
+
+    async fn capabilities(&self) -> Result<Capabilities, Error> {
+        run(self, Operation::new("capabilities"), |conn| {
+            Ok(Capabilities::detect(conn)?)
+        })
+        .await
+    }
+
+    async fn table_info(&self, table: &str) -> Result<Vec<Column>, Error> {
+        run(self, Operation::new("table_info"), move |conn| {
+            Ok(schema::table_info(conn, table)?)
+        })
+        .await
+    }
+
+    async fn index_list(&self, table: &str) -> Result<Vec<Index>, Error> {
+        run(self, Operation::new("index_list"), move |conn| {
+            Ok(schema::index_list(conn, table)?)
+        })
+        .await
+    }
+
+    async fn foreign_key_list(&self, table: &str) -> Result<Vec<ForeignKey>, Error> {
+        run(self, Operation::new("foreign_key_list"), move |conn| {
+            Ok(schema::foreign_key_list(conn, table)?)
+        })
+        .await
+    }
+
+    async fn check_foreign_keys(&self) -> Result<Vec<ForeignKeyViolation>, Error> {
+        run(self, Operation::new("foreign_key_check"), |conn| {
+            Ok(schema::foreign_key_check(conn)?)
+        })
+        .await
+    }
+
+    async fn execute_named(&self, sql: &str, params: NamedParams) -> Result<usize, Error> {
+        run(
+            self,
+            Operation::new("execute_named").statement(sql),
+            move |conn| Ok(params::execute(conn, sql, &params)?),
+        )
+        .await
+    }
+
+    async fn execute_with<P>(&self, sql: &str, params: &P) -> Result<usize, Error>
+    where
+        P: ToParams + Sync + ?Sized,
+    {
+        let params = params.to_params()?;
+        run(
+            self,
+            Operation::new("execute_with").statement(sql),
+            move |conn| Ok(params::execute(conn, sql, &params)?),
+        )
+        .await
+    }
+
+    async fn query_named<T, F>(&self, sql: &str, params: NamedParams, f: F) -> Result<Vec<T>, Error>
+    where
+        F: FnMut(&Row<'_>) -> rusqlite::Result<T> + Send,
+        T: Send,
+    {
+        run(
+            self,
+            Operation::new("query_named").statement(sql),
+            move |conn| params::query(conn, sql, &params, f),
+        )
+        .await
+    }
+
+    async fn query_rows_dynamic<P>(&self, sql: &str, params: P) -> Result<Vec<DynamicRow>, Error>
+    where
+        P: IntoIterator + Send,
+        P::Item: ToSql,
+    {
+        run(
+            self,
+            Operation::new("query_rows_dynamic").statement(sql),
+            move |conn| dynamic::query(conn, sql, params),
+        )
+        .await
+    }
+
+    async fn pipeline(
+        &self,
+        statements: Vec<PipelineStatement>,
+    ) -> Result<Vec<Result<PipelineOutput, rusqlite::Error>>, Error> {
+        run(self, Operation::new("pipeline"), move |conn| {
+            Ok(pipeline::run(conn, statements))
+        })
+        .await
+    }
+
+    async fn bulk_insert<I, R>(
+        &self,
+        table: &str,
+        columns: &[&str],
+        rows: I,
+        options: BulkInsertOptions,
+    ) -> Result<u64, Error>
+    where
+        I: IntoIterator<Item = R> + Send,
+        I::IntoIter: Send,
+        R: IntoIterator,
+        R::Item: ToSql,
+    {
+        run(self, Operation::new("bulk_insert"), move |conn| {
+            bulk::insert(conn, table, columns, rows, &options)
+        })
+        .await
+    }
+
+    async fn upsert<P>(&self, upsert: &Upsert, params: P) -> Result<usize, Error>
+    where
+        P: IntoIterator + Send,
+        P::Item: ToSql,
+    {
+        let sql = &upsert.sql();
+        run(self, Operation::new("upsert").statement(sql), move |conn| {
+            Ok(conn.prepare_cached(sql)?.execute(params)?)
+        })
+        .await
+    }
+
+    async fn explain_plan<P>(&self, sql: &str, params: P) -> Result<QueryPlan, Error>
+    where
+        P: IntoIterator + Send,
+        P::Item: ToSql,
+    {
+        run(
+            self,
+            Operation::new("explain_plan")
+                .statement(sql)
+                .prefixed(plan::PREFIX),
+            move |conn| Ok(plan::explain(conn, sql, params)?),
+        )
+        .await
+    }
+
+    #[cfg(feature = "begin-concurrent")]
+    async fn concurrent_transaction<F, T>(
+        &self,
+        options: ConcurrentOptions,
+        f: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut(&Connection) -> Result<T, Error> + Send,
+        T: Send,
+    {
+        run(
+            self,
+            Operation::new("concurrent_transaction"),
+            move |conn| concurrent::transaction(conn, "BEGIN CONCURRENT", &options, f),
+        )
+        .await
+    }
+
+    async fn write_transaction<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnMut(&Connection) -> Result<T, Error> + Send,
+        T: Send,
+    {
+        self.write_transaction_with(WriteOptions::default(), f)
+            .await
+    }
+
+    async fn write_transaction_with<F, T>(&self, options: WriteOptions, f: F) -> Result<T, Error>
+    where
+        F: FnMut(&Connection) -> Result<T, Error> + Send,
+        T: Send,
+    {
+        transaction::write(self, &options, f).await
+    }
+
+    async fn attached_transaction<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&Connection) -> Result<T, Error> + Send,
+        T: Send,
+    {
+        run(self, Operation::new("attached_transaction"), move |conn| {
+            attach::transaction(conn, f)
+        })
+        .await
+    }
+
+    async fn dump<W>(&self, writer: W) -> Result<(), Error>
+    where
+        W: std::io::Write + Send,
+    {
+        run(self, Operation::new("dump"), move |conn| {
+            dump::dump(conn, writer)
+        })
+        .await
+    }
+
+    async fn export_snapshot<P>(&self, path: P) -> Result<(), Error>
+    where
+        P: AsRef<std::path::Path> + Send,
+    {
+        let path = path.as_ref().to_path_buf();
+        run(self, Operation::new("export_snapshot"), move |conn| {
+            backup::export_snapshot(conn, &path)
+        })
+        .await
+    }
+
+    async fn restore_from_sql<R>(
+        &self,
+        reader: R,
+        options: SqlRestoreOptions,
+    ) -> Result<RestoreProgress, Error>
+    where
+        R: std::io::Read + Send,
+    {
+        run(self, Operation::new("restore_from_sql"), move |conn| {
+            dump::restore(conn, reader, &options)
+        })
+        .await
+    }
+
+    #[cfg(feature = "csv")]
+    async fn export_csv<W>(&self, query: &str, writer: W) -> Result<u64, Error>
+    where
+        W: std::io::Write + Send,
+    {
+        run(
+            self,
+            Operation::new("export_csv").statement(query),
+            move |conn| csv_io::export(conn, query, writer),
+        )
+        .await
+    }
+
+    #[cfg(feature = "csv")]
+    async fn import_csv<R>(
+        &self,
+        table: &str,
+        reader: R,
+        options: CsvImportOptions,
+    ) -> Result<u64, Error>
+    where
+        R: std::io::Read + Send,
+    {
+        run(self, Operation::new("import_csv"), move |conn| {
+            csv_io::import(conn, table, reader, &options)
+        })
+        .await
+    }
+
+    async fn changes_stream(&self, interval: Duration) -> Result<ChangeStream, Error> {
+        ChangeStream::new(self.dedicated_connection().await?, interval)
+    }
+
+    async fn subscribe(&self, table: &str) -> Result<broadcast::Receiver<RowChange>, Error> {
+        Ok(self.get().await?.subscribe(table))
+    }
+}
+
+/// Describes an operation run through [`run()`], for tracing.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(any(feature = "log", feature = "otel")), allow(dead_code))]
+pub(crate) struct Operation<'a> {
+    pub(crate) name: &'static str,
+    pub(crate) statement: Option<&'a str>,
+    /// How many bytes of SQL are prepared ahead of the statement, which
+    /// SQLite's error offsets count.
+    pub(crate) prefix: usize,
+}
+
+impl<'a> Operation<'a> {
+    pub(crate) fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            statement: None,
+            prefix: 0,
+        }
+    }
+
+    /// Sets the SQL the operation runs, if it's a single statement given by
+    /// the caller.
+    pub(crate) fn statement(mut self, sql: &'a str) -> Self {
+        self.statement = Some(sql);
+        self
+    }
+
+    /// Notes that the statement is prepared after `prefix`, so errors that
+    /// locate where it failed can be given relative to the caller's SQL.
+    pub(crate) fn prefixed(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.len();
+        self
+    }
+}
+
+/// Checks out a connection and runs `f` on it without starving the runtime.
+#[cfg_attr(
+    not(any(feature = "error-offset", feature = "log", feature = "otel")),
+    allow(unused_variables)
+)]
+pub(crate) async fn run<F, T>(
+    pool: &bb8::Pool<RusqliteConnectionManager>,
+    op: Operation<'_>,
+    f: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(&mut RusqliteConnection) -> Result<T, Error> + Send,
+    T: Send,
+{
+    #[cfg(feature = "otel")]
+    let mut span = otel::OperationSpan::start(&op);
+    let context = deadline::current();
+
+    let result = async {
+        if let Some(context) = &context {
+            context.throttle().await?;
+        }
+        let waiting = Instant::now();
+        let mut conn = get(pool, context.as_ref()).await?;
+        let wait = waiting.elapsed();
+        conn.checked_out(Some(wait));
+        #[cfg(feature = "otel")]
+        span.acquired(wait, &conn.file().path);
+        let _permit = match conn.execution_limit().cloned() {
+            Some(permits) => {
+                let permit = async {
+                    Ok(permits
+                        .acquire_owned()
+                        .await
+                        .expect("the execution limit is never closed"))
+                };
+                Some(match &context {
+                    Some(context) => context.wait(permit).await?,
+                    None => permit.await?,
+                })
+            }
+            None => None,
+        };
+        task::block_in_place(|| {
+            conn.inject_faults()?;
+            #[cfg(feature = "log")]
+            let started = Instant::now();
+            let result = match &context {
+                Some(context) => context.run(&mut conn, f),
+                None => f(&mut conn),
+            };
+            #[cfg(feature = "error-offset")]
+            let result = match (result, op.statement) {
+                (Err(e), Some(sql)) => Err(error_offset::locate(&conn, sql, op.prefix, e)),
+                (result, _) => result,
+            };
+            #[cfg(feature = "log")]
+            logging::operation_finished(&conn, &op, started.elapsed());
+            result
+        })
+    }
+    .await;
+
+    #[cfg(feature = "otel")]
+    span.finish(&result);
+    result
+}
+
+/// Checks out a connection, giving up if `context` expires first.
+async fn get<'a>(
+    pool: &'a bb8::Pool<RusqliteConnectionManager>,
+    context: Option<&deadline::ExecutionContext>,
+) -> Result<bb8::PooledConnection<'a, RusqliteConnectionManager>, Error> {
+    match context {
+        Some(context) => context.wait(async { Ok(pool.get().await?) }).await,
+        None => Ok(pool.get().await?),
+    }
+}
+
+pub(crate) fn schema_version(conn: &Connection) -> Result<i32, rusqlite::Error> {
+    conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+}
@@ -0,0 +1,278 @@
+//! Row change capture, through `sqlite3_preupdate_hook()`.
+//!
+//! This requires SQLite to be built with `SQLITE_ENABLE_PREUPDATE_HOOK`,
+//! which the `preupdate-hook` feature assumes; the crate fails to link
+//! otherwise.
+
+use std::{
+    ffi::CStr,
+    fmt,
+    os::raw::{c_char, c_int, c_void},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+    sync::Arc,
+};
+
+use rusqlite::{ffi, types::Value, Connection};
+
+#[cfg(feature = "audit")]
+use crate::audit;
+use crate::ffi_ext::{
+    sqlite3_preupdate_count, sqlite3_preupdate_depth, sqlite3_preupdate_hook,
+    sqlite3_preupdate_new, sqlite3_preupdate_old,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// The kind of change a [`PreUpdate`] describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreUpdateAction {
+    /// A row is being inserted.
+    Insert,
+    /// A row is being updated.
+    Update,
+    /// A row is being deleted.
+    Delete,
+}
+
+/// A change about to be made to a row, as passed to the callback set with
+/// [`RusqliteConnectionManager::on_preupdate()`](crate::RusqliteConnectionManager::on_preupdate).
+///
+/// The row's values are only available while the callback runs.
+pub struct PreUpdate<'a> {
+    /// The ID of the connection making the change, as returned by
+    /// [`RusqliteConnection::id()`](crate::RusqliteConnection::id).
+    pub connection: u64,
+
+    /// The kind of change.
+    pub action: PreUpdateAction,
+
+    /// The schema the table is in, which is `main` unless the connection has
+    /// attached other databases.
+    pub database: &'a str,
+
+    /// The table being changed.
+    pub table: &'a str,
+
+    /// The row's rowid before the change, for updates and deletes.
+    pub old_rowid: Option<i64>,
+
+    /// The row's rowid after the change, for inserts and updates.
+    pub new_rowid: Option<i64>,
+
+    db: *mut ffi::sqlite3,
+}
+
+impl fmt::Debug for PreUpdate<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreUpdate")
+            .field("connection", &self.connection)
+            .field("action", &self.action)
+            .field("database", &self.database)
+            .field("table", &self.table)
+            .field("old_rowid", &self.old_rowid)
+            .field("new_rowid", &self.new_rowid)
+            .finish()
+    }
+}
+
+impl PreUpdate<'_> {
+    /// Returns the number of columns in the row.
+    pub fn column_count(&self) -> usize {
+        // Safety: the handle is only used while the hook is running.
+        unsafe { sqlite3_preupdate_count(self.db) as usize }
+    }
+
+    /// Returns how deeply nested in triggers the change is: 0 for a change
+    /// made by a statement directly, 1 for one made by a trigger, and so on.
+    pub fn depth(&self) -> u32 {
+        // Safety: as above.
+        unsafe { sqlite3_preupdate_depth(self.db) as u32 }
+    }
+
+    /// Returns the value of `column` before the change, or `None` for
+    /// inserts, or if there's no such column.
+    pub fn old_value(&self, column: usize) -> Option<Value> {
+        self.value(sqlite3_preupdate_old, column)
+    }
+
+    /// Returns the value of `column` after the change, or `None` for
+    /// deletes, or if there's no such column.
+    pub fn new_value(&self, column: usize) -> Option<Value> {
+        self.value(sqlite3_preupdate_new, column)
+    }
+
+    /// Returns every value of the row before the change, or `None` for
+    /// inserts.
+    pub fn old_values(&self) -> Option<Vec<Value>> {
+        (0..self.column_count())
+            .map(|column| self.old_value(column))
+            .collect()
+    }
+
+    /// Returns every value of the row after the change, or `None` for
+    /// deletes.
+    pub fn new_values(&self) -> Option<Vec<Value>> {
+        (0..self.column_count())
+            .map(|column| self.new_value(column))
+            .collect()
+    }
+
+    fn value(
+        &self,
+        get: unsafe extern "C" fn(*mut ffi::sqlite3, c_int, *mut *mut ffi::sqlite3_value) -> c_int,
+        column: usize,
+    ) -> Option<Value> {
+        let mut value = ptr::null_mut();
+        // Safety: SQLite checks the column and the kind of change, and the
+        // value is copied before the hook returns.
+        unsafe {
+            if get(self.db, column as c_int, &mut value) != ffi::SQLITE_OK || value.is_null() {
+                return None;
+            }
+            Some(to_value(value))
+        }
+    }
+}
+
+/// Copies a protected `sqlite3_value`.
+unsafe fn to_value(value: *mut ffi::sqlite3_value) -> Value {
+    match ffi::sqlite3_value_type(value) {
+        ffi::SQLITE_INTEGER => Value::Integer(ffi::sqlite3_value_int64(value)),
+        ffi::SQLITE_FLOAT => Value::Real(ffi::sqlite3_value_double(value)),
+        ffi::SQLITE_TEXT => {
+            let text = ffi::sqlite3_value_text(value);
+            let len = ffi::sqlite3_value_bytes(value) as usize;
+            if text.is_null() {
+                return Value::Text(String::new());
+            }
+            Value::Text(String::from_utf8_lossy(slice::from_raw_parts(text, len)).into_owned())
+        }
+        ffi::SQLITE_BLOB => {
+            let blob = ffi::sqlite3_value_blob(value) as *const u8;
+            let len = ffi::sqlite3_value_bytes(value) as usize;
+            if blob.is_null() {
+                return Value::Blob(Vec::new());
+            }
+            Value::Blob(slice::from_raw_parts(blob, len).to_vec())
+        }
+        _ => Value::Null,
+    }
+}
+
+type Callback = dyn Fn(&PreUpdate<'_>) + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct PreUpdateHook(Arc<Callback>);
+
+impl fmt::Debug for PreUpdateHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreUpdateHook").finish_non_exhaustive()
+    }
+}
+
+struct Context {
+    hook: Option<PreUpdateHook>,
+    #[cfg(feature = "audit")]
+    audit: Option<Arc<audit::Pending>>,
+    id: u64,
+}
+
+impl PreUpdateHook {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&PreUpdate<'_>) + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+}
+
+/// Installs the preupdate hook on `conn`, which has the given ID, calling
+/// `hook` and recording changes for the audit log, if given. SQLite only
+/// allows one hook per connection, so this does both.
+pub(crate) fn install(
+    conn: &Connection,
+    id: u64,
+    hook: Option<PreUpdateHook>,
+    #[cfg(feature = "audit")] audit: Option<Arc<audit::Pending>>,
+) -> Registration {
+    let context = Box::new(Context {
+        hook,
+        #[cfg(feature = "audit")]
+        audit,
+        id,
+    });
+    // Safety: the context is boxed, so its address is stable until the
+    // registration is dropped, which happens after the connection is
+    // closed, or after uninstall() has removed the hook.
+    unsafe {
+        sqlite3_preupdate_hook(
+            conn.handle(),
+            Some(changing),
+            &*context as *const Context as *mut c_void,
+        );
+    }
+    Registration { context }
+}
+
+pub(crate) struct Registration {
+    context: Box<Context>,
+}
+
+impl fmt::Debug for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registration")
+            .field("id", &self.context.id)
+            .finish()
+    }
+}
+
+impl Registration {
+    /// Removes the hook from `conn`, so the connection can outlive the
+    /// registration.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: clearing the hook can't fail on an open handle.
+        unsafe {
+            sqlite3_preupdate_hook(conn.handle(), None, ptr::null_mut());
+        }
+    }
+}
+
+unsafe extern "C" fn changing(
+    context: *mut c_void,
+    db: *mut ffi::sqlite3,
+    op: c_int,
+    database: *const c_char,
+    table: *const c_char,
+    old_rowid: i64,
+    new_rowid: i64,
+) {
+    let context = &*(context as *const Context);
+    let action = match op {
+        ffi::SQLITE_INSERT => PreUpdateAction::Insert,
+        ffi::SQLITE_UPDATE => PreUpdateAction::Update,
+        ffi::SQLITE_DELETE => PreUpdateAction::Delete,
+        _ => return,
+    };
+    let database = CStr::from_ptr(database).to_string_lossy();
+    let table = CStr::from_ptr(table).to_string_lossy();
+    let change = PreUpdate {
+        connection: context.id,
+        action,
+        database: &database,
+        table: &table,
+        old_rowid: (action != PreUpdateAction::Insert).then_some(old_rowid),
+        new_rowid: (action != PreUpdateAction::Delete).then_some(new_rowid),
+        db,
+    };
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        if let Some(hook) = &context.hook {
+            (hook.0)(&change);
+        }
+        #[cfg(feature = "audit")]
+        if let Some(audit) = &context.audit {
+            audit.record(&change);
+        }
+    }));
+}
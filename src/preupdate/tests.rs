@@ -0,0 +1,76 @@
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{types::Value, NO_PARAMS};
+
+use super::*;
+use crate::{tests::TempDir, RusqliteConnectionManager};
+
+type Change = (
+    PreUpdateAction,
+    String,
+    u32,
+    String,
+    Option<i64>,
+    Option<Vec<Value>>,
+    Option<Vec<Value>>,
+);
+
+#[tokio::test(flavor = "multi_thread")]
+async fn captures_changes() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let changes: Arc<Mutex<Vec<Change>>> = Arc::default();
+    let manager = RusqliteConnectionManager::new(temp.file("preupdate.db")).on_preupdate({
+        let changes = changes.clone();
+        move |change| {
+            // Panics in the callback are swallowed, so everything is checked
+            // afterwards.
+            changes.lock().unwrap().push((
+                change.action,
+                change.database.to_owned(),
+                change.depth(),
+                change.table.to_owned(),
+                change.old_rowid.or(change.new_rowid),
+                change.old_values(),
+                change.new_values(),
+            ));
+        }
+    });
+    let pool = bb8::Pool::builder().build(manager).await?;
+
+    let conn = pool.get().await?;
+    conn.execute_batch(
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, v TEXT, b BLOB);
+        INSERT INTO t VALUES (1, 'a', x'00ff');
+        UPDATE t SET v = 'b' WHERE id = 1;
+        DELETE FROM t;",
+    )?;
+    conn.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |_| Ok(()))?;
+
+    let row = |v: &str| {
+        vec![
+            Value::Integer(1),
+            Value::Text(v.into()),
+            Value::Blob(vec![0x00, 0xff]),
+        ]
+    };
+    let change = |action, old: Option<&str>, new: Option<&str>| -> Change {
+        (
+            action,
+            "main".into(),
+            0,
+            "t".into(),
+            Some(1),
+            old.map(row),
+            new.map(row),
+        )
+    };
+    assert_eq!(
+        *changes.lock().unwrap(),
+        [
+            change(PreUpdateAction::Insert, None, Some("a")),
+            change(PreUpdateAction::Update, Some("a"), Some("b")),
+            change(PreUpdateAction::Delete, Some("b"), None),
+        ]
+    );
+    Ok(())
+}
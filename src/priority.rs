@@ -0,0 +1,177 @@
+//! Connection checkouts that are served in priority order.
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt,
+    future::{poll_fn, Future},
+    pin::pin,
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::Instant,
+};
+
+use tokio::sync::Notify;
+
+use crate::{deadline, Error, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// How urgently a checkout from a [`PriorityPool`] needs a connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Batch and background work, which can wait.
+    Low,
+    /// Everything else.
+    #[default]
+    Normal,
+    /// Latency critical work, such as serving interactive requests.
+    High,
+}
+
+/// A pool whose checkouts, when they have to wait, are served highest
+/// priority first, and in the order they arrived within a priority.
+///
+/// bb8 serves waiters in the order they arrived, so only one checkout made
+/// through the wrapper waits in the pool at a time: the one at the front of
+/// the queue. A checkout that arrives ahead of it takes over, and the one
+/// that was waiting goes back to the queue. Checkouts made on the pool
+/// directly, rather than through the wrapper, bypass the queue altogether.
+///
+/// Clones share the same queue.
+#[derive(Clone)]
+pub struct PriorityPool {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl fmt::Debug for PriorityPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriorityPool")
+            .field("pool", &self.pool)
+            .field("waiting", &self.queue.lock().unwrap().waiting.len())
+            .finish()
+    }
+}
+
+// Waiters are ordered by priority, highest first, then by arrival.
+type Key = (Reverse<Priority>, u64);
+
+#[derive(Debug, Default)]
+struct Queue {
+    next: u64,
+    waiting: BTreeMap<Key, Arc<Notify>>,
+}
+
+impl PriorityPool {
+    /// Wraps `pool`.
+    pub fn new(pool: bb8::Pool<RusqliteConnectionManager>) -> Self {
+        Self {
+            pool,
+            queue: Arc::default(),
+        }
+    }
+
+    /// Returns the wrapped pool.
+    pub fn pool(&self) -> &bb8::Pool<RusqliteConnectionManager> {
+        &self.pool
+    }
+
+    /// Checks out a connection at [`Priority::Normal`].
+    pub async fn get(&self) -> Result<bb8::PooledConnection<'_, RusqliteConnectionManager>, Error> {
+        self.get_with_priority(Priority::Normal).await
+    }
+
+    /// Checks out a connection, ahead of any waiting checkouts with a lower
+    /// priority. The wait is recorded as for
+    /// [`PoolExt::acquire()`](crate::PoolExt::acquire), and within an
+    /// [`ExecutionContext`](crate::ExecutionContext) scope stops when the
+    /// context expires.
+    pub async fn get_with_priority(
+        &self,
+        priority: Priority,
+    ) -> Result<bb8::PooledConnection<'_, RusqliteConnectionManager>, Error> {
+        let waiting = Instant::now();
+        let mut conn = match deadline::current() {
+            Some(context) => context.wait(self.queued(priority)).await?,
+            None => self.queued(priority).await?,
+        };
+        conn.checked_out(Some(waiting.elapsed()));
+        Ok(conn)
+    }
+
+    async fn queued(
+        &self,
+        priority: Priority,
+    ) -> Result<bb8::PooledConnection<'_, RusqliteConnectionManager>, Error> {
+        let place = Place::join(&self.queue, priority);
+        loop {
+            if !place.is_front() {
+                place.notify.notified().await;
+                continue;
+            }
+
+            // Wait in the pool until a connection is free, or until another
+            // checkout joins the queue ahead of this one.
+            let mut get = pin!(self.pool.get());
+            let mut overtaken = pin!(place.notify.notified());
+            let got = poll_fn(|cx| {
+                if let Poll::Ready(result) = get.as_mut().poll(cx) {
+                    return Poll::Ready(Some(result));
+                }
+                overtaken.as_mut().poll(cx).map(|_| None)
+            })
+            .await;
+            if let Some(result) = got {
+                return Ok(result?);
+            }
+        }
+    }
+}
+
+/// A checkout's place in the queue, which it leaves when dropped.
+struct Place<'a> {
+    queue: &'a Mutex<Queue>,
+    key: Key,
+    notify: Arc<Notify>,
+}
+
+impl<'a> Place<'a> {
+    fn join(queue: &'a Mutex<Queue>, priority: Priority) -> Self {
+        let notify = Arc::new(Notify::new());
+        let mut locked = queue.lock().unwrap();
+        let key = (Reverse(priority), locked.next);
+        locked.next += 1;
+        // Whoever was at the front has to stop waiting in the pool if this
+        // checkout is now ahead of it.
+        let overtaken = locked
+            .waiting
+            .first_key_value()
+            .filter(|(front, _)| key < **front)
+            .map(|(_, notify)| notify.clone());
+        locked.waiting.insert(key, notify.clone());
+        if let Some(overtaken) = overtaken {
+            overtaken.notify_one();
+        }
+        Self { queue, key, notify }
+    }
+
+    fn is_front(&self) -> bool {
+        let queue = self.queue.lock().unwrap();
+        queue.waiting.keys().next() == Some(&self.key)
+    }
+}
+
+impl Drop for Place<'_> {
+    fn drop(&mut self) {
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        let front = queue.waiting.keys().next() == Some(&self.key);
+        queue.waiting.remove(&self.key);
+        if front {
+            if let Some(notify) = queue.waiting.values().next() {
+                notify.notify_one();
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    ExecutionContext,
+};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn priority_order() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = PriorityPool::new(
+        pool(
+            RusqliteConnectionManager::new(temp.file("priority.db")),
+            1,
+            "",
+        )
+        .await?,
+    );
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let held = pool.get().await?;
+
+    // The first waiter is already waiting in the pool when the others
+    // arrive.
+    let mut waiters = Vec::new();
+    for priority in [
+        Priority::Low,
+        Priority::Normal,
+        Priority::Low,
+        Priority::High,
+    ] {
+        let (pool, order) = (pool.clone(), order.clone());
+        waiters.push(tokio::spawn(async move {
+            let _conn = pool.get_with_priority(priority).await?;
+            order.lock().unwrap().push(priority);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok::<_, Error>(())
+        }));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    drop(held);
+    for waiter in waiters {
+        waiter.await??;
+    }
+
+    assert_eq!(
+        *order.lock().unwrap(),
+        vec![
+            Priority::High,
+            Priority::Normal,
+            Priority::Low,
+            Priority::Low
+        ]
+    );
+    assert!(pool.queue.lock().unwrap().waiting.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn abandoned_checkout() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = PriorityPool::new(
+        pool(
+            RusqliteConnectionManager::new(temp.file("priority.db")),
+            1,
+            "",
+        )
+        .await?,
+    );
+    let held = pool.get().await?;
+
+    let waiter = tokio::spawn({
+        let pool = pool.clone();
+        async move {
+            pool.get_with_priority(Priority::Low).await?;
+            Ok::<_, Error>(())
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // A checkout that gives up at the front of the queue hands its place on.
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_millis(50))
+        .scope(pool.get_with_priority(Priority::High))
+        .await;
+    assert!(matches!(result, Err(Error::DeadlineExceeded)));
+
+    drop(held);
+    waiter.await??;
+    Ok(())
+}
@@ -0,0 +1,159 @@
+//! Statement profiling, through SQLite's `sqlite3_trace_v2()` interface.
+//!
+//! With a profiler installed through
+//! [`RusqliteConnectionManager::with_profiler()`](crate::RusqliteConnectionManager::with_profiler),
+//! every connection in the pool reports each statement it finishes running,
+//! along with how long it took. This is intended as a building block for
+//! feeding query timings into metrics or APM systems.
+
+use std::{
+    ffi::CStr,
+    fmt,
+    os::raw::{c_int, c_uint, c_void},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    sync::Arc,
+    time::Duration,
+};
+
+use rusqlite::{ffi, Connection};
+
+use crate::ffi_ext::{sqlite3_expanded_sql, sqlite3_trace_v2, TRACE_PROFILE};
+
+#[cfg(test)]
+mod tests;
+
+/// A statement that has finished running, as reported to a profiler.
+pub struct StatementProfile<'a> {
+    stmt: *mut ffi::sqlite3_stmt,
+    sql: &'a str,
+    duration: Duration,
+}
+
+impl StatementProfile<'_> {
+    /// Returns the SQL of the statement, as it was prepared.
+    pub fn sql(&self) -> &str {
+        self.sql
+    }
+
+    /// Returns the SQL of the statement with its bound parameters
+    /// substituted in, or `None` if SQLite couldn't allocate it.
+    ///
+    /// This may include sensitive values, so take care before logging it.
+    pub fn expanded_sql(&self) -> Option<String> {
+        // Safety: the statement is valid for the duration of the trace
+        // callback, which outlives self, and SQLite hands ownership of the
+        // expanded string to us.
+        unsafe {
+            let expanded = sqlite3_expanded_sql(self.stmt);
+            if expanded.is_null() {
+                return None;
+            }
+            let sql = CStr::from_ptr(expanded).to_string_lossy().into_owned();
+            ffi::sqlite3_free(expanded as *mut c_void);
+            Some(sql)
+        }
+    }
+
+    /// Returns how long the statement took to run, as measured by SQLite.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+impl fmt::Debug for StatementProfile<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StatementProfile")
+            .field("sql", &self.sql)
+            .field("duration", &self.duration)
+            .finish()
+    }
+}
+
+type Callback = dyn Fn(&StatementProfile<'_>) + Send + Sync;
+
+/// A profiling callback, shared by every connection in a pool.
+#[derive(Clone)]
+pub(crate) struct Profiler(Arc<Callback>);
+
+impl fmt::Debug for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Profiler").finish()
+    }
+}
+
+impl Profiler {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&StatementProfile<'_>) + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+
+    /// Installs the profiler on `conn`. The returned registration must be
+    /// kept alive for as long as the profiler is installed.
+    pub(crate) fn install(&self, conn: &Connection) -> Result<Registration, rusqlite::Error> {
+        let context = Box::new(self.clone());
+        // Safety: the context is boxed, so its address is stable until the
+        // registration is dropped, which uninstalls the profiler first if
+        // the connection is still open.
+        let rc = unsafe {
+            sqlite3_trace_v2(
+                conn.handle(),
+                TRACE_PROFILE,
+                Some(trace),
+                &*context as *const Profiler as *mut c_void,
+            )
+        };
+        if rc != ffi::SQLITE_OK {
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+        }
+        Ok(Registration { _context: context })
+    }
+}
+
+/// Keeps a profiler's context alive while it is installed on a connection.
+#[derive(Debug)]
+pub(crate) struct Registration {
+    _context: Box<Profiler>,
+}
+
+impl Registration {
+    /// Removes the profiler from `conn`, so the connection can outlive the
+    /// registration.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: clearing the trace callback can't fail on an open handle.
+        unsafe {
+            sqlite3_trace_v2(conn.handle(), 0, None, ptr::null_mut());
+        }
+    }
+}
+
+pub(crate) unsafe extern "C" fn trace(
+    event: c_uint,
+    context: *mut c_void,
+    stmt: *mut c_void,
+    nanos: *mut c_void,
+) -> c_int {
+    if event != TRACE_PROFILE {
+        return 0;
+    }
+    let profiler = &*(context as *const Profiler);
+    let stmt = stmt as *mut ffi::sqlite3_stmt;
+    let sql = ffi::sqlite3_sql(stmt);
+    let sql = if sql.is_null() {
+        ""
+    } else {
+        CStr::from_ptr(sql).to_str().unwrap_or("")
+    };
+    let profile = StatementProfile {
+        stmt,
+        sql,
+        duration: Duration::from_nanos(*(nanos as *const i64) as u64),
+    };
+
+    // Unwinding into SQLite would be undefined behaviour, so a panicking
+    // profiler is ignored.
+    let _ = catch_unwind(AssertUnwindSafe(|| (profiler.0)(&profile)));
+    0
+}
@@ -0,0 +1,63 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{tests::TempDir, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn profiler() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let profiles = Arc::new(Mutex::new(Vec::new()));
+    let manager = RusqliteConnectionManager::new(temp.file("profile.db")).with_profiler({
+        let profiles = profiles.clone();
+        move |profile| {
+            profiles.lock().unwrap().push((
+                profile.sql().to_string(),
+                profile.expanded_sql(),
+                profile.duration(),
+            ))
+        }
+    });
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+
+    {
+        let conn = pool.get().await?;
+        conn.execute_batch("CREATE TABLE t (a INTEGER, b TEXT)")?;
+        profiles.lock().unwrap().clear();
+        conn.execute("INSERT INTO t VALUES (?, ?)", rusqlite::params![1, "x"])?;
+    }
+
+    let seen = profiles.lock().unwrap().clone();
+    let (sql, expanded, _) = seen
+        .iter()
+        .find(|(sql, _, _)| sql.starts_with("INSERT"))
+        .expect("a profile for the insert");
+    assert_eq!(sql, "INSERT INTO t VALUES (?, ?)");
+    assert_eq!(expanded.as_deref(), Some("INSERT INTO t VALUES (1, 'x')"));
+
+    // The profiler is removed from connections that leave the pool.
+    let conn = pool.dedicated_connection().await?.into_inner();
+    drop(pool);
+    let before = profiles.lock().unwrap().len();
+    conn.execute_batch("SELECT 1")?;
+    assert_eq!(profiles.lock().unwrap().len(), before);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn profiler_with_contention_monitor() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let profiles = Arc::new(Mutex::new(Vec::new()));
+    let manager = RusqliteConnectionManager::new(temp.file("profile.db"))
+        .with_profiler({
+            let profiles = profiles.clone();
+            move |profile| profiles.lock().unwrap().push(profile.sql().to_string())
+        })
+        .with_contention_monitor(crate::contention::ContentionMonitor::new(|_| {}));
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+
+    pool.get().await?.execute_batch("CREATE TABLE t (a)")?;
+    assert!(profiles
+        .lock()
+        .unwrap()
+        .contains(&"CREATE TABLE t (a)".to_string()));
+    Ok(())
+}
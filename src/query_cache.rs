@@ -0,0 +1,266 @@
+//! Caching query results until the database changes.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{ffi, types::Value, Connection, NO_PARAMS};
+
+use crate::{dynamic, task, DynamicRow, Error, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// A cache of query results, which are kept until the database changes.
+///
+/// Every lookup still checks out a connection, but only to read
+/// `PRAGMA data_version` and the connection's own change counter, which
+/// together show whether anything has been committed since the results were
+/// cached; if so, the whole cache is cleared. No manual invalidation is
+/// needed, even for writes made by other processes.
+///
+/// A connection the cache hasn't seen before has nothing to compare against,
+/// so its first use also clears the cache. Schema changes made through a
+/// pooled connection aren't noticed by that connection, so
+/// [`clear()`](Self::clear) the cache after migrating.
+///
+/// Clones share the same cache.
+#[derive(Clone)]
+pub struct QueryCache {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    capacity: usize,
+    state: Arc<Mutex<State>>,
+}
+
+impl fmt::Debug for QueryCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryCache")
+            .field("pool", &self.pool)
+            .field("capacity", &self.capacity)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl QueryCache {
+    /// Creates a cache of up to 128 results from queries on `pool`.
+    pub fn new(pool: bb8::Pool<RusqliteConnectionManager>) -> Self {
+        Self {
+            pool,
+            capacity: 128,
+            state: Default::default(),
+        }
+    }
+
+    /// Sets how many results are kept. Once the cache is full, the least
+    /// recently used result is dropped to make room.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Runs `sql` with `params`, unless its results are already cached and
+    /// the database hasn't changed since.
+    ///
+    /// Only use this for queries without side effects, whose results depend
+    /// on nothing but the database: a query using `random()` or `'now'` will
+    /// keep returning the same results.
+    pub async fn query<P>(&self, sql: &str, params: P) -> Result<Arc<Vec<DynamicRow>>, Error>
+    where
+        P: IntoIterator,
+        P::Item: Into<Value>,
+    {
+        let key = Key {
+            sql: sql.to_string(),
+            params: params.into_iter().map(Into::into).collect(),
+        };
+        let conn = self.pool.get().await?;
+        task::block_in_place(|| {
+            let epoch = {
+                let fingerprint = Fingerprint::read(&conn)?;
+                let connections = self.pool.state().connections as usize;
+                let mut state = self.state.lock().unwrap();
+                state.check(conn.id(), fingerprint, connections);
+                if let Some(rows) = state.get(&key) {
+                    return Ok(rows);
+                }
+                state.epoch
+            };
+
+            let rows = Arc::new(dynamic::query(&conn, &key.sql, &key.params)?);
+            let mut state = self.state.lock().unwrap();
+            // If the cache was cleared while the query ran, the results may
+            // predate the change that cleared it.
+            if state.epoch == epoch {
+                state.insert(key, rows.clone(), self.capacity);
+            }
+            Ok(rows)
+        })
+    }
+
+    /// Drops every cached result.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().clear();
+    }
+
+    /// Returns the number of cached results.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Returns true if no results are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The SQL and parameters of a cached query.
+#[derive(Debug, Clone)]
+pub(crate) struct Key {
+    pub(crate) sql: String,
+    pub(crate) params: Vec<Value>,
+}
+
+impl Key {
+    fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
+        std::mem::discriminant(value).hash(state);
+        match value {
+            Value::Null => {}
+            Value::Integer(i) => i.hash(state),
+            Value::Real(f) => f.to_bits().hash(state),
+            Value::Text(s) => s.hash(state),
+            Value::Blob(b) => b.hash(state),
+        }
+    }
+
+    fn value_eq(a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            // Comparing the bits keeps this consistent with the hash, and
+            // lets NaN parameters be cached.
+            (Value::Real(a), Value::Real(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sql.hash(state);
+        self.params.len().hash(state);
+        for value in &self.params {
+            Self::hash_value(value, state);
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.sql == other.sql
+            && self.params.len() == other.params.len()
+            && self
+                .params
+                .iter()
+                .zip(&other.params)
+                .all(|(a, b)| Self::value_eq(a, b))
+    }
+}
+
+impl Eq for Key {}
+
+/// What a connection knows about changes to the database.
+///
+/// `data_version` changes when another connection commits, and the total
+/// change count when this one writes, so between them they catch every
+/// commit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Fingerprint {
+    data_version: i64,
+    total_changes: i32,
+}
+
+impl Fingerprint {
+    fn read(conn: &Connection) -> Result<Self, rusqlite::Error> {
+        Ok(Self {
+            data_version: conn.query_row("PRAGMA data_version", NO_PARAMS, |row| row.get(0))?,
+            // Safety: the handle is only used for the duration of the call.
+            total_changes: unsafe { ffi::sqlite3_total_changes(conn.handle()) },
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    rows: Arc<Vec<DynamicRow>>,
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<Key, Entry>,
+    // The fingerprint each connection had when it was last used, and when
+    // that was by the clock.
+    fingerprints: HashMap<u64, (Fingerprint, u64)>,
+    // Bumped whenever the cache is cleared.
+    epoch: u64,
+    clock: u64,
+}
+
+impl State {
+    /// Clears the cache if the database may have changed since connection
+    /// `id` was last used.
+    ///
+    /// Closed connections' fingerprints are never used again, so only as
+    /// many are kept as the pool has `connections` open, forgetting the
+    /// least recently used. Forgetting one that's still open just means its
+    /// next use clears the cache.
+    fn check(&mut self, id: u64, fingerprint: Fingerprint, connections: usize) {
+        self.clock += 1;
+        let previous = self.fingerprints.insert(id, (fingerprint, self.clock));
+        if previous.map(|(previous, _)| previous) != Some(fingerprint) {
+            self.clear();
+        }
+        while self.fingerprints.len() > connections.max(1) {
+            if let Some(oldest) = self
+                .fingerprints
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(id, _)| *id)
+            {
+                self.fingerprints.remove(&oldest);
+            }
+        }
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Arc<Vec<DynamicRow>>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.used = self.clock;
+        Some(entry.rows.clone())
+    }
+
+    fn insert(&mut self, key: Key, rows: Arc<Vec<DynamicRow>>, capacity: usize) {
+        if self.entries.len() >= capacity && !self.entries.contains_key(&key) {
+            // Caches are small enough that a scan is cheaper than
+            // maintaining a list.
+            if let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+            {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        let used = self.clock;
+        self.entries.insert(key, Entry { rows, used });
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.epoch += 1;
+    }
+}
@@ -0,0 +1,114 @@
+use super::*;
+use crate::tests::{pool, TempDir};
+
+const SETUP: &str = "CREATE TABLE t (a INTEGER);
+                    INSERT INTO t (a) VALUES (1), (2), (3);";
+
+const QUERY: &str = "SELECT a FROM t WHERE a > ? ORDER BY a";
+
+fn values(rows: &[DynamicRow]) -> Vec<Value> {
+    rows.iter().map(|row| row["a"].clone()).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hit() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    // With a single connection, only the first lookup finds a connection
+    // the cache hasn't seen.
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("cache.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let cache = QueryCache::new(pool.clone());
+
+    let first = cache.query(QUERY, vec![1]).await?;
+    assert_eq!(values(&first), vec![Value::Integer(2), Value::Integer(3)]);
+    let second = cache.query(QUERY, vec![1]).await?;
+    assert!(Arc::ptr_eq(&first, &second));
+
+    // Different parameters are a different query.
+    let other = cache.query(QUERY, vec![2]).await?;
+    assert_eq!(values(&other), vec![Value::Integer(3)]);
+    assert_eq!(cache.len(), 2);
+
+    cache.clear();
+    assert!(cache.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalidated_by_writes() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("cache.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+    let cache = QueryCache::new(pool.clone());
+
+    // Whichever connection the write and the next lookup are made on, the
+    // change is noticed.
+    for a in 4..8 {
+        let before = cache.query(QUERY, vec![0]).await?;
+        pool.get()
+            .await?
+            .execute("INSERT INTO t (a) VALUES (?)", [a])?;
+        let after = cache.query(QUERY, vec![0]).await?;
+        assert_eq!(after.len(), before.len() + 1);
+    }
+
+    // So are writes from other processes.
+    let before = cache.query(QUERY, vec![0]).await?;
+    Connection::open(temp.file("cache.db"))?.execute("DELETE FROM t", NO_PARAMS)?;
+    let after = cache.query(QUERY, vec![0]).await?;
+    assert!(!before.is_empty());
+    assert!(after.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn capacity() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let cache = QueryCache::new(
+        pool(
+            RusqliteConnectionManager::new(temp.file("cache.db")),
+            1,
+            SETUP,
+        )
+        .await?,
+    )
+    .with_capacity(2);
+    let none = Vec::<Value>::new;
+
+    let one = cache.query("SELECT 1", none()).await?;
+    cache.query("SELECT 2", none()).await?;
+    // Using the first query makes the second the least recently used.
+    assert!(Arc::ptr_eq(&one, &cache.query("SELECT 1", none()).await?));
+    cache.query("SELECT 3", none()).await?;
+    assert_eq!(cache.len(), 2);
+    assert!(Arc::ptr_eq(&one, &cache.query("SELECT 1", none()).await?));
+    Ok(())
+}
+
+#[test]
+fn forgets_closed_connections() {
+    let mut state = State::default();
+    let fingerprint = Fingerprint {
+        data_version: 1,
+        total_changes: 0,
+    };
+
+    // Each replacement connection is new to the cache, but the pool only
+    // ever has two open.
+    for id in 0..100 {
+        state.check(id, fingerprint, 2);
+        assert!(state.fingerprints.len() <= 2);
+    }
+    let epoch = state.epoch;
+    state.check(99, fingerprint, 2);
+    assert_eq!(state.epoch, epoch);
+    assert!(state.fingerprints.contains_key(&98));
+}
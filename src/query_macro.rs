@@ -0,0 +1,65 @@
+//! Support for the code generated by the `query!` macro, which is checked at
+//! compile time by the `bb8-rusqlite-macros` crate.
+
+use rusqlite::{
+    types::{ToSqlOutput, Value},
+    Row, ToSql,
+};
+
+use crate::{
+    pool::{run, Operation},
+    Error, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Converts a parameter to a value that can be moved into the pool.
+pub fn value<T>(param: &T) -> rusqlite::Result<Value>
+where
+    T: ToSql + ?Sized,
+{
+    match param.to_sql()? {
+        ToSqlOutput::Borrowed(value) => Ok(value.into()),
+        ToSqlOutput::Owned(value) => Ok(value),
+        // Zero blobs and arrays only exist with some of rusqlite's features.
+        #[allow(unreachable_patterns)]
+        _ => Err(rusqlite::Error::ToSqlConversionFailure(
+            "only plain values can be passed to query!".into(),
+        )),
+    }
+}
+
+/// Runs a query, returning the result of `f` for each row.
+pub async fn query<T, F>(
+    pool: &bb8::Pool<RusqliteConnectionManager>,
+    sql: &str,
+    params: Vec<rusqlite::Result<Value>>,
+    f: F,
+) -> Result<Vec<T>, Error>
+where
+    F: FnMut(&Row<'_>) -> rusqlite::Result<T> + Send,
+    T: Send,
+{
+    let params = params.into_iter().collect::<rusqlite::Result<Vec<_>>>()?;
+    run(pool, Operation::new("query!").statement(sql), move |conn| {
+        let mut stmt = conn.prepare_cached(sql)?;
+        let rows = stmt.query(params)?;
+        conn.result_limits().collect(rows, f)
+    })
+    .await
+}
+
+/// Runs a statement that doesn't return rows, returning the number of rows
+/// changed.
+pub async fn execute(
+    pool: &bb8::Pool<RusqliteConnectionManager>,
+    sql: &str,
+    params: Vec<rusqlite::Result<Value>>,
+) -> Result<usize, Error> {
+    let params = params.into_iter().collect::<rusqlite::Result<Vec<_>>>()?;
+    run(pool, Operation::new("query!").statement(sql), move |conn| {
+        Ok(conn.prepare_cached(sql)?.execute(params)?)
+    })
+    .await
+}
@@ -0,0 +1,64 @@
+use super::*;
+use crate::tests::TempDir;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn generated_calls() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("macro.db")))
+        .await?;
+    pool.get()
+        .await?
+        .execute_batch("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)")?;
+
+    // These are the calls query! generates for an INSERT and a SELECT.
+    let changed = execute(
+        &pool,
+        "INSERT INTO users (id, name) VALUES (?, ?)",
+        vec![value(&1), value("alice")],
+    )
+    .await?;
+    assert_eq!(changed, 1);
+
+    let rows = query(
+        &pool,
+        "SELECT name FROM users WHERE id = ?",
+        vec![value(&1)],
+        |row| row.get::<_, Option<String>>(0),
+    )
+    .await?;
+    assert_eq!(rows, vec![Some("alice".to_string())]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn expanded() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("macro.db")))
+        .await?;
+    // The same schema as schema.db, which the macro checks these against.
+    pool.get()
+        .await?
+        .execute_batch("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)")?;
+
+    let changed = crate::query!(
+        pool,
+        "INSERT INTO users (id, name) VALUES (?, ?)",
+        1,
+        "alice"
+    )
+    .await?;
+    assert_eq!(changed, 1);
+
+    let users = crate::query!(
+        pool,
+        "SELECT id AS \"id!\", name FROM users WHERE id = ?",
+        1
+    )
+    .await?;
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].id, 1);
+    assert_eq!(users[0].name.as_deref(), Some("alice"));
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! Token bucket rate limiting for work run through [`PoolExt`](crate::PoolExt).
+//!
+//! A [`RateLimiter`] is attached to work with
+//! [`ExecutionContext::with_rate_limiter()`](crate::ExecutionContext::with_rate_limiter),
+//! and each helper the work calls then waits for a token before checking out
+//! a connection. Limits can apply to all of the work the limiter is attached
+//! to, or only to work tagged with
+//! [`ExecutionContext::with_tag()`](crate::ExecutionContext::with_tag), so
+//! that a background job sharing the limiter can be held to a lower rate
+//! than interactive requests.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// A rate, and how far above it bursts may go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: u32,
+}
+
+impl RateLimit {
+    /// Allows `per_second` operations a second on average, in bursts of up
+    /// to `burst` at once. The bucket starts full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `per_second` isn't positive, or `burst` is zero.
+    pub fn new(per_second: f64, burst: u32) -> Self {
+        assert!(per_second > 0.0, "rate must be positive");
+        assert!(burst > 0, "burst must be at least one");
+        Self { per_second, burst }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    // The tokens in the bucket, and when they were counted.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Clone for Bucket {
+    fn clone(&self) -> Self {
+        Self {
+            limit: self.limit,
+            state: Mutex::new(*self.state.lock().unwrap()),
+        }
+    }
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new((limit.burst.into(), Instant::now())),
+        }
+    }
+
+    /// Takes a token, or returns how long until one is available.
+    fn try_take(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, updated) = &mut *state;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * self.limit.per_second)
+            .min(self.limit.burst.into());
+        *updated = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            // A tiny rate can put the next token further off than a Duration
+            // can hold.
+            Err(
+                Duration::try_from_secs_f64((1.0 - *tokens) / self.limit.per_second)
+                    .unwrap_or(Duration::MAX),
+            )
+        }
+    }
+
+    async fn take(&self) {
+        while let Err(wait) = self.try_take() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// A set of token buckets, shared by every clone of the limiter once it's
+/// configured.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter(Arc<Buckets>);
+
+#[derive(Debug, Clone, Default)]
+struct Buckets {
+    all: Option<Bucket>,
+    tags: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter without any limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits all work the limiter is attached to, tagged or not, to
+    /// `limit`.
+    pub fn limit_all(self, limit: RateLimit) -> Self {
+        self.update(|buckets| buckets.all = Some(Bucket::new(limit)))
+    }
+
+    /// Limits work tagged with `tag` to `limit`. Tagged work is also held to
+    /// the limit set with [`limit_all()`](Self::limit_all), if there is one.
+    pub fn limit_tag(self, tag: &str, limit: RateLimit) -> Self {
+        self.update(|buckets| {
+            buckets.tags.insert(tag.into(), Bucket::new(limit));
+        })
+    }
+
+    fn update<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut Buckets),
+    {
+        // Configuring a clone separates it from the original, along with the
+        // tokens left in its buckets.
+        f(Arc::make_mut(&mut self.0));
+        self
+    }
+
+    /// Waits until the work, tagged with `tag`, may go ahead.
+    pub(crate) async fn acquire(&self, tag: Option<&str>) {
+        if let Some(bucket) = tag.and_then(|tag| self.0.tags.get(tag)) {
+            bucket.take().await;
+        }
+        if let Some(bucket) = &self.0.all {
+            bucket.take().await;
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use super::*;
+use crate::{tests::TempDir, Error, ExecutionContext, PoolExt, RusqliteConnectionManager};
+
+#[test]
+fn bucket() {
+    let bucket = Bucket::new(RateLimit::new(10.0, 2));
+    assert_eq!(bucket.try_take(), Ok(()));
+    assert_eq!(bucket.try_take(), Ok(()));
+    let wait = bucket.try_take().unwrap_err();
+    assert!(wait > Duration::from_millis(50) && wait <= Duration::from_millis(100));
+
+    let bucket = Bucket::new(RateLimit::new(f64::MIN_POSITIVE, 1));
+    assert_eq!(bucket.try_take(), Ok(()));
+    assert_eq!(bucket.try_take(), Err(Duration::MAX));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn limits_tagged_work() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("rate.db")))
+        .await?;
+    let limiter = RateLimiter::new().limit_tag("backfill", RateLimit::new(20.0, 1));
+    let run = |tag: Option<&'static str>| {
+        let pool = pool.clone();
+        let mut context = ExecutionContext::new().with_rate_limiter(limiter.clone());
+        if let Some(tag) = tag {
+            context = context.with_tag(tag);
+        }
+        async move {
+            let started = Instant::now();
+            context
+                .scope(async {
+                    for _ in 0..5 {
+                        pool.schema_version().await?;
+                    }
+                    Ok::<_, Error>(())
+                })
+                .await?;
+            Ok::<_, Error>(started.elapsed())
+        }
+    };
+
+    // Untagged work isn't held to the tag's limit.
+    assert!(run(None).await? < Duration::from_millis(150));
+    // The first operation uses the burst, and each of the other four waits
+    // 50ms for a token.
+    assert!(run(Some("backfill")).await? >= Duration::from_millis(190));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deadline_applies() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("rate.db")))
+        .await?;
+    let limiter = RateLimiter::new().limit_all(RateLimit::new(0.1, 1));
+
+    let context = ExecutionContext::new()
+        .with_rate_limiter(limiter)
+        .with_timeout(Duration::from_millis(100));
+    let result = context
+        .scope(async {
+            pool.schema_version().await?;
+            pool.schema_version().await
+        })
+        .await;
+    assert!(
+        matches!(result, Err(Error::DeadlineExceeded)),
+        "{:?}",
+        result
+    );
+    Ok(())
+}
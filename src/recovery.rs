@@ -0,0 +1,250 @@
+//! Opt-in recovery from database corruption.
+//!
+//! With a [`RecoveryPolicy`] configured through
+//! [`RusqliteConnectionManager::with_recovery()`], a connection attempt that
+//! finds the database corrupt quarantines the file by renaming it out of the
+//! way, rebuilds the database (either from a replica or from scratch), and
+//! then carries on opening connections to the rebuilt file. Connections still
+//! open on the corrupt file are discarded as they are returned to the pool.
+//!
+//! Corruption found elsewhere, such as by a query or a scheduled integrity
+//! check, can be recovered from in the same way by calling
+//! [`RusqliteConnectionManager::recover()`].
+
+use std::{
+    fmt, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{ffi, NO_PARAMS};
+
+use crate::{
+    replication::{self, ReplicaSource},
+    task, DatabaseFile, Error, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// How a quarantined database is rebuilt.
+#[derive(Clone)]
+enum Rebuild {
+    Restore(Arc<dyn ReplicaSource>),
+    Recreate(String),
+}
+
+/// A step in recovering from corruption, as reported to
+/// [`RecoveryPolicy::on_event()`].
+#[derive(Debug)]
+pub enum RecoveryEvent<'a> {
+    /// Corruption was detected, and recovery is starting.
+    Detected(&'a Error),
+
+    /// The corrupt database file (and any journal, WAL, or shared memory
+    /// files) were moved aside.
+    Quarantined {
+        /// The database path.
+        from: &'a Path,
+
+        /// Where the corrupt database now lives.
+        to: &'a Path,
+    },
+
+    /// The database was restored from a replica.
+    Restored(replication::RestorePoint),
+
+    /// The database was recreated from the configured schema.
+    Recreated,
+
+    /// New connections are being opened on the rebuilt database.
+    Resumed,
+}
+
+type EventCallback = Arc<dyn Fn(&RecoveryEvent<'_>) + Send + Sync>;
+
+/// How to recover from a corrupt database.
+#[derive(Clone)]
+pub struct RecoveryPolicy {
+    rebuild: Rebuild,
+    on_event: Option<EventCallback>,
+}
+
+impl fmt::Debug for RecoveryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rebuild: &dyn fmt::Debug = match &self.rebuild {
+            Rebuild::Restore(source) => source,
+            Rebuild::Recreate(ddl) => ddl,
+        };
+        f.debug_struct("RecoveryPolicy")
+            .field("rebuild", rebuild)
+            .finish()
+    }
+}
+
+impl RecoveryPolicy {
+    /// Rebuilds the database by restoring the latest generation in `source`.
+    pub fn restore<S>(source: S) -> Self
+    where
+        S: ReplicaSource + 'static,
+    {
+        Self {
+            rebuild: Rebuild::Restore(Arc::new(source)),
+            on_event: None,
+        }
+    }
+
+    /// Rebuilds the database by creating a new, empty database and executing
+    /// `ddl` against it.
+    pub fn recreate<S>(ddl: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            rebuild: Rebuild::Recreate(ddl.into()),
+            on_event: None,
+        }
+    }
+
+    /// Sets a callback that is invoked at each step of recovery.
+    pub fn on_event<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&RecoveryEvent<'_>) + Send + Sync + 'static,
+    {
+        self.on_event = Some(Arc::new(callback));
+        self
+    }
+
+    fn emit(&self, event: RecoveryEvent<'_>) {
+        if let Some(callback) = &self.on_event {
+            callback(&event);
+        }
+    }
+}
+
+impl Error {
+    /// Returns true if the error indicates that the database file is corrupt,
+    /// or isn't a database at all.
+    pub fn is_corruption(&self) -> bool {
+        match self {
+            Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _)) => matches!(
+                e.code,
+                ffi::ErrorCode::DatabaseCorrupt | ffi::ErrorCode::NotADatabase
+            ),
+            Error::IntegrityCheck { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+impl RusqliteConnectionManager {
+    /// Quarantines and rebuilds the database according to the configured
+    /// [`RecoveryPolicy`], returning where the corrupt file now lives. `cause`
+    /// is passed along to the policy's callback.
+    ///
+    /// Only one recovery runs at a time. If another recovery replaces the
+    /// database while this one is waiting to start, this does nothing and
+    /// returns `None`. Without a recovery policy, `cause` is returned as the
+    /// error.
+    pub async fn recover(&self, cause: Error) -> Result<Option<PathBuf>, Error> {
+        self.recover_file(self.current_file(), cause).await
+    }
+
+    /// Recovers `previous`, unless it has already been replaced.
+    pub(crate) async fn recover_file(
+        &self,
+        previous: Arc<DatabaseFile>,
+        cause: Error,
+    ) -> Result<Option<PathBuf>, Error> {
+        let policy = match &self.options.recovery {
+            Some(policy) => policy.clone(),
+            None => return Err(cause),
+        };
+
+        let _recovering = self.files.recovering.lock().await;
+        if !Arc::ptr_eq(&previous, &self.current_file()) {
+            return Ok(None);
+        }
+
+        policy.emit(RecoveryEvent::Detected(&cause));
+        let from = previous.path.clone();
+        let to = task::spawn_blocking("bb8_rusqlite::recovery::quarantine", move || {
+            quarantine(&from)
+        })
+        .await??;
+        policy.emit(RecoveryEvent::Quarantined {
+            from: &previous.path,
+            to: &to,
+        });
+
+        match &policy.rebuild {
+            Rebuild::Restore(source) => {
+                let point = replication::restore(&**source, &previous.path).await?;
+                policy.emit(RecoveryEvent::Restored(point));
+            }
+            Rebuild::Recreate(ddl) => {
+                let options = self.options.clone();
+                let key = self.settings.current().key;
+                let path = previous.path.clone();
+                let ddl = ddl.clone();
+                task::spawn_blocking(
+                    "bb8_rusqlite::recovery::recreate",
+                    move || -> Result<(), Error> {
+                        Ok(options.open(&path, key.as_deref())?.execute_batch(&ddl)?)
+                    },
+                )
+                .await??;
+                policy.emit(RecoveryEvent::Recreated);
+            }
+        }
+
+        // Swapping in a new file at the same path retires every connection to
+        // the corrupt one, without reporting it as a rotation.
+        *self.files.current.write().unwrap() =
+            Arc::new(DatabaseFile::new(previous.path.clone(), false));
+        policy.emit(RecoveryEvent::Resumed);
+
+        Ok(Some(to))
+    }
+}
+
+/// Checks that the database header and schema can be read, since SQLite
+/// doesn't touch the file when opening it.
+pub(crate) fn probe(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", NO_PARAMS, |_| Ok(()))
+}
+
+/// Moves the database at `path` and its associated files to a new name
+/// recording when it was quarantined, returning the new database path.
+fn quarantine(path: &Path) -> Result<PathBuf, Error> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".corrupt-{}", secs));
+    let mut to = PathBuf::from(&name);
+    let mut n = 1;
+    while to.exists() {
+        let mut next = name.clone();
+        next.push(format!("-{}", n));
+        to = next.into();
+        n += 1;
+    }
+
+    fs::rename(path, &to)?;
+    for suffix in &["-journal", "-wal", "-shm"] {
+        let mut from = path.as_os_str().to_owned();
+        from.push(suffix);
+        let mut dest = to.as_os_str().to_owned();
+        dest.push(suffix);
+        match fs::rename(&from, &dest) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    Ok(to)
+}
@@ -0,0 +1,135 @@
+use std::sync::Mutex;
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use super::*;
+use crate::{
+    replication::{FileSink, ReplicaSink, Snapshot},
+    tests::TempDir,
+};
+
+fn recorder() -> (Arc<Mutex<Vec<String>>>, impl Fn(&RecoveryEvent<'_>)) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let callback = {
+        let events = events.clone();
+        move |event: &RecoveryEvent<'_>| {
+            let name = match event {
+                RecoveryEvent::Detected(e) => {
+                    assert!(e.is_corruption());
+                    "detected"
+                }
+                RecoveryEvent::Quarantined { .. } => "quarantined",
+                RecoveryEvent::Restored(_) => "restored",
+                RecoveryEvent::Recreated => "recreated",
+                RecoveryEvent::Resumed => "resumed",
+            };
+            events.lock().unwrap().push(name.to_string());
+        }
+    };
+    (events, callback)
+}
+
+fn quarantined(temp: &TempDir, name: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let prefix = format!("{}.corrupt-", name);
+    Ok(fs::read_dir(temp.file(""))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix))
+        })
+        .collect())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn recreate() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("corrupt.db");
+    fs::write(&path, vec![0xa5; 8192])?;
+
+    let (events, callback) = recorder();
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(&path).with_recovery(
+            RecoveryPolicy::recreate("CREATE TABLE t (a INTEGER)").on_event(callback),
+        ))
+        .await?;
+
+    pool.get()
+        .await?
+        .execute("INSERT INTO t (a) VALUES (1)", NO_PARAMS)?;
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec!["detected", "quarantined", "recreated", "resumed"]
+    );
+
+    let quarantined = quarantined(&temp, "corrupt.db")?;
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(fs::read(&quarantined[0])?, vec![0xa5; 8192]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restore() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("corrupt.db");
+
+    let good = temp.file("good.db");
+    let conn = Connection::open(&good)?;
+    conn.execute_batch("CREATE TABLE t (a INTEGER); INSERT INTO t (a) VALUES (42);")?;
+    conn.close().map_err(|(_, e)| e)?;
+    let sink = FileSink::new(temp.file("replica"));
+    sink.write_snapshot(Snapshot {
+        generation: 1,
+        data: fs::read(&good)?,
+    })
+    .await?;
+
+    let (events, callback) = recorder();
+    let manager = RusqliteConnectionManager::new(&path)
+        .with_recovery(RecoveryPolicy::restore(sink).on_event(callback));
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(manager.clone())
+        .await?;
+
+    // Corruption found after connections are already open is recovered from
+    // explicitly, and retires the existing connections.
+    let held = pool.get().await?;
+    held.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+    let cause = Error::IntegrityCheck {
+        problems: vec!["simulated".into()],
+    };
+    assert!(manager.recover(cause).await?.is_some());
+    assert!(manager.is_retired(&held));
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec!["detected", "quarantined", "restored", "resumed"]
+    );
+    drop(held);
+
+    let a: i64 = pool
+        .get()
+        .await?
+        .query_row("SELECT a FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(a, 42);
+    assert_eq!(quarantined(&temp, "corrupt.db")?.len(), 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn without_policy() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("corrupt.db");
+    fs::write(&path, vec![0xa5; 8192])?;
+
+    let manager = RusqliteConnectionManager::new(&path);
+    let cause = Error::IntegrityCheck { problems: vec![] };
+    assert!(matches!(
+        manager.recover(cause).await,
+        Err(Error::IntegrityCheck { .. })
+    ));
+    assert_eq!(fs::read(&path)?, vec![0xa5; 8192]);
+    Ok(())
+}
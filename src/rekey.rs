@@ -0,0 +1,109 @@
+//! Changing the encryption key of a SQLCipher database while the pool is
+//! running.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{encryption, task, EncryptionKey, Error, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// Rekey state, shared by every clone of a manager.
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    rekeying: AtomicBool,
+    // Held for reading while connections are opened, and for writing while
+    // the database is rekeyed, so nothing opens with a stale key.
+    gate: tokio::sync::RwLock<()>,
+}
+
+impl State {
+    /// Returns true if connections shouldn't be handed out, because the
+    /// database is being rekeyed.
+    pub(crate) fn refuses_checkout(&self) -> bool {
+        self.rekeying.load(Ordering::SeqCst)
+    }
+
+    /// Waits for any rekey in progress, and holds off new ones until the
+    /// guard is dropped.
+    pub(crate) async fn opening(&self) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        self.gate.read().await
+    }
+
+    /// Holds off new rekeys until the guard is dropped, or returns `None` if
+    /// one is in progress.
+    pub(crate) fn try_opening(&self) -> Option<tokio::sync::RwLockReadGuard<'_, ()>> {
+        self.gate.try_read().ok()
+    }
+}
+
+/// Clears the rekeying flag, even if the rekey is cancelled.
+struct Rekeying<'a>(&'a AtomicBool);
+
+impl Drop for Rekeying<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl RusqliteConnectionManager {
+    /// Changes the key of the database behind `pool`, which must use this
+    /// manager (or a clone of it), from the key given to
+    /// [`with_key()`](Self::with_key) to `key`.
+    ///
+    /// The database can't be used with the old key during a rekey, so this
+    /// takes the pool out of service: checkouts block until the rekey is done,
+    /// or the pool's connection timeout expires, and this waits for every
+    /// checked out connection to be returned. It then runs `PRAGMA rekey` on
+    /// a connection of its own (or `PRAGMA hexrekey`, for raw keys with SEE),
+    /// and stores `key` for new connections. Idle connections opened with the
+    /// old key are closed rather than handed out again.
+    ///
+    /// Nothing stops a connection being held forever, so wrap this in a
+    /// timeout if that's a possibility; cancelling it before the rekey itself
+    /// starts leaves the database and the pool as they were.
+    ///
+    /// SQLite builds without the configured
+    /// [`EncryptionBackend`](crate::EncryptionBackend) ignore the key
+    /// pragmas, so this fails with [`Error::EncryptionUnsupported`] rather
+    /// than leaving the database unencrypted.
+    pub async fn rekey<K>(&self, pool: &bb8::Pool<Self>, key: K) -> Result<(), Error>
+    where
+        K: Into<EncryptionKey>,
+    {
+        let key = Arc::new(key.into());
+        let state = &self.rekey;
+        let _gate = state.gate.write().await;
+        state.rekeying.store(true, Ordering::SeqCst);
+        let _rekeying = Rekeying(&state.rekeying);
+
+        loop {
+            let pool = pool.state();
+            if pool.connections == pool.idle_connections {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // Opening a connection directly, rather than through the pool,
+        // doesn't need a slot that a blocked checkout may be holding.
+        let conn = self.open(self.current_file()).await?;
+        let backend = self.options.encryption;
+        let rekeyed = key.clone();
+        task::spawn_blocking("bb8_rusqlite::rekey", move || -> Result<(), Error> {
+            if !encryption::supported(&conn, backend)? {
+                return Err(Error::EncryptionUnsupported);
+            }
+            Ok(encryption::rekey(&conn, backend, &rekeyed)?)
+        })
+        .await??;
+
+        // Bumping the generation retires every connection with the old key.
+        self.settings.update(|current| current.key = Some(key));
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, Error, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rekey_unsupported() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("rekey.db")).with_key("secret");
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(manager.clone())
+        .await?;
+    pool.get()
+        .await?
+        .execute_batch("CREATE TABLE t (v TEXT); INSERT INTO t VALUES ('a');")?;
+
+    // The rekey waits for checked out connections to be returned.
+    let held = pool.get().await?;
+    let rekey = tokio::spawn({
+        let manager = manager.clone();
+        let pool = pool.clone();
+        async move { manager.rekey(&pool, "rotated").await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!rekey.is_finished());
+    drop(held);
+
+    // Stock SQLite doesn't have SQLCipher, so the key can't be changed.
+    assert!(matches!(rekey.await?, Err(Error::EncryptionUnsupported)));
+
+    // The pool is back in service afterwards.
+    let count: i64 = pool
+        .get()
+        .await?
+        .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(count, 1);
+    Ok(())
+}
+
+#[test]
+fn key_is_redacted() {
+    let manager = RusqliteConnectionManager::new("redacted.db").with_key("secret");
+    assert!(!format!("{:?}", manager).contains("secret"));
+}
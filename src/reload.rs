@@ -0,0 +1,58 @@
+//! Connect-time settings that can be replaced while the pool is running.
+
+use std::sync::{Arc, RwLock};
+
+use crate::{EncryptionKey, PragmaCustomizer};
+
+#[cfg(test)]
+mod tests;
+
+/// The settings new connections are opened with, and a generation that's
+/// bumped whenever they're replaced. Each connection remembers the
+/// generation it was opened with, so connections that predate a reload can
+/// be recycled as they come back to the pool.
+#[derive(Debug, Default)]
+pub(crate) struct Settings {
+    current: RwLock<Current>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Current {
+    pub(crate) generation: u64,
+    pub(crate) pragmas: Arc<PragmaCustomizer>,
+    pub(crate) key: Option<Arc<EncryptionKey>>,
+}
+
+impl Settings {
+    /// Returns a copy of these settings, with `f` applied, for configuring a
+    /// manager without affecting any clones of it.
+    pub(crate) fn with<F>(&self, f: F) -> Self
+    where
+        F: FnOnce(&mut Current),
+    {
+        let mut current = self.current();
+        f(&mut current);
+        Self {
+            current: RwLock::new(current),
+        }
+    }
+
+    /// Returns the current generation, and the settings that go with it.
+    pub(crate) fn current(&self) -> Current {
+        self.current.read().unwrap().clone()
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.current.read().unwrap().generation
+    }
+
+    /// Applies `f` to the settings, and bumps the generation.
+    pub(crate) fn update<F>(&self, f: F)
+    where
+        F: FnOnce(&mut Current),
+    {
+        let mut current = self.current.write().unwrap();
+        f(&mut current);
+        current.generation += 1;
+    }
+}
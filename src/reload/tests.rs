@@ -0,0 +1,43 @@
+use rusqlite::NO_PARAMS;
+
+use bb8::ManageConnection;
+
+use crate::{tests::TempDir, PragmaCustomizer, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reload_pragmas() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("reload.db"))
+        .with_pragmas(PragmaCustomizer::new().pragma("cache_size", -1000));
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(manager.clone())
+        .await?;
+    let cache_size = |conn: &rusqlite::Connection| -> rusqlite::Result<i64> {
+        conn.query_row("PRAGMA cache_size", NO_PARAMS, |row| row.get(0))
+    };
+
+    let held = pool.get().await?;
+    assert_eq!(cache_size(&held)?, -1000);
+
+    manager.reload_pragmas(PragmaCustomizer::new().pragma("cache_size", -2000));
+
+    // Checked out connections keep their settings, while new ones get the
+    // reloaded settings.
+    assert_eq!(cache_size(&held)?, -1000);
+    let fresh = pool.get().await?;
+    assert_eq!(cache_size(&fresh)?, -2000);
+
+    // The outdated connection is recycled once it's returned.
+    drop(held);
+    drop(fresh);
+    for _ in 0..2 {
+        assert_eq!(cache_size(&*pool.get().await?)?, -2000);
+    }
+
+    // Outdated connections are discarded when they come back.
+    let mut conn = manager.connect().await?;
+    manager.reload_pragmas(PragmaCustomizer::new());
+    assert!(manager.has_broken(&mut conn));
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! Detection of database files replaced from outside the pool.
+//!
+//! Some deployments ship data as whole files: an updater downloads a new
+//! dataset next to the live one and renames it over the top. Connections
+//! already open keep reading the old, now unlinked, file until they're
+//! closed. A [`ReplacementWatcher`] polls the database path and, when the
+//! file there changes, moves the pool onto the new file, reporting a
+//! [`FileReplaced`] event.
+//!
+//! Connections to the old file are drained as they are by
+//! [`RusqliteConnectionManager::rotate()`]: checked out connections remain
+//! usable, but are discarded instead of being returned to the pool, and idle
+//! connections are discarded when next checked out.
+//!
+//! Files are identified by their device and inode numbers, so replacements
+//! are only detected on Unix. Temporary databases created by the manager are
+//! never watched.
+
+use std::{
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{identity::FileIdentity, task, DatabaseFile, Error, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// An event raised when the database file is found to have been replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReplaced {
+    /// The path of the database file.
+    pub path: PathBuf,
+
+    /// The number of connections still open on the old file, which will be
+    /// discarded rather than returned to the pool.
+    pub open_connections: usize,
+}
+
+type Callback<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+/// A background task that watches a manager's database file for
+/// replacement.
+pub struct ReplacementWatcher {
+    manager: RusqliteConnectionManager,
+    interval: Duration,
+    on_replaced: Option<Callback<FileReplaced>>,
+}
+
+impl fmt::Debug for ReplacementWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplacementWatcher")
+            .field("manager", &self.manager)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl ReplacementWatcher {
+    /// Creates a watcher for the file `manager` opens connections on,
+    /// polling every second by default. Since clones of a manager share
+    /// their files, this can be given a clone of the manager the pool was
+    /// built with.
+    pub fn new(manager: RusqliteConnectionManager) -> Self {
+        Self {
+            manager,
+            interval: Duration::from_secs(1),
+            on_replaced: None,
+        }
+    }
+
+    /// Sets how often the file is polled.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets a callback that's called each time the file is replaced, once
+    /// new connections have been switched to the new file.
+    pub fn on_replaced<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&FileReplaced) + Send + Sync + 'static,
+    {
+        self.on_replaced = Some(Arc::new(callback));
+        self
+    }
+
+    /// Starts the background watching task.
+    ///
+    /// The file is compared against the one at the path when this is called,
+    /// and again whenever the manager moves to a different file, such as by
+    /// [`RusqliteConnectionManager::rotate()`], so those aren't reported as
+    /// replacements.
+    pub fn start(self) -> ReplacementHandle {
+        let (stop, mut stopped) = oneshot::channel();
+        let mut task = WatcherTask {
+            watcher: self,
+            watched: Weak::new(),
+            identity: None,
+        };
+        task.observe();
+
+        let join = task::spawn("bb8_rusqlite::replacement_watcher", async move {
+            let interval = task.watcher.interval;
+            while tokio::time::timeout(interval, &mut stopped).await.is_err() {
+                let watcher = &mut task;
+                task::block_in_place(|| watcher.poll());
+            }
+        });
+
+        ReplacementHandle { stop, join }
+    }
+}
+
+/// A handle to a running watcher. Dropping the handle also stops the
+/// watcher.
+#[derive(Debug)]
+pub struct ReplacementHandle {
+    stop: oneshot::Sender<()>,
+    join: JoinHandle<()>,
+}
+
+impl ReplacementHandle {
+    /// Returns the ID of the watcher's task.
+    pub fn task_id(&self) -> tokio::task::Id {
+        self.join.id()
+    }
+
+    /// Stops the watcher, waiting for any poll in progress to finish.
+    pub async fn stop(self) -> Result<(), Error> {
+        let _ = self.stop.send(());
+        Ok(self.join.await?)
+    }
+}
+
+struct WatcherTask {
+    watcher: ReplacementWatcher,
+    // The file the identity was taken for. This is weak so that the watcher
+    // isn't counted as one of the file's open connections.
+    watched: Weak<DatabaseFile>,
+    identity: Option<FileIdentity>,
+}
+
+impl WatcherTask {
+    /// Records the identity of the manager's current file.
+    fn observe(&mut self) {
+        let file = self.watcher.manager.current_file();
+        self.identity = if file.temporary {
+            None
+        } else {
+            FileIdentity::of(&file.path)
+        };
+        self.watched = Arc::downgrade(&file);
+    }
+
+    fn poll(&mut self) {
+        let current = self.watcher.manager.current_file();
+        if !Weak::ptr_eq(&self.watched, &Arc::downgrade(&current)) || self.identity.is_none() {
+            // The manager has moved to another file since the last poll, or
+            // the file didn't exist yet.
+            return self.observe();
+        }
+
+        // A file that's missing is assumed to be midway through being
+        // replaced by something that doesn't rename atomically.
+        let identity = match FileIdentity::of(&current.path) {
+            Some(identity) if Some(identity) != self.identity => identity,
+            _ => return,
+        };
+
+        // Swapping in a new file at the same path retires every connection to
+        // the old one, without reporting it as a rotation.
+        let file = Arc::new(DatabaseFile::new(current.path.clone(), false));
+        {
+            let mut slot = self.watcher.manager.files.current.write().unwrap();
+            if !Arc::ptr_eq(&slot, &current) {
+                // Something else replaced the file while we were looking.
+                return;
+            }
+            *slot = file.clone();
+        }
+        self.watched = Arc::downgrade(&file);
+        self.identity = Some(identity);
+
+        // The only reference that isn't a connection is our own.
+        let open_connections = Arc::strong_count(&current) - 1;
+        if let Some(callback) = &self.watcher.on_replaced {
+            callback(&FileReplaced {
+                path: file.path.clone(),
+                open_connections,
+            });
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::{fs, sync::Mutex};
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use super::*;
+use crate::tests::TempDir;
+
+fn dataset(path: &std::path::Path, version: i64) -> Result<(), anyhow::Error> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(&format!(
+        "CREATE TABLE dataset (version INTEGER); INSERT INTO dataset VALUES ({})",
+        version
+    ))?;
+    Ok(())
+}
+
+async fn version(pool: &bb8::Pool<RusqliteConnectionManager>) -> Result<i64, anyhow::Error> {
+    Ok(pool
+        .get()
+        .await?
+        .query_row("SELECT version FROM dataset", NO_PARAMS, |row| row.get(0))?)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn swaps_in_replacement() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("dataset.db");
+    dataset(&path, 1)?;
+
+    let manager = RusqliteConnectionManager::new(&path);
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(manager.clone())
+        .await?;
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let handle = ReplacementWatcher::new(manager)
+        .with_interval(Duration::from_millis(10))
+        .on_replaced({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event.clone())
+        })
+        .start();
+
+    assert_eq!(version(&pool).await?, 1);
+    let held = pool.get().await?;
+
+    let staged = temp.file("dataset.db.new");
+    dataset(&staged, 2)?;
+    fs::rename(&staged, &path)?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // The connection checked out before the swap still reads the old file.
+    let old: i64 = held.query_row("SELECT version FROM dataset", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(old, 1);
+    assert_eq!(version(&pool).await?, 2);
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![FileReplaced {
+            path: path.clone(),
+            open_connections: 1,
+        }]
+    );
+
+    handle.stop().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ignores_rotation() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    dataset(&temp.file("first.db"), 1)?;
+    dataset(&temp.file("second.db"), 2)?;
+
+    let manager = RusqliteConnectionManager::new(temp.file("first.db"));
+    let events = Arc::new(Mutex::new(0));
+    let handle = ReplacementWatcher::new(manager.clone())
+        .with_interval(Duration::from_millis(10))
+        .on_replaced({
+            let events = events.clone();
+            move |_| *events.lock().unwrap() += 1
+        })
+        .start();
+
+    manager.rotate(temp.file("second.db"));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(*events.lock().unwrap(), 0);
+    assert!(manager.retired_files()[0].is_drained());
+
+    handle.stop().await?;
+    Ok(())
+}
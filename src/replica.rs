@@ -0,0 +1,260 @@
+//! Read-only pools fed from replicated snapshots.
+//!
+//! A [`ReplicaPool`] periodically restores the latest state from a
+//! [`ReplicaSource`] into a fresh local file, builds a read only pool over it,
+//! and atomically swaps new checkouts onto that pool. Combined with a
+//! [`Replicator`](crate::replication::Replicator) on the writer, this allows
+//! reads to scale out horizontally from a single writing host.
+//!
+//! Connections checked out before a swap keep reading from the previous file
+//! until they're dropped. On Unix, retired files are removed as soon as
+//! they've been replaced; elsewhere, removal is best effort.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock, RwLock, Weak,
+    },
+    time::Duration,
+};
+
+use rusqlite::OpenFlags;
+
+use crate::{
+    replication::{self, ReplicaSource, RestorePoint},
+    task, Error, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+type BuilderFactory = Arc<dyn Fn() -> bb8::Builder<RusqliteConnectionManager> + Send + Sync>;
+
+/// A builder for [`ReplicaPool`]s.
+pub struct ReplicaPoolBuilder {
+    source: Arc<dyn ReplicaSource>,
+    dir: PathBuf,
+    interval: Duration,
+    pool_builder: BuilderFactory,
+}
+
+impl fmt::Debug for ReplicaPoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplicaPoolBuilder")
+            .field("source", &self.source)
+            .field("dir", &self.dir)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl ReplicaPoolBuilder {
+    /// Sets how often the source is checked for new state. Defaults to 10
+    /// seconds.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the function used to create a `bb8::Builder` each time a new
+    /// reader pool is built, which allows the pool size and timeouts to be
+    /// configured.
+    pub fn with_pool_builder<F>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> bb8::Builder<RusqliteConnectionManager> + Send + Sync + 'static,
+    {
+        self.pool_builder = Arc::new(factory);
+        self
+    }
+
+    /// Performs the initial restore and starts the background refresh task.
+    ///
+    /// This fails with [`Error::NoReplica`] if the source is empty.
+    pub async fn build(self) -> Result<ReplicaPool, Error> {
+        task::spawn_blocking("bb8_rusqlite::replica::create_dir", {
+            let dir = self.dir.clone();
+            move || fs::create_dir_all(dir)
+        })
+        .await??;
+
+        let inner = Arc::new(Inner {
+            source: self.source,
+            dir: self.dir,
+            pool_builder: self.pool_builder,
+            current: RwLock::new(None),
+            next_id: AtomicU64::new(0),
+            refresh_task: OnceLock::new(),
+        });
+        inner.refresh().await?;
+
+        let refresh = task::spawn(
+            "bb8_rusqlite::replica::refresh",
+            refresh_loop(Arc::downgrade(&inner), self.interval),
+        );
+        let _ = inner.refresh_task.set(refresh.id());
+        Ok(ReplicaPool(inner))
+    }
+}
+
+/// A read-only pool over a database restored from a [`ReplicaSource`].
+///
+/// Cloning a `ReplicaPool` is cheap, and clones share the same underlying
+/// reader pool. The background refresh task stops once every clone has been
+/// dropped.
+#[derive(Clone, Debug)]
+pub struct ReplicaPool(Arc<Inner>);
+
+impl ReplicaPool {
+    /// Creates a builder that restores from `source` into files within `dir`.
+    pub fn builder<S, P>(source: S, dir: P) -> ReplicaPoolBuilder
+    where
+        S: ReplicaSource + 'static,
+        P: AsRef<Path>,
+    {
+        ReplicaPoolBuilder {
+            source: Arc::new(source),
+            dir: dir.as_ref().into(),
+            interval: Duration::from_secs(10),
+            pool_builder: Arc::new(bb8::Pool::builder),
+        }
+    }
+
+    /// Checks out a connection from the current reader pool.
+    pub async fn get(
+        &self,
+    ) -> Result<bb8::PooledConnection<'static, RusqliteConnectionManager>, Error> {
+        let pool = self.current().pool.clone();
+        Ok(pool.get_owned().await?)
+    }
+
+    /// Returns the state the current reader pool was restored to.
+    pub fn restore_point(&self) -> RestorePoint {
+        self.current().point
+    }
+
+    /// Checks the source for new state immediately, swapping onto a new
+    /// reader pool if anything changed. Returns true if a swap happened.
+    pub async fn refresh(&self) -> Result<bool, Error> {
+        self.0.refresh().await
+    }
+
+    /// Returns the ID of the background refresh task.
+    pub fn refresh_task_id(&self) -> tokio::task::Id {
+        *self
+            .0
+            .refresh_task
+            .get()
+            .expect("the refresh task is started before the pool is built")
+    }
+
+    fn current(&self) -> Arc<Replica> {
+        self.0
+            .current
+            .read()
+            .unwrap()
+            .clone()
+            .expect("a replica is always restored before the pool is built")
+    }
+}
+
+struct Inner {
+    source: Arc<dyn ReplicaSource>,
+    dir: PathBuf,
+    pool_builder: BuilderFactory,
+    current: RwLock<Option<Arc<Replica>>>,
+    next_id: AtomicU64,
+    refresh_task: OnceLock<tokio::task::Id>,
+}
+
+impl fmt::Debug for Inner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inner")
+            .field("source", &self.source)
+            .field("dir", &self.dir)
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+impl Inner {
+    async fn refresh(&self) -> Result<bool, Error> {
+        let previous = self.current.read().unwrap().as_ref().map(|r| r.point);
+
+        // Cheaply check whether anything has changed before restoring. Only
+        // the segments a restore would apply count, so segments after a gap
+        // don't cause a restore every time.
+        if let Some(previous) = previous {
+            let generation = self.source.latest_generation().await?;
+            if generation == Some(previous.generation) {
+                let segments = self.source.read_segments(previous.generation).await?;
+                let wal_len: u64 = replication::gapless(&segments)
+                    .iter()
+                    .map(|segment| segment.data.len() as u64)
+                    .sum();
+                if wal_len == previous.wal_len {
+                    return Ok(false);
+                }
+            }
+        }
+
+        let path = self.dir.join(format!(
+            "replica-{}-{}.db",
+            std::process::id(),
+            self.next_id.fetch_add(1, Ordering::Relaxed)
+        ));
+        let point = match replication::restore(self.source.as_ref(), &path).await {
+            Ok(point) => point,
+            Err(e) => {
+                remove_database(&path);
+                return Err(e);
+            }
+        };
+
+        let manager =
+            RusqliteConnectionManager::new_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY);
+        let pool = (self.pool_builder)().build(manager).await?;
+
+        let replica = Arc::new(Replica { pool, path, point });
+        *self.current.write().unwrap() = Some(replica);
+        Ok(true)
+    }
+}
+
+async fn refresh_loop(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        match inner.upgrade() {
+            // Errors are retried on the next tick; the current pool remains
+            // usable in the meantime.
+            Some(inner) => {
+                let _ = inner.refresh().await;
+            }
+            None => return,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Replica {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    path: PathBuf,
+    point: RestorePoint,
+}
+
+impl Drop for Replica {
+    fn drop(&mut self) {
+        remove_database(&self.path);
+    }
+}
+
+fn remove_database(path: &Path) {
+    for file in &[
+        path.to_path_buf(),
+        replication::wal_path(path),
+        replication::shm_path(path),
+    ] {
+        let _ = fs::remove_file(file);
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use super::*;
+use crate::{
+    replication::{FileSink, ReplicaSink, Replicator, Snapshot, WalSegment},
+    tests::TempDir,
+};
+
+fn count(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn follows_replication() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("origin.db");
+    let replica = temp.file("replica");
+
+    let conn = Connection::open(&path)?;
+    conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+    conn.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+    conn.execute("INSERT INTO t (a) VALUES (1)", NO_PARAMS)?;
+
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(&path))
+        .await?;
+    let handle = Replicator::new(pool.clone(), FileSink::new(&replica))
+        .with_interval(Duration::from_millis(10))
+        .start()
+        .await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The refresh interval is long enough that only explicit refreshes will
+    // happen during the test.
+    let readers = ReplicaPool::builder(FileSink::new(&replica), temp.file("readers"))
+        .with_interval(Duration::from_secs(3600))
+        .build()
+        .await?;
+    assert_eq!(count(&*readers.get().await?)?, 1);
+    assert!(!readers.refresh().await?);
+
+    // Replicas are read only.
+    readers
+        .get()
+        .await?
+        .execute("INSERT INTO t (a) VALUES (2)", NO_PARAMS)
+        .expect_err("replicas must be read only");
+
+    // A connection held across a swap keeps reading the old replica.
+    let held = readers.get().await?;
+    pool.get()
+        .await?
+        .execute("INSERT INTO t (a) VALUES (2)", NO_PARAMS)?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(readers.refresh().await?);
+    assert_eq!(count(&*readers.get().await?)?, 2);
+    assert_eq!(count(&held)?, 1);
+
+    drop(held);
+    handle.stop().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn empty_source() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let result = ReplicaPool::builder(FileSink::new(temp.file("empty")), temp.file("readers"))
+        .build()
+        .await;
+    assert!(matches!(result, Err(Error::NoReplica)));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn gap_in_segments() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("origin.db");
+    let conn = Connection::open(&path)?;
+    conn.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+    conn.execute("INSERT INTO t (a) VALUES (1)", NO_PARAMS)?;
+    drop(conn);
+
+    // A segment that doesn't start where the WAL does can't be applied, so
+    // it isn't a change to restore.
+    let sink = FileSink::new(temp.file("replica"));
+    sink.write_snapshot(Snapshot {
+        generation: 1,
+        data: std::fs::read(&path)?,
+    })
+    .await?;
+    sink.write_segment(WalSegment {
+        generation: 1,
+        offset: 4096,
+        data: vec![0; 64],
+    })
+    .await?;
+
+    let readers = ReplicaPool::builder(sink, temp.file("readers"))
+        .with_interval(Duration::from_secs(3600))
+        .build()
+        .await?;
+    assert_eq!(readers.restore_point().wal_len, 0);
+    assert_eq!(count(&*readers.get().await?)?, 1);
+    assert!(!readers.refresh().await?);
+    Ok(())
+}
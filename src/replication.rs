@@ -0,0 +1,602 @@
+//! Continuous WAL shipping, in the style of litestream.
+//!
+//! A [`Replicator`] runs as a background task that watches the write-ahead
+//! log of a pooled database and streams each newly committed run of frames to
+//! a [`ReplicaSink`]. Whenever SQLite restarts the WAL (typically after a
+//! checkpoint), the replicator takes a full snapshot and starts a new
+//! _generation_, so a sink always holds enough to rebuild the database: the
+//! latest snapshot, plus every segment of that generation in order.
+//!
+//! The database must be in WAL mode, and because the replicator polls the WAL,
+//! frames written and checkpointed away between two polls are only captured
+//! by the following snapshot.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use rusqlite::{DatabaseName, NO_PARAMS};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{task, Error, RusqliteConnectionManager};
+
+mod wal;
+
+#[cfg(test)]
+mod tests;
+
+use wal::{Checksum, WalHeader, HEADER_SIZE};
+
+/// A full copy of the database, which begins a new generation.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// The generation this snapshot begins. Generations increase over time.
+    pub generation: u64,
+
+    /// The contents of the database file.
+    pub data: Vec<u8>,
+}
+
+/// A contiguous range of the WAL file, ending on a commit frame.
+#[derive(Debug, Clone)]
+pub struct WalSegment {
+    /// The generation this segment belongs to.
+    pub generation: u64,
+
+    /// The offset of this segment within the WAL file. The first segment in a
+    /// generation has an offset of 0, and includes the WAL header.
+    pub offset: u64,
+
+    /// The raw WAL bytes.
+    pub data: Vec<u8>,
+}
+
+/// A destination for replicated database state.
+///
+/// Implementations might write to the local filesystem (see [`FileSink`]), an
+/// object store, or another host entirely. Calls are made sequentially from
+/// the replication task, and snapshots are always written before any segments
+/// of their generation.
+#[async_trait]
+pub trait ReplicaSink: fmt::Debug + Send + Sync {
+    /// Stores a snapshot of the database.
+    async fn write_snapshot(&self, snapshot: Snapshot) -> Result<(), Error>;
+
+    /// Stores a WAL segment.
+    async fn write_segment(&self, segment: WalSegment) -> Result<(), Error>;
+}
+
+/// A source of replicated database state, which can be restored from.
+///
+/// This is the read side of a [`ReplicaSink`]; sinks that can be read back
+/// should implement both.
+#[async_trait]
+pub trait ReplicaSource: fmt::Debug + Send + Sync {
+    /// Returns the latest generation with a complete snapshot, if any.
+    async fn latest_generation(&self) -> Result<Option<u64>, Error>;
+
+    /// Reads the snapshot that began `generation`.
+    async fn read_snapshot(&self, generation: u64) -> Result<Snapshot, Error>;
+
+    /// Reads every segment of `generation`, ordered by offset.
+    async fn read_segments(&self, generation: u64) -> Result<Vec<WalSegment>, Error>;
+}
+
+/// The state a database was restored to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestorePoint {
+    /// The generation that was restored.
+    pub generation: u64,
+
+    /// The number of contiguous WAL bytes that were applied to the snapshot.
+    pub wal_len: u64,
+}
+
+/// Returns the leading segments of a generation that form a gapless run from
+/// the start of its WAL, which are the only ones that can be applied.
+pub(crate) fn gapless(segments: &[WalSegment]) -> &[WalSegment] {
+    let mut len = 0;
+    let run = segments
+        .iter()
+        .take_while(|segment| {
+            let next = segment.offset == len;
+            len += segment.data.len() as u64;
+            next
+        })
+        .count();
+    &segments[..run]
+}
+
+/// Restores the latest generation in `source` to a standalone database at
+/// `path`, replacing anything already there.
+///
+/// The restored database is checkpointed and switched out of WAL mode, so it
+/// can be opened read only without needing a `-shm` file.
+pub async fn restore<S, P>(source: &S, path: P) -> Result<RestorePoint, Error>
+where
+    S: ReplicaSource + ?Sized,
+    P: AsRef<Path>,
+{
+    let generation = source.latest_generation().await?.ok_or(Error::NoReplica)?;
+    let snapshot = source.read_snapshot(generation).await?;
+    let segments = source.read_segments(generation).await?;
+
+    let path = path.as_ref().to_path_buf();
+    task::spawn_blocking(
+        "bb8_rusqlite::replication::restore",
+        move || -> Result<_, Error> {
+            let wal: Vec<u8> = gapless(&segments)
+                .iter()
+                .flat_map(|segment| segment.data.iter().copied())
+                .collect();
+
+            for stale in &[wal_path(&path), shm_path(&path)] {
+                match fs::remove_file(stale) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+            }
+            fs::write(&path, snapshot.data)?;
+            fs::write(wal_path(&path), &wal)?;
+
+            let conn = rusqlite::Connection::open(&path)?;
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", NO_PARAMS, |_| Ok(()))?;
+            conn.query_row("PRAGMA journal_mode = DELETE", NO_PARAMS, |_| Ok(()))?;
+            conn.close().map_err(|(_, e)| e)?;
+
+            Ok(RestorePoint {
+                generation,
+                wal_len: wal.len() as u64,
+            })
+        },
+    )
+    .await?
+}
+
+/// A [`ReplicaSink`] that writes to a local directory, and can be read back as
+/// a [`ReplicaSource`].
+///
+/// Each generation gets its own subdirectory (named with the generation in
+/// 16 digit hex), containing `snapshot.db` and a `wal` directory of segments
+/// named by their offset.
+#[derive(Debug, Clone)]
+pub struct FileSink {
+    dir: PathBuf,
+}
+
+impl FileSink {
+    /// Creates a sink that writes to `dir`, which will be created if needed.
+    pub fn new<P>(dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            dir: dir.as_ref().into(),
+        }
+    }
+
+    fn generation_dir(&self, generation: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}", generation))
+    }
+}
+
+#[async_trait]
+impl ReplicaSink for FileSink {
+    async fn write_snapshot(&self, snapshot: Snapshot) -> Result<(), Error> {
+        let dir = self.generation_dir(snapshot.generation);
+        Ok(
+            task::spawn_blocking("bb8_rusqlite::replication::write_snapshot", move || {
+                fs::create_dir_all(dir.join("wal"))?;
+                write_atomically(&dir.join("snapshot.db"), &snapshot.data)
+            })
+            .await??,
+        )
+    }
+
+    async fn write_segment(&self, segment: WalSegment) -> Result<(), Error> {
+        let dir = self.generation_dir(segment.generation).join("wal");
+        Ok(
+            task::spawn_blocking("bb8_rusqlite::replication::write_segment", move || {
+                fs::create_dir_all(&dir)?;
+                write_atomically(
+                    &dir.join(format!("{:016x}.wal", segment.offset)),
+                    &segment.data,
+                )
+            })
+            .await??,
+        )
+    }
+}
+
+#[async_trait]
+impl ReplicaSource for FileSink {
+    async fn latest_generation(&self) -> Result<Option<u64>, Error> {
+        let dir = self.dir.clone();
+        Ok(task::spawn_blocking(
+            "bb8_rusqlite::replication::latest_generation",
+            move || -> std::io::Result<_> {
+                let entries = match fs::read_dir(dir) {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                    Err(e) => return Err(e),
+                };
+
+                let mut latest = None;
+                for entry in entries {
+                    let entry = entry?;
+                    // Generations without a snapshot are still being written.
+                    if !entry.path().join("snapshot.db").is_file() {
+                        continue;
+                    }
+                    if let Some(generation) = entry
+                        .file_name()
+                        .to_str()
+                        .and_then(|name| u64::from_str_radix(name, 16).ok())
+                    {
+                        latest = latest.max(Some(generation));
+                    }
+                }
+                Ok(latest)
+            },
+        )
+        .await??)
+    }
+
+    async fn read_snapshot(&self, generation: u64) -> Result<Snapshot, Error> {
+        let path = self.generation_dir(generation).join("snapshot.db");
+        let data = task::spawn_blocking("bb8_rusqlite::replication::read_snapshot", move || {
+            fs::read(path)
+        })
+        .await??;
+        Ok(Snapshot { generation, data })
+    }
+
+    async fn read_segments(&self, generation: u64) -> Result<Vec<WalSegment>, Error> {
+        let dir = self.generation_dir(generation).join("wal");
+        Ok(task::spawn_blocking(
+            "bb8_rusqlite::replication::read_segments",
+            move || -> std::io::Result<_> {
+                let mut segments = Vec::new();
+                for entry in fs::read_dir(dir)? {
+                    let path = entry?.path();
+                    if path.extension().and_then(|ext| ext.to_str()) != Some("wal") {
+                        continue;
+                    }
+                    if let Some(offset) = path
+                        .file_stem()
+                        .and_then(|stem| stem.to_str())
+                        .and_then(|stem| u64::from_str_radix(stem, 16).ok())
+                    {
+                        segments.push(WalSegment {
+                            generation,
+                            offset,
+                            data: fs::read(&path)?,
+                        });
+                    }
+                }
+                segments.sort_by_key(|segment| segment.offset);
+                Ok(segments)
+            },
+        )
+        .await??)
+    }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+type ErrorCallback = Arc<dyn Fn(&Error) + Send + Sync>;
+
+/// Configures and starts WAL shipping for a pool.
+pub struct Replicator {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    sink: Arc<dyn ReplicaSink>,
+    interval: Duration,
+    on_error: Option<ErrorCallback>,
+}
+
+impl fmt::Debug for Replicator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replicator")
+            .field("pool", &self.pool)
+            .field("sink", &self.sink)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+impl Replicator {
+    /// Creates a replicator that will ship the pool's database to `sink`,
+    /// polling the WAL once a second by default.
+    pub fn new<S>(pool: bb8::Pool<RusqliteConnectionManager>, sink: S) -> Self
+    where
+        S: ReplicaSink + 'static,
+    {
+        Self {
+            pool,
+            sink: Arc::new(sink),
+            interval: Duration::from_secs(1),
+            on_error: None,
+        }
+    }
+
+    /// Sets how often the WAL is polled for new frames.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets a callback for errors encountered while replicating. The
+    /// replicator keeps running after an error, and retries on the next poll.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Starts the background replication task.
+    ///
+    /// This fails if the database is not in WAL mode.
+    pub async fn start(self) -> Result<ReplicationHandle, Error> {
+        let path = {
+            let conn = self.pool.get().await?;
+            task::block_in_place(|| -> Result<_, Error> {
+                let mode: String =
+                    conn.query_row("PRAGMA journal_mode", NO_PARAMS, |row| row.get(0))?;
+                if !mode.eq_ignore_ascii_case("wal") {
+                    return Err(Error::NotWalMode);
+                }
+                Ok(main_database_path(&conn)?)
+            })?
+        };
+
+        let mut task = ReplicationTask {
+            pool: self.pool,
+            sink: self.sink,
+            wal_path: wal_path(&path),
+            path,
+            state: None,
+        };
+        let interval = self.interval;
+        let on_error = self.on_error;
+        let (stop, mut stopped) = oneshot::channel();
+
+        let join = task::spawn("bb8_rusqlite::replication", async move {
+            loop {
+                let stopping = tokio::time::timeout(interval, &mut stopped).await.is_ok();
+                match task.sync().await {
+                    Err(e) if stopping => return Err(e),
+                    Err(e) => {
+                        if let Some(callback) = &on_error {
+                            callback(&e);
+                        }
+                    }
+                    Ok(()) if stopping => return Ok(()),
+                    Ok(()) => {}
+                }
+            }
+        });
+
+        Ok(ReplicationHandle { stop, join })
+    }
+}
+
+/// A handle to a running replication task. Dropping the handle also stops
+/// replication, but without waiting for the final sync to finish.
+#[derive(Debug)]
+pub struct ReplicationHandle {
+    stop: oneshot::Sender<()>,
+    join: JoinHandle<Result<(), Error>>,
+}
+
+impl ReplicationHandle {
+    /// Returns the ID of the replication task.
+    pub fn task_id(&self) -> tokio::task::Id {
+        self.join.id()
+    }
+
+    /// Stops replication after shipping any remaining committed frames,
+    /// returning the error from that final sync, if any.
+    pub async fn stop(self) -> Result<(), Error> {
+        // If the task has already gone away, the join handle will tell us why.
+        let _ = self.stop.send(());
+        self.join.await?
+    }
+}
+
+#[derive(Debug)]
+struct GenerationState {
+    generation: u64,
+    salt: [u8; 8],
+    offset: u64,
+    checksum: Checksum,
+}
+
+struct ReplicationTask {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    sink: Arc<dyn ReplicaSink>,
+    path: PathBuf,
+    wal_path: PathBuf,
+    state: Option<GenerationState>,
+}
+
+impl ReplicationTask {
+    async fn sync(&mut self) -> Result<(), Error> {
+        let wal_path = self.wal_path.clone();
+        let header =
+            match task::spawn_blocking("bb8_rusqlite::replication::read_header", move || {
+                read_header(&wal_path)
+            })
+            .await??
+            {
+                Some(header) => header,
+                // No WAL yet (or one mid-rewrite), so there's nothing to ship.
+                None => return Ok(()),
+            };
+
+        if self.state.as_ref().map(|state| state.salt) != Some(header.salt) {
+            let previous = self.state.take().map(|state| state.generation);
+            self.state = Some(self.snapshot(&header, previous).await?);
+        }
+
+        let (generation, offset, checksum) = match &self.state {
+            Some(state) => (state.generation, state.offset, state.checksum),
+            None => unreachable!(),
+        };
+        let wal_path = self.wal_path.clone();
+        let (data, checksum) =
+            task::spawn_blocking("bb8_rusqlite::replication::read_wal", move || {
+                read_committed(&wal_path, &header, offset, checksum)
+            })
+            .await??;
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let len = data.len() as u64;
+        self.sink
+            .write_segment(WalSegment {
+                generation,
+                offset,
+                data,
+            })
+            .await?;
+
+        if let Some(state) = &mut self.state {
+            state.offset += len;
+            state.checksum = checksum;
+        }
+        Ok(())
+    }
+
+    async fn snapshot(
+        &self,
+        header: &WalHeader,
+        previous: Option<u64>,
+    ) -> Result<GenerationState, Error> {
+        let generation = next_generation(previous);
+        let tmp = self
+            .path
+            .with_file_name(format!(".{:016x}.snapshot", generation));
+
+        let conn = self.pool.get().await?;
+        let data = task::block_in_place(|| -> Result<_, Error> {
+            let result = conn
+                .backup(DatabaseName::Main, &tmp, None)
+                .map_err(Error::from)
+                .and_then(|_| Ok(fs::read(&tmp)?));
+            let _ = fs::remove_file(&tmp);
+            result
+        })?;
+        drop(conn);
+
+        self.sink
+            .write_snapshot(Snapshot { generation, data })
+            .await?;
+
+        Ok(GenerationState {
+            generation,
+            salt: header.salt,
+            offset: 0,
+            checksum: header.checksum,
+        })
+    }
+}
+
+fn next_generation(previous: Option<u64>) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default();
+    match previous {
+        Some(previous) if previous >= now => previous + 1,
+        _ => now,
+    }
+}
+
+pub(crate) fn main_database_path(conn: &rusqlite::Connection) -> Result<PathBuf, rusqlite::Error> {
+    let mut stmt = conn.prepare("PRAGMA database_list")?;
+    let mut rows = stmt.query(NO_PARAMS)?;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get(1)?;
+        if name == "main" {
+            let file: String = row.get(2)?;
+            return Ok(file.into());
+        }
+    }
+    Err(rusqlite::Error::QueryReturnedNoRows)
+}
+
+pub(crate) fn wal_path(path: &Path) -> PathBuf {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    wal.into()
+}
+
+/// Returns the paths of the WAL files that can exist alongside the database:
+/// the `-wal` file, and the `-wal2` file used in wal2 mode.
+pub(crate) fn wal_paths(path: &Path) -> [PathBuf; 2] {
+    let mut wal2 = path.as_os_str().to_owned();
+    wal2.push("-wal2");
+    [wal_path(path), wal2.into()]
+}
+
+pub(crate) fn shm_path(path: &Path) -> PathBuf {
+    let mut shm = path.as_os_str().to_owned();
+    shm.push("-shm");
+    shm.into()
+}
+
+fn read_header(wal_path: &Path) -> std::io::Result<Option<WalHeader>> {
+    let mut file = match File::open(wal_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut bytes = [0u8; HEADER_SIZE];
+    match file.read_exact(&mut bytes) {
+        Ok(()) => Ok(WalHeader::parse(&bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the committed frames after `offset`, returning them (preceded by the
+/// header if `offset` is 0) along with the checksum after the last one.
+fn read_committed(
+    wal_path: &Path,
+    header: &WalHeader,
+    offset: u64,
+    checksum: Checksum,
+) -> std::io::Result<(Vec<u8>, Checksum)> {
+    let mut file = File::open(wal_path)?;
+    let start = offset.max(HEADER_SIZE as u64);
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut frames = Vec::new();
+    file.read_to_end(&mut frames)?;
+    let (len, checksum) = header.committed(&frames, checksum);
+    frames.truncate(len);
+
+    if offset == 0 && len > 0 {
+        let mut data = vec![0u8; HEADER_SIZE];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut data)?;
+        data.extend(frames);
+        Ok((data, checksum))
+    } else {
+        Ok((frames, checksum))
+    }
+}
@@ -0,0 +1,85 @@
+use std::{fs, time::Duration};
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use super::*;
+use crate::tests::TempDir;
+
+/// Rebuilds the database in `replica` from the latest generation, writing it
+/// to `path`.
+fn rebuild(replica: &Path, path: &Path) -> anyhow::Result<()> {
+    let mut generations = fs::read_dir(replica)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    generations.sort();
+    let latest = generations.last().expect("there must be a generation");
+
+    fs::copy(latest.join("snapshot.db"), path)?;
+
+    let mut segments = fs::read_dir(latest.join("wal"))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    segments.sort();
+    let mut wal = Vec::new();
+    for segment in segments {
+        wal.extend(fs::read(segment)?);
+    }
+    fs::write(super::wal_path(path), wal)?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn ships_committed_frames() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("origin.db");
+    let replica = temp.file("replica");
+
+    let conn = Connection::open(&path)?;
+    conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+    conn.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+
+    let manager = RusqliteConnectionManager::new(&path);
+    let pool = bb8::Pool::builder().build(manager).await?;
+    let handle = Replicator::new(pool.clone(), FileSink::new(&replica))
+        .with_interval(Duration::from_millis(10))
+        .start()
+        .await?;
+
+    // Spread the writes out so that they're shipped as several segments.
+    for i in 0..10 {
+        pool.get()
+            .await?
+            .execute("INSERT INTO t (a) VALUES (?)", [i])?;
+        tokio::time::sleep(Duration::from_millis(15)).await;
+    }
+    handle.stop().await?;
+    drop(pool);
+    drop(conn);
+
+    let restored = temp.file("restored.db");
+    rebuild(&replica, &restored)?;
+    let conn = Connection::open(&restored)?;
+    let (count, sum): (i64, i64) =
+        conn.query_row("SELECT COUNT(*), SUM(a) FROM t", NO_PARAMS, |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+    assert_eq!(count, 10);
+    assert_eq!(sum, 45);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn requires_wal() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("rollback.db"));
+    let pool = bb8::Pool::builder().build(manager).await?;
+
+    let result = Replicator::new(pool, FileSink::new(temp.file("replica")))
+        .start()
+        .await;
+    assert!(matches!(result, Err(Error::NotWalMode)));
+
+    Ok(())
+}
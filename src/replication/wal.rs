@@ -0,0 +1,109 @@
+//! Just enough of the SQLite WAL file format to find committed frames.
+//!
+//! See <https://www.sqlite.org/fileformat2.html#walformat> for the details.
+
+use std::convert::TryInto;
+
+pub(crate) const HEADER_SIZE: usize = 32;
+pub(crate) const FRAME_HEADER_SIZE: usize = 24;
+
+const MAGIC_LE: u32 = 0x377f_0682;
+const MAGIC_BE: u32 = 0x377f_0683;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Checksum(pub(crate) u32, pub(crate) u32);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WalHeader {
+    big_endian: bool,
+    page_size: usize,
+    pub(crate) salt: [u8; 8],
+    pub(crate) checksum: Checksum,
+}
+
+impl WalHeader {
+    /// Parses and verifies a WAL header. Returns `None` if the header is
+    /// incomplete or invalid, which is also what SQLite sees while a header is
+    /// being rewritten.
+    pub(crate) fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return None;
+        }
+
+        let big_endian = match read_u32(bytes, 0) {
+            MAGIC_LE => false,
+            MAGIC_BE => true,
+            _ => return None,
+        };
+
+        let page_size = match read_u32(bytes, 8) {
+            1 => 65536,
+            n if n >= 512 && n.is_power_of_two() => n as usize,
+            _ => return None,
+        };
+
+        let checksum = Checksum(read_u32(bytes, 24), read_u32(bytes, 28));
+        if checksum_bytes(big_endian, &bytes[..24], Checksum(0, 0)) != checksum {
+            return None;
+        }
+
+        Some(Self {
+            big_endian,
+            page_size,
+            salt: bytes[16..24].try_into().unwrap(),
+            checksum,
+        })
+    }
+
+    pub(crate) fn frame_size(&self) -> usize {
+        FRAME_HEADER_SIZE + self.page_size
+    }
+
+    /// Walks the frames in `frames`, which must start on a frame boundary
+    /// immediately after the frame with the checksum `previous`, and returns
+    /// the number of bytes up to and including the last valid commit frame,
+    /// along with the running checksum at that point.
+    pub(crate) fn committed(&self, frames: &[u8], previous: Checksum) -> (usize, Checksum) {
+        let mut committed = (0, previous);
+        let mut checksum = previous;
+
+        for (i, frame) in frames.chunks_exact(self.frame_size()).enumerate() {
+            if frame[8..16] != self.salt {
+                break;
+            }
+
+            checksum = checksum_bytes(self.big_endian, &frame[..8], checksum);
+            checksum = checksum_bytes(self.big_endian, &frame[FRAME_HEADER_SIZE..], checksum);
+            if checksum != Checksum(read_u32(frame, 16), read_u32(frame, 20)) {
+                break;
+            }
+
+            // A non-zero database size marks the final frame of a transaction.
+            if read_u32(frame, 4) != 0 {
+                committed = ((i + 1) * self.frame_size(), checksum);
+            }
+        }
+
+        committed
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn checksum_bytes(big_endian: bool, bytes: &[u8], Checksum(mut s1, mut s2): Checksum) -> Checksum {
+    for pair in bytes.chunks_exact(8) {
+        let (x0, x1) = if big_endian {
+            (read_u32(pair, 0), read_u32(pair, 4))
+        } else {
+            (
+                u32::from_le_bytes(pair[0..4].try_into().unwrap()),
+                u32::from_le_bytes(pair[4..8].try_into().unwrap()),
+            )
+        };
+        s1 = s1.wrapping_add(x0).wrapping_add(s2);
+        s2 = s2.wrapping_add(x1).wrapping_add(s1);
+    }
+    Checksum(s1, s2)
+}
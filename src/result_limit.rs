@@ -0,0 +1,82 @@
+//! Limits on how much of a query's result the helpers will load into memory.
+
+use std::fmt;
+
+use rusqlite::{types::ValueRef, Row, Rows};
+
+use crate::Error;
+
+#[cfg(test)]
+mod tests;
+
+/// A limit a query's result went over, as reported by
+/// [`Error::ResultTooLarge`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultLimit {
+    /// The most rows a query may return, as set with
+    /// [`RusqliteConnectionManager::with_max_result_rows()`](crate::RusqliteConnectionManager::with_max_result_rows).
+    Rows(usize),
+    /// The most bytes of values a query may return, as set with
+    /// [`RusqliteConnectionManager::with_max_result_bytes()`](crate::RusqliteConnectionManager::with_max_result_bytes).
+    Bytes(u64),
+}
+
+impl fmt::Display for ResultLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rows(rows) => write!(f, "{} rows", rows),
+            Self::Bytes(bytes) => write!(f, "{} bytes", bytes),
+        }
+    }
+}
+
+/// The limits set on a manager, which are copied onto each connection.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ResultLimits {
+    pub(crate) rows: Option<usize>,
+    pub(crate) bytes: Option<u64>,
+}
+
+impl ResultLimits {
+    /// Returns the result of `f` for each of `rows`, failing with
+    /// [`Error::ResultTooLarge`] as soon as the rows go over a limit.
+    ///
+    /// Integers and reals count as eight bytes each, text and blobs as their
+    /// length, and nulls as nothing, whatever `f` makes of them.
+    pub(crate) fn collect<T, F>(self, mut rows: Rows<'_>, mut f: F) -> Result<Vec<T>, Error>
+    where
+        F: FnMut(&Row<'_>) -> rusqlite::Result<T>,
+    {
+        let mut collected = Vec::new();
+        let mut bytes = 0u64;
+        while let Some(row) = rows.next()? {
+            if let Some(limit) = self.rows.filter(|&limit| collected.len() >= limit) {
+                return Err(Error::ResultTooLarge {
+                    limit: ResultLimit::Rows(limit),
+                });
+            }
+            if let Some(limit) = self.bytes {
+                bytes += row_size(row)?;
+                if bytes > limit {
+                    return Err(Error::ResultTooLarge {
+                        limit: ResultLimit::Bytes(limit),
+                    });
+                }
+            }
+            collected.push(f(row)?);
+        }
+        Ok(collected)
+    }
+}
+
+fn row_size(row: &Row<'_>) -> rusqlite::Result<u64> {
+    let mut size = 0;
+    for i in 0..row.column_count() {
+        size += match row.get_raw_checked(i)? {
+            ValueRef::Null => 0,
+            ValueRef::Integer(_) | ValueRef::Real(_) => 8,
+            ValueRef::Text(bytes) | ValueRef::Blob(bytes) => bytes.len() as u64,
+        };
+    }
+    Ok(size)
+}
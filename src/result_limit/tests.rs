@@ -0,0 +1,99 @@
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    NamedParams, PoolExt, QueryCache, RusqliteConnectionManager,
+};
+
+// Each row is 8 bytes of integer and 4 of text.
+const SETUP: &str = "CREATE TABLE t (a INTEGER, b TEXT);
+                    INSERT INTO t VALUES (1, 'abcd'), (2, 'efgh'), (3, 'ijkl'), (4, NULL);";
+
+fn assert_too_large<T: fmt::Debug>(result: Result<T, Error>, expected: ResultLimit) {
+    match result {
+        Err(Error::ResultTooLarge { limit }) => assert_eq!(limit, expected),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_rows() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("limit.db")).with_max_result_rows(3),
+        10,
+        SETUP,
+    )
+    .await?;
+
+    let rows = pool
+        .query_rows_dynamic("SELECT * FROM t WHERE a <= 3", Vec::<i64>::new())
+        .await?;
+    assert_eq!(rows.len(), 3);
+
+    assert_too_large(
+        pool.query_rows_dynamic("SELECT * FROM t", Vec::<i64>::new())
+            .await,
+        ResultLimit::Rows(3),
+    );
+    assert_too_large(
+        pool.query_named("SELECT a FROM t", NamedParams::new(), |row| {
+            row.get::<_, i64>(0)
+        })
+        .await,
+        ResultLimit::Rows(3),
+    );
+
+    let cache = QueryCache::new(pool.clone());
+    assert_too_large(
+        cache.query("SELECT * FROM t", Vec::<i64>::new()).await,
+        ResultLimit::Rows(3),
+    );
+
+    // Connections used directly aren't limited.
+    let count = pool
+        .get()
+        .await?
+        .prepare("SELECT * FROM t")?
+        .query_map(rusqlite::NO_PARAMS, |_| Ok(()))?
+        .count();
+    assert_eq!(count, 4);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_bytes() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("limit.db")).with_max_result_bytes(36),
+        10,
+        SETUP,
+    )
+    .await?;
+
+    // Nulls count for nothing, so the last row is only 8 bytes.
+    let rows = pool
+        .query_rows_dynamic("SELECT * FROM t WHERE a != 2", Vec::<i64>::new())
+        .await?;
+    assert_eq!(rows.len(), 3);
+
+    assert_too_large(
+        pool.query_rows_dynamic("SELECT * FROM t", Vec::<i64>::new())
+            .await,
+        ResultLimit::Bytes(36),
+    );
+
+    // Only the columns returned count, not what the caller makes of them.
+    let rows = pool
+        .query_named("SELECT a FROM t", NamedParams::new(), |row| {
+            Ok(format!("{:?}", row.get::<_, i64>(0)?))
+        })
+        .await?;
+    assert_eq!(rows.len(), 4);
+    Ok(())
+}
+
+#[test]
+fn display() {
+    assert_eq!(ResultLimit::Rows(10).to_string(), "10 rows");
+    assert_eq!(ResultLimit::Bytes(1024).to_string(), "1024 bytes");
+}
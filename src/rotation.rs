@@ -0,0 +1,89 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc},
+};
+
+use crate::{DatabaseFile, RusqliteConnection, RusqliteConnectionManager};
+
+/// A database file that has been rotated away from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetiredFile {
+    /// The path to the file.
+    pub path: PathBuf,
+
+    /// The number of connections still open on the file, not counting any
+    /// still being opened.
+    pub open_connections: usize,
+}
+
+impl RetiredFile {
+    /// Returns true if no connections remain open on the file. A connection
+    /// that was being opened as the file was rotated away from may still
+    /// hold it, until it's discarded on checkout, so archive files returned
+    /// by [`take_drained_files()`](RusqliteConnectionManager::take_drained_files),
+    /// which nothing holds.
+    pub fn is_drained(&self) -> bool {
+        self.open_connections == 0
+    }
+}
+
+impl RusqliteConnectionManager {
+    /// Returns the path new connections are opened on.
+    pub fn path(&self) -> PathBuf {
+        self.current_file().path.clone()
+    }
+
+    /// Switches new connections to the database at `path`, such as when
+    /// moving to a new daily log file.
+    ///
+    /// Connections to the previous file are drained rather than interrupted:
+    /// checked out connections remain usable, but are discarded instead of
+    /// being returned to the pool, and idle connections are discarded when
+    /// next checked out. The previous file is then reported by
+    /// [`retired_files()`](Self::retired_files) until it is taken with
+    /// [`take_drained_files()`](Self::take_drained_files).
+    pub fn rotate<P>(&self, path: P)
+    where
+        P: AsRef<Path>,
+    {
+        let file = Arc::new(DatabaseFile::new(
+            self.options.path_policy.resolve(path.as_ref()),
+            false,
+        ));
+        let previous = std::mem::replace(&mut *self.files.current.write().unwrap(), file);
+        self.files.retired.lock().unwrap().push(previous);
+    }
+
+    /// Returns every file that has been rotated away from and not yet taken,
+    /// oldest first.
+    pub fn retired_files(&self) -> Vec<RetiredFile> {
+        self.files
+            .retired
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|file| RetiredFile {
+                path: file.path.clone(),
+                open_connections: file.connections.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Removes every retired file that no longer has open connections, or
+    /// connections being opened, from the retired list, and returns their
+    /// paths for archival.
+    pub fn take_drained_files(&self) -> Vec<PathBuf> {
+        let mut retired = self.files.retired.lock().unwrap();
+        let (drained, open) = retired
+            .drain(..)
+            // One reference is held by the retired list itself.
+            .partition::<Vec<_>, _>(|file| Arc::strong_count(file) == 1);
+        *retired = open;
+
+        drained.into_iter().map(|file| file.path.clone()).collect()
+    }
+
+    pub(crate) fn is_retired(&self, conn: &RusqliteConnection) -> bool {
+        !Arc::ptr_eq(conn.file(), &*self.files.current.read().unwrap())
+    }
+}
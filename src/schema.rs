@@ -0,0 +1,476 @@
+//! Schema introspection, and drift detection against an expected schema.
+//!
+//! [`Schema::read()`] (or [`PoolExt::schema()`](crate::PoolExt::schema))
+//! describes the tables, columns, and indexes in a database. Comparing that
+//! to a [`Schema`] built from the DDL the application expects with [`diff()`]
+//! catches databases that have drifted, such as through a half applied
+//! migration or a manual fix that was never written down.
+//!
+//! [`table_info()`], [`index_list()`], and [`foreign_key_list()`] (or their
+//! [`PoolExt`](crate::PoolExt) equivalents) describe a single table, without
+//! reading the whole schema. [`foreign_key_check()`] finds rows that break
+//! their foreign keys, such as after a bulk import made with enforcement
+//! disabled.
+//!
+//! [`non_strict_tables()`] lists the tables that aren't declared `STRICT`,
+//! for applications that want every column's type enforced. The same check
+//! can be made on each new connection with
+//! [`RusqliteConnectionManager::with_strict_tables()`](crate::RusqliteConnectionManager::with_strict_tables).
+
+use std::collections::HashSet;
+
+use rusqlite::{Connection, NO_PARAMS};
+
+#[cfg(test)]
+mod tests;
+
+/// The tables in a database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    /// The tables, ordered by name.
+    pub tables: Vec<Table>,
+}
+
+/// A table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    /// The table name.
+    pub name: String,
+
+    /// The columns, in declaration order.
+    pub columns: Vec<Column>,
+
+    /// The indexes on the table, ordered by name.
+    pub indexes: Vec<Index>,
+
+    /// True if the table is declared `STRICT`. This is always false before
+    /// SQLite 3.37, which added strict tables.
+    pub strict: bool,
+}
+
+/// A column within a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    /// The column name.
+    pub name: String,
+
+    /// The declared type, which may be empty.
+    pub decl_type: String,
+
+    /// True if the column is declared `NOT NULL`.
+    pub not_null: bool,
+
+    /// The default value expression, if any.
+    pub default: Option<String>,
+
+    /// The 1-based position of the column within the primary key, or 0 if
+    /// it isn't part of the primary key.
+    pub primary_key: u32,
+}
+
+/// An index on a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    /// The index name. Indexes SQLite creates for `UNIQUE` and `PRIMARY KEY`
+    /// constraints are named `sqlite_autoindex_*`.
+    pub name: String,
+
+    /// True if the index enforces uniqueness.
+    pub unique: bool,
+
+    /// The indexed columns, in index order. Expressions appear as `None`.
+    pub columns: Vec<Option<String>>,
+}
+
+/// A foreign key constraint on a table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignKey {
+    /// The constraint's ID, which is unique within the table.
+    pub id: i64,
+
+    /// The referenced table.
+    pub table: String,
+
+    /// The referencing columns, in constraint order.
+    pub from: Vec<String>,
+
+    /// The referenced columns, matching `from`. A column is `None` if the
+    /// constraint refers to the referenced table's primary key implicitly.
+    pub to: Vec<Option<String>>,
+
+    /// The `ON UPDATE` action, such as `CASCADE` or `NO ACTION`.
+    pub on_update: String,
+
+    /// The `ON DELETE` action.
+    pub on_delete: String,
+}
+
+/// A row that breaks a foreign key constraint, as reported by
+/// `PRAGMA foreign_key_check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForeignKeyViolation {
+    /// The table containing the row.
+    pub table: String,
+
+    /// The row's rowid, or `None` if the table is `WITHOUT ROWID`.
+    pub rowid: Option<i64>,
+
+    /// The table the row should refer to.
+    pub parent: String,
+
+    /// The ID of the broken constraint, matching [`ForeignKey::id`] in the
+    /// table's [`foreign_key_list()`].
+    pub foreign_key: i64,
+}
+
+impl Schema {
+    /// Reads the schema of the main database on `conn`.
+    pub fn read(conn: &Connection) -> Result<Self, rusqlite::Error> {
+        let names = {
+            let mut stmt = conn.prepare(
+                "SELECT name FROM sqlite_master
+                 WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+                 ORDER BY name",
+            )?;
+            let names = stmt
+                .query_map(NO_PARAMS, |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?;
+            names
+        };
+        let strict = strict_tables(conn)?;
+
+        let tables = names
+            .into_iter()
+            .map(|name| {
+                Ok(Table {
+                    columns: table_info(conn, &name)?,
+                    indexes: index_list(conn, &name)?,
+                    strict: strict.contains(&name),
+                    name,
+                })
+            })
+            .collect::<Result<_, rusqlite::Error>>()?;
+
+        Ok(Self { tables })
+    }
+
+    /// Builds the schema that results from executing `ddl` against an empty
+    /// database. This is the easiest way to describe the expected schema for
+    /// [`diff()`].
+    pub fn from_sql(ddl: &str) -> Result<Self, rusqlite::Error> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(ddl)?;
+        Self::read(&conn)
+    }
+
+    /// Returns the table with the given name, if it exists.
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables.iter().find(|table| table.name == name)
+    }
+}
+
+impl Table {
+    /// Returns the column with the given name, if it exists.
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    /// Returns the index with the given name, if it exists.
+    pub fn index(&self, name: &str) -> Option<&Index> {
+        self.indexes.iter().find(|index| index.name == name)
+    }
+}
+
+/// Returns the columns of `table`, in declaration order, as reported by
+/// `PRAGMA table_info`. A table that doesn't exist has no columns.
+pub fn table_info(conn: &Connection, table: &str) -> Result<Vec<Column>, rusqlite::Error> {
+    let mut columns = Vec::new();
+    conn.pragma(None, "table_info", &table, |row| {
+        columns.push(Column {
+            name: row.get("name")?,
+            decl_type: row.get("type")?,
+            not_null: row.get("notnull")?,
+            default: row.get("dflt_value")?,
+            primary_key: row.get("pk")?,
+        });
+        Ok(())
+    })?;
+    Ok(columns)
+}
+
+/// Returns the indexes on `table`, ordered by name, as reported by
+/// `PRAGMA index_list` and `PRAGMA index_info`.
+pub fn index_list(conn: &Connection, table: &str) -> Result<Vec<Index>, rusqlite::Error> {
+    let mut indexes = Vec::new();
+    conn.pragma(None, "index_list", &table, |row| {
+        indexes.push(Index {
+            name: row.get("name")?,
+            unique: row.get("unique")?,
+            columns: Vec::new(),
+        });
+        Ok(())
+    })?;
+
+    for index in &mut indexes {
+        let mut columns = Vec::new();
+        conn.pragma(None, "index_info", &index.name, |row| {
+            columns.push((row.get::<_, i64>("seqno")?, row.get("name")?));
+            Ok(())
+        })?;
+        columns.sort_by_key(|(seqno, _)| *seqno);
+        index.columns = columns.into_iter().map(|(_, name)| name).collect();
+    }
+
+    indexes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(indexes)
+}
+
+/// Returns the foreign keys on `table`, ordered by ID, as reported by
+/// `PRAGMA foreign_key_list`. Composite keys are returned as a single
+/// [`ForeignKey`], rather than one row per column.
+pub fn foreign_key_list(
+    conn: &Connection,
+    table: &str,
+) -> Result<Vec<ForeignKey>, rusqlite::Error> {
+    let mut rows = Vec::new();
+    conn.pragma(None, "foreign_key_list", &table, |row| {
+        rows.push((
+            row.get::<_, i64>("seq")?,
+            ForeignKey {
+                id: row.get("id")?,
+                table: row.get("table")?,
+                from: vec![row.get("from")?],
+                to: vec![row.get("to")?],
+                on_update: row.get("on_update")?,
+                on_delete: row.get("on_delete")?,
+            },
+        ));
+        Ok(())
+    })?;
+    rows.sort_by_key(|(seq, key)| (key.id, *seq));
+
+    let mut keys: Vec<ForeignKey> = Vec::new();
+    for (_, key) in rows {
+        match keys.last_mut() {
+            Some(last) if last.id == key.id => {
+                last.from.extend(key.from);
+                last.to.extend(key.to);
+            }
+            _ => keys.push(key),
+        }
+    }
+    Ok(keys)
+}
+
+/// Returns the names of the tables in the main database that are declared
+/// `STRICT`.
+fn strict_tables(conn: &Connection) -> Result<HashSet<String>, rusqlite::Error> {
+    // PRAGMA table_list, which reports whether a table is strict, is as new
+    // as strict tables themselves.
+    if rusqlite::version_number() < 3_037_000 {
+        return Ok(HashSet::new());
+    }
+    let mut stmt = conn.prepare(
+        "SELECT name FROM pragma_table_list
+         WHERE schema = 'main' AND type = 'table' AND strict",
+    )?;
+    let names = stmt
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(names)
+}
+
+/// Returns the names of the tables in the main database that aren't declared
+/// `STRICT`, ordered by name, leaving out SQLite's own tables. Before SQLite
+/// 3.37, this is every table.
+pub fn non_strict_tables(conn: &Connection) -> Result<Vec<String>, rusqlite::Error> {
+    let strict = strict_tables(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT name FROM sqlite_master
+         WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+         ORDER BY name",
+    )?;
+    let names = stmt
+        .query_map(NO_PARAMS, |row| row.get::<_, String>(0))?
+        .filter(|name| !matches!(name, Ok(name) if strict.contains(name)))
+        .collect::<Result<_, _>>()?;
+    Ok(names)
+}
+
+/// Returns every row in the main database that breaks a foreign key
+/// constraint, ordered by table and rowid. This works whether or not
+/// `PRAGMA foreign_keys` is enabled.
+pub fn foreign_key_check(conn: &Connection) -> Result<Vec<ForeignKeyViolation>, rusqlite::Error> {
+    let mut violations = Vec::new();
+    conn.pragma_query(None, "foreign_key_check", |row| {
+        violations.push(ForeignKeyViolation {
+            table: row.get("table")?,
+            rowid: row.get("rowid")?,
+            parent: row.get("parent")?,
+            foreign_key: row.get("fkid")?,
+        });
+        Ok(())
+    })?;
+    violations.sort_by(|a, b| (&a.table, a.rowid).cmp(&(&b.table, b.rowid)));
+    Ok(violations)
+}
+
+/// A way in which an actual schema differs from the expected schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaDifference {
+    /// An expected table doesn't exist.
+    MissingTable(String),
+
+    /// A table exists that wasn't expected.
+    UnexpectedTable(String),
+
+    /// A table is `STRICT` when it wasn't expected to be, or the other way
+    /// around.
+    StrictMismatch {
+        /// The table name.
+        table: String,
+
+        /// Whether the table was expected to be strict.
+        expected: bool,
+    },
+
+    /// An expected column doesn't exist.
+    MissingColumn {
+        /// The table name.
+        table: String,
+
+        /// The column name.
+        column: String,
+    },
+
+    /// A column exists that wasn't expected.
+    UnexpectedColumn {
+        /// The table name.
+        table: String,
+
+        /// The column name.
+        column: String,
+    },
+
+    /// A column exists, but is defined differently.
+    ColumnMismatch {
+        /// The table name.
+        table: String,
+
+        /// The expected definition.
+        expected: Column,
+
+        /// The actual definition.
+        actual: Column,
+    },
+
+    /// An expected index doesn't exist.
+    MissingIndex {
+        /// The table name.
+        table: String,
+
+        /// The index name.
+        index: String,
+    },
+
+    /// An index exists that wasn't expected.
+    UnexpectedIndex {
+        /// The table name.
+        table: String,
+
+        /// The index name.
+        index: String,
+    },
+
+    /// An index exists, but is defined differently.
+    IndexMismatch {
+        /// The table name.
+        table: String,
+
+        /// The expected definition.
+        expected: Index,
+
+        /// The actual definition.
+        actual: Index,
+    },
+}
+
+/// Compares `actual` against `expected`, returning every difference. An empty
+/// result means the schemas match.
+///
+/// Column order is not significant, since `ALTER TABLE ... ADD COLUMN` always
+/// appends, and so migrated databases rarely match fresh ones.
+pub fn diff(expected: &Schema, actual: &Schema) -> Vec<SchemaDifference> {
+    let mut differences = Vec::new();
+
+    for table in &expected.tables {
+        match actual.table(&table.name) {
+            Some(actual) => diff_table(table, actual, &mut differences),
+            None => differences.push(SchemaDifference::MissingTable(table.name.clone())),
+        }
+    }
+    for table in &actual.tables {
+        if expected.table(&table.name).is_none() {
+            differences.push(SchemaDifference::UnexpectedTable(table.name.clone()));
+        }
+    }
+
+    differences
+}
+
+fn diff_table(expected: &Table, actual: &Table, differences: &mut Vec<SchemaDifference>) {
+    let table = || expected.name.clone();
+
+    if expected.strict != actual.strict {
+        differences.push(SchemaDifference::StrictMismatch {
+            table: table(),
+            expected: expected.strict,
+        });
+    }
+
+    for column in &expected.columns {
+        match actual.column(&column.name) {
+            Some(found) if found == column => {}
+            Some(found) => differences.push(SchemaDifference::ColumnMismatch {
+                table: table(),
+                expected: column.clone(),
+                actual: found.clone(),
+            }),
+            None => differences.push(SchemaDifference::MissingColumn {
+                table: table(),
+                column: column.name.clone(),
+            }),
+        }
+    }
+    for column in &actual.columns {
+        if expected.column(&column.name).is_none() {
+            differences.push(SchemaDifference::UnexpectedColumn {
+                table: table(),
+                column: column.name.clone(),
+            });
+        }
+    }
+
+    for index in &expected.indexes {
+        match actual.index(&index.name) {
+            Some(found) if found == index => {}
+            Some(found) => differences.push(SchemaDifference::IndexMismatch {
+                table: table(),
+                expected: index.clone(),
+                actual: found.clone(),
+            }),
+            None => differences.push(SchemaDifference::MissingIndex {
+                table: table(),
+                index: index.name.clone(),
+            }),
+        }
+    }
+    for index in &actual.indexes {
+        if expected.index(&index.name).is_none() {
+            differences.push(SchemaDifference::UnexpectedIndex {
+                table: table(),
+                index: index.name.clone(),
+            });
+        }
+    }
+}
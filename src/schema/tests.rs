@@ -0,0 +1,230 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager};
+
+const DDL: &str = "
+    CREATE TABLE users (
+        id INTEGER PRIMARY KEY,
+        email TEXT NOT NULL UNIQUE,
+        name TEXT DEFAULT 'anonymous'
+    );
+    CREATE INDEX users_name ON users (name, id);
+    CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id INTEGER);
+";
+
+#[test]
+fn from_sql() -> Result<(), anyhow::Error> {
+    let schema = Schema::from_sql(DDL)?;
+    assert_eq!(
+        schema
+            .tables
+            .iter()
+            .map(|t| t.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["posts", "users"]
+    );
+
+    let users = schema.table("users").unwrap();
+    assert_eq!(
+        users.column("id"),
+        Some(&Column {
+            name: "id".into(),
+            decl_type: "INTEGER".into(),
+            not_null: false,
+            default: None,
+            primary_key: 1,
+        })
+    );
+    assert!(users.column("email").unwrap().not_null);
+    assert_eq!(
+        users.column("name").unwrap().default.as_deref(),
+        Some("'anonymous'")
+    );
+
+    assert_eq!(
+        users.index("users_name"),
+        Some(&Index {
+            name: "users_name".into(),
+            unique: false,
+            columns: vec![Some("name".into()), Some("id".into())],
+        })
+    );
+    assert!(users.index("sqlite_autoindex_users_1").unwrap().unique);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn drift() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+    pool.get().await?.execute_batch(DDL)?;
+
+    let expected = Schema::from_sql(DDL)?;
+    assert_eq!(diff(&expected, &pool.schema().await?), vec![]);
+
+    pool.get().await?.execute_batch(
+        "DROP INDEX users_name;
+         CREATE INDEX users_name ON users (name);
+         ALTER TABLE posts ADD COLUMN body TEXT;
+         CREATE TABLE extra (a);",
+    )?;
+    let actual = pool.schema().await?;
+    assert_eq!(
+        diff(&expected, &actual),
+        vec![
+            SchemaDifference::UnexpectedColumn {
+                table: "posts".into(),
+                column: "body".into(),
+            },
+            SchemaDifference::IndexMismatch {
+                table: "users".into(),
+                expected: expected
+                    .table("users")
+                    .unwrap()
+                    .index("users_name")
+                    .unwrap()
+                    .clone(),
+                actual: actual
+                    .table("users")
+                    .unwrap()
+                    .index("users_name")
+                    .unwrap()
+                    .clone(),
+            },
+            SchemaDifference::UnexpectedTable("extra".into()),
+        ]
+    );
+
+    pool.get().await?.execute("DROP TABLE users", NO_PARAMS)?;
+    assert!(diff(&expected, &pool.schema().await?)
+        .contains(&SchemaDifference::MissingTable("users".into())));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn table_helpers() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+    pool.get().await?.execute_batch(
+        "CREATE TABLE parents (a, b, PRIMARY KEY (a, b));
+         CREATE TABLE users (id INTEGER PRIMARY KEY);
+         CREATE TABLE children (
+             id INTEGER PRIMARY KEY,
+             pa, pb,
+             user_id INTEGER REFERENCES users ON DELETE CASCADE,
+             FOREIGN KEY (pa, pb) REFERENCES parents (a, b)
+         );
+         CREATE INDEX children_user ON children (user_id);",
+    )?;
+
+    let columns = pool.table_info("children").await?;
+    assert_eq!(
+        columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(),
+        vec!["id", "pa", "pb", "user_id"]
+    );
+    assert_eq!(columns[0].primary_key, 1);
+    assert!(pool.table_info("missing").await?.is_empty());
+
+    assert_eq!(
+        pool.index_list("children").await?,
+        vec![Index {
+            name: "children_user".into(),
+            unique: false,
+            columns: vec![Some("user_id".into())],
+        }]
+    );
+
+    let keys = pool.foreign_key_list("children").await?;
+    assert_eq!(keys.len(), 2);
+    let parents = keys.iter().find(|key| key.table == "parents").unwrap();
+    assert_eq!(parents.from, vec!["pa", "pb"]);
+    assert_eq!(parents.to, vec![Some("a".into()), Some("b".into())]);
+    let users = keys.iter().find(|key| key.table == "users").unwrap();
+    assert_eq!(users.from, vec!["user_id"]);
+    assert_eq!(users.to, vec![None]);
+    assert_eq!(users.on_delete, "CASCADE");
+    assert_eq!(users.on_update, "NO ACTION");
+
+    Ok(())
+}
+
+#[test]
+fn strict() -> Result<(), anyhow::Error> {
+    let ddl = "CREATE TABLE a (id INTEGER PRIMARY KEY) STRICT;
+               CREATE TABLE b (id INTEGER PRIMARY KEY);";
+    if rusqlite::version_number() < 3_037_000 {
+        assert!(Schema::from_sql(ddl).is_err());
+        return Ok(());
+    }
+
+    let conn = Connection::open_in_memory()?;
+    conn.execute_batch(ddl)?;
+    assert_eq!(non_strict_tables(&conn)?, vec!["b"]);
+
+    let schema = Schema::read(&conn)?;
+    assert!(schema.table("a").unwrap().strict);
+    assert!(!schema.table("b").unwrap().strict);
+
+    let loose = Schema::from_sql(&ddl.replace("STRICT", ""))?;
+    assert_eq!(
+        diff(&schema, &loose),
+        vec![SchemaDifference::StrictMismatch {
+            table: "a".into(),
+            expected: true,
+        }]
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_foreign_keys() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+    pool.get().await?.execute_batch(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY);
+         CREATE TABLE posts (id INTEGER PRIMARY KEY, user_id REFERENCES users);
+         CREATE TABLE tags (
+             name TEXT PRIMARY KEY,
+             post_id REFERENCES posts
+         ) WITHOUT ROWID;
+         INSERT INTO users VALUES (1);
+         INSERT INTO posts VALUES (1, 1), (2, 2);
+         INSERT INTO tags VALUES ('a', 1), ('b', 3);",
+    )?;
+
+    assert_eq!(
+        pool.check_foreign_keys().await?,
+        vec![
+            ForeignKeyViolation {
+                table: "posts".into(),
+                rowid: Some(2),
+                parent: "users".into(),
+                foreign_key: 0,
+            },
+            ForeignKeyViolation {
+                table: "tags".into(),
+                rowid: None,
+                parent: "posts".into(),
+                foreign_key: 0,
+            },
+        ]
+    );
+
+    pool.get()
+        .await?
+        .execute_batch("DELETE FROM posts WHERE id = 2; DELETE FROM tags WHERE name = 'b';")?;
+    assert!(pool.check_foreign_keys().await?.is_empty());
+    Ok(())
+}
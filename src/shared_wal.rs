@@ -0,0 +1,60 @@
+//! Sharing a WAL database between a writing process and read-only readers.
+//!
+//! A connection to a WAL database reads the WAL through its index, the
+//! `-shm` file, which is normally created and repaired by whichever
+//! connection needs it. Readers without write access to the database's
+//! directory can't do either, so they need `readonly_shm=1`, and a writer
+//! that keeps the `-wal` and `-shm` files in place, even once it has closed
+//! its last connection.
+
+use std::os::raw::{c_int, c_void};
+
+use rusqlite::{ffi, Connection, ErrorCode, NO_PARAMS};
+
+use crate::ffi_ext::{FCNTL_PERSIST_WAL, READONLY_CANTINIT, READONLY_CANTLOCK, READONLY_RECOVERY};
+use crate::Error;
+
+#[cfg(test)]
+mod tests;
+
+/// Leaves the WAL and its index in place when `conn` is the last connection
+/// to the database to close.
+pub(crate) fn persist(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let mut persist: c_int = 1;
+    // Safety: the handle is valid for the lifetime of the connection, and
+    // this file control reads and writes a single int.
+    let rc = unsafe {
+        ffi::sqlite3_file_control(
+            conn.handle(),
+            b"main\0".as_ptr() as *const _,
+            FCNTL_PERSIST_WAL,
+            &mut persist as *mut c_int as *mut c_void,
+        )
+    };
+    if rc != ffi::SQLITE_OK {
+        return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+    }
+    Ok(())
+}
+
+/// Reads the schema through `conn`, which makes SQLite open the WAL index,
+/// so that a missing or unreadable one fails the connect with
+/// [`Error::WalIndexUnavailable`], rather than the first query with
+/// `SQLITE_CANTOPEN`.
+pub(crate) fn probe(conn: &Connection) -> Result<(), Error> {
+    match conn.query_row("SELECT COUNT(*) FROM sqlite_master", NO_PARAMS, |_| Ok(())) {
+        Ok(()) => Ok(()),
+        Err(rusqlite::Error::SqliteFailure(e, message))
+            if e.code == ErrorCode::CannotOpen
+                || matches!(
+                    e.extended_code,
+                    READONLY_RECOVERY | READONLY_CANTLOCK | READONLY_CANTINIT
+                ) =>
+        {
+            Err(Error::WalIndexUnavailable {
+                source: rusqlite::Error::SqliteFailure(e, message),
+            })
+        }
+        Err(e) => Err(e.into()),
+    }
+}
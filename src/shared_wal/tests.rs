@@ -0,0 +1,51 @@
+use bb8::ManageConnection;
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, Error, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn persistent_wal() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("shared.db");
+    let writer = RusqliteConnectionManager::new(&path).with_persistent_wal(true);
+    writer.connect().await?.execute_batch(
+        "PRAGMA journal_mode = WAL; CREATE TABLE t (a); INSERT INTO t VALUES (1);",
+    )?;
+
+    // The writer has gone, but left its WAL and index behind for readers.
+    assert!(temp.file("shared.db-shm").exists());
+    let reader = RusqliteConnectionManager::read_only_wal(&path);
+    let conn = reader.connect().await?;
+    let a: i64 = conn.query_row("SELECT a FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(a, 1);
+    assert!(conn.execute("INSERT INTO t VALUES (2)", NO_PARAMS).is_err());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_index() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("shared.db");
+    RusqliteConnectionManager::new(&path)
+        .connect()
+        .await?
+        .execute_batch("PRAGMA journal_mode = WAL; CREATE TABLE t (a);")?;
+    assert!(!temp.file("shared.db-shm").exists());
+
+    let reader = RusqliteConnectionManager::read_only_wal(&path);
+    match reader.connect().await {
+        Err(Error::WalIndexUnavailable { .. }) => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    // Readers work alongside a writer that has the database open.
+    let writer = RusqliteConnectionManager::new(&path).connect().await?;
+    writer.execute("INSERT INTO t VALUES (1)", NO_PARAMS)?;
+    let count: i64 =
+        reader
+            .connect()
+            .await?
+            .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(count, 1);
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! Graceful pool shutdown.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use rusqlite::NO_PARAMS;
+
+use crate::{task, Error, RusqliteConnection, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+tokio::task_local! {
+    // Set while the shutdown itself is checking out connections, which are
+    // refused to everyone else.
+    static DRAINING: ();
+}
+
+/// Options for [`RusqliteConnectionManager::shutdown()`].
+#[derive(Debug, Clone)]
+pub struct ShutdownOptions {
+    timeout: Duration,
+    optimize: bool,
+    checkpoint: bool,
+}
+
+impl Default for ShutdownOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            optimize: true,
+            checkpoint: true,
+        }
+    }
+}
+
+impl ShutdownOptions {
+    /// Creates the default options, which wait up to 30 seconds for
+    /// checked out connections, then optimize and checkpoint the database.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long to wait for checked out connections to be returned.
+    /// Connections still checked out after this are closed whenever they
+    /// are returned, but aren't included in the report.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets whether to run `PRAGMA optimize` before closing the connections.
+    pub fn optimize(mut self, optimize: bool) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    /// Sets whether to run a `TRUNCATE` checkpoint before closing the
+    /// connections, which copies the WAL back into the database and empties
+    /// it. This does nothing if the database isn't in WAL mode.
+    pub fn checkpoint(mut self, checkpoint: bool) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+}
+
+/// The outcome of [`RusqliteConnectionManager::shutdown()`].
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    /// The number of connections the shutdown closed.
+    pub closed: u32,
+
+    /// The number of connections that were still checked out when the
+    /// timeout expired.
+    pub abandoned: u32,
+
+    /// Errors from optimizing, checkpointing, and closing connections. A
+    /// failure doesn't stop the shutdown, so there may be several.
+    pub errors: Vec<Error>,
+}
+
+/// Shutdown state, shared by every clone of a manager.
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    closing: AtomicBool,
+    closed: AtomicU32,
+    errors: Mutex<Vec<Error>>,
+}
+
+impl State {
+    /// Returns true if the pool is shutting down.
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if connections shouldn't be handed out, because the pool
+    /// is shutting down and the checkout isn't the shutdown's own.
+    pub(crate) fn refuses_checkout(&self) -> bool {
+        self.is_closing() && DRAINING.try_with(|_| ()).is_err()
+    }
+
+    /// Closes `conn` in place, recording the outcome.
+    pub(crate) fn close(&self, conn: &mut RusqliteConnection) {
+        match conn.close() {
+            Ok(()) => {
+                self.closed.fetch_add(1, Ordering::SeqCst);
+            }
+            Err(e) => self.errors.lock().unwrap().push(e.into()),
+        }
+    }
+
+    fn record(&self, result: Result<(), Error>) {
+        if let Err(e) = result {
+            self.errors.lock().unwrap().push(e);
+        }
+    }
+}
+
+impl RusqliteConnectionManager {
+    /// Shuts down `pool`, which must use this manager (or a clone of it).
+    ///
+    /// This stops the pool opening new connections or handing out idle
+    /// ones, and waits for checked out connections to be returned. It then
+    /// optimizes and checkpoints the database through one last connection,
+    /// and closes every connection, reporting any errors along the way.
+    ///
+    /// Other tasks' checkouts fail from then on, although only once the
+    /// pool's connection timeout expires, since bb8 retries failed opens
+    /// until then. Idle connections are only refused to checkouts that
+    /// validate them, so `test_on_check_out` must be left enabled.
+    pub async fn shutdown(
+        &self,
+        pool: bb8::Pool<Self>,
+        options: ShutdownOptions,
+    ) -> ShutdownReport {
+        let state = &self.shutdown;
+        state.closing.store(true, Ordering::SeqCst);
+
+        // Connections returned in the meantime are closed as they come back.
+        let deadline = Instant::now() + options.timeout;
+        let in_use = |pool: &bb8::Pool<Self>| {
+            let pool = pool.state();
+            pool.connections - pool.idle_connections
+        };
+        while in_use(&pool) > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let abandoned = in_use(&pool);
+
+        DRAINING
+            .scope((), async {
+                let mut first = true;
+                while pool.state().idle_connections > 0 {
+                    let conn = match pool.get().await {
+                        Ok(conn) => conn,
+                        Err(e) => {
+                            state.record(Err(e.into()));
+                            break;
+                        }
+                    };
+                    if first {
+                        first = false;
+                        task::block_in_place(|| {
+                            if options.optimize {
+                                state.record(
+                                    conn.execute_batch("PRAGMA optimize").map_err(Error::from),
+                                );
+                            }
+                            if options.checkpoint {
+                                state.record(
+                                    conn.query_row(
+                                        "PRAGMA wal_checkpoint(TRUNCATE)",
+                                        NO_PARAMS,
+                                        |_| Ok(()),
+                                    )
+                                    .map_err(Error::from),
+                                );
+                            }
+                        });
+                    }
+                    // Returning the connection closes it.
+                    drop(conn);
+                }
+            })
+            .await;
+
+        ShutdownReport {
+            closed: state.closed.swap(0, Ordering::SeqCst),
+            abandoned,
+            errors: std::mem::take(&mut *state.errors.lock().unwrap()),
+        }
+    }
+}
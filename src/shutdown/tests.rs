@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{replication, tests::TempDir};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("shutdown.db");
+    let manager = RusqliteConnectionManager::new(&path);
+    let pool = bb8::Pool::builder()
+        .max_size(3)
+        .min_idle(Some(3))
+        .connection_timeout(Duration::from_millis(200))
+        .build(manager.clone())
+        .await?;
+
+    let held = pool.get().await?;
+    held.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+    held.execute_batch("CREATE TABLE t (a); INSERT INTO t VALUES (1);")?;
+    assert!(std::fs::metadata(replication::wal_path(&path))?.len() > 0);
+
+    // The shutdown waits for the held connection to be returned.
+    let shutdown = tokio::spawn({
+        let manager = manager.clone();
+        let pool = pool.clone();
+        async move {
+            manager
+                .shutdown(pool, ShutdownOptions::new().timeout(Duration::from_secs(5)))
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!shutdown.is_finished());
+    drop(held);
+
+    let report = shutdown.await?;
+    assert_eq!(report.closed, 3);
+    assert_eq!(report.abandoned, 0);
+    assert!(report.errors.is_empty(), "{:?}", report.errors);
+    assert_eq!(pool.state().connections, 0);
+    // The WAL has been emptied, if not removed when the last connection
+    // closed, and everything in it is in the database.
+    let wal = std::fs::metadata(replication::wal_path(&path)).map(|meta| meta.len());
+    assert!(wal.unwrap_or(0) == 0);
+    let count: i64 = rusqlite::Connection::open(&path)?.query_row(
+        "SELECT COUNT(*) FROM t",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+    assert_eq!(count, 1);
+
+    // Nothing more is handed out.
+    assert!(pool.get().await.is_err());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn abandoned() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("shutdown.db"));
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .min_idle(Some(2))
+        .build(manager.clone())
+        .await?;
+
+    let held = pool.get().await?;
+    let report = manager
+        .shutdown(
+            pool.clone(),
+            ShutdownOptions::new().timeout(Duration::from_millis(50)),
+        )
+        .await;
+    assert_eq!(report.closed, 1);
+    assert_eq!(report.abandoned, 1);
+
+    // The abandoned connection still works until it's returned.
+    held.query_row("SELECT 1", NO_PARAMS, |_| Ok(()))?;
+    drop(held);
+    assert_eq!(pool.state().connections, 0);
+    Ok(())
+}
@@ -0,0 +1,11 @@
+//! Helpers for building SQL.
+
+/// Quotes `name` for use as an identifier, such as a table or column name.
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Returns `n` comma separated placeholders, as for a `VALUES` list.
+pub(crate) fn placeholders(n: usize) -> String {
+    vec!["?"; n].join(", ")
+}
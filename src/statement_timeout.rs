@@ -0,0 +1,117 @@
+//! A limit on how long any one statement may run, enforced by a progress
+//! handler on every connection.
+//!
+//! SQLite calls the progress handler every few thousand virtual machine
+//! instructions, but doesn't say which statement is running, so the handler
+//! looks for the connection's busy statements itself. Each run of a
+//! statement is told apart by its `SQLITE_STMTSTATUS_RUN` counter, so a
+//! cached statement that's run again starts over, and is timed from the
+//! first time the handler sees it. That includes any time spent between
+//! steps, such as while the caller reads the rows a query has returned so
+//! far.
+//!
+//! A connection only has one progress handler, so while an
+//! [`ExecutionContext`](crate::ExecutionContext) is guarding work the
+//! context's handler checks the timer too, and reinstalls this one once the
+//! work is done.
+
+use std::{
+    cell::{Cell, RefCell},
+    os::raw::{c_int, c_void},
+    ptr,
+    time::{Duration, Instant},
+};
+
+use rusqlite::{ffi, Connection};
+
+use crate::deadline::PROGRESS_INTERVAL;
+use crate::ffi_ext::{sqlite3_stmt_busy, STMTSTATUS_RUN};
+
+#[cfg(test)]
+mod tests;
+
+/// The running statements of a connection, and when each started.
+#[derive(Debug)]
+pub(crate) struct StatementTimer {
+    timeout: Duration,
+    // The handle the timer is installed on, kept as an address so the timer
+    // can be sent with its connection.
+    db: Cell<usize>,
+    // The statement, its run counter, and when that run was first seen.
+    running: RefCell<Vec<(usize, c_int, Instant)>>,
+}
+
+impl StatementTimer {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            db: Cell::new(0),
+            running: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Returns true if any statement running on the connection the timer
+    /// is installed on has run for longer than the timeout.
+    ///
+    /// # Safety
+    ///
+    /// This must only be called from a progress handler on that connection.
+    pub(crate) unsafe fn exceeded(&self) -> bool {
+        let db = self.db.get() as *mut ffi::sqlite3;
+        let now = Instant::now();
+        let mut running = self.running.borrow_mut();
+        let mut seen = Vec::with_capacity(running.len());
+        let mut stmt = ffi::sqlite3_next_stmt(db, ptr::null_mut());
+        while !stmt.is_null() {
+            if sqlite3_stmt_busy(stmt) != 0 {
+                let run = ffi::sqlite3_stmt_status(stmt, STMTSTATUS_RUN, 0);
+                let started = running
+                    .iter()
+                    .find(|&&(s, r, _)| s == stmt as usize && r == run)
+                    .map_or(now, |&(_, _, started)| started);
+                seen.push((stmt as usize, run, started));
+            }
+            stmt = ffi::sqlite3_next_stmt(db, stmt);
+        }
+        *running = seen;
+        running
+            .iter()
+            .any(|&(_, _, started)| now.duration_since(started) >= self.timeout)
+    }
+
+    /// Installs the timer's progress handler on `db`, replacing any other.
+    ///
+    /// # Safety
+    ///
+    /// The timer must stay at the same address, and outlive the handler.
+    pub(crate) unsafe fn install(&self, db: *mut ffi::sqlite3) {
+        self.db.set(db as usize);
+        ffi::sqlite3_progress_handler(
+            db,
+            PROGRESS_INTERVAL,
+            Some(progress),
+            self as *const StatementTimer as *mut c_void,
+        );
+    }
+}
+
+/// Installs `timer` on `conn`.
+pub(crate) fn install(conn: &Connection, timer: &StatementTimer) {
+    // Safety: the connection keeps the timer boxed, so its address is
+    // stable, and drops it only after the connection is closed.
+    unsafe { timer.install(conn.handle()) }
+}
+
+/// Removes the timer's progress handler from `conn`, so the connection can
+/// outlive the timer.
+pub(crate) fn uninstall(conn: &Connection) {
+    // Safety: clearing the handler can't fail on an open handle.
+    unsafe {
+        ffi::sqlite3_progress_handler(conn.handle(), 0, None, ptr::null_mut());
+    }
+}
+
+unsafe extern "C" fn progress(timer: *mut c_void) -> c_int {
+    let timer = &*(timer as *const StatementTimer);
+    timer.exceeded() as c_int
+}
@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+use rusqlite::{ErrorCode, NO_PARAMS};
+
+use crate::{
+    tests::{pool, TempDir},
+    Error, ExecutionContext, PoolExt, RusqliteConnectionManager,
+};
+
+/// A query that never finishes on its own.
+const FOREVER: &str =
+    "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c";
+
+fn is_interrupted(e: &rusqlite::Error) -> bool {
+    matches!(e, rusqlite::Error::SqliteFailure(e, _) if e.code == ErrorCode::OperationInterrupted)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn interrupts_statements() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("timeout.db"))
+            .with_statement_timeout(Duration::from_millis(100)),
+        1,
+        "",
+    )
+    .await?;
+    let conn = pool.get().await?;
+
+    let started = Instant::now();
+    let result = conn.query_row(FOREVER, NO_PARAMS, |row| row.get::<_, i64>(0));
+    assert!(
+        matches!(&result, Err(e) if is_interrupted(e)),
+        "{:?}",
+        result
+    );
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // Quick statements still run.
+    let one: i64 = conn.query_row("SELECT 1", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(one, 1);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn times_each_run() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("timeout.db"))
+            .with_statement_timeout(Duration::from_millis(100)),
+        1,
+        "",
+    )
+    .await?;
+    let conn = pool.get().await?;
+
+    // Running the same cached statement again starts its timer over, however
+    // long the runs take between them.
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_millis(300) {
+        let mut stmt = conn.prepare_cached(
+            "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 10000)
+             SELECT count(*) FROM c",
+        )?;
+        let count: i64 = stmt.query_row(NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(count, 10000);
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn within_execution_context() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("timeout.db"))
+            .with_statement_timeout(Duration::from_millis(100)),
+        1,
+        "",
+    )
+    .await?;
+
+    // The statement timeout applies under a longer deadline.
+    let started = Instant::now();
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_secs(30))
+        .scope(pool.query_rows_dynamic(FOREVER, Vec::<i64>::new()))
+        .await;
+    assert!(
+        matches!(&result, Err(Error::Rusqlite(e)) if is_interrupted(e)),
+        "{:?}",
+        result
+    );
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    // And the deadline still applies under a longer statement timeout.
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_millis(20))
+        .scope(pool.query_rows_dynamic(FOREVER, Vec::<i64>::new()))
+        .await;
+    assert!(
+        matches!(result, Err(Error::DeadlineExceeded)),
+        "{:?}",
+        result
+    );
+
+    // The timer is reinstalled once the context's handler is removed.
+    let conn = pool.get().await?;
+    let result = conn.query_row(FOREVER, NO_PARAMS, |row| row.get::<_, i64>(0));
+    assert!(
+        matches!(&result, Err(e) if is_interrupted(e)),
+        "{:?}",
+        result
+    );
+    Ok(())
+}
@@ -0,0 +1,257 @@
+//! Broadcasting committed row changes to subscribers, through
+//! `sqlite3_update_hook()`.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    ffi::CStr,
+    os::raw::{c_char, c_int, c_void},
+    ptr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use rusqlite::{ffi, Connection};
+use tokio::sync::broadcast;
+
+#[cfg(test)]
+mod tests;
+
+/// The kind of change a [`RowChange`] describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowAction {
+    /// The row was inserted.
+    Insert,
+    /// The row was updated.
+    Update,
+    /// The row was deleted.
+    Delete,
+}
+
+/// A committed change to a row, as received from
+/// [`PoolExt::subscribe()`](crate::PoolExt::subscribe).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowChange {
+    /// The kind of change.
+    pub action: RowAction,
+
+    /// The schema the table is in, which is `main` unless the connection has
+    /// attached other databases.
+    pub database: String,
+
+    /// The table that was changed.
+    pub table: String,
+
+    /// The rowid of the row that was changed.
+    pub rowid: i64,
+}
+
+/// The subscribers to each table, shared by every clone of a manager.
+#[derive(Debug)]
+pub(crate) struct Hub {
+    capacity: usize,
+    // Keyed by lowercased table name, since SQLite's names aren't case
+    // sensitive.
+    tables: Mutex<HashMap<String, broadcast::Sender<RowChange>>>,
+    // Bumped whenever a table gains its first subscriber or loses its last,
+    // so that connections know to refresh their copy of the table names
+    // without locking the map for every row they change.
+    version: AtomicUsize,
+    // The number of tables in the map, so that connections can tell whether
+    // anyone is subscribed without locking it.
+    subscribed: AtomicUsize,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl Hub {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            tables: Mutex::default(),
+            version: AtomicUsize::new(0),
+            subscribed: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn subscribe(&self, table: &str) -> broadcast::Receiver<RowChange> {
+        let mut tables = self.tables();
+        let receiver = match tables.entry(table.to_ascii_lowercase()) {
+            Entry::Occupied(entry) => entry.get().subscribe(),
+            Entry::Vacant(entry) => {
+                self.version.fetch_add(1, Ordering::SeqCst);
+                entry
+                    .insert(broadcast::channel(self.capacity).0)
+                    .subscribe()
+            }
+        };
+        self.subscribed.store(tables.len(), Ordering::SeqCst);
+        receiver
+    }
+
+    /// Returns true if any table has subscribers, in which case connections
+    /// need to track their changes.
+    pub(crate) fn has_subscribers(&self) -> bool {
+        self.subscribed.load(Ordering::SeqCst) > 0
+    }
+
+    fn tables(&self) -> MutexGuard<'_, HashMap<String, broadcast::Sender<RowChange>>> {
+        // Nothing panics while holding the lock, but this is called from
+        // SQLite's callbacks, which mustn't panic regardless.
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the names of the tables with subscribers, and the version
+    /// they're current as of.
+    fn table_names(&self) -> (usize, Vec<String>) {
+        let tables = self.tables();
+        (
+            self.version.load(Ordering::SeqCst),
+            tables.keys().cloned().collect(),
+        )
+    }
+
+    fn send(&self, changes: Vec<RowChange>) {
+        let mut tables = self.tables();
+        for change in changes {
+            let key = change.table.to_ascii_lowercase();
+            if let Some(sender) = tables.get(&key) {
+                // Sending only fails once every receiver has been dropped.
+                if sender.send(change).is_err() {
+                    tables.remove(&key);
+                    self.version.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+        self.subscribed.store(tables.len(), Ordering::SeqCst);
+    }
+}
+
+/// The changes made on a connection, which are sent to subscribers once
+/// their transaction has committed, and discarded if it rolls back.
+#[derive(Debug)]
+pub(crate) struct Pending {
+    hub: Arc<Hub>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // The tables with subscribers, as of the hub's version.
+    version: Option<usize>,
+    tables: Vec<String>,
+    // Changes made by the transaction in progress.
+    current: Vec<RowChange>,
+    // Changes made by a transaction that's committing, but may yet fail to.
+    committing: Vec<RowChange>,
+}
+
+impl Pending {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sets the transaction's changes aside until it's known whether it
+    /// committed. This is called by the commit hook.
+    pub(crate) fn commit(&self) {
+        let mut state = self.state();
+        let mut current = std::mem::take(&mut state.current);
+        state.committing.append(&mut current);
+    }
+
+    /// Sends the committed changes to subscribers, once the commit has
+    /// finished.
+    pub(crate) fn publish(&self) {
+        let changes = std::mem::take(&mut self.state().committing);
+        if !changes.is_empty() {
+            self.hub.send(changes);
+        }
+    }
+
+    /// Discards the changes. This is called by the rollback hook.
+    pub(crate) fn discard(&self) {
+        let mut state = self.state();
+        state.current.clear();
+        state.committing.clear();
+    }
+}
+
+/// Installs the update hook on `conn`, collecting the changes to tables in
+/// `hub` for the commit hook to publish, or the rollback hook to discard.
+pub(crate) fn install(conn: &Connection, hub: Arc<Hub>) -> Registration {
+    let pending = Arc::new(Pending {
+        hub,
+        state: Mutex::default(),
+    });
+    let context = Arc::as_ptr(&pending) as *mut c_void;
+    // Safety: the registration keeps the context alive until it's dropped,
+    // which happens after the connection is closed, or after uninstall() has
+    // removed the hooks.
+    unsafe {
+        ffi::sqlite3_update_hook(conn.handle(), Some(changed), context);
+    }
+    Registration { pending }
+}
+
+#[derive(Debug)]
+pub(crate) struct Registration {
+    pending: Arc<Pending>,
+}
+
+impl Registration {
+    /// Returns the changes for the commit hook to publish.
+    pub(crate) fn pending(&self) -> Arc<Pending> {
+        self.pending.clone()
+    }
+
+    /// Removes the hook from `conn`, so the connection can outlive the
+    /// registration.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: clearing the hook can't fail on an open handle.
+        unsafe {
+            ffi::sqlite3_update_hook(conn.handle(), None, ptr::null_mut());
+        }
+    }
+}
+
+unsafe extern "C" fn changed(
+    context: *mut c_void,
+    op: c_int,
+    database: *const c_char,
+    table: *const c_char,
+    rowid: i64,
+) {
+    let pending = &*(context as *const Pending);
+    let mut state = pending.state();
+    let version = pending.hub.version.load(Ordering::SeqCst);
+    if state.version != Some(version) {
+        let (version, tables) = pending.hub.table_names();
+        state.version = Some(version);
+        state.tables = tables;
+    }
+    let table = CStr::from_ptr(table).to_string_lossy();
+    if !state
+        .tables
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(&table))
+    {
+        return;
+    }
+    let action = match op {
+        ffi::SQLITE_INSERT => RowAction::Insert,
+        ffi::SQLITE_UPDATE => RowAction::Update,
+        ffi::SQLITE_DELETE => RowAction::Delete,
+        _ => return,
+    };
+    state.current.push(RowChange {
+        action,
+        database: CStr::from_ptr(database).to_string_lossy().into_owned(),
+        table: table.into_owned(),
+        rowid,
+    });
+}
@@ -0,0 +1,153 @@
+use rusqlite::NO_PARAMS;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    PoolExt, RusqliteConnectionManager,
+};
+
+const SETUP: &str = "CREATE TABLE orders (id INTEGER PRIMARY KEY, total INTEGER);
+                    CREATE TABLE other (id INTEGER PRIMARY KEY);";
+
+fn change(action: RowAction, rowid: i64) -> RowChange {
+    RowChange {
+        action,
+        database: "main".into(),
+        table: "orders".into(),
+        rowid,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn committed() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("subscribe.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+    let mut orders = pool.subscribe("Orders").await?;
+
+    pool.get().await?.execute_batch(
+        "BEGIN;
+         INSERT INTO orders (id, total) VALUES (1, 10), (2, 20);
+         INSERT INTO other (id) VALUES (1);
+         UPDATE orders SET total = 15 WHERE id = 1;
+         DELETE FROM orders WHERE id = 2;
+         COMMIT;",
+    )?;
+    // Autocommitted statements are transactions too.
+    pool.get()
+        .await?
+        .execute("INSERT INTO orders (id, total) VALUES (3, 30)", NO_PARAMS)?;
+
+    for expected in &[
+        change(RowAction::Insert, 1),
+        change(RowAction::Insert, 2),
+        change(RowAction::Update, 1),
+        change(RowAction::Delete, 2),
+        change(RowAction::Insert, 3),
+    ] {
+        assert_eq!(&orders.recv().await?, expected);
+    }
+    assert_eq!(orders.try_recv(), Err(TryRecvError::Empty));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rolled_back() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("subscribe.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+    let mut orders = pool.subscribe("orders").await?;
+
+    let conn = pool.get().await?;
+    conn.execute_batch(
+        "BEGIN;
+         INSERT INTO orders (id, total) VALUES (1, 10);
+         ROLLBACK;",
+    )?;
+    assert_eq!(orders.try_recv(), Err(TryRecvError::Empty));
+
+    // Nothing from the rolled back transaction leaks into the next one, and
+    // rolling back the one after that doesn't take the commit with it.
+    conn.execute("INSERT INTO orders (id, total) VALUES (2, 20)", NO_PARAMS)?;
+    conn.execute_batch(
+        "BEGIN;
+         INSERT INTO orders (id, total) VALUES (3, 30);
+         ROLLBACK;",
+    )?;
+    drop(conn);
+    assert_eq!(orders.recv().await?, change(RowAction::Insert, 2));
+    assert_eq!(orders.try_recv(), Err(TryRecvError::Empty));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_commit() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("subscribe.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+    let mut orders = pool.subscribe("orders").await?;
+
+    // Outside WAL mode, a reader's shared lock keeps a writer from
+    // committing, after SQLite has run the commit hook.
+    let reader = pool.get().await?;
+    let writer = pool.get().await?;
+    writer.execute_batch("PRAGMA busy_timeout = 0")?;
+    for id in 1..=2 {
+        reader.execute_batch("BEGIN; SELECT * FROM orders;")?;
+        writer.execute_batch(&format!(
+            "BEGIN; INSERT INTO orders (id, total) VALUES ({}, 10);",
+            id
+        ))?;
+        writer
+            .execute_batch("COMMIT")
+            .expect_err("the reader holds a shared lock");
+        assert!(!writer.is_autocommit());
+        reader.execute_batch("COMMIT")?;
+        if id == 1 {
+            writer.execute_batch("ROLLBACK")?;
+        } else {
+            writer.execute_batch("COMMIT")?;
+        }
+    }
+    drop(writer);
+    drop(reader);
+
+    // Only the transaction that was committed on retrying is published.
+    assert_eq!(orders.recv().await?, change(RowAction::Insert, 2));
+    assert_eq!(orders.try_recv(), Err(TryRecvError::Empty));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lagged() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("subscribe.db")).with_subscription_capacity(2),
+        2,
+        SETUP,
+    )
+    .await?;
+    let mut orders = pool.subscribe("orders").await?;
+
+    // The writer isn't held up by a receiver that isn't keeping up.
+    pool.get()
+        .await?
+        .execute_batch("INSERT INTO orders (id) VALUES (1), (2), (3), (4), (5);")?;
+    assert_eq!(orders.recv().await, Err(RecvError::Lagged(3)));
+    assert_eq!(orders.recv().await?, change(RowAction::Insert, 4));
+    assert_eq!(orders.recv().await?, change(RowAction::Insert, 5));
+    Ok(())
+}
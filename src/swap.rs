@@ -0,0 +1,111 @@
+//! Switching a running pool to a different database file.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{DatabaseFile, Error, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+/// Swap state, shared by every clone of a manager.
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    swapping: AtomicBool,
+    // Held for reading while connections are opened, and for writing while
+    // the file is swapped, so nothing opens on the old file once the swap
+    // has started.
+    gate: tokio::sync::RwLock<()>,
+}
+
+impl State {
+    /// Returns true if connections shouldn't be handed out, because the
+    /// database file is being swapped.
+    pub(crate) fn refuses_checkout(&self) -> bool {
+        self.swapping.load(Ordering::SeqCst)
+    }
+
+    /// Waits for any swap in progress, and holds off new ones until the
+    /// guard is dropped.
+    pub(crate) async fn opening(&self) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        self.gate.read().await
+    }
+
+    /// Holds off new swaps until the guard is dropped, or returns `None` if
+    /// one is in progress.
+    pub(crate) fn try_opening(&self) -> Option<tokio::sync::RwLockReadGuard<'_, ()>> {
+        self.gate.try_read().ok()
+    }
+}
+
+/// Clears the swapping flag, even if the swap is cancelled.
+struct Swapping<'a>(&'a AtomicBool);
+
+impl Drop for Swapping<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl RusqliteConnectionManager {
+    /// Switches `pool`, which must use this manager (or a clone of it), to
+    /// the database at `path`, returning the path of the previous database.
+    ///
+    /// Unlike [`rotate()`](Self::rotate), this takes the pool out of service
+    /// so that nothing uses the old file once the swap is done: checkouts
+    /// block until the swap is done, or the pool's connection timeout
+    /// expires, and this waits for every checked out connection to be
+    /// returned. Idle connections to the old file are then closed, and a new
+    /// connection is opened on the new file before the swap returns. Idle
+    /// connections are only refused to checkouts that validate them, so
+    /// `test_on_check_out` must be left enabled.
+    ///
+    /// The new database is opened before the pool is taken out of service,
+    /// so a file that can't be opened fails the swap without interrupting
+    /// the pool. As with [`rekey()`](Self::rekey), nothing stops a
+    /// connection being held forever, so wrap this in a timeout if that's a
+    /// possibility; cancelling it before the file is switched leaves the pool
+    /// on the old file.
+    pub async fn swap_database<P>(&self, pool: &bb8::Pool<Self>, path: P) -> Result<PathBuf, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let file = Arc::new(DatabaseFile::new(
+            self.options.path_policy.resolve(path.as_ref()),
+            false,
+        ));
+        // Opening a connection directly, rather than through the pool, checks
+        // the new file without needing a slot.
+        drop(self.open(file.clone()).await?);
+
+        let previous = {
+            let state = &self.swap;
+            let _gate = state.gate.write().await;
+            state.swapping.store(true, Ordering::SeqCst);
+            let _swapping = Swapping(&state.swapping);
+
+            loop {
+                let pool = pool.state();
+                if pool.connections == pool.idle_connections {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            // The previous file isn't retired, since no connections remain
+            // checked out on it, and the idle ones are closed below.
+            std::mem::replace(&mut *self.files.current.write().unwrap(), file)
+        };
+
+        // Checking out a connection discards every idle connection to the
+        // previous file, then opens one on the new file.
+        drop(pool.get().await?);
+        Ok(previous.path.clone())
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Instant;
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use super::*;
+use crate::tests::TempDir;
+
+fn dataset(path: &Path, name: &str) -> Result<(), anyhow::Error> {
+    Connection::open(path)?.execute_batch(&format!(
+        "CREATE TABLE dataset (name TEXT); INSERT INTO dataset VALUES ('{}')",
+        name
+    ))?;
+    Ok(())
+}
+
+async fn name(pool: &bb8::Pool<RusqliteConnectionManager>) -> Result<String, anyhow::Error> {
+    Ok(pool
+        .get()
+        .await?
+        .query_row("SELECT name FROM dataset", NO_PARAMS, |row| row.get(0))?)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn swap_database() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let (blue, green) = (temp.file("blue.db"), temp.file("green.db"));
+    dataset(&blue, "blue")?;
+    dataset(&green, "green")?;
+
+    let manager = RusqliteConnectionManager::new(&blue);
+    let pool = bb8::Pool::builder()
+        .max_size(3)
+        .build(manager.clone())
+        .await?;
+    assert_eq!(name(&pool).await?, "blue");
+
+    // The swap waits for checked out connections to be returned.
+    let held = pool.get().await?;
+    let swap = tokio::spawn({
+        let (manager, pool, green) = (manager.clone(), pool.clone(), green.clone());
+        async move { manager.swap_database(&pool, green).await }
+    });
+    let started = Instant::now();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!swap.is_finished());
+    drop(held);
+
+    assert_eq!(swap.await??, blue);
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    assert_eq!(manager.path(), green);
+    assert!(manager.retired_files().is_empty());
+    assert_eq!(pool.state().connections, 1);
+    assert_eq!(name(&pool).await?, "green");
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unopenable() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("swap.db");
+    dataset(&path, "current")?;
+
+    let manager = RusqliteConnectionManager::new(&path);
+    let pool = bb8::Pool::builder().build(manager.clone()).await?;
+    let result = manager
+        .swap_database(&pool, temp.file("missing/swap.db"))
+        .await;
+    assert!(result.is_err());
+    assert_eq!(manager.path(), path);
+    assert_eq!(name(&pool).await?, "current");
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Spawning of the crate's tasks, with names that tokio-console and the
+//! runtime's task metrics can attribute to this crate.
+//!
+//! Names are only attached when built with `--cfg tokio_unstable`, which is
+//! also what tokio-console needs; otherwise these are plain `tokio::spawn()`
+//! and `tokio::task::spawn_blocking()`. Every name starts with
+//! `bb8_rusqlite::`. The handles to long running tasks return their IDs
+//! from `task_id()`, for finding them in tokio-console and the runtime's task
+//! metrics.
+//!
+//! This is also where blocking SQLite calls are kept off the runtime's
+//! worker threads. WASI has no threads to move them to, and only tokio's
+//! single threaded runtime, so there they run inline on the task instead.
+
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+
+/// Spawns `future` as a task named `name`.
+#[track_caller]
+pub(crate) fn spawn<F>(name: &'static str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(tokio_unstable)]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("task is spawned");
+
+    #[cfg(not(tokio_unstable))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
+
+/// Runs `f` on the blocking thread pool, as a task named `name`.
+#[cfg(not(target_os = "wasi"))]
+#[track_caller]
+pub(crate) fn spawn_blocking<F, T>(name: &'static str, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    #[cfg(tokio_unstable)]
+    return tokio::task::Builder::new()
+        .name(name)
+        .spawn_blocking(f)
+        .expect("task is spawned");
+
+    #[cfg(not(tokio_unstable))]
+    {
+        let _ = name;
+        tokio::task::spawn_blocking(f)
+    }
+}
+
+/// Runs `f` as a task named `name`, which blocks the runtime while it runs.
+#[cfg(target_os = "wasi")]
+#[track_caller]
+pub(crate) fn spawn_blocking<F, T>(name: &'static str, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn(name, async move { f() })
+}
+
+/// Runs `f`, which blocks, without starving the runtime's other tasks. This
+/// needs the multi-threaded runtime, except on WASI.
+pub(crate) fn block_in_place<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    #[cfg(not(target_os = "wasi"))]
+    return tokio::task::block_in_place(f);
+
+    #[cfg(target_os = "wasi")]
+    f()
+}
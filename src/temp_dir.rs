@@ -0,0 +1,250 @@
+//! A shim VFS that places SQLite's temporary files in a specific directory.
+//!
+//! SQLite only allows the temporary directory to be set process wide (through
+//! `SQLITE_TMPDIR` or the deprecated `temp_store_directory` pragma), so to
+//! configure it per pool we register a VFS for each directory in use. The shim
+//! forwards everything to the underlying VFS, except that anonymous files
+//! (which is how SQLite asks for temporary files) are given a name within the
+//! configured directory.
+
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    os::raw::{c_char, c_double, c_int, c_void},
+    path::{Path, PathBuf},
+    ptr,
+    sync::Mutex,
+};
+
+use rusqlite::ffi;
+
+#[cfg(test)]
+mod tests;
+
+/// A version 1 `sqlite3_vfs`. We declare this ourselves rather than use the
+/// bindings, since the bindings' layout depends on which SQLite version they
+/// were generated for, and we always register the shim as version 1.
+#[repr(C)]
+struct Vfs {
+    i_version: c_int,
+    sz_os_file: c_int,
+    mx_pathname: c_int,
+    p_next: *mut Vfs,
+    z_name: *const c_char,
+    p_app_data: *mut c_void,
+    x_open: Option<
+        unsafe extern "C" fn(*mut Vfs, *const c_char, *mut c_void, c_int, *mut c_int) -> c_int,
+    >,
+    x_delete: Option<unsafe extern "C" fn(*mut Vfs, *const c_char, c_int) -> c_int>,
+    x_access: Option<unsafe extern "C" fn(*mut Vfs, *const c_char, c_int, *mut c_int) -> c_int>,
+    x_full_pathname:
+        Option<unsafe extern "C" fn(*mut Vfs, *const c_char, c_int, *mut c_char) -> c_int>,
+    x_dl_open: Option<unsafe extern "C" fn(*mut Vfs, *const c_char) -> *mut c_void>,
+    x_dl_error: Option<unsafe extern "C" fn(*mut Vfs, c_int, *mut c_char)>,
+    x_dl_sym: Option<
+        unsafe extern "C" fn(
+            *mut Vfs,
+            *mut c_void,
+            *const c_char,
+        ) -> Option<unsafe extern "C" fn()>,
+    >,
+    x_dl_close: Option<unsafe extern "C" fn(*mut Vfs, *mut c_void)>,
+    x_randomness: Option<unsafe extern "C" fn(*mut Vfs, c_int, *mut c_char) -> c_int>,
+    x_sleep: Option<unsafe extern "C" fn(*mut Vfs, c_int) -> c_int>,
+    x_current_time: Option<unsafe extern "C" fn(*mut Vfs, *mut c_double) -> c_int>,
+    x_get_last_error: Option<unsafe extern "C" fn(*mut Vfs, c_int, *mut c_char) -> c_int>,
+}
+
+/// The data hung off each shim's `pAppData`.
+struct Shim {
+    real: *mut Vfs,
+    /// The directory, with a trailing separator, as bytes.
+    prefix: Vec<u8>,
+}
+
+/// Shims are keyed by the name of the VFS they wrap and their directory.
+type ShimKey = (Option<String>, PathBuf);
+
+// Registered shims live for the rest of the process, so we only ever hand out
+// their names.
+static SHIMS: Mutex<Option<HashMap<ShimKey, String>>> = Mutex::new(None);
+
+/// Returns the name of a VFS that wraps `base` (or the default VFS), but puts
+/// temporary files in `dir`, registering it if necessary.
+pub(crate) fn vfs_for(base: Option<&str>, dir: &Path) -> Result<String, rusqlite::Error> {
+    let mut shims = SHIMS.lock().unwrap();
+    let shims = shims.get_or_insert_with(HashMap::new);
+    let key = (base.map(String::from), dir.to_path_buf());
+    if let Some(name) = shims.get(&key) {
+        return Ok(name.clone());
+    }
+
+    let name = format!("bb8-rusqlite-tmp-{}", shims.len());
+    let base = base.map(CString::new).transpose().map_err(|_| misuse())?;
+    let mut prefix = dir.to_str().ok_or_else(misuse)?.as_bytes().to_vec();
+    if !prefix.ends_with(&[std::path::MAIN_SEPARATOR as u8]) {
+        prefix.push(std::path::MAIN_SEPARATOR as u8);
+    }
+
+    // Safety: the shim and everything it points to is leaked, since SQLite
+    // holds onto registered VFSes until they're unregistered, which we never
+    // do. The real VFS is likewise never unregistered by us.
+    unsafe {
+        let real =
+            ffi::sqlite3_vfs_find(base.as_ref().map_or(ptr::null(), |b| b.as_ptr())) as *mut Vfs;
+        if real.is_null() {
+            return Err(misuse());
+        }
+
+        // Methods the real VFS doesn't provide are left out of the shim too,
+        // so that SQLite sees exactly the same capabilities.
+        let shim = Box::leak(Box::new(Vfs {
+            i_version: 1,
+            sz_os_file: (*real).sz_os_file,
+            mx_pathname: (*real).mx_pathname,
+            p_next: ptr::null_mut(),
+            z_name: CString::new(name.clone()).unwrap().into_raw(),
+            p_app_data: Box::into_raw(Box::new(Shim { real, prefix })) as *mut c_void,
+            x_open: Some(x_open),
+            x_delete: (*real).x_delete.and(Some(x_delete)),
+            x_access: (*real).x_access.and(Some(x_access)),
+            x_full_pathname: (*real).x_full_pathname.and(Some(x_full_pathname)),
+            x_dl_open: (*real).x_dl_open.and(Some(x_dl_open)),
+            x_dl_error: (*real).x_dl_error.and(Some(x_dl_error)),
+            x_dl_sym: (*real).x_dl_sym.and(Some(x_dl_sym)),
+            x_dl_close: (*real).x_dl_close.and(Some(x_dl_close)),
+            x_randomness: (*real).x_randomness.and(Some(x_randomness)),
+            x_sleep: (*real).x_sleep.and(Some(x_sleep)),
+            x_current_time: (*real).x_current_time.and(Some(x_current_time)),
+            x_get_last_error: (*real).x_get_last_error.and(Some(x_get_last_error)),
+        }));
+
+        let rc = ffi::sqlite3_vfs_register(shim as *mut Vfs as *mut _, 0);
+        if rc != ffi::SQLITE_OK {
+            return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+        }
+    }
+
+    shims.insert(key, name.clone());
+    Ok(name)
+}
+
+fn misuse() -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_MISUSE), None)
+}
+
+unsafe fn shim(vfs: *mut Vfs) -> &'static Shim {
+    &*((*vfs).p_app_data as *const Shim)
+}
+
+unsafe fn real(vfs: *mut Vfs) -> *mut Vfs {
+    shim(vfs).real
+}
+
+unsafe extern "C" fn x_open(
+    vfs: *mut Vfs,
+    name: *const c_char,
+    file: *mut c_void,
+    flags: c_int,
+    out_flags: *mut c_int,
+) -> c_int {
+    let shim = shim(vfs);
+    let real = shim.real;
+    let open = (*real).x_open.unwrap();
+    if !name.is_null() {
+        return open(real, name, file, flags, out_flags);
+    }
+
+    // Temporary file names are double NUL terminated, since SQLite may look
+    // for URI parameters after them. The underlying VFS doesn't hold onto the
+    // name of a delete-on-close file after opening it (SQLite's own temporary
+    // names live on the stack), so this can be dropped once we're done.
+    let mut random = [0u8; 8];
+    ffi::sqlite3_randomness(random.len() as c_int, random.as_mut_ptr() as *mut c_void);
+    let mut path = shim.prefix.clone();
+    path.extend(b"etilqs_");
+    for byte in &random {
+        path.extend(format!("{:02x}", byte).as_bytes());
+    }
+    if path.len() >= (*real).mx_pathname as usize {
+        return ffi::SQLITE_CANTOPEN;
+    }
+    path.extend(&[0, 0]);
+
+    open(
+        real,
+        path.as_ptr() as *const c_char,
+        file,
+        flags | ffi::SQLITE_OPEN_DELETEONCLOSE,
+        out_flags,
+    )
+}
+
+unsafe extern "C" fn x_delete(vfs: *mut Vfs, name: *const c_char, sync: c_int) -> c_int {
+    let real = real(vfs);
+    (*real).x_delete.unwrap()(real, name, sync)
+}
+
+unsafe extern "C" fn x_access(
+    vfs: *mut Vfs,
+    name: *const c_char,
+    flags: c_int,
+    out: *mut c_int,
+) -> c_int {
+    let real = real(vfs);
+    (*real).x_access.unwrap()(real, name, flags, out)
+}
+
+unsafe extern "C" fn x_full_pathname(
+    vfs: *mut Vfs,
+    name: *const c_char,
+    n: c_int,
+    out: *mut c_char,
+) -> c_int {
+    let real = real(vfs);
+    (*real).x_full_pathname.unwrap()(real, name, n, out)
+}
+
+unsafe extern "C" fn x_dl_open(vfs: *mut Vfs, name: *const c_char) -> *mut c_void {
+    let real = real(vfs);
+    (*real).x_dl_open.unwrap()(real, name)
+}
+
+unsafe extern "C" fn x_dl_error(vfs: *mut Vfs, n: c_int, out: *mut c_char) {
+    let real = real(vfs);
+    (*real).x_dl_error.unwrap()(real, n, out)
+}
+
+unsafe extern "C" fn x_dl_sym(
+    vfs: *mut Vfs,
+    handle: *mut c_void,
+    symbol: *const c_char,
+) -> Option<unsafe extern "C" fn()> {
+    let real = real(vfs);
+    (*real).x_dl_sym.unwrap()(real, handle, symbol)
+}
+
+unsafe extern "C" fn x_dl_close(vfs: *mut Vfs, handle: *mut c_void) {
+    let real = real(vfs);
+    (*real).x_dl_close.unwrap()(real, handle)
+}
+
+unsafe extern "C" fn x_randomness(vfs: *mut Vfs, n: c_int, out: *mut c_char) -> c_int {
+    let real = real(vfs);
+    (*real).x_randomness.unwrap()(real, n, out)
+}
+
+unsafe extern "C" fn x_sleep(vfs: *mut Vfs, micros: c_int) -> c_int {
+    let real = real(vfs);
+    (*real).x_sleep.unwrap()(real, micros)
+}
+
+unsafe extern "C" fn x_current_time(vfs: *mut Vfs, out: *mut c_double) -> c_int {
+    let real = real(vfs);
+    (*real).x_current_time.unwrap()(real, out)
+}
+
+unsafe extern "C" fn x_get_last_error(vfs: *mut Vfs, n: c_int, out: *mut c_char) -> c_int {
+    let real = real(vfs);
+    (*real).x_get_last_error.unwrap()(real, n, out)
+}
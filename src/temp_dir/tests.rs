@@ -0,0 +1,39 @@
+use rusqlite::{Connection, OpenFlags, NO_PARAMS};
+
+use super::*;
+use crate::tests::TempDir;
+
+/// Forces SQLite to spill a temporary table to disk.
+fn spill(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "PRAGMA temp_store = FILE;
+         PRAGMA temp.cache_size = 2;
+         CREATE TEMP TABLE t (a BLOB);
+         WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 200)
+         INSERT INTO t SELECT randomblob(4096) FROM n;",
+    )
+}
+
+#[test]
+fn temp_files_go_to_dir() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("temp_dir.db");
+
+    // Pointing temporary files at a missing directory proves they're being
+    // redirected, since the spill then fails.
+    let vfs = vfs_for(None, &temp.file("missing"))?;
+    let conn = Connection::open_with_flags_and_vfs(&path, OpenFlags::default(), &vfs)?;
+    spill(&conn).expect_err("temporary files must be created in the missing directory");
+
+    std::fs::create_dir(temp.file("tmp"))?;
+    let vfs = vfs_for(None, &temp.file("tmp"))?;
+    let conn = Connection::open_with_flags_and_vfs(&path, OpenFlags::default(), &vfs)?;
+    spill(&conn)?;
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(count, 200);
+
+    // The same directory reuses the same shim.
+    assert_eq!(vfs_for(None, &temp.file("tmp"))?, vfs);
+
+    Ok(())
+}
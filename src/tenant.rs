@@ -0,0 +1,521 @@
+//! A pool per tenant, for the "one database per customer" architecture.
+//!
+//! [`TenantPools`] opens a pool for each tenant's database the first time it's
+//! used, in a file named by substituting the tenant's ID into a path
+//! template. Each tenant's pool reserves its full size against a connection
+//! budget shared by every tenant, until it's been evicted and shut down. Once
+//! the budget is spent, the least recently used tenant whose connections are
+//! all idle is evicted to make room.
+//!
+//! With [`TenantPoolsBuilder::with_idle_timeout()`], tenants that go unused
+//! for long enough are evicted too, so that a process serving thousands of
+//! databases only holds files and memory for the ones in active use. Evicted
+//! pools are [shut down](RusqliteConnectionManager::shutdown): the database
+//! is optimized and its WAL checkpointed before the connections are closed.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+use crate::{task, Error, RusqliteConnectionManager, ShutdownOptions, ShutdownReport};
+
+#[cfg(test)]
+mod tests;
+
+type BuilderFactory = Arc<dyn Fn() -> bb8::Builder<RusqliteConnectionManager> + Send + Sync>;
+type ManagerFactory =
+    Arc<dyn Fn(&str, RusqliteConnectionManager) -> RusqliteConnectionManager + Send + Sync>;
+type EvictCallback = Arc<dyn Fn(&str, &ShutdownReport) + Send + Sync>;
+
+/// The placeholder replaced by the tenant's ID in a path template.
+pub const TENANT_PLACEHOLDER: &str = "{tenant}";
+
+/// A builder for [`TenantPools`].
+pub struct TenantPoolsBuilder {
+    template: String,
+    max_connections: u32,
+    connections_per_tenant: u32,
+    pool_builder: BuilderFactory,
+    manager: Option<ManagerFactory>,
+    idle_timeout: Option<Duration>,
+    shutdown: ShutdownOptions,
+    on_evict: Option<EvictCallback>,
+}
+
+impl fmt::Debug for TenantPoolsBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantPoolsBuilder")
+            .field("template", &self.template)
+            .field("max_connections", &self.max_connections)
+            .field("connections_per_tenant", &self.connections_per_tenant)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("shutdown", &self.shutdown)
+            .finish()
+    }
+}
+
+impl TenantPoolsBuilder {
+    /// Sets the number of connections that all tenants' pools may have
+    /// between them. Defaults to 64.
+    pub fn with_max_connections(mut self, connections: u32) -> Self {
+        self.max_connections = connections;
+        self
+    }
+
+    /// Sets the size of each tenant's pool, all of which is reserved against
+    /// the connection budget while the pool is open. Defaults to 4.
+    pub fn with_connections_per_tenant(mut self, connections: u32) -> Self {
+        self.connections_per_tenant = connections.max(1);
+        self
+    }
+
+    /// Sets the function used to create a `bb8::Builder` for each tenant's
+    /// pool, which allows timeouts to be configured. The pool's size is
+    /// always set from
+    /// [`with_connections_per_tenant()`](Self::with_connections_per_tenant).
+    pub fn with_pool_builder<F>(mut self, factory: F) -> Self
+    where
+        F: Fn() -> bb8::Builder<RusqliteConnectionManager> + Send + Sync + 'static,
+    {
+        self.pool_builder = Arc::new(factory);
+        self
+    }
+
+    /// Sets a function to configure each tenant's manager, which is given
+    /// the tenant's ID, such as to set a per-tenant encryption key.
+    pub fn with_manager<F>(mut self, configure: F) -> Self
+    where
+        F: Fn(&str, RusqliteConnectionManager) -> RusqliteConnectionManager + Send + Sync + 'static,
+    {
+        self.manager = Some(Arc::new(configure));
+        self
+    }
+
+    /// Evicts tenants whose pools haven't been looked up for `timeout`, and
+    /// aren't in use. By default, tenants are only evicted
+    /// to make room for others.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the options used to shut down evicted tenants' pools. By
+    /// default, the database is optimized and checkpointed.
+    pub fn with_shutdown_options(mut self, options: ShutdownOptions) -> Self {
+        self.shutdown = options;
+        self
+    }
+
+    /// Sets a callback for when an evicted tenant's pool has been shut down,
+    /// which is given the tenant's ID and the outcome of the shutdown.
+    pub fn on_evict<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&str, &ShutdownReport) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(Arc::new(callback));
+        self
+    }
+
+    /// Builds the tenant pools. No databases are opened until they're used.
+    ///
+    /// If an idle timeout is set, this starts a background task to evict
+    /// idle tenants, which stops once every clone of the pools has been
+    /// dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an idle timeout is set, and this isn't called from within a
+    /// Tokio runtime.
+    pub fn build(self) -> TenantPools {
+        let inner = Arc::new(Inner {
+            template: self.template,
+            max_connections: self.max_connections,
+            connections_per_tenant: self.connections_per_tenant,
+            pool_builder: self.pool_builder,
+            manager: self.manager,
+            shutdown: self.shutdown,
+            on_evict: self.on_evict,
+            tenants: Mutex::default(),
+            budget: Arc::default(),
+            evict_task: OnceLock::new(),
+        });
+        if let Some(timeout) = self.idle_timeout {
+            let evict = task::spawn(
+                "bb8_rusqlite::tenant::evict",
+                evict_loop(Arc::downgrade(&inner), timeout),
+            );
+            let _ = inner.evict_task.set(evict.id());
+        }
+        TenantPools(inner)
+    }
+}
+
+/// Lazily opened pools, one per tenant database.
+///
+/// Cloning `TenantPools` is cheap, and clones share the same pools.
+///
+/// A tenant is in use, and can't be evicted, while it has connections
+/// checked out, or a [`TenantPool`] returned by [`pool()`](Self::pool)
+/// hasn't been dropped. Look pools up for each unit of work, rather than
+/// holding on to them, so that idle tenants can make room for others.
+#[derive(Clone, Debug)]
+pub struct TenantPools(Arc<Inner>);
+
+impl TenantPools {
+    /// Creates a builder for pools over the files named by `template`, in
+    /// which [`TENANT_PLACEHOLDER`] is replaced by each tenant's ID.
+    pub fn builder<T>(template: T) -> TenantPoolsBuilder
+    where
+        T: Into<String>,
+    {
+        TenantPoolsBuilder {
+            template: template.into(),
+            max_connections: 64,
+            connections_per_tenant: 4,
+            pool_builder: Arc::new(bb8::Pool::builder),
+            manager: None,
+            idle_timeout: None,
+            shutdown: ShutdownOptions::default(),
+            on_evict: None,
+        }
+    }
+
+    /// Checks out a connection to `tenant`'s database, opening its pool if
+    /// needed.
+    ///
+    /// Unlike [`pool()`](Self::pool), if the connection budget is held by
+    /// evicted tenants whose pools are still shutting down, this waits for
+    /// them to finish.
+    pub async fn get(
+        &self,
+        tenant: &str,
+    ) -> Result<bb8::PooledConnection<'static, RusqliteConnectionManager>, Error> {
+        // The tenant can't be evicted while its connection is being checked
+        // out, before the pool counts it as in use.
+        let (pool, checkout) = loop {
+            // Created before the budget is checked, so that a shutdown
+            // finishing in between isn't missed.
+            let released = self.0.budget.released.notified();
+            let opened = {
+                let mut tenants = self.0.tenants.lock().unwrap();
+                match self.0.open(&mut tenants, tenant) {
+                    Ok(open) => Some((open.pool.clone(), Checkout::new(&open.checkouts))),
+                    Err(Error::TenantBudgetExhausted) => {
+                        if !self.0.closing(&tenants) {
+                            return Err(Error::TenantBudgetExhausted);
+                        }
+                        None
+                    }
+                    Err(e) => return Err(e),
+                }
+            };
+            match opened {
+                Some(opened) => break opened,
+                None => released.await,
+            }
+        };
+        let conn = pool.get_owned().await;
+        drop(checkout);
+        Ok(conn?)
+    }
+
+    /// Returns `tenant`'s pool, opening it if needed.
+    ///
+    /// Tenant IDs may only contain ASCII letters, digits, `-`, `_`, and `.`,
+    /// and mustn't start with `.`, so they can't escape the directory in the
+    /// template; other IDs fail with [`Error::InvalidTenant`]. If opening the
+    /// pool would exceed the connection budget, and no tenant is idle enough
+    /// to evict, this fails with [`Error::TenantBudgetExhausted`]. That
+    /// includes while evicted tenants' pools are shutting down, since their
+    /// connections count against the budget until they've been closed.
+    ///
+    /// The tenant can't be evicted until the returned pool is dropped.
+    pub fn pool(&self, tenant: &str) -> Result<TenantPool, Error> {
+        let mut tenants = self.0.tenants.lock().unwrap();
+        let open = self.0.open(&mut tenants, tenant)?;
+        Ok(TenantPool {
+            pool: open.pool.clone(),
+            in_use: Checkout::new(&open.checkouts),
+        })
+    }
+
+    /// Evicts `tenant`, if its pool is open, returning true if it was. The
+    /// pool is shut down in the background, and its connections count
+    /// against the budget until it has.
+    ///
+    /// A tenant that's in use isn't evicted: this fails with
+    /// [`Error::TenantInUse`] instead.
+    pub fn evict(&self, tenant: &str) -> Result<bool, Error> {
+        let mut tenants = self.0.tenants.lock().unwrap();
+        match tenants.pools.get(tenant) {
+            Some(open) if !open.is_idle() => Err(Error::TenantInUse(tenant.to_string())),
+            Some(_) => {
+                let evicted = tenants.pools.remove(tenant).unwrap();
+                self.0.close(tenant.to_string(), evicted);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the IDs of the tenants whose pools are open.
+    pub fn tenants(&self) -> Vec<String> {
+        self.0
+            .tenants
+            .lock()
+            .unwrap()
+            .pools
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the ID of the background task evicting idle tenants, if
+    /// [`with_idle_timeout()`](TenantPoolsBuilder::with_idle_timeout) was
+    /// set.
+    pub fn evict_task_id(&self) -> Option<tokio::task::Id> {
+        self.0.evict_task.get().copied()
+    }
+}
+
+/// A tenant's pool, returned by [`TenantPools::pool()`], which keeps the
+/// tenant in use until it's dropped.
+///
+/// This dereferences to the `bb8::Pool`. Clones of that don't keep the tenant
+/// in use, whereas clones of this do.
+#[derive(Debug)]
+pub struct TenantPool {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    in_use: Checkout,
+}
+
+impl Clone for TenantPool {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            in_use: Checkout::new(&self.in_use.0),
+        }
+    }
+}
+
+impl Deref for TenantPool {
+    type Target = bb8::Pool<RusqliteConnectionManager>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+fn valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && !tenant.starts_with('.')
+        && tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+struct Inner {
+    template: String,
+    max_connections: u32,
+    connections_per_tenant: u32,
+    pool_builder: BuilderFactory,
+    manager: Option<ManagerFactory>,
+    shutdown: ShutdownOptions,
+    on_evict: Option<EvictCallback>,
+    tenants: Mutex<Tenants>,
+    budget: Arc<Budget>,
+    evict_task: OnceLock<tokio::task::Id>,
+}
+
+impl Inner {
+    /// Returns `tenant`'s pool from `tenants`, which is locked, opening it if
+    /// needed.
+    fn open<'a>(&self, tenants: &'a mut Tenants, tenant: &str) -> Result<&'a mut Tenant, Error> {
+        if !valid_tenant(tenant) {
+            return Err(Error::InvalidTenant(tenant.to_string()));
+        }
+
+        tenants.clock += 1;
+        let now = tenants.clock;
+        // Looking the tenant up twice keeps the borrow checker from holding
+        // the map borrowed for the rest of the function.
+        if tenants.pools.contains_key(tenant) {
+            let open = tenants.pools.get_mut(tenant).unwrap();
+            open.used = now;
+            open.used_at = Instant::now();
+            return Ok(open);
+        }
+
+        while (tenants.pools.len() as u32 + 1) * self.connections_per_tenant > self.max_connections
+        {
+            let idle = tenants
+                .pools
+                .iter()
+                .filter(|(_, open)| open.is_idle())
+                .min_by_key(|(_, open)| open.used)
+                .map(|(tenant, _)| tenant.clone());
+            match idle {
+                Some(idle) => {
+                    let evicted = tenants.pools.remove(&idle).unwrap();
+                    self.close(idle, evicted);
+                }
+                None => return Err(Error::TenantBudgetExhausted),
+            }
+        }
+
+        // Evicted tenants hold on to their share until they've shut down.
+        if self.budget.reserved.load(Ordering::SeqCst) + self.connections_per_tenant
+            > self.max_connections
+        {
+            return Err(Error::TenantBudgetExhausted);
+        }
+        self.budget
+            .reserved
+            .fetch_add(self.connections_per_tenant, Ordering::SeqCst);
+
+        let manager =
+            RusqliteConnectionManager::new(self.template.replace(TENANT_PLACEHOLDER, tenant));
+        let manager = match &self.manager {
+            Some(configure) => configure(tenant, manager),
+            None => manager,
+        };
+        let pool = (self.pool_builder)()
+            .max_size(self.connections_per_tenant)
+            .build_unchecked(manager.clone());
+        Ok(tenants.pools.entry(tenant.to_string()).or_insert(Tenant {
+            pool,
+            manager,
+            used: now,
+            used_at: Instant::now(),
+            checkouts: Arc::default(),
+        }))
+    }
+
+    /// Returns true if some of the connection budget is held by evicted
+    /// tenants whose pools are still shutting down.
+    fn closing(&self, tenants: &Tenants) -> bool {
+        self.budget.reserved.load(Ordering::SeqCst)
+            > tenants.pools.len() as u32 * self.connections_per_tenant
+    }
+
+    /// Shuts down an evicted tenant's pool in the background, releasing its
+    /// share of the connection budget once it has.
+    fn close(&self, id: String, tenant: Tenant) {
+        let options = self.shutdown.clone();
+        let on_evict = self.on_evict.clone();
+        let budget = self.budget.clone();
+        let connections = self.connections_per_tenant;
+        task::spawn("bb8_rusqlite::tenant::shutdown", async move {
+            let report = tenant.manager.shutdown(tenant.pool, options).await;
+            budget.reserved.fetch_sub(connections, Ordering::SeqCst);
+            budget.released.notify_waiters();
+            if let Some(callback) = on_evict {
+                callback(&id, &report);
+            }
+        });
+    }
+
+    /// Evicts every tenant that has been idle for `timeout`.
+    fn evict_idle(&self, timeout: Duration) {
+        let mut tenants = self.tenants.lock().unwrap();
+        let idle: Vec<_> = tenants
+            .pools
+            .iter()
+            .filter(|(_, open)| open.used_at.elapsed() >= timeout && open.is_idle())
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in idle {
+            let evicted = tenants.pools.remove(&id).unwrap();
+            self.close(id, evicted);
+        }
+    }
+}
+
+async fn evict_loop(inner: Weak<Inner>, timeout: Duration) {
+    // Checking twice per timeout evicts tenants within half a timeout of
+    // them becoming idle.
+    let interval = (timeout / 2).max(Duration::from_millis(10));
+    loop {
+        tokio::time::sleep(interval).await;
+        match inner.upgrade() {
+            Some(inner) => inner.evict_idle(timeout),
+            None => return,
+        }
+    }
+}
+
+impl fmt::Debug for Inner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inner")
+            .field("template", &self.template)
+            .field("max_connections", &self.max_connections)
+            .field("connections_per_tenant", &self.connections_per_tenant)
+            .field("shutdown", &self.shutdown)
+            .field("tenants", &self.tenants)
+            .field("budget", &self.budget)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tenants {
+    pools: HashMap<String, Tenant>,
+    // Incremented on every lookup, to order tenants by when they were last
+    // used.
+    clock: u64,
+}
+
+/// The connections reserved by tenants' pools.
+#[derive(Debug, Default)]
+struct Budget {
+    reserved: AtomicU32,
+    // Notified as evicted pools finish shutting down.
+    released: Notify,
+}
+
+#[derive(Debug)]
+struct Tenant {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    manager: RusqliteConnectionManager,
+    used: u64,
+    used_at: Instant,
+    // The get() calls checking out a connection, and the pools returned by
+    // pool() that haven't been dropped.
+    checkouts: Arc<AtomicUsize>,
+}
+
+impl Tenant {
+    /// Returns true if none of the tenant's connections are checked out, or
+    /// being checked out, and none of its pools are held.
+    fn is_idle(&self) -> bool {
+        let state = self.pool.state();
+        self.checkouts.load(Ordering::SeqCst) == 0 && state.connections == state.idle_connections
+    }
+}
+
+/// A connection being checked out from a tenant's pool, or a pool that's
+/// held, which keeps the tenant in use until it's dropped.
+#[derive(Debug)]
+struct Checkout(Arc<AtomicUsize>);
+
+impl Checkout {
+    fn new(checkouts: &Arc<AtomicUsize>) -> Self {
+        checkouts.fetch_add(1, Ordering::SeqCst);
+        Self(checkouts.clone())
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,240 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::tests::TempDir;
+
+fn template(temp: &TempDir) -> String {
+    temp.file("tenant-{tenant}.db")
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn separate_databases() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let tenants = TenantPools::builder(template(&temp))
+        .with_manager(|tenant, manager| {
+            let application_id = if tenant == "acme" { 1 } else { 2 };
+            manager.with_application_id(application_id)
+        })
+        .build();
+
+    tenants
+        .get("acme")
+        .await?
+        .execute_batch("CREATE TABLE t (a); INSERT INTO t VALUES (1);")?;
+    tenants
+        .get("initech")
+        .await?
+        .execute_batch("CREATE TABLE t (a);")?;
+
+    let count = |conn: &rusqlite::Connection| {
+        conn.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| {
+            row.get::<_, i64>(0)
+        })
+    };
+    assert_eq!(count(&*tenants.get("acme").await?)?, 1);
+    assert_eq!(count(&*tenants.get("initech").await?)?, 0);
+    assert!(temp.file("tenant-acme.db").exists());
+    assert!(temp.file("tenant-initech.db").exists());
+
+    let application_id = |conn: &rusqlite::Connection| {
+        conn.query_row("PRAGMA application_id", NO_PARAMS, |row| {
+            row.get::<_, i32>(0)
+        })
+    };
+    assert_eq!(application_id(&*tenants.get("acme").await?)?, 1);
+    assert_eq!(application_id(&*tenants.get("initech").await?)?, 2);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn invalid_tenant() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let tenants = TenantPools::builder(template(&temp)).build();
+    for tenant in &["", "../escape", "a/b", ".hidden", "a b"] {
+        assert!(
+            matches!(tenants.pool(tenant), Err(Error::InvalidTenant(ref id)) if id == tenant),
+            "{:?} should be rejected",
+            tenant
+        );
+    }
+    assert!(tenants.pool("customer-1.v2_eu").is_ok());
+
+    // Without an idle timeout, there's no eviction task.
+    assert!(tenants.evict_task_id().is_none());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn budget() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let tenants = TenantPools::builder(template(&temp))
+        .with_max_connections(5)
+        .with_connections_per_tenant(2)
+        .build();
+
+    let a = tenants.get("a").await?;
+    let b = tenants.get("b").await?;
+    assert!(matches!(
+        tenants.pool("c"),
+        Err(Error::TenantBudgetExhausted)
+    ));
+
+    // Once both are idle, the least recently used is evicted.
+    drop((a, b));
+    tenants.get("a").await?;
+    tenants.get("c").await?;
+    let mut open = tenants.tenants();
+    open.sort();
+    assert_eq!(open, vec!["a", "c"]);
+
+    assert!(tenants.evict("a")?);
+    assert!(!tenants.evict("a")?);
+    assert_eq!(tenants.tenants(), vec!["c"]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn checkout_in_progress() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let tenants = TenantPools::builder(template(&temp))
+        .with_max_connections(2)
+        .with_connections_per_tenant(2)
+        .with_idle_timeout(Duration::from_millis(20))
+        .build();
+
+    // A get() that has looked the tenant up, but not yet checked out a
+    // connection, keeps the tenant from being evicted for another, or for
+    // being idle.
+    let checkout = {
+        let mut open = tenants.0.tenants.lock().unwrap();
+        let a = tenants.0.open(&mut open, "a")?;
+        Checkout::new(&a.checkouts)
+    };
+    assert!(matches!(
+        tenants.pool("b"),
+        Err(Error::TenantBudgetExhausted)
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(tenants.tenants(), vec!["a"]);
+
+    drop(checkout);
+    tenants.get("b").await?;
+    assert_eq!(tenants.tenants(), vec!["b"]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn idle_timeout() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let evicted = Arc::new(Mutex::new(Vec::new()));
+    let tenants = TenantPools::builder(template(&temp))
+        .with_idle_timeout(Duration::from_millis(50))
+        .on_evict({
+            let evicted = evicted.clone();
+            move |tenant, report| {
+                assert!(report.errors.is_empty());
+                evicted
+                    .lock()
+                    .unwrap()
+                    .push((tenant.to_string(), report.closed));
+            }
+        })
+        .build();
+    assert!(tenants.evict_task_id().is_some());
+
+    for tenant in &["idle", "busy"] {
+        let conn = tenants.get(tenant).await?;
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+        conn.execute_batch("CREATE TABLE t (a); INSERT INTO t VALUES (1);")?;
+    }
+    let busy = tenants.get("busy").await?;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // Only the tenant without connections checked out is evicted, and its
+    // WAL is checkpointed and removed as its connections are closed.
+    assert_eq!(tenants.tenants(), vec!["busy"]);
+    assert_eq!(*evicted.lock().unwrap(), vec![("idle".to_string(), 1)]);
+    assert!(temp.file("tenant-idle.db").exists());
+    assert!(!temp.file("tenant-idle.db-wal").exists());
+
+    // The tenant's pool is reopened the next time it's used.
+    let count: i64 =
+        tenants
+            .get("idle")
+            .await?
+            .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(count, 1);
+    drop(busy);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn evict_while_saturated() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let tenants = TenantPools::builder(template(&temp))
+        .with_max_connections(2)
+        .with_connections_per_tenant(2)
+        .build();
+    tenants
+        .get("a")
+        .await?
+        .execute_batch("CREATE TABLE t (a)")?;
+
+    // Holding a lock on a's database keeps its pool shutting down, and its
+    // connections open, after it's evicted.
+    let lock = rusqlite::Connection::open(temp.file("tenant-a.db"))?;
+    lock.execute_batch("BEGIN EXCLUSIVE")?;
+    assert!(tenants.evict("a")?);
+    assert!(matches!(
+        tenants.pool("b"),
+        Err(Error::TenantBudgetExhausted)
+    ));
+    let b = tokio::spawn({
+        let tenants = tenants.clone();
+        async move { tenants.get("b").await.map(drop) }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!b.is_finished());
+    assert!(tenants.tenants().is_empty());
+
+    // Once the shutdown has finished, b's pool can be opened.
+    lock.execute_batch("COMMIT")?;
+    tokio::time::timeout(Duration::from_secs(5), b).await???;
+    assert_eq!(tenants.tenants(), vec!["b"]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn in_use() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let tenants = TenantPools::builder(template(&temp))
+        .with_max_connections(2)
+        .with_connections_per_tenant(2)
+        .with_idle_timeout(Duration::from_millis(20))
+        .build();
+
+    // A held pool keeps its tenant from being evicted, whether explicitly,
+    // for another tenant, or for being idle.
+    let pool = tenants.pool("a")?;
+    let clone = pool.clone();
+    drop(pool);
+    assert!(matches!(tenants.evict("a"), Err(Error::TenantInUse(ref id)) if id == "a"));
+    assert!(matches!(
+        tenants.pool("b"),
+        Err(Error::TenantBudgetExhausted)
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(tenants.tenants(), vec!["a"]);
+
+    // As does a checked out connection.
+    let conn = clone.get_owned().await?;
+    drop(clone);
+    assert!(matches!(tenants.evict("a"), Err(Error::TenantInUse(_))));
+    drop(conn);
+    assert!(tenants.evict("a")?);
+    tenants.get("b").await?;
+    assert_eq!(tenants.tenants(), vec!["b"]);
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Isolated databases for tests, copied from a template.
+
+use std::{
+    fmt, fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bytes::{self, TemporaryFile},
+    replication, task, Error, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+type ManagerConfig = Box<dyn FnOnce(RusqliteConnectionManager) -> RusqliteConnectionManager + Send>;
+
+/// A builder for [`TestPool`]s.
+pub struct TestPoolBuilder {
+    template: PathBuf,
+    pool_builder: bb8::Builder<RusqliteConnectionManager>,
+    manager: Option<ManagerConfig>,
+}
+
+impl fmt::Debug for TestPoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestPoolBuilder")
+            .field("template", &self.template)
+            .field("pool_builder", &self.pool_builder)
+            .finish()
+    }
+}
+
+impl TestPoolBuilder {
+    /// Sets the `bb8::Builder` used to build the pool.
+    pub fn with_pool_builder(mut self, builder: bb8::Builder<RusqliteConnectionManager>) -> Self {
+        self.pool_builder = builder;
+        self
+    }
+
+    /// Sets a function to configure the pool's manager.
+    pub fn with_manager<F>(mut self, configure: F) -> Self
+    where
+        F: FnOnce(RusqliteConnectionManager) -> RusqliteConnectionManager + Send + 'static,
+    {
+        self.manager = Some(Box::new(configure));
+        self
+    }
+
+    /// Copies the template and builds a pool over the copy.
+    pub async fn build(self) -> Result<TestPool, Error> {
+        let path = task::spawn_blocking("bb8_rusqlite::testing::copy_template", {
+            let template = self.template;
+            move || copy(&template)
+        })
+        .await??;
+
+        let manager = RusqliteConnectionManager::new(&path).with_temporary_file(path.clone());
+        let manager = match self.manager {
+            Some(configure) => configure(manager),
+            None => manager,
+        };
+        Ok(TestPool {
+            pool: self.pool_builder.build(manager).await?,
+            path,
+        })
+    }
+}
+
+/// A pool over a private copy of a template database, for tests.
+///
+/// Running migrations for every test is slow; copying a database that has
+/// already been migrated is not. Each `TestPool` gets its own copy in the
+/// system's temporary directory, so tests can run in parallel without
+/// seeing each other's writes. The copy is removed once the pool and its
+/// connections have been dropped.
+///
+/// This derefs to the underlying `bb8::Pool`.
+#[derive(Debug)]
+pub struct TestPool {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    path: PathBuf,
+}
+
+impl TestPool {
+    /// Builds a pool with the default settings over a copy of the database
+    /// at `template`, which shouldn't be written to while it's copied.
+    pub async fn from_template<P>(template: P) -> Result<Self, Error>
+    where
+        P: AsRef<Path>,
+    {
+        Self::builder(template).build().await
+    }
+
+    /// Creates a builder for a pool over a copy of the database at
+    /// `template`.
+    pub fn builder<P>(template: P) -> TestPoolBuilder
+    where
+        P: AsRef<Path>,
+    {
+        TestPoolBuilder {
+            template: template.as_ref().into(),
+            pool_builder: bb8::Pool::builder(),
+            manager: None,
+        }
+    }
+
+    /// Returns the path of the copy.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Unwraps the underlying pool. The copy is still removed once the pool
+    /// has been dropped.
+    pub fn into_inner(self) -> bb8::Pool<RusqliteConnectionManager> {
+        self.pool
+    }
+}
+
+impl Deref for TestPool {
+    type Target = bb8::Pool<RusqliteConnectionManager>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+/// Copies `template` to a new temporary path, along with its WAL, since
+/// frames that haven't been checkpointed are part of the database too.
+fn copy(template: &Path) -> Result<PathBuf, Error> {
+    let path = bytes::temporary_path();
+    let temporary = TemporaryFile::new(path.clone());
+    fs::copy(template, &path)?;
+    let wal = replication::wal_path(template);
+    if wal.exists() {
+        fs::copy(wal, replication::wal_path(&path))?;
+    }
+    Ok(temporary.keep())
+}
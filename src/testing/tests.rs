@@ -0,0 +1,61 @@
+use rusqlite::{Connection, NO_PARAMS};
+
+use super::*;
+use crate::{tests::TempDir, PragmaCustomizer};
+
+fn template(temp: &TempDir) -> Result<(PathBuf, Connection), anyhow::Error> {
+    let path = temp.file("template.db");
+    let conn = Connection::open(&path)?;
+    conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+    conn.execute_batch(
+        "CREATE TABLE users (name TEXT);
+         INSERT INTO users VALUES ('template');",
+    )?;
+    Ok((path, conn))
+}
+
+fn users(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row("SELECT COUNT(*) FROM users", NO_PARAMS, |row| row.get(0))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn isolated() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    // The template's connection stays open, so its writes are still in the
+    // WAL, and have to be copied from there.
+    let (template, template_conn) = template(&temp)?;
+
+    let a = TestPool::from_template(&template).await?;
+    let b = TestPool::builder(&template)
+        .with_pool_builder(bb8::Pool::builder().max_size(1))
+        .with_manager(|manager| {
+            manager.with_pragmas(PragmaCustomizer::new().pragma("cache_size", -1234))
+        })
+        .build()
+        .await?;
+    assert_ne!(a.path(), b.path());
+
+    a.get()
+        .await?
+        .execute("INSERT INTO users VALUES ('a')", NO_PARAMS)?;
+    assert_eq!(users(&*a.get().await?)?, 2);
+    assert_eq!(users(&*b.get().await?)?, 1);
+    assert_eq!(users(&template_conn)?, 1);
+    let cache_size: i64 = b
+        .get()
+        .await?
+        .query_row("PRAGMA cache_size", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(cache_size, -1234);
+
+    let path = a.path().to_path_buf();
+    drop(a);
+    for _ in 0..100 {
+        if !path.exists() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert!(!path.exists());
+    assert!(!replication::wal_path(&path).exists());
+    Ok(())
+}
@@ -1,24 +1,39 @@
 use futures::future::join_all;
-use rusqlite::NO_PARAMS;
+use rusqlite::{Connection, NO_PARAMS};
 use tempfile::tempdir;
 
 use super::*;
 
 #[derive(Debug)]
-struct TempDir {
+pub(crate) struct TempDir {
     dir: tempfile::TempDir,
 }
 
 impl TempDir {
-    fn new() -> anyhow::Result<Self> {
+    pub(crate) fn new() -> anyhow::Result<Self> {
         Ok(Self { dir: tempdir()? })
     }
 
-    fn file(&self, name: &str) -> PathBuf {
+    pub(crate) fn file(&self, name: &str) -> PathBuf {
         self.dir.path().join(name)
     }
 }
 
+/// Builds a pool of up to `max_size` connections from `manager`, and runs
+/// `setup` on one of them.
+pub(crate) async fn pool(
+    manager: RusqliteConnectionManager,
+    max_size: u32,
+    setup: &str,
+) -> Result<bb8::Pool<RusqliteConnectionManager>, anyhow::Error> {
+    let pool = bb8::Pool::builder()
+        .max_size(max_size)
+        .build(manager)
+        .await?;
+    pool.get().await?.execute_batch(setup)?;
+    Ok(pool)
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn connect_error() -> Result<(), anyhow::Error> {
     let temp = TempDir::new()?;
@@ -94,3 +109,624 @@ async fn plain() -> Result<(), anyhow::Error> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn rotate() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let first = temp.file("events-1.db");
+    let second = temp.file("events-2.db");
+    let manager = RusqliteConnectionManager::new(&first);
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(manager.clone())
+        .await?;
+
+    let held = pool.get().await?;
+    held.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+
+    manager.rotate(&second);
+    assert_eq!(manager.path(), second);
+
+    // New checkouts must be on the new file, even though an idle connection to
+    // the old file may still be in the pool.
+    for _ in 0..2 {
+        let conn = pool.get().await?;
+        assert_eq!(replication::main_database_path(&conn)?, second);
+    }
+
+    // The held connection still works, but keeps the old file from draining.
+    held.execute("INSERT INTO t (a) VALUES (?)", [42])?;
+    assert_eq!(
+        manager.retired_files(),
+        vec![RetiredFile {
+            path: first.clone(),
+            open_connections: 1
+        }]
+    );
+    assert!(manager.take_drained_files().is_empty());
+
+    // Once it's returned, it's discarded rather than pooled.
+    drop(held);
+    assert!(manager.retired_files()[0].is_drained());
+    assert_eq!(manager.take_drained_files(), vec![first]);
+    assert!(manager.retired_files().is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rotate_while_connecting() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let first = temp.file("events-1.db");
+    let manager = RusqliteConnectionManager::new(&first);
+
+    // A connection being opened holds the file it's opening, but isn't open
+    // on it yet.
+    let connecting = manager.current_file();
+    manager.rotate(temp.file("events-2.db"));
+    assert_eq!(
+        manager.retired_files(),
+        vec![RetiredFile {
+            path: first.clone(),
+            open_connections: 0
+        }]
+    );
+    assert!(manager.take_drained_files().is_empty());
+
+    drop(connecting);
+    assert_eq!(manager.take_drained_files(), vec![first]);
+    Ok(())
+}
+
+#[test]
+fn connect_timeout() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("connect_timeout.db");
+
+    // With only one blocking thread, we can stall opens by keeping it busy.
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .max_blocking_threads(1)
+        .enable_all()
+        .build()?;
+
+    rt.block_on(async {
+        // A generous timeout shouldn't get in the way.
+        let manager =
+            RusqliteConnectionManager::new(&path).with_connect_timeout(Duration::from_secs(30));
+        manager.connect().await?;
+
+        let stall = tokio::task::spawn_blocking(|| std::thread::sleep(Duration::from_millis(500)));
+        let manager =
+            RusqliteConnectionManager::new(&path).with_connect_timeout(Duration::from_millis(50));
+        let result = manager.connect().await;
+        assert!(matches!(result, Err(Error::ConnectTimeout)), "{:?}", result);
+        stall.await?;
+
+        Ok(())
+    })
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn validate() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+
+    // Databases that don't exist yet are fine, provided they can be created.
+    RusqliteConnectionManager::new(temp.file("new.db"))
+        .validate()
+        .await?;
+    let result = RusqliteConnectionManager::new_with_flags(
+        temp.file("new.db"),
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )
+    .validate()
+    .await;
+    assert!(matches!(result, Err(Error::InvalidPath { .. })));
+
+    let result = RusqliteConnectionManager::new(temp.file("missing/new.db"))
+        .validate()
+        .await;
+    assert!(matches!(result, Err(Error::InvalidPath { .. })));
+
+    // Existing files must be SQLite databases.
+    let path = temp.file("existing.db");
+    Connection::open(&path)?.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+    RusqliteConnectionManager::new(&path).validate().await?;
+
+    let path = temp.file("text.db");
+    std::fs::write(&path, "this is not a database")?;
+    let result = RusqliteConnectionManager::new(&path).validate().await;
+    assert!(matches!(result, Err(Error::InvalidPath { .. })));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn conflicting_flags() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("conflicting_flags.db");
+
+    for (flags, reason) in [
+        (
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_CREATE,
+            "CREATE needs READ_WRITE",
+        ),
+        (
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_READ_WRITE,
+            "READ_ONLY and READ_WRITE",
+        ),
+        (
+            OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            "one of READ_ONLY or READ_WRITE",
+        ),
+        (
+            OpenFlags::default() | OpenFlags::SQLITE_OPEN_MEMORY,
+            "MEMORY",
+        ),
+        (
+            OpenFlags::default() | OpenFlags::SQLITE_OPEN_FULL_MUTEX,
+            "NO_MUTEX and FULL_MUTEX",
+        ),
+    ] {
+        let matches = |result: Result<(), Error>| match result {
+            Err(Error::InvalidFlags {
+                flags: found,
+                reason: found_reason,
+            }) => found == flags && found_reason.starts_with(reason),
+            _ => false,
+        };
+        assert!(matches(
+            RusqliteConnectionManager::try_new_with_flags(&path, flags).map(|_| ())
+        ));
+
+        // Managers created without checking fail just as clearly later on.
+        let manager = RusqliteConnectionManager::new_with_flags(&path, flags);
+        assert!(matches(manager.validate().await));
+        assert!(matches(manager.connect().await.map(|_| ())));
+    }
+    assert!(!path.exists());
+
+    // Pooled connections are never used by two threads at once, so NO_MUTEX,
+    // which is rusqlite's default, is fine.
+    let manager = RusqliteConnectionManager::try_new_with_flags(
+        &path,
+        OpenFlags::SQLITE_OPEN_READ_WRITE
+            | OpenFlags::SQLITE_OPEN_CREATE
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    manager.connect().await?;
+    assert!(matches!(
+        RusqliteConnectionManager::try_new_with_flags_and_vfs(
+            &path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_CREATE,
+            "unix",
+        ),
+        Err(Error::InvalidFlags { .. })
+    ));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn create_dirs() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("a/b/create_dirs.db");
+
+    // Without the option, the missing directories are an error.
+    RusqliteConnectionManager::new(&path)
+        .connect()
+        .await
+        .expect_err("the parent directory doesn't exist");
+
+    let manager = RusqliteConnectionManager::new(&path).with_create_dirs(true);
+    #[cfg(unix)]
+    let manager = manager.with_dir_mode(0o700);
+    manager.validate().await?;
+    manager
+        .connect()
+        .await?
+        .execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+    assert!(path.is_file());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(temp.file("a/b"))?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn file_mode() -> Result<(), anyhow::Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new()?;
+    let path = temp.file("file_mode.db");
+    let manager = RusqliteConnectionManager::new(&path).with_file_mode(0o600);
+    let conn = manager.connect().await?;
+    conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+    conn.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+
+    for file in &["file_mode.db", "file_mode.db-wal", "file_mode.db-shm"] {
+        let mode = std::fs::metadata(temp.file(file))?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "unexpected mode on {}", file);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn temp_dir() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    std::fs::create_dir(temp.file("tmp"))?;
+    let manager =
+        RusqliteConnectionManager::new(temp.file("temp_dir.db")).with_temp_dir(temp.file("tmp"));
+    let pool = bb8::Pool::builder().build(manager).await?;
+
+    // Spill a temporary table to disk, so SQLite has a temporary file open.
+    let spill = "PRAGMA temp_store = FILE;
+                 PRAGMA temp.cache_size = 2;
+                 CREATE TEMP TABLE t (a BLOB);
+                 WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 200)
+                 INSERT INTO t SELECT randomblob(4096) FROM n;";
+    let conn = pool.get().await?;
+    conn.execute_batch(spill)?;
+
+    // Temporary files are unlinked as soon as they're opened, so look for it
+    // through the file descriptors the process has open.
+    #[cfg(target_os = "linux")]
+    {
+        let dir = temp.file("tmp");
+        let open = std::fs::read_dir("/proc/self/fd")?
+            .filter_map(|fd| std::fs::read_link(fd.ok()?.path()).ok())
+            .filter(|target| target.starts_with(&dir))
+            .count();
+        assert!(open > 0, "no temporary files were opened in {:?}", dir);
+    }
+    drop(conn);
+
+    // A directory that doesn't exist leaves SQLite nowhere to put them.
+    let manager = RusqliteConnectionManager::new(temp.file("temp_dir.db"))
+        .with_temp_dir(temp.file("missing"));
+    let pool = bb8::Pool::builder().build(manager).await?;
+    pool.get()
+        .await?
+        .execute_batch(spill)
+        .expect_err("temporary files must be created in the missing directory");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn schema_version() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("schema_version.db");
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(&path))
+        .await?;
+
+    assert_eq!(pool.schema_version().await?, 0);
+    pool.set_schema_version(3).await?;
+    assert_eq!(pool.schema_version().await?, 3);
+
+    // Versions up to and including the supported version are fine.
+    bb8::Pool::builder()
+        .min_idle(Some(1))
+        .build(RusqliteConnectionManager::new(&path).with_max_schema_version(3))
+        .await?;
+
+    let result = bb8::Pool::builder()
+        .min_idle(Some(1))
+        .connection_timeout(Duration::from_secs(1))
+        .build(RusqliteConnectionManager::new(&path).with_max_schema_version(2))
+        .await;
+    assert!(matches!(
+        result,
+        Err(Error::SchemaTooNew {
+            found: 3,
+            supported: 2
+        })
+    ));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn application_id() -> Result<(), anyhow::Error> {
+    const ID: i32 = 0x6262_3872;
+
+    let temp = TempDir::new()?;
+    let path = temp.file("application_id.db");
+
+    // New databases are stamped.
+    let conn = RusqliteConnectionManager::new(&path)
+        .with_application_id(ID)
+        .connect()
+        .await?;
+    let id: i32 = conn.query_row("PRAGMA application_id", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(id, ID);
+    drop(conn);
+
+    let result = RusqliteConnectionManager::new(&path)
+        .with_application_id(ID + 1)
+        .connect()
+        .await;
+    assert!(matches!(
+        result,
+        Err(Error::WrongApplication {
+            found: ID,
+            expected
+        }) if expected == ID + 1
+    ));
+
+    // Existing databases without an ID are accepted, but not claimed.
+    let path = temp.file("legacy.db");
+    Connection::open(&path)?.execute("CREATE TABLE t (a INTEGER)", NO_PARAMS)?;
+    let conn = RusqliteConnectionManager::new(&path)
+        .with_application_id(ID)
+        .connect()
+        .await?;
+    let id: i32 = conn.query_row("PRAGMA application_id", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(id, 0);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn replaced() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("replaced.db");
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(&path))
+        .await?;
+    pool.get()
+        .await?
+        .execute("CREATE TABLE old (a INTEGER)", NO_PARAMS)?;
+
+    // Atomically replace the database, as a deployment might.
+    let staged = temp.file("staged.db");
+    Connection::open(&staged)?.execute("CREATE TABLE new (a INTEGER)", NO_PARAMS)?;
+    std::fs::rename(&staged, &path)?;
+
+    let tables = |conn: &Connection| -> rusqlite::Result<String> {
+        conn.query_row(
+            "SELECT group_concat(name) FROM sqlite_master",
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+    };
+    assert_eq!(tables(&*pool.get().await?)?, "new");
+
+    // A deleted database is recreated, rather than written to while unlinked.
+    std::fs::remove_file(&path)?;
+    pool.get()
+        .await?
+        .execute("CREATE TABLE recreated (a INTEGER)", NO_PARAMS)?;
+    assert_eq!(tables(&Connection::open(&path)?)?, "recreated");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn heap_limits() -> Result<(), anyhow::Error> {
+    const SOFT: i64 = 256 * 1024 * 1024;
+    const HARD: i64 = 1024 * 1024 * 1024;
+
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("heap_limits.db"))
+        .with_soft_heap_limit(SOFT)
+        .with_hard_heap_limit(HARD);
+    let pool = bb8::Pool::builder().build(manager).await?;
+
+    let conn = pool.get().await?;
+    let limit = |pragma: &str| -> rusqlite::Result<i64> {
+        conn.query_row(&format!("PRAGMA {}", pragma), NO_PARAMS, |row| row.get(0))
+    };
+    assert_eq!(limit("soft_heap_limit")?, SOFT);
+    assert_eq!(limit("hard_heap_limit")?, HARD);
+
+    // The limits are process wide, so put them back for the other tests. The
+    // pragma can only lower the hard limit, so that has to be done directly.
+    extern "C" {
+        fn sqlite3_hard_heap_limit64(limit: i64) -> i64;
+    }
+    unsafe {
+        sqlite3_hard_heap_limit64(0);
+    }
+    limit("soft_heap_limit = 0")?;
+
+    // They're only applied by the first connection.
+    pool.dedicated_connection().await?;
+    assert_eq!(limit("soft_heap_limit")?, 0);
+    assert_eq!(limit("hard_heap_limit")?, 0);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn strict_tables() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("strict.db")).with_strict_tables(true);
+
+    // An empty database has no tables that could be loose.
+    let conn = manager.connect().await?;
+    conn.execute_batch("CREATE TABLE loose (a); CREATE TABLE looser (b);")?;
+    match manager.connect().await {
+        Err(Error::NotStrict { tables }) => assert_eq!(tables, vec!["loose", "looser"]),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    if rusqlite::version_number() >= 3_037_000 {
+        conn.execute_batch(
+            "DROP TABLE loose; DROP TABLE looser; CREATE TABLE strict (a ANY) STRICT;",
+        )?;
+        manager.connect().await?;
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_only_immutable() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let path = temp.file("what? #1.db");
+    {
+        let conn = Connection::open(&path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL; CREATE TABLE t (a); INSERT INTO t VALUES (1);",
+        )?;
+    }
+
+    let manager = RusqliteConnectionManager::read_only_immutable(&path);
+    manager.validate().await?;
+    let pool = bb8::Pool::builder().max_size(2).build(manager).await?;
+    let conn = pool.get().await?;
+    let a: i64 = conn.query_row("SELECT a FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(a, 1);
+    assert!(conn.execute("INSERT INTO t VALUES (2)", NO_PARAMS).is_err());
+
+    // Without immutable=1, a read only connection to a WAL database still
+    // needs the shared memory file.
+    assert!(!temp.file("what? #1.db-shm").exists());
+    assert!(!temp.file("what? #1.db-wal").exists());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wal2_unsupported() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("wal2.db")).with_wal2(true);
+
+    // Stock SQLite builds don't have wal2, and leave the journal mode alone.
+    match manager.connect().await {
+        Ok(conn) => {
+            let mode: String =
+                conn.query_row("PRAGMA journal_mode", NO_PARAMS, |row| row.get(0))?;
+            assert_eq!(mode, "wal2");
+        }
+        Err(Error::Wal2Unsupported { found }) => assert_eq!(found, "delete"),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+#[cfg(feature = "profiling")]
+#[tokio::test(flavor = "multi_thread")]
+async fn validation_ttl() -> Result<(), anyhow::Error> {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    let temp = TempDir::new()?;
+    let validations = Arc::new(AtomicUsize::new(0));
+    let manager = RusqliteConnectionManager::new(temp.file("ttl.db"))
+        .with_validation_ttl(Duration::from_millis(100))
+        .with_profiler({
+            let validations = validations.clone();
+            move |profile| {
+                if profile.sql() == "SELECT 1" {
+                    validations.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .test_on_check_out(true)
+        .build(manager)
+        .await?;
+
+    // Recently opened connections are handed straight back out.
+    for _ in 0..3 {
+        pool.get().await?;
+    }
+    assert_eq!(validations.load(Ordering::SeqCst), 0);
+
+    // Ones that haven't been validated since are validated again, even if
+    // they've been in use, and not again until the TTL is up.
+    for _ in 0..4 {
+        pool.get().await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(validations.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn checkpoint_on_release() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+
+    // Returns the number of pages in the database file itself, which only
+    // grows once the WAL has been checkpointed.
+    async fn pages(path: PathBuf, checkpoint: bool) -> Result<u64, anyhow::Error> {
+        Connection::open(&path)?.query_row("PRAGMA journal_mode = wal", NO_PARAMS, |_| Ok(()))?;
+        let manager = RusqliteConnectionManager::new(&path).with_checkpoint_on_release(checkpoint);
+        let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+
+        {
+            let conn = pool.get().await?;
+            conn.execute_batch("CREATE TABLE t (v TEXT); INSERT INTO t VALUES ('a');")?;
+        }
+
+        // Checking a connection out again ensures the previous one has been
+        // returned to the pool.
+        let conn = pool.get().await?;
+        let count: i64 = conn.query_row("PRAGMA page_count", NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(count, 2);
+        Ok(std::fs::metadata(&path)?.len() / 4096)
+    }
+
+    assert_eq!(pages(temp.file("off.db"), false).await?, 1);
+    assert_eq!(pages(temp.file("on.db"), true).await?, 2);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn max_concurrent_queries() -> Result<(), anyhow::Error> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let temp = TempDir::new()?;
+    let manager =
+        RusqliteConnectionManager::new(temp.file("limit.db")).with_max_concurrent_queries(2);
+    let pool = bb8::Pool::builder().max_size(4).build(manager).await?;
+    let running = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+
+    let query = |sleep: u64| {
+        let (pool, running, most) = (pool.clone(), running.clone(), most.clone());
+        tokio::spawn(async move {
+            pool.query_named("SELECT 1", NamedParams::new(), |_| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(sleep));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .await
+        })
+    };
+    for result in join_all((0..4).map(|_| query(50))).await {
+        result??;
+    }
+    assert_eq!(most.load(Ordering::SeqCst), 2);
+
+    // Waiting for a turn stops when the context expires.
+    let slow = [query(500), query(500)];
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let result = ExecutionContext::new()
+        .with_timeout(Duration::from_millis(50))
+        .scope(pool.schema_version())
+        .await;
+    assert!(
+        matches!(result, Err(Error::DeadlineExceeded)),
+        "{:?}",
+        result
+    );
+    for result in join_all(slow).await {
+        result??;
+    }
+    Ok(())
+}
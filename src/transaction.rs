@@ -0,0 +1,161 @@
+//! Transaction behavior, and write transactions that take the write lock up
+//! front.
+//!
+//! A deferred transaction that reads before it writes can fail with
+//! `SQLITE_BUSY` partway through, once another connection has written, and
+//! the busy timeout can't help because waiting wouldn't make its snapshot
+//! current. `BEGIN IMMEDIATE` takes the write lock before running anything,
+//! so the only place it can be busy is the `BEGIN` itself, and the whole
+//! transaction can safely be run again.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use rusqlite::{Connection, ErrorCode, TransactionBehavior};
+
+use crate::{
+    pool::{run, Operation},
+    Error, ExecutionContext, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// The behavior transactions that don't choose one begin with, as set with
+/// [`RusqliteConnectionManager::with_transaction_behavior()`].
+#[derive(Clone, Copy)]
+pub(crate) struct DefaultBehavior(pub(crate) TransactionBehavior);
+
+impl Default for DefaultBehavior {
+    fn default() -> Self {
+        Self(TransactionBehavior::Deferred)
+    }
+}
+
+impl fmt::Debug for DefaultBehavior {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.begin())
+    }
+}
+
+impl DefaultBehavior {
+    /// Returns the statement that begins a transaction with this behavior.
+    pub(crate) fn begin(self) -> &'static str {
+        match self.0 {
+            TransactionBehavior::Immediate => "BEGIN IMMEDIATE",
+            TransactionBehavior::Exclusive => "BEGIN EXCLUSIVE",
+            _ => "BEGIN DEFERRED",
+        }
+    }
+}
+
+/// Options for
+/// [`PoolExt::write_transaction_with()`](crate::PoolExt::write_transaction_with).
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    max_duration: Duration,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            max_duration: Duration::from_secs(30),
+            initial_backoff: Duration::from_millis(5),
+            max_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl WriteOptions {
+    /// Creates the default options, which retry for up to 30 seconds,
+    /// backing off from 5ms to 500ms between attempts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long the whole transaction may take, including waiting for
+    /// connections, retries, and the closure itself. Statements still
+    /// running when it passes are interrupted, failing with
+    /// [`Error::DeadlineExceeded`].
+    pub fn max_duration(mut self, duration: Duration) -> Self {
+        self.max_duration = duration;
+        self
+    }
+
+    /// Sets how long to wait before the first retry. Each retry after that
+    /// waits twice as long as the last, up to the
+    /// [maximum](Self::max_backoff).
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the longest wait between retries.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+}
+
+/// Runs `f` within a `BEGIN IMMEDIATE` transaction on a connection from
+/// `pool`, running it again from the start while the database is busy.
+pub(crate) async fn write<F, T>(
+    pool: &bb8::Pool<RusqliteConnectionManager>,
+    options: &WriteOptions,
+    mut f: F,
+) -> Result<T, Error>
+where
+    F: FnMut(&Connection) -> Result<T, Error> + Send,
+    T: Send,
+{
+    let deadline = Instant::now() + options.max_duration;
+    let mut backoff = options.initial_backoff;
+    let attempts = async {
+        loop {
+            let result = run(pool, Operation::new("write_transaction"), |conn| {
+                transaction(conn, &mut f)
+            })
+            .await;
+            match result {
+                // Give up with the busy error, rather than the deadline,
+                // once the next attempt would start too late.
+                Err(e) if is_busy(&e) && Instant::now() + backoff < deadline => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(options.max_backoff);
+                }
+                result => return result,
+            }
+        }
+    };
+    ExecutionContext::new()
+        .with_deadline(deadline)
+        .scope(attempts)
+        .await
+}
+
+fn transaction<F, T>(conn: &Connection, f: &mut F) -> Result<T, Error>
+where
+    F: FnMut(&Connection) -> Result<T, Error>,
+{
+    conn.execute_batch("BEGIN IMMEDIATE")?;
+    let result = f(conn).and_then(|value| {
+        conn.execute_batch("COMMIT")?;
+        Ok(value)
+    });
+    // A failed COMMIT leaves the transaction open.
+    if result.is_err() && !conn.is_autocommit() {
+        conn.execute_batch("ROLLBACK")?;
+    }
+    result
+}
+
+fn is_busy(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Rusqlite(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::DatabaseBusy
+    )
+}
@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    PoolExt, PragmaCustomizer,
+};
+
+const SETUP: &str = "PRAGMA journal_mode = WAL; CREATE TABLE t (a);";
+
+fn manager(temp: &TempDir) -> RusqliteConnectionManager {
+    // Without a busy timeout, BEGIN IMMEDIATE fails as soon as another
+    // connection holds the write lock.
+    RusqliteConnectionManager::new(temp.file("write.db"))
+        .with_pragmas(PragmaCustomizer::new().busy_timeout(Duration::ZERO))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_while_busy() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(manager(&temp), 2, SETUP).await?;
+    let conn = Connection::open(temp.file("write.db"))?;
+    conn.execute_batch("BEGIN IMMEDIATE; INSERT INTO t VALUES (0);")?;
+    let holder = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        conn.execute_batch("COMMIT")
+    });
+
+    let started = Instant::now();
+    let count = pool
+        .write_transaction(|tx| {
+            let count: i64 = tx.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+            tx.execute("INSERT INTO t VALUES (?)", [count + 1])?;
+            Ok(count)
+        })
+        .await?;
+    holder.join().unwrap()?;
+
+    // The transaction only ran once the other one had committed.
+    assert_eq!(count, 1);
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn gives_up() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(manager(&temp), 2, SETUP).await?;
+    let holder = pool.get().await?;
+    holder.execute_batch("BEGIN IMMEDIATE")?;
+
+    let started = Instant::now();
+    let result = pool
+        .write_transaction_with(
+            WriteOptions::new()
+                .max_duration(Duration::from_millis(100))
+                .initial_backoff(Duration::from_millis(10)),
+            |tx| Ok(tx.execute("INSERT INTO t VALUES (1)", NO_PARAMS)?),
+        )
+        .await;
+    assert!(matches!(&result, Err(e) if is_busy(e)), "{:?}", result);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    holder.execute_batch("ROLLBACK")?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rolls_back_errors() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(manager(&temp), 2, SETUP).await?;
+
+    let attempts = AtomicU32::new(0);
+    let result: Result<(), _> = pool
+        .write_transaction(|tx| {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            tx.execute("INSERT INTO t VALUES (1)", NO_PARAMS)?;
+            Err(Error::Cancelled)
+        })
+        .await;
+    assert!(matches!(result, Err(Error::Cancelled)));
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+    let conn = pool.get().await?;
+    assert!(conn.is_autocommit());
+    let rows: i64 = conn.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(rows, 0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn default_behavior() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("behavior.db"))
+        .with_pragmas(PragmaCustomizer::new().busy_timeout(Duration::ZERO))
+        .with_transaction_behavior(TransactionBehavior::Immediate);
+    let pool = bb8::Pool::builder().max_size(2).build(manager).await?;
+    let mut conn = pool.get().await?;
+    assert!(matches!(
+        conn.transaction_behavior(),
+        TransactionBehavior::Immediate
+    ));
+    assert_eq!(
+        format!("{:?}", DefaultBehavior(conn.transaction_behavior())),
+        "BEGIN IMMEDIATE"
+    );
+
+    // The transaction holds the write lock before it has written anything.
+    let tx = conn.transaction()?;
+    let other = pool.get().await?;
+    let result = other.execute_batch("BEGIN IMMEDIATE").map_err(Error::from);
+    assert!(matches!(&result, Err(e) if is_busy(e)), "{:?}", result);
+    tx.rollback()?;
+    Ok(())
+}
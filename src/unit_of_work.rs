@@ -0,0 +1,183 @@
+//! Units of work, whose nested transactions become savepoints.
+//!
+//! Service code that wants its writes to be atomic usually can't know
+//! whether its caller already has a transaction open: `BEGIN` inside a
+//! transaction is an error, and a savepoint outside one commits on its own
+//! when released. A [`UnitOfWork`] pins one connection and tracks how deeply
+//! its transactions are nested, so [`UnitOfWork::begin()`] starts a
+//! transaction when nothing is open, and a savepoint within it otherwise.
+
+use std::{cell::Cell, fmt, ops::Deref};
+
+use crate::{transaction::DefaultBehavior, Error, RusqliteConnection, RusqliteConnectionManager};
+
+#[cfg(test)]
+mod tests;
+
+type Pooled<'a> = bb8::PooledConnection<'a, RusqliteConnectionManager>;
+
+/// A connection pinned for a unit of work, as returned by
+/// [`PoolExt::unit_of_work()`](crate::PoolExt::unit_of_work).
+///
+/// Functions that need to be atomic take a `&UnitOfWork`, and call
+/// [`begin()`](Self::begin) for a [`Work`], which dereferences back to the
+/// unit of work so it can be passed on to functions that begin their own.
+/// Statements run on the unit of work itself, which dereferences to the
+/// connection, run within whatever is open.
+///
+/// Like the connection, this should be used within
+/// `task::block_in_place()` or `spawn_blocking()`. Anything still
+/// open when it's dropped is rolled back before the connection is returned
+/// to the pool.
+pub struct UnitOfWork<'a> {
+    conn: Pooled<'a>,
+    depth: Cell<usize>,
+}
+
+impl fmt::Debug for UnitOfWork<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnitOfWork")
+            .field("conn", &*self.conn)
+            .field("depth", &self.depth.get())
+            .finish()
+    }
+}
+
+impl<'a> UnitOfWork<'a> {
+    pub(crate) fn new(conn: Pooled<'a>) -> Self {
+        Self {
+            conn,
+            depth: Cell::new(0),
+        }
+    }
+
+    /// Begins a transaction, with the connection's default behavior, if
+    /// none is open, or a savepoint within the innermost open one. Either
+    /// way, the changes made until the returned [`Work`] is committed are
+    /// rolled back if it's rolled back or dropped instead.
+    pub fn begin(&self) -> Result<Work<'_, 'a>, Error> {
+        let level = self.depth.get();
+        if level == 0 {
+            self.conn
+                .execute_batch(DefaultBehavior(self.conn.transaction_behavior()).begin())?;
+        } else {
+            self.conn
+                .execute_batch(&format!("SAVEPOINT {}", savepoint(level)))?;
+        }
+        self.depth.set(level + 1);
+        Ok(Work {
+            unit: self,
+            level,
+            finished: false,
+        })
+    }
+
+    /// Returns how many transactions and savepoints are open.
+    pub fn depth(&self) -> usize {
+        self.depth.get()
+    }
+
+    /// Commits or rolls back the work at `level`, along with anything still
+    /// open within it.
+    fn finish(&self, level: usize, commit: bool) -> Result<(), Error> {
+        if self.depth.get() <= level {
+            // Already finished along with work it was nested in.
+            return Ok(());
+        }
+        let sql = match (level, commit) {
+            (0, true) => "COMMIT".to_string(),
+            (0, false) => "ROLLBACK".to_string(),
+            (_, true) => format!("RELEASE {}", savepoint(level)),
+            // Rolling back to a savepoint leaves it open.
+            (_, false) => format!(
+                "ROLLBACK TO {name}; RELEASE {name}",
+                name = savepoint(level)
+            ),
+        };
+        let result = self.conn.execute_batch(&sql);
+        // A failed COMMIT leaves the transaction open, to be rolled back
+        // when the work is dropped.
+        if result.is_ok() || self.conn.is_autocommit() {
+            self.depth.set(level);
+        }
+        Ok(result?)
+    }
+}
+
+impl Deref for UnitOfWork<'_> {
+    type Target = RusqliteConnection;
+
+    fn deref(&self) -> &RusqliteConnection {
+        &self.conn
+    }
+}
+
+impl Drop for UnitOfWork<'_> {
+    fn drop(&mut self) {
+        if !self.conn.is_autocommit() {
+            let _ = self.conn.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+/// A transaction or savepoint begun with [`UnitOfWork::begin()`], which is
+/// rolled back when dropped unless it's committed.
+///
+/// Committing or rolling back work also finishes any work begun within it
+/// that's still open.
+pub struct Work<'u, 'a> {
+    unit: &'u UnitOfWork<'a>,
+    level: usize,
+    finished: bool,
+}
+
+impl fmt::Debug for Work<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Work")
+            .field("level", &self.level)
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+impl Work<'_, '_> {
+    /// Returns true if this is a savepoint within another transaction,
+    /// rather than the transaction itself.
+    pub fn is_nested(&self) -> bool {
+        self.level > 0
+    }
+
+    /// Commits the work: the transaction, if this is the outermost work,
+    /// or otherwise the savepoint, whose changes are then committed or
+    /// rolled back with the work it's nested in.
+    pub fn commit(mut self) -> Result<(), Error> {
+        self.finished = true;
+        self.unit.finish(self.level, true)
+    }
+
+    /// Rolls back the changes made since the work began.
+    pub fn rollback(mut self) -> Result<(), Error> {
+        self.finished = true;
+        self.unit.finish(self.level, false)
+    }
+}
+
+impl<'a> Deref for Work<'_, 'a> {
+    type Target = UnitOfWork<'a>;
+
+    fn deref(&self) -> &UnitOfWork<'a> {
+        self.unit
+    }
+}
+
+impl Drop for Work<'_, '_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.unit.finish(self.level, false);
+        }
+    }
+}
+
+fn savepoint(level: usize) -> String {
+    format!("unit_of_work_{}", level)
+}
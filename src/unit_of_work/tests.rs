@@ -0,0 +1,132 @@
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::{
+    tests::{pool, TempDir},
+    PoolExt,
+};
+
+const SETUP: &str = "CREATE TABLE t (a)";
+
+fn values(conn: &RusqliteConnection) -> Result<Vec<i64>, rusqlite::Error> {
+    let mut stmt = conn.prepare("SELECT a FROM t ORDER BY a")?;
+    let rows = stmt.query_map(NO_PARAMS, |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Inserts `a`, then fails if it's odd, as service code would.
+fn insert(unit: &UnitOfWork<'_>, a: i64) -> Result<(), Error> {
+    let work = unit.begin()?;
+    work.execute("INSERT INTO t VALUES (?)", [a])?;
+    if a % 2 == 1 {
+        return Err(Error::Rusqlite(rusqlite::Error::InvalidQuery));
+    }
+    work.commit()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn nested_work() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("unit.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let unit = pool.unit_of_work().await?;
+
+    {
+        let work = unit.begin()?;
+        assert!(!work.is_nested());
+        insert(&work, 2)?;
+        assert!(insert(&work, 3).is_err());
+        insert(&work, 4)?;
+        assert_eq!(work.depth(), 1);
+
+        // Nothing is committed until the outermost work is.
+        let other = rusqlite::Connection::open(temp.file("unit.db"))?;
+        let count: i64 = other.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?;
+        assert_eq!(count, 0);
+        work.commit()?;
+    }
+    assert_eq!(unit.depth(), 0);
+    assert_eq!(values(&unit)?, vec![2, 4]);
+
+    // Outside any other work, the function's work is the transaction.
+    insert(&unit, 6)?;
+    assert!(insert(&unit, 7).is_err());
+    assert_eq!(values(&unit)?, vec![2, 4, 6]);
+    assert!(unit.is_autocommit());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rollback_discards_nested() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("unit.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let unit = pool.unit_of_work().await?;
+
+    let work = unit.begin()?;
+    insert(&work, 2)?;
+    let nested = work.begin()?;
+    assert!(nested.is_nested());
+    nested.execute("INSERT INTO t VALUES (4)", NO_PARAMS)?;
+    nested.commit()?;
+    work.rollback()?;
+
+    assert_eq!(values(&unit)?, Vec::<i64>::new());
+    assert!(unit.is_autocommit());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn finishing_outer_work() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("unit.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+    let unit = pool.unit_of_work().await?;
+
+    let work = unit.begin()?;
+    let nested = unit.begin()?;
+    nested.execute("INSERT INTO t VALUES (2)", NO_PARAMS)?;
+    // Committing the transaction commits the savepoint still open in it,
+    // and dropping the savepoint afterwards does nothing.
+    work.commit()?;
+    drop(nested);
+
+    assert_eq!(unit.depth(), 0);
+    assert_eq!(values(&unit)?, vec![2]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropped_unit_rolls_back() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("unit.db")),
+        1,
+        SETUP,
+    )
+    .await?;
+
+    {
+        let unit = pool.unit_of_work().await?;
+        let work = unit.begin()?;
+        work.execute("INSERT INTO t VALUES (2)", NO_PARAMS)?;
+        std::mem::forget(work);
+    }
+
+    let conn = pool.get().await?;
+    assert!(conn.is_autocommit());
+    assert_eq!(values(&conn)?, Vec::<i64>::new());
+    Ok(())
+}
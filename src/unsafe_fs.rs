@@ -0,0 +1,74 @@
+//! Options for filesystems that don't give SQLite what it needs to keep a
+//! database consistent.
+
+#[cfg(test)]
+mod tests;
+
+/// Options that let the pool work on network filesystems, such as NFS and
+/// SMB, by turning off safeguards SQLite relies on.
+///
+/// SQLite's locking depends on POSIX advisory locks (or their Windows
+/// equivalent) behaving correctly, and many network filesystems get them
+/// wrong, so opening a database on one either fails with `SQLITE_BUSY` or
+/// `SQLITE_IOERR`, or appears to work until two processes write at once and
+/// corrupt it. None of these options make that safe: each trades away a
+/// guarantee, which is only sound if something outside SQLite provides it
+/// instead. Every option is off by default, and each method says what it
+/// gives up.
+///
+/// Set these with
+/// [`RusqliteConnectionManager::with_unsafe_fs_options()`](crate::RusqliteConnectionManager::with_unsafe_fs_options).
+/// They're passed to SQLite as `file:` URI parameters.
+#[derive(Clone, Debug, Default)]
+pub struct UnsafeFsOptions {
+    pub(crate) no_locking: bool,
+    pub(crate) powersafe_overwrite: Option<bool>,
+}
+
+impl UnsafeFsOptions {
+    /// Creates a set of options that keep all of SQLite's safeguards.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops SQLite from locking the database file at all, with the `nolock=1`
+    /// URI parameter, so it can be opened on filesystems whose locks fail or
+    /// hang.
+    ///
+    /// Nothing then stops a writer from changing the database under a reader,
+    /// or two writers from interleaving their changes, even within this
+    /// pool. This is only safe if a single connection, in a single process,
+    /// ever uses the database, such as with a pool of size 1 and no other
+    /// programs touching the file, or if the database is never written to.
+    pub fn no_locking_single_process_only(mut self, no_locking: bool) -> Self {
+        self.no_locking = no_locking;
+        self
+    }
+
+    /// Overrides whether SQLite assumes the filesystem has powersafe
+    /// overwrite, with the `psow` URI parameter: that writing one byte of a
+    /// file never changes the bytes around it, even if the power fails
+    /// mid-write.
+    ///
+    /// SQLite assumes it does by default, which lets it skip rewriting whole
+    /// sectors of the journal. Many network filesystems and some disks don't
+    /// actually honour this, and setting it to false is the safe choice for
+    /// those, at some cost in write performance. Setting it to true on a
+    /// filesystem without it risks corruption on a crash.
+    pub fn powersafe_overwrite(mut self, powersafe_overwrite: bool) -> Self {
+        self.powersafe_overwrite = Some(powersafe_overwrite);
+        self
+    }
+
+    /// Returns the URI parameters these options add.
+    pub(crate) fn uri_params(&self) -> Vec<(&'static str, &'static str)> {
+        let mut params = Vec::new();
+        if self.no_locking {
+            params.push(("nolock", "1"));
+        }
+        if let Some(psow) = self.powersafe_overwrite {
+            params.push(("psow", if psow { "1" } else { "0" }));
+        }
+        params
+    }
+}
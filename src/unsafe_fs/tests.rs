@@ -0,0 +1,32 @@
+use bb8::ManageConnection;
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, RusqliteConnectionManager, UnsafeFsOptions};
+
+#[test]
+fn uri_params() {
+    assert!(UnsafeFsOptions::new().uri_params().is_empty());
+    assert_eq!(
+        UnsafeFsOptions::new()
+            .no_locking_single_process_only(true)
+            .powersafe_overwrite(false)
+            .uri_params(),
+        vec![("nolock", "1"), ("psow", "0")]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn no_locking() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("nolock.db"))
+        .with_unsafe_fs_options(UnsafeFsOptions::new().no_locking_single_process_only(true));
+
+    let first = manager.connect().await?;
+    first.execute_batch("CREATE TABLE t (a); BEGIN IMMEDIATE;")?;
+
+    // Without locks, nothing stops a second writer.
+    let second = manager.connect().await?;
+    second.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")?;
+    first.execute("ROLLBACK", NO_PARAMS)?;
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! `INSERT ... ON CONFLICT DO UPDATE` statements.
+
+use crate::sql;
+
+#[cfg(test)]
+mod tests;
+
+/// An upsert into a table: an insert that updates the existing row instead
+/// when it conflicts with a uniqueness constraint.
+///
+/// Upserts require SQLite 3.24 or later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upsert {
+    table: String,
+    columns: Vec<String>,
+    conflict: Vec<String>,
+    update: Option<Vec<String>>,
+}
+
+impl Upsert {
+    /// Creates an upsert that inserts values for `columns` into `table`,
+    /// given as parameters in the same order, and updates the existing row
+    /// when one conflicts on `conflict`: the columns of the primary key or
+    /// unique index that identify it. These would typically also be among the
+    /// inserted columns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `conflict` is empty, since SQLite can only update a
+    /// conflicting row given a conflict target.
+    pub fn new<T, I, S, C, D>(table: T, columns: I, conflict: C) -> Self
+    where
+        T: Into<String>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+        C: IntoIterator<Item = D>,
+        D: Into<String>,
+    {
+        let conflict: Vec<String> = conflict.into_iter().map(Into::into).collect();
+        assert!(!conflict.is_empty(), "an upsert needs a conflict target");
+        Self {
+            table: table.into(),
+            columns: columns.into_iter().map(Into::into).collect(),
+            conflict,
+            update: None,
+        }
+    }
+
+    /// Sets the columns to update from the new values when the row already
+    /// exists. By default, every inserted column not in the conflict target
+    /// is updated. If there are no columns to update, conflicting rows are
+    /// left as they are.
+    pub fn update<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.update = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Returns the SQL for the upsert.
+    pub fn sql(&self) -> String {
+        let quote = |columns: &[String]| {
+            columns
+                .iter()
+                .map(|column| sql::quote_identifier(column))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut sql = format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({})",
+            sql::quote_identifier(&self.table),
+            quote(&self.columns),
+            sql::placeholders(self.columns.len()),
+            quote(&self.conflict)
+        );
+
+        let update: Vec<&String> = match &self.update {
+            Some(update) => update.iter().collect(),
+            None => self
+                .columns
+                .iter()
+                .filter(|column| !self.conflict.contains(column))
+                .collect(),
+        };
+        if update.is_empty() {
+            sql.push_str(" DO NOTHING");
+        } else {
+            let set = update
+                .iter()
+                .map(|column| {
+                    let column = sql::quote_identifier(column);
+                    format!("{} = excluded.{}", column, column)
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            sql.push_str(&format!(" DO UPDATE SET {}", set));
+        }
+        sql
+    }
+}
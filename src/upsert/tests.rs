@@ -0,0 +1,67 @@
+use rusqlite::{types::Value, NO_PARAMS};
+
+use crate::{tests::TempDir, PoolExt, RusqliteConnectionManager, Upsert};
+
+#[test]
+fn sql() {
+    let upsert = Upsert::new("t", vec!["a", "b", "c"], vec!["a", "b"]);
+    assert_eq!(
+        upsert.sql(),
+        r#"INSERT INTO "t" ("a", "b", "c") VALUES (?, ?, ?) ON CONFLICT ("a", "b") DO UPDATE SET "c" = excluded."c""#
+    );
+
+    let upsert = upsert.update(Vec::<String>::new());
+    assert_eq!(
+        upsert.sql(),
+        r#"INSERT INTO "t" ("a", "b", "c") VALUES (?, ?, ?) ON CONFLICT ("a", "b") DO NOTHING"#
+    );
+}
+
+#[test]
+#[should_panic(expected = "an upsert needs a conflict target")]
+fn no_conflict_target() {
+    Upsert::new("t", vec!["a"], Vec::<String>::new());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn composite_target() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("upsert.db")))
+        .await?;
+    pool.get().await?.execute(
+        "CREATE TABLE scores (player TEXT, game TEXT, score INTEGER, plays INTEGER,
+                              PRIMARY KEY (player, game))",
+        NO_PARAMS,
+    )?;
+
+    let upsert = Upsert::new(
+        "scores",
+        vec!["player", "game", "score", "plays"],
+        vec!["player", "game"],
+    )
+    .update(vec!["score"]);
+    let row = |player: &str, game: &str, score: i64| {
+        vec![
+            Value::from(player.to_string()),
+            Value::from(game.to_string()),
+            Value::from(score),
+            Value::from(1),
+        ]
+    };
+
+    assert_eq!(pool.upsert(&upsert, row("alice", "chess", 10)).await?, 1);
+    assert_eq!(pool.upsert(&upsert, row("alice", "go", 20)).await?, 1);
+    assert_eq!(pool.upsert(&upsert, row("alice", "chess", 30)).await?, 1);
+
+    let conn = pool.get().await?;
+    let mut stmt = conn.prepare("SELECT game, score, plays FROM scores ORDER BY game")?;
+    let rows = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<(String, i64, i64)>, _>>()?;
+    assert_eq!(rows, vec![("chess".into(), 30, 1), ("go".into(), 20, 1)]);
+
+    Ok(())
+}
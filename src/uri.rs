@@ -0,0 +1,65 @@
+//! `file:` URIs, which are how SQLite takes per-database options such as
+//! `immutable=1` when opening a connection.
+
+use std::path::Path;
+
+#[cfg(test)]
+mod tests;
+
+/// Returns a `file:` URI for `path`, with the given query parameters.
+///
+/// Every byte of the path that isn't unreserved is percent encoded, which
+/// SQLite decodes before handing the path to the VFS, so this works for any
+/// path, including ones containing `?` or `#`.
+pub(crate) fn file_uri(path: &Path, params: &[(&str, &str)]) -> String {
+    let path = path_bytes(path);
+    let mut uri = String::from("file:");
+    // Absolute paths get an empty authority, so a path starting with `//`
+    // can't be mistaken for one.
+    if path.starts_with(b"/") {
+        uri.push_str("//");
+    }
+    #[cfg(windows)]
+    if path.get(1) == Some(&b':') {
+        uri.push_str("///");
+    }
+    for &byte in &path {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                uri.push(byte as char)
+            }
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    for (i, (name, value)) in params.iter().enumerate() {
+        uri.push(if i == 0 { '?' } else { '&' });
+        uri.push_str(name);
+        uri.push('=');
+        uri.push_str(value);
+    }
+    uri
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    windows_path_bytes(&path.to_string_lossy())
+}
+
+/// SQLite's Windows VFS takes UTF-8, and URIs use forward slashes, except in
+/// verbatim paths: there, backslashes are percent encoded too, so that the
+/// path reaches the VFS exactly as it was given.
+#[cfg_attr(unix, allow(dead_code))]
+fn windows_path_bytes(path: &str) -> Vec<u8> {
+    if path.starts_with(r"\\?\") {
+        path.into()
+    } else {
+        path.replace('\\', "/").into_bytes()
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use super::{file_uri, windows_path_bytes};
+
+#[cfg(unix)]
+#[test]
+fn paths() {
+    assert_eq!(file_uri(Path::new("/tmp/a.db"), &[]), "file:///tmp/a.db");
+    assert_eq!(file_uri(Path::new("data/a.db"), &[]), "file:data/a.db");
+    assert_eq!(
+        file_uri(Path::new("/tmp/what? #1%.db"), &[]),
+        "file:///tmp/what%3F%20%231%25.db"
+    );
+    assert_eq!(
+        file_uri(Path::new("/tmp/caf\u{e9}.db"), &[]),
+        "file:///tmp/caf%C3%A9.db"
+    );
+}
+
+#[test]
+fn params() {
+    assert_eq!(
+        file_uri(Path::new("a.db"), &[("immutable", "1"), ("mode", "ro")]),
+        "file:a.db?immutable=1&mode=ro"
+    );
+}
+
+#[test]
+fn windows_paths() {
+    assert_eq!(windows_path_bytes(r"C:\data\a.db"), b"C:/data/a.db");
+    assert_eq!(
+        windows_path_bytes(r"\\server\share\a.db"),
+        b"//server/share/a.db"
+    );
+    assert_eq!(
+        windows_path_bytes(r"\\?\C:\data\a.db"),
+        br"\\?\C:\data\a.db"
+    );
+}
@@ -0,0 +1,198 @@
+//! Per-connection usage counters, for spotting skew in how a pool's
+//! connections are used.
+//!
+//! Checkouts are always counted. Statements and rows are counted through
+//! `sqlite3_trace_v2()` once
+//! [`RusqliteConnectionManager::with_connection_stats()`](crate::RusqliteConnectionManager::with_connection_stats)
+//! is set, and time spent waiting on locks by the busy handler of a
+//! [`ContentionMonitor`](crate::contention::ContentionMonitor), since
+//! SQLite's own busy handler can't be observed.
+
+use std::{
+    ffi::CStr,
+    fmt,
+    os::raw::{c_char, c_int, c_uint, c_void},
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rusqlite::{ffi, Connection};
+
+#[cfg(feature = "profiling")]
+use crate::ffi_ext::TRACE_PROFILE;
+use crate::ffi_ext::{sqlite3_trace_v2, TRACE_ROW, TRACE_STMT};
+#[cfg(feature = "profiling")]
+use crate::profile::{self, Profiler};
+
+#[cfg(test)]
+mod tests;
+
+/// How much one of the pool's connections has been used, since it was
+/// opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// The ID of the connection, as returned by
+    /// [`RusqliteConnection::id()`](crate::RusqliteConnection::id).
+    pub connection: u64,
+
+    /// The number of times the connection has been checked out and returned
+    /// to the pool.
+    pub checkouts: u64,
+
+    /// The number of statements the connection has run, not including those
+    /// run by triggers. This is only counted with
+    /// [`with_connection_stats()`](crate::RusqliteConnectionManager::with_connection_stats).
+    pub statements: u64,
+
+    /// The number of rows the connection's statements have returned. This is
+    /// also only counted with `with_connection_stats()`.
+    pub rows: u64,
+
+    /// How long the connection has spent waiting on locks held by other
+    /// connections. This is only measured with a
+    /// [`ContentionMonitor`](crate::contention::ContentionMonitor).
+    pub busy_wait: Duration,
+}
+
+/// A connection's counters, shared between the connection and the pool's
+/// metrics.
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    checkouts: AtomicU64,
+    statements: AtomicU64,
+    rows: AtomicU64,
+    busy_wait_micros: AtomicU64,
+}
+
+impl Usage {
+    pub(crate) fn record_checkout(&self) {
+        self.checkouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_busy_wait(&self, wait: Duration) {
+        let micros = wait.as_micros().min(u128::from(u64::MAX)) as u64;
+        self.busy_wait_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Counts a `sqlite3_trace_v2()` event, given the callback's last two
+    /// arguments.
+    ///
+    /// # Safety
+    ///
+    /// `stmt` and `extra` must be as passed to the trace callback.
+    pub(crate) unsafe fn record_trace(&self, event: c_uint, stmt: *mut c_void, extra: *mut c_void) {
+        match event {
+            // Statements run by triggers are reported with their SQL as a
+            // comment.
+            TRACE_STMT => {
+                let text = extra as *const c_char;
+                if text.is_null() || !CStr::from_ptr(text).to_bytes().starts_with(b"--") {
+                    self.statements.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            // SQLite's own queries of the schema aren't traced as
+            // statements, but their rows are, without any SQL.
+            TRACE_ROW if !ffi::sqlite3_sql(stmt as *mut ffi::sqlite3_stmt).is_null() => {
+                self.rows.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn stats(&self, connection: u64) -> ConnectionStats {
+        ConnectionStats {
+            connection,
+            checkouts: self.checkouts.load(Ordering::Relaxed),
+            statements: self.statements.load(Ordering::Relaxed),
+            rows: self.rows.load(Ordering::Relaxed),
+            busy_wait: Duration::from_micros(self.busy_wait_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+struct Context {
+    usage: Arc<Usage>,
+    #[cfg(feature = "profiling")]
+    profiler: Option<Profiler>,
+}
+
+/// Installs a trace callback counting statements and rows into `usage` on
+/// `conn`, which also forwards statement profiles to `profiler`, since a
+/// connection can only have one trace callback installed.
+pub(crate) fn install(
+    conn: &Connection,
+    usage: Arc<Usage>,
+    #[cfg(feature = "profiling")] profiler: Option<Profiler>,
+) -> Result<Registration, rusqlite::Error> {
+    let mask = TRACE_STMT | TRACE_ROW;
+    #[cfg(feature = "profiling")]
+    let mask = match profiler {
+        Some(_) => mask | TRACE_PROFILE,
+        None => mask,
+    };
+    let context = Box::new(Context {
+        usage,
+        #[cfg(feature = "profiling")]
+        profiler,
+    });
+
+    // Safety: the context is boxed, so its address is stable until the
+    // registration is dropped, which happens after the connection is closed,
+    // or after uninstall() has removed the callback.
+    let ptr = &*context as *const Context as *mut c_void;
+    let rc = unsafe { sqlite3_trace_v2(conn.handle(), mask, Some(trace), ptr) };
+    if rc != ffi::SQLITE_OK {
+        return Err(rusqlite::Error::SqliteFailure(ffi::Error::new(rc), None));
+    }
+    Ok(Registration { context })
+}
+
+unsafe extern "C" fn trace(
+    event: c_uint,
+    context: *mut c_void,
+    stmt: *mut c_void,
+    extra: *mut c_void,
+) -> c_int {
+    let context = &*(context as *const Context);
+    context.usage.record_trace(event, stmt, extra);
+
+    #[cfg(feature = "profiling")]
+    if let Some(profiler) = &context.profiler {
+        profile::trace(
+            event,
+            profiler as *const Profiler as *mut c_void,
+            stmt,
+            extra,
+        );
+    }
+    0
+}
+
+/// Keeps the trace callback's context alive while it is installed on a
+/// connection.
+pub(crate) struct Registration {
+    context: Box<Context>,
+}
+
+impl fmt::Debug for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registration")
+            .field("usage", &self.context.usage)
+            .finish()
+    }
+}
+
+impl Registration {
+    /// Removes the callback from `conn`, so the connection can outlive the
+    /// registration.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: clearing the trace callback can't fail on an open handle.
+        unsafe {
+            sqlite3_trace_v2(conn.handle(), 0, None, ptr::null_mut());
+        }
+    }
+}
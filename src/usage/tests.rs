@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use rusqlite::{ErrorCode, NO_PARAMS};
+
+use crate::{contention::ContentionMonitor, tests::TempDir, RusqliteConnectionManager};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn counts() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let manager = RusqliteConnectionManager::new(temp.file("usage.db")).with_connection_stats(true);
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .test_on_check_out(false)
+        .build(manager.clone())
+        .await?;
+
+    let id = {
+        let conn = pool.get().await?;
+        conn.execute_batch(
+            "CREATE TABLE t (a);
+             CREATE TABLE log (a);
+             CREATE TRIGGER t_log AFTER INSERT ON t BEGIN INSERT INTO log VALUES (new.a); END;",
+        )?;
+        conn.execute("INSERT INTO t VALUES (1), (2), (3)", NO_PARAMS)?;
+        conn.id()
+    };
+    let conn = pool.get().await?;
+    assert_eq!(conn.id(), id);
+    let mut stmt = conn.prepare("SELECT a FROM t")?;
+    let rows: Vec<i64> = stmt
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(rows, vec![1, 2, 3]);
+    drop(stmt);
+
+    // The trigger's statements aren't counted, and the current checkout is
+    // only counted once the connection is returned.
+    let stats = conn.stats();
+    assert_eq!(stats.connection, id);
+    assert_eq!(stats.checkouts, 1);
+    assert_eq!(stats.statements, 5);
+    assert_eq!(stats.rows, 3);
+    assert_eq!(stats.busy_wait, Duration::ZERO);
+
+    drop(conn);
+    let metrics = manager.metrics(&pool);
+    assert_eq!(metrics.connection_stats.len(), 1);
+    assert_eq!(metrics.connection_stats[0].checkouts, 2);
+    assert!(metrics.prometheus("sqlite").contains(&format!(
+        "sqlite_connection_rows_total{{connection=\"{}\"}} 3",
+        id
+    )));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn disabled() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .max_size(1)
+        .build(RusqliteConnectionManager::new(temp.file("usage.db")))
+        .await?;
+
+    pool.get()
+        .await?
+        .execute_batch("CREATE TABLE t (a); SELECT * FROM t;")?;
+    let stats = pool.get().await?.stats();
+    assert_eq!(stats.checkouts, 1);
+    assert_eq!(stats.statements, 0);
+    assert_eq!(stats.rows, 0);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn busy_wait() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let monitor = ContentionMonitor::new(|_| {}).busy_timeout(Duration::from_millis(100));
+    let pool = bb8::Pool::builder()
+        .max_size(2)
+        .build(
+            RusqliteConnectionManager::new(temp.file("usage.db"))
+                .with_contention_monitor(monitor)
+                .with_connection_stats(true),
+        )
+        .await?;
+
+    let holder = pool.get().await?;
+    holder.execute_batch("CREATE TABLE t (a); BEGIN IMMEDIATE;")?;
+    let waiter = pool.get().await?;
+    match waiter.execute("INSERT INTO t VALUES (1)", NO_PARAMS) {
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::DatabaseBusy => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let stats = waiter.stats();
+    assert!(stats.busy_wait >= Duration::from_millis(50), "{:?}", stats);
+    assert_eq!(stats.statements, 1);
+    assert_eq!(holder.stats().busy_wait, Duration::ZERO);
+    Ok(())
+}
+
+#[cfg(feature = "profiling")]
+#[tokio::test(flavor = "multi_thread")]
+async fn forwards_profiles() -> Result<(), anyhow::Error> {
+    use std::sync::{Arc, Mutex};
+
+    let temp = TempDir::new()?;
+    let profiled = Arc::new(Mutex::new(Vec::new()));
+    let manager = RusqliteConnectionManager::new(temp.file("usage.db"))
+        .with_connection_stats(true)
+        .with_profiler({
+            let profiled = profiled.clone();
+            move |profile| profiled.lock().unwrap().push(profile.sql().to_string())
+        });
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+
+    let conn = pool.get().await?;
+    conn.execute_batch("CREATE TABLE t (a)")?;
+    assert_eq!(conn.stats().statements, 1);
+    assert_eq!(*profiled.lock().unwrap(), vec!["CREATE TABLE t (a)"]);
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read},
+    path::Path,
+};
+
+use rusqlite::OpenFlags;
+
+use crate::{task, Error, OpenMode, RusqliteConnectionManager};
+
+const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+impl RusqliteConnectionManager {
+    /// Eagerly checks that connections can plausibly be opened, so that
+    /// misconfiguration is caught at startup rather than on first checkout.
+    ///
+    /// This verifies that the manager's flags don't conflict, that the parent
+    /// directory exists (and is writable, unless the manager opens read only),
+    /// and that the database file either exists and is a SQLite database, or
+    /// can be created. If
+    /// [`with_create_dirs()`](Self::with_create_dirs) is enabled, missing
+    /// directories are created first.
+    pub async fn validate(&self) -> Result<(), Error> {
+        let options = self.options.clone();
+        let path = self.path();
+        task::spawn_blocking("bb8_rusqlite::validate", move || {
+            check_flags(options.mode.flags())?;
+            options.path_policy.check(&path)?;
+            options.prepare_dirs(&path)?;
+            validate_path(&path, options.mode.flags())
+        })
+        .await?
+    }
+}
+
+impl OpenMode {
+    pub(crate) fn flags(&self) -> OpenFlags {
+        match self {
+            OpenMode::Plain => OpenFlags::default(),
+            OpenMode::WithFlags { flags } | OpenMode::WithFlagsAndVFS { flags, .. } => *flags,
+        }
+    }
+}
+
+/// Checks that `flags` make sense together, and for a pool.
+pub(crate) fn check_flags(flags: OpenFlags) -> Result<(), Error> {
+    let has = |flag| flags.contains(flag);
+    let reason = if has(OpenFlags::SQLITE_OPEN_READ_ONLY) && has(OpenFlags::SQLITE_OPEN_READ_WRITE)
+    {
+        "READ_ONLY and READ_WRITE can't both be set"
+    } else if !has(OpenFlags::SQLITE_OPEN_READ_ONLY) && !has(OpenFlags::SQLITE_OPEN_READ_WRITE) {
+        "one of READ_ONLY or READ_WRITE must be set"
+    } else if has(OpenFlags::SQLITE_OPEN_READ_ONLY) && has(OpenFlags::SQLITE_OPEN_CREATE) {
+        "CREATE needs READ_WRITE, since a read only connection can't create the database"
+    } else if has(OpenFlags::SQLITE_OPEN_MEMORY) {
+        "MEMORY gives each pooled connection a separate empty database; use \
+         from_bytes() to pool a database held in memory"
+    } else if has(OpenFlags::SQLITE_OPEN_NO_MUTEX) && has(OpenFlags::SQLITE_OPEN_FULL_MUTEX) {
+        // NO_MUTEX on its own is rusqlite's default, and safe for a pool,
+        // whose connections are only used by one task at a time.
+        "NO_MUTEX and FULL_MUTEX can't both be set"
+    } else if has(OpenFlags::SQLITE_OPEN_SHARED_CACHE) && has(OpenFlags::SQLITE_OPEN_PRIVATE_CACHE)
+    {
+        "SHARED_CACHE and PRIVATE_CACHE can't both be set"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidFlags { flags, reason })
+}
+
+fn invalid(path: &Path, reason: impl Into<String>) -> Error {
+    Error::InvalidPath {
+        path: path.into(),
+        reason: reason.into(),
+    }
+}
+
+fn validate_path(path: &Path, flags: OpenFlags) -> Result<(), Error> {
+    let read_only = flags.contains(OpenFlags::SQLITE_OPEN_READ_ONLY);
+    let parent = match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => return Err(invalid(path, "path has no parent directory")),
+    };
+
+    match fs::metadata(parent) {
+        Ok(meta) if meta.is_dir() => {}
+        Ok(_) => return Err(invalid(path, "parent is not a directory")),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(invalid(path, "parent directory does not exist"))
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    // SQLite needs to create journal files alongside the database, so the
+    // directory itself must be writable, not just the file.
+    if !read_only {
+        let probe = parent.join(format!(".bb8-rusqlite-probe-{}", std::process::id()));
+        match OpenOptions::new().write(true).create_new(true).open(&probe) {
+            Ok(_) => fs::remove_file(&probe)?,
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                return Err(invalid(path, "parent directory is not writable"))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return if flags.contains(OpenFlags::SQLITE_OPEN_CREATE) && !read_only {
+                Ok(())
+            } else {
+                Err(invalid(path, "database does not exist"))
+            };
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if !file.metadata()?.is_file() {
+        return Err(invalid(path, "not a regular file"));
+    }
+
+    // An empty file is a valid (empty) database as far as SQLite is concerned.
+    let mut header = Vec::with_capacity(MAGIC.len());
+    file.by_ref()
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut header)?;
+    if !header.is_empty() && header != MAGIC {
+        return Err(invalid(path, "not a SQLite database"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Notifications of commits to the WAL, through `sqlite3_wal_hook()`.
+
+use std::{
+    ffi::CStr,
+    fmt,
+    os::raw::{c_char, c_int, c_void},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    sync::Arc,
+};
+
+use rusqlite::{ffi, Connection, NO_PARAMS};
+
+use crate::ffi_ext::{sqlite3_wal_autocheckpoint, sqlite3_wal_checkpoint, sqlite3_wal_hook};
+
+#[cfg(test)]
+mod tests;
+
+/// A commit to the WAL, as passed to the callback set with
+/// [`RusqliteConnectionManager::on_wal_commit()`](crate::RusqliteConnectionManager::on_wal_commit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalCommit<'a> {
+    /// The ID of the connection that committed, as returned by
+    /// [`RusqliteConnection::id()`](crate::RusqliteConnection::id).
+    pub connection: u64,
+
+    /// The schema the commit was to, which is `main` unless the connection
+    /// has attached other databases.
+    pub database: &'a str,
+
+    /// The number of pages now in the WAL, including those written by
+    /// earlier commits that haven't been checkpointed yet.
+    pub pages: u32,
+}
+
+type Callback = dyn Fn(&WalCommit<'_>) + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct WalHook(Arc<Callback>);
+
+impl fmt::Debug for WalHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WalHook").finish_non_exhaustive()
+    }
+}
+
+struct Context {
+    hook: WalHook,
+    id: u64,
+    // SQLite's automatic checkpoints are run from its own WAL hook, which
+    // this one replaces, and so runs them in its place.
+    autocheckpoint: c_int,
+}
+
+impl WalHook {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&WalCommit<'_>) + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+
+    /// Installs the hook on `conn`, which has the given ID.
+    pub(crate) fn install(&self, conn: &Connection, id: u64) -> rusqlite::Result<Registration> {
+        let autocheckpoint =
+            conn.query_row("PRAGMA wal_autocheckpoint", NO_PARAMS, |row| row.get(0))?;
+        let context = Box::new(Context {
+            hook: self.clone(),
+            id,
+            autocheckpoint,
+        });
+        // Safety: the context is boxed, so its address is stable until the
+        // registration is dropped, which happens after the connection is
+        // closed, or after uninstall() has removed the hook.
+        unsafe {
+            sqlite3_wal_hook(
+                conn.handle(),
+                Some(committed),
+                &*context as *const Context as *mut c_void,
+            );
+        }
+        Ok(Registration { context })
+    }
+}
+
+pub(crate) struct Registration {
+    context: Box<Context>,
+}
+
+impl fmt::Debug for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registration")
+            .field("id", &self.context.id)
+            .finish()
+    }
+}
+
+impl Registration {
+    /// Removes the hook from `conn`, so the connection can outlive the
+    /// registration, and puts SQLite's automatic checkpoints back.
+    pub(crate) fn uninstall(self, conn: &Connection) {
+        // Safety: neither call can fail on an open handle.
+        unsafe {
+            sqlite3_wal_hook(conn.handle(), None, ptr::null_mut());
+            sqlite3_wal_autocheckpoint(conn.handle(), self.context.autocheckpoint);
+        }
+    }
+}
+
+unsafe extern "C" fn committed(
+    context: *mut c_void,
+    db: *mut ffi::sqlite3,
+    schema: *const c_char,
+    pages: c_int,
+) -> c_int {
+    let context = &*(context as *const Context);
+    let database = CStr::from_ptr(schema).to_string_lossy();
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        (context.hook.0)(&WalCommit {
+            connection: context.id,
+            database: &database,
+            pages: pages as u32,
+        })
+    }));
+
+    // As in SQLite's own hook, a failed checkpoint isn't the commit's
+    // problem.
+    if context.autocheckpoint > 0 && pages >= context.autocheckpoint {
+        sqlite3_wal_checkpoint(db, schema);
+    }
+    ffi::SQLITE_OK
+}
@@ -0,0 +1,62 @@
+use std::sync::{Arc, Mutex};
+
+use rusqlite::NO_PARAMS;
+
+use crate::{tests::TempDir, PragmaCustomizer, RusqliteConnectionManager};
+
+/// Runs three inserts with the given autocheckpoint threshold, returning the
+/// connection's ID and the WAL commits it reported.
+async fn commits(autocheckpoint: i64) -> Result<(u64, Vec<(u64, String, u32)>), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let manager = RusqliteConnectionManager::new(temp.file("wal_hook.db"))
+        .with_pragmas(
+            PragmaCustomizer::new()
+                .pragma("journal_mode", String::from("WAL"))
+                .pragma("wal_autocheckpoint", autocheckpoint),
+        )
+        .on_wal_commit({
+            let seen = seen.clone();
+            move |commit| {
+                seen.lock().unwrap().push((
+                    commit.connection,
+                    commit.database.to_owned(),
+                    commit.pages,
+                ))
+            }
+        });
+    let pool = bb8::Pool::builder().max_size(1).build(manager).await?;
+
+    let conn = pool.get().await?;
+    conn.execute_batch("CREATE TABLE t (v TEXT)")?;
+    for _ in 0..2 {
+        conn.execute("INSERT INTO t VALUES ('a')", NO_PARAMS)?;
+    }
+    let seen = seen.lock().unwrap().clone();
+    Ok((conn.id(), seen))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wal_commits() -> Result<(), anyhow::Error> {
+    let (id, seen) = commits(1000).await?;
+    assert_eq!(seen.len(), 3);
+    assert!(seen
+        .iter()
+        .all(|(connection, database, _)| *connection == id && database == "main"));
+
+    // Without checkpoints, the WAL keeps growing.
+    let pages: Vec<u32> = seen.iter().map(|(_, _, pages)| *pages).collect();
+    assert!(pages.windows(2).all(|w| w[0] < w[1]), "{:?}", pages);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn autocheckpoint_kept() -> Result<(), anyhow::Error> {
+    // Checkpointing after every commit lets SQLite restart the WAL from the
+    // beginning each time.
+    let (_, seen) = commits(1).await?;
+    let pages: Vec<u32> = seen.iter().map(|(_, _, pages)| *pages).collect();
+    assert_eq!(pages.len(), 3);
+    assert!(pages[1..].iter().all(|&n| n == pages[1]), "{:?}", pages);
+    Ok(())
+}
@@ -0,0 +1,352 @@
+//! Monitoring of WAL growth.
+//!
+//! In WAL mode, the `-wal` file grows until a checkpoint copies its frames
+//! back into the database and the WAL can be restarted. A single long-lived
+//! read transaction is enough to stop checkpoints making progress, after
+//! which the WAL grows without bound. A [`WalWatchdog`] polls the WAL, reports
+//! its state, and raises [`WalAlert`]s when it grows too large or checkpoints
+//! stall, optionally forcing a checkpoint to get things moving again.
+//!
+//! Databases in wal2 mode (see
+//! [`RusqliteConnectionManager::with_wal2()`]) are also supported. There,
+//! writers switch between the `-wal` and `-wal2` files, and each checkpoint
+//! copies whichever of the two isn't being written, so sizes are reported for
+//! both files combined.
+
+use std::{
+    fmt, fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rusqlite::NO_PARAMS;
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{
+    pool::{self, Operation},
+    replication, task, Error, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// A sample of the WAL's state, taken on each poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalStatus {
+    /// The size of the `-wal` file, in bytes. In wal2 mode, this is the
+    /// combined size of the `-wal` and `-wal2` files.
+    pub size: u64,
+
+    /// The number of frames in the WAL.
+    pub frames: u64,
+
+    /// The number of those frames that have been checkpointed.
+    pub checkpointed: u64,
+
+    /// How long checkpoints have been unable to copy every frame, if they
+    /// currently are. This is typically caused by a long-lived reader.
+    pub stalled_for: Option<Duration>,
+}
+
+/// A problem detected by the watchdog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalAlert {
+    /// The WAL has grown past the configured size. This is raised once each
+    /// time the threshold is crossed.
+    Oversized {
+        /// The size of the WAL, in bytes.
+        size: u64,
+
+        /// The configured threshold, in bytes.
+        threshold: u64,
+    },
+
+    /// Checkpoints have been unable to copy every frame for longer than the
+    /// configured reader age. This is raised once per stall.
+    CheckpointStalled {
+        /// How long checkpoints have been stalled.
+        duration: Duration,
+
+        /// The number of frames that couldn't be checkpointed.
+        backlog: u64,
+    },
+
+    /// A `TRUNCATE` checkpoint was forced because the WAL grew past the
+    /// configured size.
+    ForcedCheckpoint {
+        /// True if the checkpoint couldn't finish before the busy timeout
+        /// expired.
+        busy: bool,
+    },
+}
+
+type Callback<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+/// A background monitor for a pooled database's WAL.
+pub struct WalWatchdog {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    interval: Duration,
+    warn_size: Option<u64>,
+    max_stall: Option<Duration>,
+    force_size: Option<u64>,
+    on_status: Option<Callback<WalStatus>>,
+    on_alert: Option<Callback<WalAlert>>,
+    on_error: Option<Callback<Error>>,
+}
+
+impl fmt::Debug for WalWatchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WalWatchdog")
+            .field("pool", &self.pool)
+            .field("interval", &self.interval)
+            .field("warn_size", &self.warn_size)
+            .field("max_stall", &self.max_stall)
+            .field("force_size", &self.force_size)
+            .finish()
+    }
+}
+
+impl WalWatchdog {
+    /// Creates a watchdog for the pool's database, polling every 10 seconds
+    /// by default. Without any thresholds configured, it only reports
+    /// [`WalStatus`] samples.
+    pub fn new(pool: bb8::Pool<RusqliteConnectionManager>) -> Self {
+        Self {
+            pool,
+            interval: Duration::from_secs(10),
+            warn_size: None,
+            max_stall: None,
+            force_size: None,
+            on_status: None,
+            on_alert: None,
+            on_error: None,
+        }
+    }
+
+    /// Sets how often the WAL is polled.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Raises [`WalAlert::Oversized`] when the WAL grows past `bytes`.
+    pub fn warn_size(mut self, bytes: u64) -> Self {
+        self.warn_size = Some(bytes);
+        self
+    }
+
+    /// Raises [`WalAlert::CheckpointStalled`] when checkpoints have been
+    /// unable to complete for longer than `age`.
+    ///
+    /// Stalls are detected by running a `PASSIVE` checkpoint on each poll,
+    /// which never waits on other connections.
+    pub fn max_reader_age(mut self, age: Duration) -> Self {
+        self.max_stall = Some(age);
+        self
+    }
+
+    /// Forces a `TRUNCATE` checkpoint whenever the WAL is larger than
+    /// `bytes`, which waits (up to the connection's busy timeout) for readers
+    /// to move off the WAL, then truncates it. A `RESTART` checkpoint would
+    /// leave the file at its size, so it would still be over the threshold on
+    /// the next poll.
+    pub fn force_checkpoint_size(mut self, bytes: u64) -> Self {
+        self.force_size = Some(bytes);
+        self
+    }
+
+    /// Sets a callback that receives a [`WalStatus`] sample on each poll, such
+    /// as for exporting as metrics.
+    pub fn on_status<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&WalStatus) + Send + Sync + 'static,
+    {
+        self.on_status = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback for alerts.
+    pub fn on_alert<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&WalAlert) + Send + Sync + 'static,
+    {
+        self.on_alert = Some(Arc::new(callback));
+        self
+    }
+
+    /// Sets a callback for errors encountered while polling. The watchdog
+    /// keeps running after an error, and retries on the next poll.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Error) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Starts the background monitoring task.
+    ///
+    /// This fails if the database is not in WAL or wal2 mode.
+    pub async fn start(self) -> Result<WatchdogHandle, Error> {
+        let wal_paths = pool::run(&self.pool, Operation::new("journal_mode"), |conn| {
+            let mode: String =
+                conn.query_row("PRAGMA journal_mode", NO_PARAMS, |row| row.get(0))?;
+            if !mode.eq_ignore_ascii_case("wal") && !mode.eq_ignore_ascii_case("wal2") {
+                return Err(Error::NotWalMode);
+            }
+            Ok(replication::wal_paths(&replication::main_database_path(
+                conn,
+            )?))
+        })
+        .await?;
+
+        let (stop, mut stopped) = oneshot::channel();
+        let mut task = WatchdogTask {
+            watchdog: self,
+            wal_paths,
+            stalled_since: None,
+            stall_reported: false,
+            oversized: false,
+        };
+
+        let join = task::spawn("bb8_rusqlite::wal_watchdog", async move {
+            let interval = task.watchdog.interval;
+            while tokio::time::timeout(interval, &mut stopped).await.is_err() {
+                if let Err(e) = task.poll().await {
+                    if let Some(callback) = &task.watchdog.on_error {
+                        callback(&e);
+                    }
+                }
+            }
+        });
+
+        Ok(WatchdogHandle { stop, join })
+    }
+}
+
+/// A handle to a running watchdog. Dropping the handle also stops the
+/// watchdog.
+#[derive(Debug)]
+pub struct WatchdogHandle {
+    stop: oneshot::Sender<()>,
+    join: JoinHandle<()>,
+}
+
+impl WatchdogHandle {
+    /// Returns the ID of the watchdog's task.
+    pub fn task_id(&self) -> tokio::task::Id {
+        self.join.id()
+    }
+
+    /// Stops the watchdog, waiting for any poll in progress to finish.
+    pub async fn stop(self) -> Result<(), Error> {
+        let _ = self.stop.send(());
+        Ok(self.join.await?)
+    }
+}
+
+struct WatchdogTask {
+    watchdog: WalWatchdog,
+    wal_paths: [PathBuf; 2],
+    stalled_since: Option<Instant>,
+    stall_reported: bool,
+    oversized: bool,
+}
+
+impl WatchdogTask {
+    async fn poll(&mut self) -> Result<(), Error> {
+        let (frames, checkpointed) = checkpoint(&self.watchdog.pool, "PASSIVE").await?.1;
+        let size = self
+            .wal_paths
+            .iter()
+            .map(|path| wal_size(path))
+            .sum::<Result<u64, Error>>()?;
+
+        let stalled = frames > checkpointed;
+        if !stalled {
+            self.stalled_since = None;
+            self.stall_reported = false;
+        }
+        let stalled_for = if stalled {
+            Some(
+                self.stalled_since
+                    .get_or_insert_with(Instant::now)
+                    .elapsed(),
+            )
+        } else {
+            None
+        };
+
+        if let Some(callback) = &self.watchdog.on_status {
+            callback(&WalStatus {
+                size,
+                frames,
+                checkpointed,
+                stalled_for,
+            });
+        }
+
+        if let (Some(max), Some(duration)) = (self.watchdog.max_stall, stalled_for) {
+            if duration >= max && !self.stall_reported {
+                self.stall_reported = true;
+                self.alert(WalAlert::CheckpointStalled {
+                    duration,
+                    backlog: frames - checkpointed,
+                });
+            }
+        }
+
+        if let Some(threshold) = self.watchdog.warn_size {
+            let oversized = size > threshold;
+            if oversized && !self.oversized {
+                self.alert(WalAlert::Oversized { size, threshold });
+            }
+            self.oversized = oversized;
+        }
+
+        if let Some(threshold) = self.watchdog.force_size {
+            if size > threshold {
+                let busy = checkpoint(&self.watchdog.pool, "TRUNCATE").await?.0;
+                self.alert(WalAlert::ForcedCheckpoint { busy });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn alert(&self, alert: WalAlert) {
+        if let Some(callback) = &self.watchdog.on_alert {
+            callback(&alert);
+        }
+    }
+}
+
+/// Runs a checkpoint, returning whether it was blocked, and the number of
+/// frames in the WAL and checkpointed.
+async fn checkpoint(
+    pool: &bb8::Pool<RusqliteConnectionManager>,
+    mode: &'static str,
+) -> Result<(bool, (u64, u64)), Error> {
+    pool::run(pool, Operation::new("wal_checkpoint"), move |conn| {
+        let sql = format!("PRAGMA wal_checkpoint({})", mode);
+        Ok(conn.query_row(&sql, NO_PARAMS, |row| {
+            let frames: i64 = row.get(1)?;
+            let checkpointed: i64 = row.get(2)?;
+            Ok((
+                row.get(0)?,
+                (frames.max(0) as u64, checkpointed.max(0) as u64),
+            ))
+        })?)
+    })
+    .await
+}
+
+fn wal_size(path: &std::path::Path) -> Result<u64, Error> {
+    match fs::metadata(path) {
+        Ok(meta) => Ok(meta.len()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,121 @@
+use std::sync::Mutex;
+
+use super::*;
+use crate::tests::TempDir;
+
+async fn wal_pool(temp: &TempDir) -> Result<bb8::Pool<RusqliteConnectionManager>, anyhow::Error> {
+    let pool = bb8::Pool::builder()
+        .max_size(3)
+        .build(RusqliteConnectionManager::new(temp.file("watchdog.db")))
+        .await?;
+    {
+        let conn = pool.get().await?;
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+        conn.execute("CREATE TABLE t (a BLOB)", NO_PARAMS)?;
+    }
+    Ok(pool)
+}
+
+async fn insert(pool: &bb8::Pool<RusqliteConnectionManager>) -> Result<(), anyhow::Error> {
+    pool.get()
+        .await?
+        .execute("INSERT INTO t (a) VALUES (zeroblob(65536))", NO_PARAMS)?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stalled_reader() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = wal_pool(&temp).await?;
+
+    let alerts = Arc::new(Mutex::new(Vec::new()));
+    let statuses = Arc::new(Mutex::new(Vec::new()));
+    let handle = WalWatchdog::new(pool.clone())
+        .with_interval(Duration::from_millis(10))
+        .warn_size(32 * 1024)
+        .max_reader_age(Duration::from_millis(30))
+        .on_alert({
+            let alerts = alerts.clone();
+            move |alert| alerts.lock().unwrap().push(*alert)
+        })
+        .on_status({
+            let statuses = statuses.clone();
+            move |status| statuses.lock().unwrap().push(*status)
+        })
+        .start()
+        .await?;
+
+    // A read transaction pins the WAL, so frames written after it began
+    // can't be checkpointed.
+    let reader = pool.get().await?;
+    reader.execute_batch("BEGIN")?;
+    reader.query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |_| Ok(()))?;
+    insert(&pool).await?;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+
+    {
+        let alerts = alerts.lock().unwrap();
+        assert!(
+            matches!(alerts[0], WalAlert::Oversized { threshold, .. } if threshold == 32 * 1024)
+        );
+        assert!(
+            matches!(alerts[1], WalAlert::CheckpointStalled { duration, backlog } if duration >= Duration::from_millis(30) && backlog > 0)
+        );
+        assert_eq!(alerts.len(), 2);
+    }
+
+    reader.execute_batch("COMMIT")?;
+    drop(reader);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    handle.stop().await?;
+
+    let last = *statuses.lock().unwrap().last().unwrap();
+    assert_eq!(last.stalled_for, None);
+    assert_eq!(last.frames, last.checkpointed);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forced_checkpoint() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = wal_pool(&temp).await?;
+    insert(&pool).await?;
+
+    let alerts = Arc::new(Mutex::new(Vec::new()));
+    let handle = WalWatchdog::new(pool.clone())
+        .with_interval(Duration::from_millis(10))
+        .force_checkpoint_size(32 * 1024)
+        .on_alert({
+            let alerts = alerts.clone();
+            move |alert| alerts.lock().unwrap().push(*alert)
+        })
+        .start()
+        .await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // After the truncating checkpoint, the WAL is back under the threshold,
+    // so it isn't checkpointed again until it grows past it.
+    let alerts = alerts.lock().unwrap().clone();
+    assert_eq!(alerts, vec![WalAlert::ForcedCheckpoint { busy: false }]);
+    assert!(wal_size(&temp.file("watchdog.db-wal"))? < 32 * 1024);
+
+    insert(&pool).await?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    handle.stop().await?;
+
+    assert!(wal_size(&temp.file("watchdog.db-wal"))? < 2 * 65536);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requires_wal() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = bb8::Pool::builder()
+        .build(RusqliteConnectionManager::new(temp.file("db")))
+        .await?;
+    assert!(matches!(
+        WalWatchdog::new(pool).start().await,
+        Err(Error::NotWalMode)
+    ));
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use std::{
+    os::raw::{c_int, c_void},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rusqlite::{Connection, NO_PARAMS};
+
+use crate::ffi_ext::FCNTL_WIN32_AV_RETRY;
+use crate::Error;
+
+#[cfg(test)]
+mod tests;
+
+/// The Windows VFS that accepts paths longer than `MAX_PATH`.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) const LONG_PATH_VFS: &str = "win32-longpath";
+
+/// The longest path the default Windows VFS can open, in UTF-16 code units,
+/// including the terminating NUL.
+const MAX_PATH: usize = 260;
+
+/// A path as it's handed to SQLite on Windows.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) struct WindowsPath {
+    pub(crate) path: PathBuf,
+    /// Whether the path is verbatim, and so needs the long path VFS.
+    pub(crate) long: bool,
+}
+
+impl WindowsPath {
+    fn plain(path: PathBuf) -> Self {
+        Self { path, long: false }
+    }
+
+    fn long(path: PathBuf) -> Self {
+        Self { path, long: true }
+    }
+}
+
+/// Options that control how databases are opened on Windows.
+///
+/// These are accepted on every platform so configuration code doesn't need to
+/// be conditionally compiled, but have no effect outside Windows.
+#[derive(Clone, Debug, Default)]
+pub struct WindowsOptions {
+    pub(crate) exclusive: bool,
+    pub(crate) io_retry: Option<(u32, Duration)>,
+    pub(crate) long_paths: bool,
+}
+
+impl WindowsOptions {
+    /// Creates a set of options that behave the same as SQLite's defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Holds the database lock for the lifetime of each connection (via
+    /// `PRAGMA locking_mode = EXCLUSIVE`), keeping other processes out of the
+    /// file entirely.
+    ///
+    /// Since this also excludes other connections in the same pool, it only
+    /// makes sense with a pool size of 1.
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Sets how many times, and how long between each attempt, SQLite retries
+    /// file operations that fail because another process (typically an
+    /// antivirus or backup agent) briefly holds the file open. SQLite's
+    /// defaults are 10 retries, starting at 25ms.
+    pub fn io_retry(mut self, count: u32, delay: Duration) -> Self {
+        self.io_retry = Some((count, delay));
+        self
+    }
+
+    /// Opens databases through the `win32-longpath` VFS, which accepts paths
+    /// longer than `MAX_PATH`, including `\\?\` prefixed paths as returned by
+    /// `std::fs::canonicalize()`. This is ignored if a VFS is given
+    /// explicitly.
+    ///
+    /// Without this, `\\?\` prefixes are stripped before opening, since the
+    /// default VFS doesn't understand them, unless the path is too long for
+    /// it or the prefix matters to which file the path names. Those paths,
+    /// and ordinary paths that are too long, are opened through the long
+    /// path VFS anyway.
+    pub fn long_paths(mut self, long_paths: bool) -> Self {
+        self.long_paths = long_paths;
+        self
+    }
+
+    /// Returns the path that should actually be handed to SQLite, and
+    /// whether only the long path VFS can open it.
+    ///
+    /// `\\?\` prefixed (verbatim) paths are turned back into ordinary ones
+    /// where that doesn't change which file they name, and ordinary paths
+    /// that are too long for the default VFS are made verbatim where that
+    /// doesn't either. Anything else that the default VFS can't open is kept
+    /// verbatim for the long path VFS.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn normalize(&self, path: &Path) -> Result<WindowsPath, Error> {
+        let s = path
+            .to_str()
+            .ok_or_else(|| invalid(path, "path is not valid Unicode"))?;
+        if self.long_paths {
+            return Ok(WindowsPath::long(path.into()));
+        }
+
+        if let Some(rest) = s.strip_prefix(r"\\?\") {
+            let plain = if let Some(unc) = rest.strip_prefix(r"UNC\") {
+                format!(r"\\{}", unc)
+            } else if is_drive(rest) {
+                rest.into()
+            } else {
+                // Volume GUIDs and the like have no ordinary form.
+                return Ok(WindowsPath::long(path.into()));
+            };
+            return Ok(if fits(&plain) && !normalized_away(rest) {
+                WindowsPath::plain(plain.into())
+            } else {
+                WindowsPath::long(path.into())
+            });
+        }
+
+        // Device paths are normalized like ordinary ones, but only those
+        // naming a drive's files can hold a database.
+        let s = match s.strip_prefix(r"\\.\") {
+            Some(rest) if is_drive(rest) => rest,
+            Some(_) => return Err(invalid(path, "device paths can't hold a database")),
+            None => s,
+        };
+        if fits(s) {
+            return Ok(WindowsPath::plain(s.into()));
+        }
+        let rest = s.replace('/', r"\");
+        let verbatim = if let Some(unc) = rest.strip_prefix(r"\\") {
+            format!(r"\\?\UNC\{}", unc)
+        } else if is_drive(&rest) {
+            format!(r"\\?\{}", rest)
+        } else {
+            return Err(invalid(
+                path,
+                "relative paths must be shorter than MAX_PATH characters, \
+                 including the current directory",
+            ));
+        };
+        if normalized_away(&rest[2..]) {
+            return Err(invalid(
+                path,
+                "paths longer than MAX_PATH characters can't contain . or .. components",
+            ));
+        }
+        Ok(WindowsPath::long(verbatim.into()))
+    }
+
+    /// Applies the per-connection settings to a newly opened connection.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn apply(&self, conn: &Connection) -> Result<(), rusqlite::Error> {
+        if self.exclusive {
+            conn.query_row("PRAGMA locking_mode = EXCLUSIVE", NO_PARAMS, |_| Ok(()))?;
+        }
+
+        if let Some((count, delay)) = self.io_retry {
+            let mut args: [c_int; 2] = [
+                count.min(c_int::MAX as u32) as c_int,
+                delay.as_millis().min(c_int::MAX as u128) as c_int,
+            ];
+            // Safety: the handle is valid for the lifetime of the connection,
+            // and this file control reads and writes exactly two ints.
+            let rc = unsafe {
+                rusqlite::ffi::sqlite3_file_control(
+                    conn.handle(),
+                    b"main\0".as_ptr() as *const _,
+                    FCNTL_WIN32_AV_RETRY,
+                    args.as_mut_ptr() as *mut c_void,
+                )
+            };
+            if rc != rusqlite::ffi::SQLITE_OK {
+                return Err(rusqlite::Error::SqliteFailure(
+                    rusqlite::ffi::Error::new(rc),
+                    None,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(path: &Path, reason: &str) -> Error {
+    Error::InvalidPath {
+        path: path.into(),
+        reason: reason.into(),
+    }
+}
+
+/// Returns true if `path` starts with a drive letter and a separator.
+fn is_drive(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes[2] == b'\\' || bytes[2] == b'/')
+}
+
+/// Returns true if the default VFS can open `path`, which it makes absolute
+/// first.
+fn fits(path: &str) -> bool {
+    let shorter = |path: &str| path.encode_utf16().count() < MAX_PATH;
+    if is_drive(path) || path.starts_with(r"\\") {
+        return shorter(path);
+    }
+    // std::path::absolute() needs Rust 1.79.
+    match std::env::current_dir() {
+        Ok(dir) => dir.join(path).to_str().is_some_and(shorter),
+        Err(_) => shorter(path),
+    }
+}
+
+/// Returns true if Windows would change `path` when normalizing it, which it
+/// doesn't do for verbatim paths: collapsing `.`, `..`, and empty components,
+/// treating `/` as a separator, and trimming trailing dots and spaces.
+fn normalized_away(path: &str) -> bool {
+    path.contains('/')
+        || path.split('\\').enumerate().any(|(i, component)| {
+            (component.is_empty() && i > 0)
+                || component == "."
+                || component == ".."
+                || component.ends_with('.')
+                || component.ends_with(' ')
+        })
+}
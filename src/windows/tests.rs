@@ -0,0 +1,103 @@
+use rusqlite::{Connection, NO_PARAMS};
+
+use super::*;
+use crate::tests::TempDir;
+
+fn normalize(options: &WindowsOptions, path: &str) -> (String, bool) {
+    let normalized = options.normalize(Path::new(path)).unwrap();
+    (normalized.path.to_str().unwrap().into(), normalized.long)
+}
+
+fn plain(path: &str) -> (String, bool) {
+    (path.into(), false)
+}
+
+fn long(path: &str) -> (String, bool) {
+    (path.into(), true)
+}
+
+#[test]
+fn verbatim_paths() {
+    let options = WindowsOptions::new();
+    assert_eq!(
+        normalize(&options, r"\\?\C:\data\app.db"),
+        plain(r"C:\data\app.db")
+    );
+    assert_eq!(
+        normalize(&options, r"\\?\UNC\server\share\app.db"),
+        plain(r"\\server\share\app.db")
+    );
+    assert_eq!(
+        normalize(&options, r"C:\data\app.db"),
+        plain(r"C:\data\app.db")
+    );
+
+    // Where stripping the prefix would name a different file, or one the
+    // default VFS can't open, the path is kept for the long path VFS.
+    for path in [
+        r"\\?\C:\data\app.db.",
+        r"\\?\C:\data\..\app.db",
+        r"\\?\C:\data/app.db",
+        r"\\?\C:\data\\app.db",
+        r"\\?\Volume{b75e2c83-0000-0000-0000-602f00000000}\app.db",
+    ] {
+        assert_eq!(normalize(&options, path), long(path));
+    }
+    let deep = format!(r"\\?\C:\{}app.db", r"directory\".repeat(30));
+    assert_eq!(normalize(&options, &deep), long(&deep));
+
+    // The long path VFS understands verbatim paths, so they're left alone.
+    let options = WindowsOptions::new().long_paths(true);
+    assert_eq!(
+        normalize(&options, r"\\?\C:\data\app.db"),
+        long(r"\\?\C:\data\app.db")
+    );
+}
+
+#[test]
+fn long_paths() {
+    let options = WindowsOptions::new();
+    let deep = format!(r"C:\{}app.db", r"directory\".repeat(30));
+    assert_eq!(normalize(&options, &deep), long(&format!(r"\\?\{}", deep)));
+
+    let unc = format!(r"\\server\share\{}app.db", "directory/".repeat(30));
+    assert_eq!(
+        normalize(&options, &unc),
+        long(&format!(r"\\?\UNC\{}", unc[2..].replace('/', r"\")))
+    );
+
+    // Verbatim paths aren't normalized, so these can't be made verbatim.
+    let relative = format!(r"data\{}app.db", r"directory\".repeat(30));
+    let dots = format!(r"C:\data\..\{}app.db", r"directory\".repeat(30));
+    // Nor can a relative path that's only too long once it's made absolute.
+    let short = "a".repeat(MAX_PATH - 2);
+    for path in [relative, dots, short] {
+        let result = options.normalize(Path::new(&path));
+        assert!(matches!(result, Err(Error::InvalidPath { .. })), "{}", path);
+    }
+}
+
+#[test]
+fn device_paths() {
+    let options = WindowsOptions::new();
+    assert_eq!(
+        normalize(&options, r"\\.\C:\data\app.db"),
+        plain(r"C:\data\app.db")
+    );
+    assert!(matches!(
+        options.normalize(Path::new(r"\\.\pipe\app.db")),
+        Err(Error::InvalidPath { .. })
+    ));
+}
+
+#[test]
+fn exclusive() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let conn = Connection::open(temp.file("exclusive.db"))?;
+    WindowsOptions::new().exclusive(true).apply(&conn)?;
+
+    let mode: String = conn.query_row("PRAGMA locking_mode", NO_PARAMS, |row| row.get(0))?;
+    assert_eq!(mode, "exclusive");
+
+    Ok(())
+}
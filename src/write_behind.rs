@@ -0,0 +1,421 @@
+//! Writes that are acknowledged before they reach the database.
+//!
+//! On slow disks, most of a small write's latency is the commit waiting for
+//! the disk to sync. A [`WriteBehind`] queue acknowledges each write as soon
+//! as it's been appended to a journal, a separate SQLite database in WAL mode
+//! with `synchronous = NORMAL`, so appending doesn't wait for a sync. A
+//! background writer applies the queue in batches, each in a single
+//! transaction, so many writes share one sync, and removes them from the
+//! journal once they're committed.
+//!
+//! Writes in the journal survive the process exiting, and the next writer
+//! started on the journal applies them before anything queued after it. The
+//! trade-off is that they aren't durable against the machine losing power
+//! until the journal is next checkpointed, and that a writer that exits
+//! between committing a batch and removing it from the journal applies the
+//! batch again when it's restarted, so writes should be idempotent.
+//! [`WriteBehindHandle::flush()`] waits for everything queued so far to be
+//! committed. Reads through the pool don't see queued writes until they've
+//! been applied.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use rusqlite::{types::Value, Connection, TransactionBehavior, NO_PARAMS};
+use tokio::{
+    sync::{mpsc, oneshot, Mutex},
+    time::Instant,
+};
+
+use crate::{
+    pool::{self, Operation},
+    task, Error, PipelineStatement, RusqliteConnectionManager,
+};
+
+#[cfg(test)]
+mod tests;
+
+type ErrorCallback = Arc<dyn Fn(&Error, &PipelineStatement) + Send + Sync>;
+
+/// The configuration of a write-behind queue.
+pub struct WriteBehind {
+    pool: bb8::Pool<RusqliteConnectionManager>,
+    journal: PathBuf,
+    capacity: usize,
+    max_batch: usize,
+    max_delay: Duration,
+    on_error: Option<ErrorCallback>,
+}
+
+impl fmt::Debug for WriteBehind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteBehind")
+            .field("pool", &self.pool)
+            .field("journal", &self.journal)
+            .field("capacity", &self.capacity)
+            .field("max_batch", &self.max_batch)
+            .field("max_delay", &self.max_delay)
+            .finish()
+    }
+}
+
+impl WriteBehind {
+    /// Creates a queue writing to the pool's database, journaled in the
+    /// database at `journal`, which is created if it doesn't exist. Only one
+    /// writer should use a journal at a time.
+    ///
+    /// By default, up to 10,000 writes can be queued, and they're applied in
+    /// batches of up to 100, no more than 50 milliseconds after the first
+    /// write in the batch was queued.
+    pub fn new<P>(pool: bb8::Pool<RusqliteConnectionManager>, journal: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            pool,
+            journal: journal.as_ref().to_path_buf(),
+            capacity: 10_000,
+            max_batch: 100,
+            max_delay: Duration::from_millis(50),
+            on_error: None,
+        }
+    }
+
+    /// Sets how many writes can be waiting to be applied. Once the queue is
+    /// full, queueing another write waits for space.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets how many writes are applied in each transaction.
+    pub fn with_max_batch(mut self, writes: usize) -> Self {
+        self.max_batch = writes.max(1);
+        self
+    }
+
+    /// Sets how long the writer waits for a batch to fill up before applying
+    /// it anyway.
+    pub fn with_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Sets a callback for writes that couldn't be applied, since errors
+    /// can't be returned to a caller that has already been told the write
+    /// succeeded.
+    ///
+    /// A write that fails by itself is rolled back to a savepoint, and the
+    /// rest of its batch is still committed. If the whole batch fails, such
+    /// as when a connection can't be checked out, the callback is called once
+    /// for every write in it instead. Either way, failed writes are removed
+    /// from the journal.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&Error, &PipelineStatement) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(callback));
+        self
+    }
+
+    /// Opens the journal and starts the background writer, which first
+    /// applies any writes left in the journal by an earlier writer.
+    pub async fn start(self) -> Result<WriteBehindHandle, Error> {
+        let (journal, removing, pending) = task::block_in_place(|| -> Result<_, Error> {
+            let journal = journal::open(&self.journal)?;
+            let pending = journal::pending(&journal)?;
+            Ok((journal, journal::open(&self.journal)?, pending))
+        })?;
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        let writer = task::spawn(
+            "bb8_rusqlite::write_behind",
+            Writer {
+                config: self,
+                journal: std::sync::Mutex::new(removing),
+            }
+            .run(pending, receiver),
+        );
+        Ok(WriteBehindHandle {
+            sender,
+            journal: Arc::new(Mutex::new(journal)),
+            task: writer.id(),
+        })
+    }
+}
+
+// A write, with its ID in the journal.
+type Write = (i64, PipelineStatement);
+
+enum Message {
+    Write(Write),
+    Flush(oneshot::Sender<()>),
+    Stop(oneshot::Sender<()>),
+}
+
+/// A handle for queueing writes. Handles can be cloned and shared between
+/// tasks; the writer stops once every handle has been dropped, after
+/// applying the writes still queued.
+#[derive(Debug, Clone)]
+pub struct WriteBehindHandle {
+    sender: mpsc::Sender<Message>,
+    // Held from appending a write until it's queued, so the writer receives
+    // writes in the order they're journaled.
+    journal: Arc<Mutex<Connection>>,
+    task: tokio::task::Id,
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Message::Write((_, statement)) => f.debug_tuple("Write").field(statement).finish(),
+            Message::Flush(_) => f.write_str("Flush"),
+            Message::Stop(_) => f.write_str("Stop"),
+        }
+    }
+}
+
+impl WriteBehindHandle {
+    /// Returns the ID of the writer's task.
+    pub fn task_id(&self) -> tokio::task::Id {
+        self.task
+    }
+
+    /// Queues `statement`, returning as soon as it's been appended to the
+    /// journal and queued, or once there's space for it if the queue is
+    /// full. Whether it's applied successfully is only reported through
+    /// [`WriteBehind::on_error()`].
+    ///
+    /// This fails with [`Error::WriteBehindStopped`] if the writer has been
+    /// stopped, and with the error from SQLite if the write can't be
+    /// journaled, in which case it isn't queued.
+    pub async fn execute(&self, statement: PipelineStatement) -> Result<(), Error> {
+        if self.sender.is_closed() {
+            return Err(Error::WriteBehindStopped);
+        }
+        let journal = self.journal.lock().await;
+        let id = task::block_in_place(|| journal::append(&journal, &statement))?;
+        let result = self.send(Message::Write((id, statement))).await;
+        if result.is_err() {
+            // The write wasn't acknowledged, so it mustn't be applied later.
+            task::block_in_place(|| journal::remove(&journal, id..=id))?;
+        }
+        result
+    }
+
+    /// Waits until every write queued before the flush has been applied, or
+    /// reported as failed.
+    pub async fn flush(&self) -> Result<(), Error> {
+        let (done, applied) = oneshot::channel();
+        self.send(Message::Flush(done)).await?;
+        applied.await.map_err(|_| Error::WriteBehindStopped)
+    }
+
+    /// Applies every queued write, then stops the writer, for all handles.
+    pub async fn stop(&self) -> Result<(), Error> {
+        let (done, stopped) = oneshot::channel();
+        self.send(Message::Stop(done)).await?;
+        stopped.await.map_err(|_| Error::WriteBehindStopped)
+    }
+
+    async fn send(&self, message: Message) -> Result<(), Error> {
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| Error::WriteBehindStopped)
+    }
+}
+
+struct Writer {
+    config: WriteBehind,
+    // A connection of its own, since a handle holds its connection while it
+    // waits for space in the queue.
+    journal: std::sync::Mutex<Connection>,
+}
+
+impl Writer {
+    async fn run(self, pending: Vec<Write>, mut receiver: mpsc::Receiver<Message>) {
+        for batch in pending.chunks(self.config.max_batch) {
+            self.apply(batch.to_vec()).await;
+        }
+
+        let mut batch = Vec::new();
+        while let Some(message) = receiver.recv().await {
+            let mut waiting = None;
+            let mut stopping = false;
+            match message {
+                Message::Write(write) => {
+                    batch.push(write);
+                    // Collect writes until the batch is full, the delay
+                    // expires, or someone wants the queue applied now.
+                    let deadline = Instant::now() + self.config.max_delay;
+                    while batch.len() < self.config.max_batch {
+                        match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                            Ok(Some(Message::Write(write))) => batch.push(write),
+                            Ok(Some(Message::Flush(done))) => {
+                                waiting = Some(done);
+                                break;
+                            }
+                            Ok(Some(Message::Stop(done))) => {
+                                waiting = Some(done);
+                                stopping = true;
+                                break;
+                            }
+                            Ok(None) | Err(_) => break,
+                        }
+                    }
+                }
+                Message::Flush(done) => waiting = Some(done),
+                Message::Stop(done) => {
+                    waiting = Some(done);
+                    stopping = true;
+                }
+            }
+
+            if !batch.is_empty() {
+                self.apply(std::mem::take(&mut batch)).await;
+            }
+            if stopping {
+                // Anything queued behind the stop still gets applied, so that
+                // every acknowledged write is accounted for.
+                receiver.close();
+                while let Some(message) = receiver.recv().await {
+                    match message {
+                        Message::Write(write) => batch.push(write),
+                        Message::Flush(done) | Message::Stop(done) => {
+                            let _ = done.send(());
+                        }
+                    }
+                }
+                if !batch.is_empty() {
+                    self.apply(std::mem::take(&mut batch)).await;
+                }
+            }
+            if let Some(done) = waiting {
+                let _ = done.send(());
+            }
+            if stopping {
+                return;
+            }
+        }
+    }
+
+    /// Applies `batch` in a single transaction, then removes it from the
+    /// journal.
+    async fn apply(&self, batch: Vec<Write>) {
+        let result = pool::run(&self.config.pool, Operation::new("write_behind"), |conn| {
+            let mut tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let mut failed = Vec::new();
+            for (i, (_, statement)) in batch.iter().enumerate() {
+                let sp = tx.savepoint()?;
+                match crate::pipeline::run_one(&sp, statement) {
+                    Ok(_) => sp.commit()?,
+                    Err(e) => failed.push((i, e.into())),
+                }
+            }
+            tx.commit()?;
+            Ok(failed)
+        })
+        .await;
+
+        // Each write is reported once: by itself if only it failed, or with
+        // the batch's error if the whole batch did.
+        if let Some(callback) = &self.config.on_error {
+            match result {
+                Ok(failed) => {
+                    for (i, e) in failed {
+                        callback(&e, &batch[i].1);
+                    }
+                }
+                Err(e) => {
+                    for (_, statement) in &batch {
+                        callback(&e, statement);
+                    }
+                }
+            }
+        }
+
+        if let (Some((first, _)), Some((last, statement))) = (batch.first(), batch.last()) {
+            let removed = task::block_in_place(|| {
+                journal::remove(&self.journal.lock().unwrap(), *first..=*last)
+            });
+            if let Err(e) = removed {
+                // The batch is applied again by the next writer.
+                if let Some(callback) = &self.config.on_error {
+                    callback(&e, statement);
+                }
+            }
+        }
+    }
+}
+
+/// The journal's schema, and reading and writing it.
+mod journal {
+    use std::ops::RangeInclusive;
+
+    use super::*;
+
+    pub(super) fn open(path: &Path) -> Result<Connection, Error> {
+        let conn = Connection::open(path)?;
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+        conn.execute_batch(
+            "PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS writes (id INTEGER PRIMARY KEY, sql TEXT NOT NULL);
+             CREATE TABLE IF NOT EXISTS params (
+                 write INTEGER NOT NULL REFERENCES writes (id) ON DELETE CASCADE,
+                 position INTEGER NOT NULL,
+                 value,
+                 PRIMARY KEY (write, position)
+             );
+             PRAGMA foreign_keys = ON;",
+        )?;
+        Ok(conn)
+    }
+
+    /// Appends `statement`, returning its ID. The `params` column has no
+    /// type, so values keep theirs.
+    pub(super) fn append(conn: &Connection, statement: &PipelineStatement) -> Result<i64, Error> {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute("INSERT INTO writes (sql) VALUES (?)", &[&statement.sql])?;
+        let id = tx.last_insert_rowid();
+        let mut insert =
+            tx.prepare_cached("INSERT INTO params (write, position, value) VALUES (?, ?, ?)")?;
+        for (position, value) in statement.params.iter().enumerate() {
+            insert.execute(rusqlite::params![id, position as i64, value])?;
+        }
+        drop(insert);
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Returns the writes in the journal, in the order they were appended.
+    pub(super) fn pending(conn: &Connection) -> Result<Vec<Write>, Error> {
+        let mut params =
+            conn.prepare("SELECT value FROM params WHERE write = ? ORDER BY position")?;
+        let mut writes = conn.prepare("SELECT id, sql FROM writes ORDER BY id")?;
+        let writes = writes
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<(i64, String)>, _>>()?;
+        writes
+            .into_iter()
+            .map(|(id, sql)| {
+                let values = params
+                    .query_map([id], |row| row.get::<_, Value>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((id, PipelineStatement::new(sql).params(values)))
+            })
+            .collect()
+    }
+
+    /// Removes the writes with IDs in `ids`.
+    pub(super) fn remove(conn: &Connection, ids: RangeInclusive<i64>) -> Result<(), Error> {
+        conn.execute(
+            "DELETE FROM writes WHERE id BETWEEN ? AND ?",
+            &[ids.start(), ids.end()],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::Mutex;
+
+use rusqlite::NO_PARAMS;
+
+use super::*;
+use crate::tests::{pool, TempDir};
+
+const SETUP: &str = "CREATE TABLE t (a INTEGER UNIQUE)";
+
+async fn count(pool: &bb8::Pool<RusqliteConnectionManager>) -> Result<i64, anyhow::Error> {
+    Ok(pool
+        .get()
+        .await?
+        .query_row("SELECT COUNT(*) FROM t", NO_PARAMS, |row| row.get(0))?)
+}
+
+fn journaled(temp: &TempDir) -> Result<i64, anyhow::Error> {
+    Ok(journal::open(&temp.file("journal.db"))?.query_row(
+        "SELECT COUNT(*) FROM writes",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?)
+}
+
+fn insert(a: i64) -> PipelineStatement {
+    PipelineStatement::new("INSERT INTO t (a) VALUES (?)").params(vec![a])
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn flush() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("write_behind.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+
+    let handle = WriteBehind::new(pool.clone(), temp.file("journal.db"))
+        .with_max_batch(7)
+        .with_max_delay(Duration::from_secs(60))
+        .start()
+        .await?;
+    for a in 0..20 {
+        handle.execute(insert(a)).await?;
+    }
+    handle.flush().await?;
+    assert_eq!(count(&pool).await?, 20);
+    assert_eq!(journaled(&temp)?, 0);
+
+    handle.stop().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn delay() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("write_behind.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+
+    let handle = WriteBehind::new(pool.clone(), temp.file("journal.db"))
+        .with_max_delay(Duration::from_millis(10))
+        .start()
+        .await?;
+    handle.execute(insert(1)).await?;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(count(&pool).await?, 1);
+
+    handle.stop().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_write() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("write_behind.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+
+    let failed = Arc::new(Mutex::new(Vec::new()));
+    let handle = WriteBehind::new(pool.clone(), temp.file("journal.db"))
+        .on_error({
+            let failed = failed.clone();
+            move |_, statement| failed.lock().unwrap().push(statement.clone())
+        })
+        .start()
+        .await?;
+    handle.execute(insert(1)).await?;
+    handle.execute(insert(1)).await?;
+    handle.execute(insert(2)).await?;
+    handle.flush().await?;
+
+    // Only the duplicate is lost; the rest of the batch is committed.
+    assert_eq!(*failed.lock().unwrap(), vec![insert(1)]);
+    assert_eq!(count(&pool).await?, 2);
+
+    handle.stop().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stop() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("write_behind.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+
+    let handle = WriteBehind::new(pool.clone(), temp.file("journal.db"))
+        .with_max_delay(Duration::from_secs(60))
+        .start()
+        .await?;
+    let other = handle.clone();
+    handle.execute(insert(1)).await?;
+    handle.stop().await?;
+
+    // Queued writes are applied before the writer stops.
+    assert_eq!(count(&pool).await?, 1);
+    assert!(matches!(
+        other.execute(insert(2)).await,
+        Err(Error::WriteBehindStopped)
+    ));
+    assert!(matches!(
+        other.flush().await,
+        Err(Error::WriteBehindStopped)
+    ));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_batch() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("write_behind.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+    pool.get()
+        .await?
+        .execute("INSERT INTO t (a) VALUES (1)", NO_PARAMS)?;
+
+    let failed = Arc::new(Mutex::new(Vec::new()));
+    let handle = WriteBehind::new(pool.clone(), temp.file("journal.db"))
+        .with_max_delay(Duration::from_secs(60))
+        .on_error({
+            let failed = failed.clone();
+            move |_, statement| failed.lock().unwrap().push(statement.clone())
+        })
+        .start()
+        .await?;
+    // The duplicate fails by itself, then the rollback takes the rest of the
+    // batch with it.
+    let rollback = PipelineStatement::new("ROLLBACK");
+    handle.execute(insert(1)).await?;
+    handle.execute(rollback.clone()).await?;
+    handle.execute(insert(2)).await?;
+    handle.flush().await?;
+
+    // Each write is reported once.
+    assert_eq!(
+        *failed.lock().unwrap(),
+        vec![insert(1), rollback, insert(2)]
+    );
+    assert_eq!(count(&pool).await?, 1);
+    assert_eq!(journaled(&temp)?, 0);
+
+    handle.stop().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replay() -> Result<(), anyhow::Error> {
+    let temp = TempDir::new()?;
+    let pool = pool(
+        RusqliteConnectionManager::new(temp.file("write_behind.db")),
+        2,
+        SETUP,
+    )
+    .await?;
+
+    // Writes left in the journal by a writer that exited before applying
+    // them.
+    {
+        let journal = journal::open(&temp.file("journal.db"))?;
+        journal::append(&journal, &insert(1))?;
+        journal::append(&journal, &PipelineStatement::new("UPDATE t SET a = a + 1"))?;
+    }
+
+    let handle = WriteBehind::new(pool.clone(), temp.file("journal.db"))
+        .start()
+        .await?;
+    handle.execute(insert(1)).await?;
+    handle.flush().await?;
+
+    // They're applied first, in order.
+    let rows: Vec<i64> = pool
+        .get()
+        .await?
+        .prepare("SELECT a FROM t ORDER BY a")?
+        .query_map(NO_PARAMS, |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    assert_eq!(rows, vec![1, 2]);
+    assert_eq!(journaled(&temp)?, 0);
+
+    handle.stop().await?;
+    Ok(())
+}